@@ -0,0 +1,138 @@
+"use strict";
+
+const REFRESH_INTERVAL_MS = 30 * 1000;
+const CHART_HOURS = 7 * 24;
+const CHART_WIDTH = 720;
+const CHART_HEIGHT = 200;
+const SVG = "http://www.w3.org/2000/svg";
+const FAULTS = {
+  sensor_fault: "Sensor fault",
+  hardware_error: "Hardware error",
+  reservoir_suspected_empty: "Reservoir empty?",
+};
+
+const cards = new Map();
+
+async function api(method, path, body) {
+  const response = await fetch(path, {
+    method,
+    headers: body ? { "Content-Type": "application/json" } : {},
+    body: body ? JSON.stringify(body) : undefined,
+  });
+  const json = await response.json();
+  if (!response.ok) {
+    throw new Error(json.error || response.statusText);
+  }
+  return json;
+}
+
+function plantPath(plant) {
+  return `/api/plants/${encodeURIComponent(plant.name)}`;
+}
+
+function card(plant) {
+  let section = cards.get(plant.name);
+  if (section) {
+    return section;
+  }
+
+  section = document.getElementById("plant-template").content.firstElementChild.cloneNode(true);
+  section.querySelector(".name").textContent = plant.name;
+  const message = section.querySelector(".message");
+  const run = async (label, action) => {
+    message.textContent = `${label}…`;
+    try {
+      await action();
+      message.textContent = `${label}: done`;
+    } catch (error) {
+      message.textContent = `${label}: ${error.message}`;
+    }
+    refresh();
+  };
+
+  section.querySelector(".water").addEventListener("click", () => {
+    const seconds = Number(section.querySelector(".duration").value);
+    run("Watering", () =>
+      api("POST", `${plantPath(plant)}/water`, { duration_ms: Math.round(seconds * 1000) }));
+  });
+  section.querySelector(".pause").addEventListener("click", () => {
+    const paused = section.dataset.paused !== "true";
+    run(paused ? "Pausing" : "Resuming", () =>
+      api("PATCH", `${plantPath(plant)}/config`, { paused }));
+  });
+
+  document.getElementById("plants").append(section);
+  cards.set(plant.name, section);
+  return section;
+}
+
+function render(plant, chart) {
+  const section = card(plant);
+  section.dataset.paused = plant.paused;
+
+  const fault = plant.reservoir_suspected_empty ? "reservoir_suspected_empty" : plant.status;
+  const faultLabel = section.querySelector(".fault");
+  faultLabel.hidden = !(fault in FAULTS);
+  faultLabel.textContent = FAULTS[fault] || "";
+
+  section.querySelector(".moisture").textContent =
+    plant.moisture === null ? "–" : `${plant.moisture}%`;
+  section.querySelector(".target").textContent = `${plant.target_min}–${plant.target_max}%`;
+  section.querySelector(".last-watered").textContent =
+    plant.last_watered === null ? "never" : new Date(plant.last_watered).toLocaleString();
+  section.querySelector(".pause").textContent = plant.paused ? "Resume" : "Pause";
+
+  drawChart(section.querySelector(".chart"), plant, chart);
+}
+
+function drawChart(svg, plant, hours) {
+  const end = Date.now();
+  const start = end - CHART_HOURS * 3600 * 1000;
+  const x = (time) => ((new Date(time).getTime() - start) / (end - start)) * CHART_WIDTH;
+  const y = (moisture) => CHART_HEIGHT - (moisture / 100) * CHART_HEIGHT;
+  const element = (name, attributes) => {
+    const child = document.createElementNS(SVG, name);
+    for (const [key, value] of Object.entries(attributes)) {
+      child.setAttribute(key, value);
+    }
+    svg.append(child);
+  };
+
+  svg.replaceChildren();
+  element("rect", {
+    class: "target",
+    x: 0,
+    y: y(plant.target_max),
+    width: CHART_WIDTH,
+    height: y(plant.target_min) - y(plant.target_max),
+  });
+
+  const measured = hours.filter((hour) => hour.mean !== null);
+  if (measured.length > 0) {
+    const top = measured.map((hour) => `${x(hour.time)},${y(hour.max)}`);
+    const bottom = measured.map((hour) => `${x(hour.time)},${y(hour.min)}`).reverse();
+    element("polygon", { class: "range", points: top.concat(bottom).join(" ") });
+    element("polyline", {
+      class: "mean",
+      points: measured.map((hour) => `${x(hour.time)},${y(hour.mean)}`).join(" "),
+    });
+  }
+  for (const hour of hours.filter((hour) => hour.waterings > 0)) {
+    element("line", { class: "watering", x1: x(hour.time), x2: x(hour.time), y1: 0, y2: CHART_HEIGHT });
+  }
+}
+
+async function refresh() {
+  try {
+    const plants = await api("GET", "/api/plants");
+    await Promise.all(plants.map(async (plant) =>
+      render(plant, await api("GET", `${plantPath(plant)}/chart`))));
+    document.getElementById("updated").textContent =
+      `Updated ${new Date().toLocaleTimeString()}`;
+  } catch (error) {
+    document.getElementById("updated").textContent = `Offline: ${error.message}`;
+  }
+}
+
+refresh();
+setInterval(refresh, REFRESH_INTERVAL_MS);
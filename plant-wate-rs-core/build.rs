@@ -0,0 +1,67 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+const ASSETS_DIR: &str = "assets/dashboard";
+
+/// Compresses the dashboard assets with gzip and generates the table embedding
+/// them, included by `src/dashboard.rs`.
+fn main() {
+    println!("cargo:rerun-if-changed={}", ASSETS_DIR);
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR not set"));
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(ASSETS_DIR)
+        .expect("cannot read the assets directory")
+        .map(|entry| entry.expect("cannot read the assets directory").path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+
+    let mut table = String::from("&[\n");
+    for path in paths {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let name = path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .expect("non-UTF-8 asset name");
+        let data = fs::read(&path).expect("cannot read an asset");
+
+        let compressed_path = out_dir.join(format!("{}.gz", name));
+        fs::write(&compressed_path, gzip(&data)).expect("cannot write a compressed asset");
+        writeln!(
+            table,
+            "    Asset {{ path: \"/{}\", content_type: \"{}\", gzip: include_bytes!({:?}) }},",
+            name,
+            content_type(&path),
+            compressed_path
+        )
+        .unwrap();
+    }
+    table.push(']');
+
+    fs::write(out_dir.join("dashboard_assets.rs"), table).expect("cannot write the asset table");
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The gzip header records no file name and a modification time of 0, to keep
+/// builds reproducible.
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).expect("cannot compress an asset");
+    encoder.finish().expect("cannot compress an asset")
+}
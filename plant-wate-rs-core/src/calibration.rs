@@ -0,0 +1,374 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::plant_irrigator::SensorCalibrationResult;
+use crate::uc::{AnalogInput, AnalogValue, Microcontroller, UcError};
+use crate::uc_utils::{retry, AnalogValueMean, HARDWARE_ATTEMPTS, HARDWARE_RETRY_DELAY};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CalibrationConfig {
+    /// Number of readings that have to be stable for a measurement to be
+    /// accepted.
+    pub samples: u8,
+    pub sample_interval: Duration,
+    /// Maximum difference between the lowest and the highest reading, in mV,
+    /// for the readings to be considered stable.
+    pub max_spread: u16,
+    /// Number of sets of readings taken before giving up on a measurement.
+    pub max_attempts: u8,
+    /// Minimum difference between the dry and the wet value, in mV.
+    pub min_range: u16,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            samples: 10,
+            sample_interval: Duration::from_millis(200),
+            max_spread: 50,
+            max_attempts: 10,
+            min_range: 500,
+        }
+    }
+}
+
+impl CalibrationConfig {
+    pub fn is_valid(&self) -> bool {
+        self.samples > 0 && self.max_attempts > 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CalibrationStep {
+    /// The sensor should be held in the air.
+    Dry,
+    /// The sensor should be put in water.
+    Wet,
+    Done,
+}
+
+impl Display for CalibrationStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let instruction = match self {
+            CalibrationStep::Dry => "hold the sensor in the air",
+            CalibrationStep::Wet => "put the sensor in water up to the line",
+            CalibrationStep::Done => "calibration finished",
+        };
+        f.write_str(instruction)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CalibrationError {
+    /// The readings did not settle within the configured number of attempts.
+    Unstable {
+        step: CalibrationStep,
+        spread: u16,
+    },
+    /// The sensor read a higher value in water than in the air.
+    Inverted {
+        dry: AnalogValue,
+        wet: AnalogValue,
+    },
+    /// The dry and the wet values are too close to each other.
+    InsufficientRange {
+        dry: AnalogValue,
+        wet: AnalogValue,
+    },
+    Hardware(UcError),
+    /// The [`CalibrationConfig`] takes no samples or makes no attempts.
+    InvalidConfig,
+}
+
+impl Display for CalibrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalibrationError::Unstable { step, spread } => write!(
+                f,
+                "readings did not settle ({} mV spread) during the {:?} step",
+                spread, step
+            ),
+            CalibrationError::Inverted { dry, wet } => {
+                write!(f, "wet value {} is not lower than dry value {}", wet, dry)
+            }
+            CalibrationError::InsufficientRange { dry, wet } => write!(
+                f,
+                "dry value {} and wet value {} are too close to each other",
+                dry, wet
+            ),
+            CalibrationError::Hardware(error) => write!(f, "{}", error),
+            CalibrationError::InvalidConfig => write!(f, "invalid calibration configuration"),
+        }
+    }
+}
+
+impl Error for CalibrationError {}
+
+impl From<UcError> for CalibrationError {
+    fn from(error: UcError) -> Self {
+        CalibrationError::Hardware(error)
+    }
+}
+
+/// Two-point soil moisture sensor calibration: the sensor is measured in the
+/// air first and then in water.
+///
+/// If the result turns out to be invalid, the calibration starts over from the
+/// [`CalibrationStep::Dry`] step.
+#[derive(Debug, Clone)]
+pub struct SensorCalibration {
+    config: CalibrationConfig,
+    dry_value: Option<AnalogValue>,
+    result: Option<SensorCalibrationResult>,
+}
+
+impl SensorCalibration {
+    pub fn new(config: CalibrationConfig) -> Result<Self, CalibrationError> {
+        if !config.is_valid() {
+            return Err(CalibrationError::InvalidConfig);
+        }
+
+        Ok(Self {
+            config,
+            dry_value: None,
+            result: None,
+        })
+    }
+
+    #[inline]
+    pub const fn config(&self) -> &CalibrationConfig {
+        &self.config
+    }
+
+    pub fn step(&self) -> CalibrationStep {
+        if self.result.is_some() {
+            CalibrationStep::Done
+        } else if self.dry_value.is_some() {
+            CalibrationStep::Wet
+        } else {
+            CalibrationStep::Dry
+        }
+    }
+
+    #[inline]
+    pub fn result(&self) -> Option<&SensorCalibrationResult> {
+        self.result.as_ref()
+    }
+
+    /// Measures the sensor for the current step and returns the next one.
+    pub fn measure<MicrocontrollerImpl: Microcontroller>(
+        &mut self,
+        microcontroller: &MicrocontrollerImpl,
+        sensor: &mut MicrocontrollerImpl::AnalogInput,
+    ) -> Result<CalibrationStep, CalibrationError> {
+        let step = self.step();
+        match step {
+            CalibrationStep::Dry => {
+                let dry = self.stable_value(microcontroller, sensor, step)?;
+                info!("Dry value: {}", dry);
+                self.dry_value = Some(dry);
+            }
+            CalibrationStep::Wet => {
+                let wet = self.stable_value(microcontroller, sensor, step)?;
+                info!("Wet value: {}", wet);
+                let dry = self.dry_value.take().expect("dry value measured");
+                self.result = Some(self.validate(dry, wet)?);
+            }
+            CalibrationStep::Done => {}
+        }
+
+        Ok(self.step())
+    }
+
+    /// Starts the calibration over.
+    pub fn reset(&mut self) {
+        self.dry_value = None;
+        self.result = None;
+    }
+
+    fn validate(
+        &self,
+        dry: AnalogValue,
+        wet: AnalogValue,
+    ) -> Result<SensorCalibrationResult, CalibrationError> {
+        if wet >= dry {
+            Err(CalibrationError::Inverted { dry, wet })
+        } else if dry.value() - wet.value() < self.config.min_range {
+            Err(CalibrationError::InsufficientRange { dry, wet })
+        } else {
+            Ok(SensorCalibrationResult::new(wet, dry))
+        }
+    }
+
+    fn stable_value<MicrocontrollerImpl: Microcontroller>(
+        &self,
+        microcontroller: &MicrocontrollerImpl,
+        sensor: &mut MicrocontrollerImpl::AnalogInput,
+        step: CalibrationStep,
+    ) -> Result<AnalogValue, CalibrationError> {
+        let mut spread = 0;
+        for attempt in 1..=self.config.max_attempts {
+            let mut readings = Vec::with_capacity(self.config.samples as usize);
+            for sample in 0..self.config.samples {
+                if sample > 0 {
+                    microcontroller.wait(self.config.sample_interval);
+                }
+                readings.push(retry(
+                    microcontroller,
+                    HARDWARE_ATTEMPTS,
+                    HARDWARE_RETRY_DELAY,
+                    || sensor.get_value(),
+                )?);
+            }
+
+            let min = readings.iter().min().expect("at least one sample");
+            let max = readings.iter().max().expect("at least one sample");
+            spread = max.value() - min.value();
+            if spread <= self.config.max_spread {
+                return Ok(readings.iter().mean());
+            }
+
+            warn!(
+                "Readings not stable ({} mV spread, attempt {}/{})",
+                spread, attempt, self.config.max_attempts
+            );
+            microcontroller.wait(self.config.sample_interval);
+        }
+
+        Err(CalibrationError::Unstable { step, spread })
+    }
+}
+
+/// Runs the whole calibration. `prompt` is called before each measurement and
+/// should return once the sensor is in place.
+pub fn calibrate_sensor<MicrocontrollerImpl: Microcontroller>(
+    microcontroller: &MicrocontrollerImpl,
+    sensor: &mut MicrocontrollerImpl::AnalogInput,
+    config: CalibrationConfig,
+    mut prompt: impl FnMut(CalibrationStep),
+) -> Result<SensorCalibrationResult, CalibrationError> {
+    let mut calibration = SensorCalibration::new(config)?;
+    while calibration.step() != CalibrationStep::Done {
+        prompt(calibration.step());
+        calibration.measure(microcontroller, sensor)?;
+    }
+
+    Ok(calibration.result().cloned().expect("calibration finished"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calibration::{
+        calibrate_sensor, CalibrationConfig, CalibrationError, CalibrationStep, SensorCalibration,
+    };
+    use crate::mock_uc::MockMicrocontroller;
+    use crate::plant_irrigator::SensorCalibrationResult;
+    use crate::uc::{AnalogValue, Microcontroller, GPIO_0};
+
+    fn config() -> CalibrationConfig {
+        CalibrationConfig {
+            samples: 3,
+            max_attempts: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test_log::test]
+    fn two_point_calibration() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let mut sensor = mock_uc.get_analog_input(GPIO_0).unwrap();
+        let mut steps = Vec::new();
+
+        let result = calibrate_sensor(&mock_uc, &mut sensor, config(), |step| {
+            steps.push(step);
+            let value = match step {
+                CalibrationStep::Dry => 2500,
+                _ => 1000,
+            };
+            mock_uc.set_analog_value(GPIO_0, AnalogValue::new(value));
+        });
+
+        assert_eq!(
+            result,
+            Ok(SensorCalibrationResult::new(
+                AnalogValue::new(1000),
+                AnalogValue::new(2500)
+            ))
+        );
+        assert_eq!(steps, [CalibrationStep::Dry, CalibrationStep::Wet]);
+    }
+
+    #[test_log::test]
+    fn waits_for_stable_readings() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let mut sensor = mock_uc.get_analog_input(GPIO_0).unwrap();
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(2500));
+        mock_uc.queue_analog_values(GPIO_0, [2000, 2300, 2500].map(AnalogValue::new));
+        let mut calibration = SensorCalibration::new(config()).unwrap();
+
+        assert_eq!(
+            calibration.measure(&mock_uc, &mut sensor),
+            Ok(CalibrationStep::Wet)
+        );
+
+        mock_uc.queue_analog_values(
+            GPIO_0,
+            [1000, 1500, 1000, 1500, 1000, 1500].map(AnalogValue::new),
+        );
+        assert_eq!(
+            calibration.measure(&mock_uc, &mut sensor),
+            Err(CalibrationError::Unstable {
+                step: CalibrationStep::Wet,
+                spread: 500
+            })
+        );
+        assert_eq!(calibration.step(), CalibrationStep::Wet);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let config = CalibrationConfig {
+            samples: 0,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            SensorCalibration::new(config),
+            Err(CalibrationError::InvalidConfig)
+        ));
+    }
+
+    #[test_log::test]
+    fn invalid_results() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let mut sensor = mock_uc.get_analog_input(GPIO_0).unwrap();
+        let mut calibration = SensorCalibration::new(config()).unwrap();
+
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(1000));
+        calibration.measure(&mock_uc, &mut sensor).unwrap();
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(2500));
+        assert_eq!(
+            calibration.measure(&mock_uc, &mut sensor),
+            Err(CalibrationError::Inverted {
+                dry: AnalogValue::new(1000),
+                wet: AnalogValue::new(2500)
+            })
+        );
+        assert_eq!(calibration.step(), CalibrationStep::Dry);
+
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(2500));
+        calibration.measure(&mock_uc, &mut sensor).unwrap();
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(2200));
+        assert_eq!(
+            calibration.measure(&mock_uc, &mut sensor),
+            Err(CalibrationError::InsufficientRange {
+                dry: AnalogValue::new(2500),
+                wet: AnalogValue::new(2200)
+            })
+        );
+        assert!(calibration.result().is_none());
+    }
+}
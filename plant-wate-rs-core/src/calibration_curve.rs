@@ -0,0 +1,221 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::plant_irrigator::{Percentage, SensorCalibrationResult};
+use crate::uc::AnalogValue;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CalibrationPoint {
+    pub value: AnalogValue,
+    pub moisture: Percentage,
+}
+
+impl CalibrationPoint {
+    #[inline]
+    pub const fn new(value: AnalogValue, moisture: Percentage) -> Self {
+        Self { value, moisture }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CalibrationCurveError {
+    TooFewPoints,
+    /// The sensor values of the points are not strictly increasing.
+    UnsortedValues,
+    /// The moisture does not consistently rise or fall with the sensor value.
+    NotMonotonic,
+    MoistureAbove100,
+}
+
+impl Display for CalibrationCurveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            CalibrationCurveError::TooFewPoints => "at least two points are needed",
+            CalibrationCurveError::UnsortedValues => "sensor values must be strictly increasing",
+            CalibrationCurveError::NotMonotonic => {
+                "moisture must strictly increase or strictly decrease with the sensor value"
+            }
+            CalibrationCurveError::MoistureAbove100 => "moisture cannot exceed 100%",
+        };
+        f.write_str(description)
+    }
+}
+
+impl Error for CalibrationCurveError {}
+
+/// Maps sensor values to moisture by linear interpolation between calibration
+/// points. Values outside of the calibrated range are clamped to it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CalibrationCurve {
+    points: Vec<CalibrationPoint>,
+}
+
+impl CalibrationCurve {
+    pub fn new(points: Vec<CalibrationPoint>) -> Result<Self, CalibrationCurveError> {
+        let curve = Self { points };
+        curve.validate()?;
+        Ok(curve)
+    }
+
+    pub fn validate(&self) -> Result<(), CalibrationCurveError> {
+        if self.points.len() < 2 {
+            return Err(CalibrationCurveError::TooFewPoints);
+        }
+
+        if self
+            .points
+            .windows(2)
+            .any(|pair| pair[0].value >= pair[1].value)
+        {
+            return Err(CalibrationCurveError::UnsortedValues);
+        }
+
+        let increasing = self
+            .points
+            .windows(2)
+            .all(|pair| pair[0].moisture < pair[1].moisture);
+        let decreasing = self
+            .points
+            .windows(2)
+            .all(|pair| pair[0].moisture > pair[1].moisture);
+        if !increasing && !decreasing {
+            return Err(CalibrationCurveError::NotMonotonic);
+        }
+
+        if self.points.iter().any(|point| point.moisture.value() > 100) {
+            return Err(CalibrationCurveError::MoistureAbove100);
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn points(&self) -> &[CalibrationPoint] {
+        &self.points
+    }
+
+    /// The lowest calibrated sensor value.
+    #[inline]
+    pub fn min_value(&self) -> AnalogValue {
+        self.points[0].value
+    }
+
+    /// The highest calibrated sensor value.
+    #[inline]
+    pub fn max_value(&self) -> AnalogValue {
+        self.points[self.points.len() - 1].value
+    }
+
+    /// The sensor value of the point with exactly `moisture`, if there is one.
+    pub fn value_at(&self, moisture: Percentage) -> Option<AnalogValue> {
+        self.points
+            .iter()
+            .find(|point| point.moisture == moisture)
+            .map(|point| point.value)
+    }
+
+    pub fn moisture(&self, value: AnalogValue) -> Percentage {
+        let segment = self
+            .points
+            .windows(2)
+            .find(|pair| value <= pair[1].value)
+            .unwrap_or(&self.points[self.points.len() - 2..]);
+        let (start, end) = (segment[0], segment[1]);
+
+        let value = value.clamp(start.value, end.value);
+        let ratio = (value.value() - start.value.value()) as f32
+            / (end.value.value() - start.value.value()) as f32;
+        let moisture = start.moisture.value() as f32
+            + ratio * (end.moisture.value() as f32 - start.moisture.value() as f32);
+
+        Percentage::new(moisture.round() as u8)
+    }
+}
+
+impl Display for CalibrationCurve {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, point) in self.points.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} mV = {}", point.value.value(), point.moisture)?;
+        }
+        Ok(())
+    }
+}
+
+/// The two-point calibration: the lowest value means 100% moisture and the
+/// highest one 0%.
+impl From<SensorCalibrationResult> for CalibrationCurve {
+    fn from(calibration_result: SensorCalibrationResult) -> Self {
+        Self {
+            points: vec![
+                CalibrationPoint::new(calibration_result.min_value(), Percentage::new(100)),
+                CalibrationPoint::new(calibration_result.max_value(), Percentage::new(0)),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(value: u16, moisture: u8) -> CalibrationPoint {
+        CalibrationPoint::new(AnalogValue::new(value), Percentage::new(moisture))
+    }
+
+    fn moisture(curve: &CalibrationCurve, value: u16) -> u8 {
+        curve.moisture(AnalogValue::new(value)).value()
+    }
+
+    #[test]
+    fn two_point_calibration() {
+        let curve: CalibrationCurve =
+            SensorCalibrationResult::new(AnalogValue::new(500), AnalogValue::new(2200)).into();
+
+        assert_eq!(curve.validate(), Ok(()));
+        assert_eq!(moisture(&curve, 0), 100);
+        assert_eq!(moisture(&curve, 500), 100);
+        assert_eq!(moisture(&curve, 1350), 50);
+        assert_eq!(moisture(&curve, 2200), 0);
+        assert_eq!(moisture(&curve, 3000), 0);
+    }
+
+    #[test]
+    fn piecewise_interpolation() {
+        let curve =
+            CalibrationCurve::new(vec![point(1000, 100), point(1200, 60), point(2500, 0)]).unwrap();
+
+        assert_eq!(moisture(&curve, 1100), 80);
+        assert_eq!(moisture(&curve, 1200), 60);
+        assert_eq!(moisture(&curve, 1850), 30);
+        assert_eq!(curve.min_value(), AnalogValue::new(1000));
+        assert_eq!(curve.max_value(), AnalogValue::new(2500));
+        assert_eq!(
+            curve.value_at(Percentage::new(60)),
+            Some(AnalogValue::new(1200))
+        );
+        assert_eq!(curve.value_at(Percentage::new(50)), None);
+    }
+
+    #[test]
+    fn invalid_points() {
+        assert_eq!(
+            CalibrationCurve::new(vec![point(1000, 100)]),
+            Err(CalibrationCurveError::TooFewPoints)
+        );
+        assert_eq!(
+            CalibrationCurve::new(vec![point(1000, 100), point(1000, 50)]),
+            Err(CalibrationCurveError::UnsortedValues)
+        );
+        assert_eq!(
+            CalibrationCurve::new(vec![point(1000, 100), point(1500, 40), point(2000, 60)]),
+            Err(CalibrationCurveError::NotMonotonic)
+        );
+        assert_eq!(
+            CalibrationCurve::new(vec![point(1000, 120), point(2000, 0)]),
+            Err(CalibrationCurveError::MoistureAbove100)
+        );
+    }
+}
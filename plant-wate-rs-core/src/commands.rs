@@ -0,0 +1,694 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::json::{parse_flat_object, JsonError, JsonObject, JsonValue};
+use crate::mqtt::MqttMessage;
+use crate::plant_irrigator::{IrrigationStatus, Percentage, PlantIrrigator, TargetMoistureLevel};
+use crate::storage::{DecodeError, Decoder, Encoder, Record};
+use crate::telemetry::status_payload;
+use crate::uc::Microcontroller;
+
+/// Where the commands of the plants are received and acknowledged.
+///
+/// Commands are JSON objects with an `id` and an `action`, e.g.
+/// `{"id": 17, "action": "water", "duration_ms": 3000}`. The other actions
+/// are `pause`, `resume`, `reset_faults` and `set_target`, which takes `min`
+/// and `max` percentages.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CommandConfig {
+    pub topic_template: String,
+    /// Where the outcome of each command is published.
+    pub response_topic_template: String,
+    /// Longest watering that can be requested.
+    pub max_manual_watering: Duration,
+    /// How far the ID of a command may be ahead of the last one of the plant,
+    /// so that a single bogus ID cannot lock the plant out of commands.
+    pub max_id_jump: u64,
+}
+
+impl Default for CommandConfig {
+    fn default() -> Self {
+        Self {
+            topic_template: "plant-wate-rs/{plant}/command".to_owned(),
+            response_topic_template: "plant-wate-rs/{plant}/response".to_owned(),
+            max_manual_watering: Duration::from_secs(30),
+            max_id_jump: 1_000_000,
+        }
+    }
+}
+
+impl CommandConfig {
+    pub fn validate(&self) -> Result<(), CommandConfigError> {
+        if !self.topic_template.contains("{plant}")
+            || !self.response_topic_template.contains("{plant}")
+        {
+            return Err(CommandConfigError::MissingPlantPlaceholder);
+        }
+        if self.topic_template == self.response_topic_template {
+            return Err(CommandConfigError::SameTopics);
+        }
+        if self.max_manual_watering.is_zero() {
+            return Err(CommandConfigError::ZeroMaxManualWatering);
+        }
+        if self.max_id_jump == 0 {
+            return Err(CommandConfigError::ZeroMaxIdJump);
+        }
+
+        Ok(())
+    }
+
+    pub fn topic(&self, plant: &str) -> String {
+        self.topic_template.replace("{plant}", plant)
+    }
+
+    pub fn response_topic(&self, plant: &str) -> String {
+        self.response_topic_template.replace("{plant}", plant)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CommandConfigError {
+    /// A topic template lacks the `{plant}` placeholder.
+    MissingPlantPlaceholder,
+    /// The commands and the responses would share the topic.
+    SameTopics,
+    ZeroMaxManualWatering,
+    ZeroMaxIdJump,
+}
+
+impl Display for CommandConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandConfigError::MissingPlantPlaceholder => {
+                write!(f, "the topic templates must contain `{{plant}}`")
+            }
+            CommandConfigError::SameTopics => {
+                write!(f, "the command and response topics must differ")
+            }
+            CommandConfigError::ZeroMaxManualWatering => {
+                write!(f, "the longest manual watering must not be zero")
+            }
+            CommandConfigError::ZeroMaxIdJump => {
+                write!(f, "the maximum command ID jump must not be zero")
+            }
+        }
+    }
+}
+
+impl Error for CommandConfigError {}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Command {
+    /// Runs the pump for the given time; see
+    /// [`PlantIrrigator::water_manually`].
+    Water(Duration),
+    Pause,
+    Resume,
+    SetTarget(TargetMoistureLevel),
+    /// Resets the sensor fault detection and the reservoir alarm.
+    ResetFaults,
+}
+
+impl Command {
+    pub fn execute<MicrocontrollerImpl: Microcontroller>(
+        self,
+        plant_irrigator: &mut PlantIrrigator<MicrocontrollerImpl>,
+        microcontroller: &MicrocontrollerImpl,
+    ) -> Result<(), CommandError> {
+        match self {
+            Command::Water(pump_time) => {
+                if let Some(fault) = plant_irrigator.latched_fault() {
+                    return Err(CommandError::Faulted(fault));
+                }
+                match plant_irrigator.water_manually(microcontroller, pump_time) {
+                    IrrigationStatus::Watered => {}
+                    status => return Err(CommandError::Failed(status)),
+                }
+            }
+            Command::Pause => plant_irrigator.pause(),
+            Command::Resume => plant_irrigator.resume(),
+            Command::SetTarget(target) => plant_irrigator.set_target_moisture_level(target),
+            Command::ResetFaults => plant_irrigator.reset_faults(),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CommandRequest {
+    /// Must increase with every command sent to a plant.
+    pub id: u64,
+    pub command: Command,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    NotUtf8,
+    Json(JsonError),
+    MissingField(&'static str),
+    InvalidField(&'static str),
+    UnknownAction(String),
+    UnknownPlant(String),
+    /// The minimum is not below the maximum, or the maximum is over 100%.
+    InvalidTarget,
+    WateringTooLong {
+        requested: Duration,
+        max: Duration,
+    },
+    /// Retained by the broker, so possibly sent long ago.
+    Retained,
+    /// The ID is not greater than the one of the last command of the plant.
+    Replayed {
+        id: u64,
+        last_id: u64,
+    },
+    /// The ID is too far ahead of the one of the last command of the plant;
+    /// see [`CommandConfig::max_id_jump`].
+    IdTooFarAhead {
+        id: u64,
+        last_id: u64,
+    },
+    /// The plant has a fault that must be reset first; see
+    /// [`PlantIrrigator::latched_fault`].
+    Faulted(IrrigationStatus),
+    /// The command was valid, but could not be carried out.
+    Failed(IrrigationStatus),
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::NotUtf8 => write!(f, "payload is not valid UTF-8"),
+            CommandError::Json(error) => write!(f, "malformed JSON: {}", error),
+            CommandError::MissingField(field) => write!(f, "missing field '{}'", field),
+            CommandError::InvalidField(field) => write!(f, "invalid value of field '{}'", field),
+            CommandError::UnknownAction(action) => write!(f, "unknown action '{}'", action),
+            CommandError::UnknownPlant(plant) => write!(f, "unknown plant '{}'", plant),
+            CommandError::InvalidTarget => write!(f, "invalid target moisture level"),
+            CommandError::WateringTooLong { requested, max } => write!(
+                f,
+                "watering of {} ms requested, at most {} ms allowed",
+                requested.as_millis(),
+                max.as_millis()
+            ),
+            CommandError::Retained => write!(f, "retained commands are not accepted"),
+            CommandError::Replayed { id, last_id } => {
+                write!(f, "command {} replayed, last one was {}", id, last_id)
+            }
+            CommandError::IdTooFarAhead { id, last_id } => {
+                write!(
+                    f,
+                    "command {} too far ahead of the last one, {}",
+                    id, last_id
+                )
+            }
+            CommandError::Faulted(status) => write!(
+                f,
+                "faulted: {}; reset the faults first",
+                status_payload(status)
+            ),
+            CommandError::Failed(status) => write!(f, "failed: {}", status_payload(status)),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+pub(crate) type Fields = BTreeMap<String, JsonValue>;
+
+/// Parses a command, without checking it against the configuration or the
+/// previous commands.
+pub fn parse_command(payload: &[u8]) -> Result<CommandRequest, CommandError> {
+    let fields = parse_fields(payload)?;
+    Ok(CommandRequest {
+        id: parse_id(&fields)?,
+        command: parse_action(&fields)?,
+    })
+}
+
+pub(crate) fn parse_fields(payload: &[u8]) -> Result<Fields, CommandError> {
+    let payload = std::str::from_utf8(payload).map_err(|_| CommandError::NotUtf8)?;
+    parse_flat_object(payload).map_err(CommandError::Json)
+}
+
+pub(crate) fn field<'a>(
+    fields: &'a Fields,
+    name: &'static str,
+) -> Result<&'a JsonValue, CommandError> {
+    fields.get(name).ok_or(CommandError::MissingField(name))
+}
+
+pub(crate) fn u64_field(fields: &Fields, name: &'static str) -> Result<u64, CommandError> {
+    field(fields, name)?
+        .as_u64()
+        .ok_or(CommandError::InvalidField(name))
+}
+
+fn parse_id(fields: &Fields) -> Result<u64, CommandError> {
+    u64_field(fields, "id")
+}
+
+pub(crate) fn parse_percentage(
+    fields: &Fields,
+    name: &'static str,
+) -> Result<Percentage, CommandError> {
+    match u64_field(fields, name)? {
+        value @ 0..=100 => Ok(Percentage::new(value as u8)),
+        _ => Err(CommandError::InvalidTarget),
+    }
+}
+
+fn parse_action(fields: &Fields) -> Result<Command, CommandError> {
+    let action = field(fields, "action")?
+        .as_str()
+        .ok_or(CommandError::InvalidField("action"))?;
+    match action {
+        "water" => Ok(Command::Water(Duration::from_millis(u64_field(
+            fields,
+            "duration_ms",
+        )?))),
+        "pause" => Ok(Command::Pause),
+        "resume" => Ok(Command::Resume),
+        "set_target" => {
+            let min = parse_percentage(fields, "min")?;
+            let max = parse_percentage(fields, "max")?;
+            if min >= max {
+                return Err(CommandError::InvalidTarget);
+            }
+            Ok(Command::SetTarget(TargetMoistureLevel::new(min, max)))
+        }
+        "reset_faults" => Ok(Command::ResetFaults),
+        _ => Err(CommandError::UnknownAction(action.to_owned())),
+    }
+}
+
+/// A message received on the command topic of a plant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedCommand {
+    pub plant: String,
+    /// `None` if the message is too malformed to tell.
+    pub id: Option<u64>,
+    pub command: Result<Command, CommandError>,
+}
+
+/// The IDs of the last commands of the plants, which should survive a reboot
+/// so that commands cannot be replayed after it.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CommandState {
+    /// Sorted by plant.
+    pub last_ids: Vec<(String, u64)>,
+}
+
+impl Record for CommandState {
+    const KEY: &'static str = "command_state";
+    const VERSION: u16 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.count(self.last_ids.len());
+        for (plant, id) in &self.last_ids {
+            encoder.str(plant);
+            encoder.u64(*id);
+        }
+    }
+
+    fn decode(_version: u16, decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let count = decoder.count()?;
+        let last_ids = (0..count)
+            .map(|_| Ok((decoder.str()?, decoder.u64()?)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { last_ids })
+    }
+}
+
+/// Validates the commands received for the plants and builds their
+/// acknowledgements.
+#[derive(Debug)]
+pub struct CommandChannel {
+    config: CommandConfig,
+    /// The plants by command topic.
+    plants: HashMap<String, String>,
+    last_ids: HashMap<String, u64>,
+}
+
+impl CommandChannel {
+    pub fn new<'a>(
+        config: CommandConfig,
+        plants: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, CommandConfigError> {
+        config.validate()?;
+
+        let plants = plants
+            .into_iter()
+            .map(|plant| (config.topic(plant), plant.to_owned()))
+            .collect();
+        Ok(Self {
+            config,
+            plants,
+            last_ids: HashMap::new(),
+        })
+    }
+
+    #[inline]
+    pub fn config(&self) -> &CommandConfig {
+        &self.config
+    }
+
+    /// The state to save whenever a command has been
+    /// [accepted](CommandChannel::accept).
+    pub fn state(&self) -> CommandState {
+        let mut last_ids: Vec<_> = self
+            .last_ids
+            .iter()
+            .map(|(plant, &id)| (plant.clone(), id))
+            .collect();
+        last_ids.sort();
+        CommandState { last_ids }
+    }
+
+    /// Restores the IDs saved with [`CommandChannel::state`]. Those of plants
+    /// that are no longer configured are ignored.
+    pub fn restore_state(&mut self, state: &CommandState) {
+        for (plant, id) in &state.last_ids {
+            if self.plants.values().any(|configured| configured == plant) {
+                self.last_ids.insert(plant.clone(), *id);
+            }
+        }
+    }
+
+    /// The command topics of all the plants.
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.plants.keys().map(String::as_str)
+    }
+
+    /// Parses and validates `message`. Returns `None` if it was not received
+    /// on a command topic.
+    pub fn accept(&mut self, message: &MqttMessage) -> Option<ReceivedCommand> {
+        let plant = self.plants.get(&message.topic)?.clone();
+
+        let mut id = None;
+        let command = self.validate(&plant, message, &mut id);
+        Some(ReceivedCommand { plant, id, command })
+    }
+
+    fn validate(
+        &mut self,
+        plant: &str,
+        message: &MqttMessage,
+        request_id: &mut Option<u64>,
+    ) -> Result<Command, CommandError> {
+        let fields = parse_fields(&message.payload)?;
+        let id = parse_id(&fields)?;
+        *request_id = Some(id);
+        if message.retain {
+            return Err(CommandError::Retained);
+        }
+        let last_id = self.last_ids.get(plant).copied();
+        if let Some(last_id) = last_id {
+            if id <= last_id {
+                return Err(CommandError::Replayed { id, last_id });
+            }
+        }
+        // The first command of a plant is bounded as if the last ID were 0
+        let last_id = last_id.unwrap_or(0);
+        if id - last_id > self.config.max_id_jump {
+            return Err(CommandError::IdTooFarAhead { id, last_id });
+        }
+        // Even an invalid command uses up its ID, so that it is never retried
+        // with a different outcome.
+        self.last_ids.insert(plant.to_owned(), id);
+
+        let command = parse_action(&fields)?;
+        if let Command::Water(requested) = command {
+            let max = self.config.max_manual_watering;
+            if requested.is_zero() {
+                return Err(CommandError::InvalidField("duration_ms"));
+            }
+            if requested > max {
+                return Err(CommandError::WateringTooLong { requested, max });
+            }
+        }
+        Ok(command)
+    }
+
+    /// The acknowledgement of a command of `plant`, telling whether it was
+    /// carried out.
+    pub fn response(
+        &self,
+        plant: &str,
+        id: Option<u64>,
+        result: &Result<(), CommandError>,
+    ) -> MqttMessage {
+        let mut payload = JsonObject::new();
+        payload = match id {
+            Some(id) => payload.number("id", id),
+            None => payload.null("id"),
+        };
+        let payload = match result {
+            Ok(()) => payload.string("status", "ok"),
+            Err(error) => payload
+                .string("status", "rejected")
+                .string("error", &error.to_string()),
+        }
+        .finish();
+
+        MqttMessage::new(self.config.response_topic(plant), payload, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, RecordStorage};
+
+    fn channel() -> CommandChannel {
+        CommandChannel::new(CommandConfig::default(), ["basil", "mint"]).unwrap()
+    }
+
+    fn command(plant: &str, payload: &str) -> MqttMessage {
+        MqttMessage::new(format!("plant-wate-rs/{}/command", plant), payload, false)
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert_eq!(CommandConfig::default().validate(), Ok(()));
+        let config = CommandConfig {
+            topic_template: "plant-wate-rs/command".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            CommandChannel::new(config, ["basil"]).map(|_| ()),
+            Err(CommandConfigError::MissingPlantPlaceholder)
+        );
+        let config = CommandConfig {
+            response_topic_template: CommandConfig::default().topic_template,
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Err(CommandConfigError::SameTopics));
+        let config = CommandConfig {
+            max_id_jump: 0,
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Err(CommandConfigError::ZeroMaxIdJump));
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            parse_command(br#"{"id": 1, "action": "water", "duration_ms": 3000}"#),
+            Ok(CommandRequest {
+                id: 1,
+                command: Command::Water(Duration::from_secs(3))
+            })
+        );
+        assert_eq!(
+            parse_command(br#"{"id": 2, "action": "set_target", "min": 40, "max": 70}"#)
+                .map(|request| request.command),
+            Ok(Command::SetTarget(TargetMoistureLevel::new(
+                Percentage::new(40),
+                Percentage::new(70)
+            )))
+        );
+        for (action, command) in [
+            ("pause", Command::Pause),
+            ("resume", Command::Resume),
+            ("reset_faults", Command::ResetFaults),
+        ] {
+            let payload = format!(r#"{{"id": 3, "action": "{}"}}"#, action);
+            assert_eq!(
+                parse_command(payload.as_bytes()).map(|request| request.command),
+                Ok(command)
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_commands() {
+        assert_eq!(parse_command(b"\xff"), Err(CommandError::NotUtf8));
+        assert_eq!(
+            parse_command(b"water"),
+            Err(CommandError::Json(JsonError::UnexpectedCharacter(0)))
+        );
+        assert_eq!(
+            parse_command(br#"{"action": "pause"}"#),
+            Err(CommandError::MissingField("id"))
+        );
+        assert_eq!(
+            parse_command(br#"{"id": -1, "action": "pause"}"#),
+            Err(CommandError::InvalidField("id"))
+        );
+        assert_eq!(
+            parse_command(br#"{"id": 1, "action": "flood"}"#),
+            Err(CommandError::UnknownAction("flood".to_owned()))
+        );
+        assert_eq!(
+            parse_command(br#"{"id": 1, "action": "water", "duration_ms": "3000"}"#),
+            Err(CommandError::InvalidField("duration_ms"))
+        );
+        assert_eq!(
+            parse_command(br#"{"id": 1, "action": "set_target", "min": 70, "max": 40}"#),
+            Err(CommandError::InvalidTarget)
+        );
+        assert_eq!(
+            parse_command(br#"{"id": 1, "action": "set_target", "min": 40, "max": 170}"#),
+            Err(CommandError::InvalidTarget)
+        );
+    }
+
+    #[test]
+    fn accept() {
+        let mut channel = channel();
+
+        assert_eq!(
+            channel.accept(&command("basil", r#"{"id": 5, "action": "pause"}"#)),
+            Some(ReceivedCommand {
+                plant: "basil".to_owned(),
+                id: Some(5),
+                command: Ok(Command::Pause),
+            })
+        );
+        assert_eq!(
+            channel.accept(&MqttMessage::new("plant-wate-rs/basil/status", "{}", false)),
+            None
+        );
+        assert_eq!(
+            channel
+                .accept(&command(
+                    "basil",
+                    r#"{"id": 6, "action": "water", "duration_ms": 60000}"#
+                ))
+                .unwrap()
+                .command,
+            Err(CommandError::WateringTooLong {
+                requested: Duration::from_secs(60),
+                max: Duration::from_secs(30)
+            })
+        );
+    }
+
+    #[test]
+    fn replayed_and_retained_commands() {
+        let mut channel = channel();
+        let pause = command("basil", r#"{"id": 5, "action": "pause"}"#);
+        assert!(channel.accept(&pause).unwrap().command.is_ok());
+
+        assert_eq!(
+            channel.accept(&pause).unwrap().command,
+            Err(CommandError::Replayed { id: 5, last_id: 5 })
+        );
+        assert_eq!(
+            channel
+                .accept(&command("basil", r#"{"id": 4, "action": "resume"}"#))
+                .unwrap()
+                .command,
+            Err(CommandError::Replayed { id: 4, last_id: 5 })
+        );
+        // IDs are per plant
+        assert!(channel
+            .accept(&command("mint", r#"{"id": 1, "action": "pause"}"#))
+            .unwrap()
+            .command
+            .is_ok());
+
+        let retained = MqttMessage {
+            retain: true,
+            ..command("mint", r#"{"id": 9, "action": "resume"}"#)
+        };
+        assert_eq!(
+            channel.accept(&retained).unwrap().command,
+            Err(CommandError::Retained)
+        );
+    }
+
+    #[test]
+    fn ids_too_far_ahead() {
+        let mut channel = channel();
+        let huge = r#"{"id": 9007199254740991, "action": "pause"}"#;
+
+        assert_eq!(
+            channel.accept(&command("basil", huge)).unwrap().command,
+            Err(CommandError::IdTooFarAhead {
+                id: 9_007_199_254_740_991,
+                last_id: 0
+            })
+        );
+        // The rejected ID is not used up
+        assert!(channel
+            .accept(&command("basil", r#"{"id": 1000000, "action": "pause"}"#))
+            .unwrap()
+            .command
+            .is_ok());
+        assert_eq!(
+            channel
+                .accept(&command("basil", r#"{"id": 2000001, "action": "pause"}"#))
+                .unwrap()
+                .command,
+            Err(CommandError::IdTooFarAhead {
+                id: 2_000_001,
+                last_id: 1_000_000
+            })
+        );
+    }
+
+    #[test]
+    fn state_survives_restart() {
+        let mut running = channel();
+        running.accept(&command("mint", r#"{"id": 3, "action": "pause"}"#));
+        running.accept(&command("basil", r#"{"id": 7, "action": "pause"}"#));
+        let mut storage = MemoryStorage::new();
+        storage.save(&running.state()).unwrap();
+
+        let mut restarted = channel();
+        restarted.restore_state(&storage.load().unwrap().unwrap());
+
+        assert_eq!(
+            restarted.state().last_ids,
+            [("basil".to_owned(), 7), ("mint".to_owned(), 3)]
+        );
+        assert_eq!(
+            restarted
+                .accept(&command("basil", r#"{"id": 7, "action": "resume"}"#))
+                .unwrap()
+                .command,
+            Err(CommandError::Replayed { id: 7, last_id: 7 })
+        );
+    }
+
+    #[test]
+    fn responses() {
+        let channel = channel();
+
+        let ok = channel.response("basil", Some(5), &Ok(()));
+        assert_eq!(ok.topic, "plant-wate-rs/basil/response");
+        assert_eq!(ok.payload, br#"{"id":5,"status":"ok"}"#);
+        assert!(!ok.retain);
+
+        let rejected = channel.response("basil", None, &Err(CommandError::MissingField("id")));
+        assert_eq!(
+            rejected.payload,
+            br#"{"id":null,"status":"rejected","error":"missing field 'id'"}"#
+        );
+    }
+}
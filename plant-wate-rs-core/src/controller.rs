@@ -1,22 +1,340 @@
 use std::time::Duration;
 
+use log::{error, info, warn};
+
+use crate::calibration_curve::CalibrationCurve;
+use crate::commands::{
+    Command, CommandChannel, CommandConfig, CommandConfigError, CommandError, CommandState,
+};
+use crate::controller_state::ControllerState;
+use crate::event_history::EventHistory;
+use crate::history_log::HistoryLog;
+use crate::http::HttpServer;
+use crate::plant_config::{PlantConfig, PlantConfigError};
+use crate::plant_irrigator::{CalibrationUpdateError, PlantIrrigator};
 use crate::plant_irrigator_controller::PlantIrrigatorController;
-use crate::uc::Microcontroller;
+use crate::rest_api::{self, RestApiConfig, RestApiConfigError};
+use crate::scheduler::{ScheduleConfig, ScheduleError, Scheduler, SchedulerState};
+use crate::storage::{RecordStorage, Storage, StorageError};
+use crate::telemetry::{Telemetry, TelemetryConfig, TelemetryConfigError};
+use crate::temperature::TemperatureSensor;
+use crate::time_window::{TimeRestrictions, TimeRestrictionsError};
+use crate::uc::{GpioId, Microcontroller};
+use crate::{dashboard, home_assistant};
 
 pub struct Controller<MicrocontrollerImpl: Microcontroller> {
     uc: MicrocontrollerImpl,
     plant_irrigator_ctrl: PlantIrrigatorController<MicrocontrollerImpl>,
+    /// The GPIOs of the plants, which the schedule must not use.
+    plant_gpios: Vec<GpioId>,
+    scheduler: Option<Scheduler<MicrocontrollerImpl>>,
+    storage: Option<Box<dyn Storage>>,
+    /// What has last been written to `storage`.
+    saved_state: Option<ControllerState>,
+    history_log: Option<HistoryLog>,
+    telemetry: Option<Telemetry>,
+    commands: Option<CommandChannel>,
+    rest_api: Option<(Box<dyn HttpServer>, RestApiConfig)>,
 }
 
 impl<MicrocontrollerImpl: Microcontroller> Controller<MicrocontrollerImpl> {
-    #[must_use]
-    pub fn new(mut microcontroller: MicrocontrollerImpl) -> Self {
-        let plant_irrigator_ctrl = PlantIrrigatorController::new(&mut microcontroller);
+    pub fn new(
+        mut microcontroller: MicrocontrollerImpl,
+        plants: &[PlantConfig],
+    ) -> Result<Self, PlantConfigError> {
+        let plant_irrigator_ctrl = PlantIrrigatorController::new(&mut microcontroller, plants)?;
 
-        Self {
+        Ok(Self {
             uc: microcontroller,
             plant_irrigator_ctrl,
+            plant_gpios: plants.iter().flat_map(PlantConfig::gpios).collect(),
+            scheduler: None,
+            storage: None,
+            saved_state: None,
+            history_log: None,
+            telemetry: None,
+            commands: None,
+            rest_api: None,
+        })
+    }
+
+    /// Restores the state saved in `storage`, and saves it there after each
+    /// cycle in which it has changed. The state of the scheduler is saved
+    /// whenever jobs have run, the IDs of the last commands whenever commands
+    /// have been received.
+    pub fn with_storage(mut self, storage: Box<dyn Storage>) -> Result<Self, StorageError> {
+        if let Some(state) = storage.load::<ControllerState>()? {
+            self.restore_state(&state);
+            self.saved_state = Some(state);
+        }
+        self.storage = Some(storage);
+        self.restore_scheduler_state()?;
+        self.restore_command_state()?;
+        Ok(self)
+    }
+
+    #[inline]
+    pub fn storage(&self) -> Option<&dyn Storage> {
+        self.storage.as_deref()
+    }
+
+    pub fn state(&self) -> ControllerState {
+        ControllerState {
+            plants: self
+                .plant_irrigator_ctrl
+                .plant_irrigators()
+                .iter()
+                .map(|plant_irrigator| plant_irrigator.state(self.uc.now()))
+                .collect(),
+        }
+    }
+
+    /// Restores the state of the plants by name. The state of plants that are
+    /// no longer configured is ignored.
+    pub fn restore_state(&mut self, state: &ControllerState) {
+        for plant_state in &state.plants {
+            match self
+                .plant_irrigator_ctrl
+                .plant_irrigator_mut(&plant_state.name)
+            {
+                Some(plant_irrigator) => plant_irrigator.restore_state(plant_state, self.uc.now()),
+                None => warn!(
+                    "[{}] Not configured anymore, ignoring its saved state",
+                    plant_state.name
+                ),
+            }
+        }
+    }
+
+    /// Saves the state to the storage, if there is any.
+    pub fn save_state(&mut self) -> Result<(), StorageError> {
+        let state = self.state();
+        let Some(storage) = &mut self.storage else {
+            return Ok(());
+        };
+
+        storage.save(&state)?;
+        self.saved_state = Some(state);
+        Ok(())
+    }
+
+    /// Records the irrigation events of the plants to `history_log`.
+    #[must_use]
+    pub fn with_history_log(mut self, history_log: HistoryLog) -> Self {
+        self.history_log = Some(history_log);
+        self
+    }
+
+    #[inline]
+    pub fn history_log(&self) -> Option<&HistoryLog> {
+        self.history_log.as_ref()
+    }
+
+    /// Publishes the readings and decisions of the plants through `telemetry`,
+    /// and announces them to Home Assistant if configured. The plant names
+    /// must be usable in the topics; see
+    /// [`TelemetryConfig::validate_plant_name`].
+    pub fn with_telemetry(
+        mut self,
+        mut telemetry: Telemetry,
+    ) -> Result<Self, TelemetryConfigError> {
+        let plants: Vec<_> = self
+            .plant_irrigator_ctrl
+            .plant_irrigators()
+            .iter()
+            .map(|plant_irrigator| plant_irrigator.name())
+            .collect();
+        for plant in &plants {
+            TelemetryConfig::validate_plant_name(plant)?;
+        }
+        if let Some(discovery) = telemetry.config().home_assistant.clone() {
+            home_assistant::announce(&mut telemetry, &discovery, &plants)?;
+        }
+
+        self.telemetry = Some(telemetry);
+        self.subscribe_commands();
+        Ok(self)
+    }
+
+    #[inline]
+    pub fn telemetry(&self) -> Option<&Telemetry> {
+        self.telemetry.as_ref()
+    }
+
+    /// Acts on the commands received on the command topics of the plants.
+    /// They are only received once telemetry is set as well.
+    pub fn with_commands(mut self, config: CommandConfig) -> Result<Self, CommandConfigError> {
+        let plants = self.plant_irrigator_ctrl.plant_irrigators();
+        self.commands = Some(CommandChannel::new(
+            config,
+            plants.iter().map(|plant_irrigator| plant_irrigator.name()),
+        )?);
+        self.subscribe_commands();
+        if let Err(error) = self.restore_command_state() {
+            error!("Could not restore the IDs of the last commands: {}", error);
+        }
+        Ok(self)
+    }
+
+    fn restore_command_state(&mut self) -> Result<(), StorageError> {
+        let (Some(commands), Some(storage)) = (&mut self.commands, &self.storage) else {
+            return Ok(());
+        };
+
+        if let Some(state) = storage.load::<CommandState>()? {
+            commands.restore_state(&state);
+        }
+        Ok(())
+    }
+
+    fn subscribe_commands(&mut self) {
+        if let (Some(commands), Some(telemetry)) = (&self.commands, &mut self.telemetry) {
+            for topic in commands.topics() {
+                telemetry.subscribe(topic);
+            }
+        }
+    }
+
+    /// Serves the [REST API](rest_api) and the [dashboard](dashboard) on
+    /// `server`.
+    pub fn with_rest_api(
+        mut self,
+        server: Box<dyn HttpServer>,
+        config: RestApiConfig,
+    ) -> Result<Self, RestApiConfigError> {
+        config.validate()?;
+
+        self.rest_api = Some((server, config));
+        Ok(self)
+    }
+
+    /// Runs the timed jobs of `schedule` alongside watering. The outputs must
+    /// not use any of the GPIOs of the plants.
+    pub fn with_schedule(mut self, schedule: &ScheduleConfig) -> Result<Self, ScheduleError> {
+        if let Some(output) = schedule
+            .outputs
+            .iter()
+            .find(|output| self.plant_gpios.contains(&output.gpio))
+        {
+            return Err(ScheduleError::GpioAlreadyUsed {
+                output: output.name.clone(),
+                gpio: output.gpio,
+            });
+        }
+
+        self.scheduler = Some(Scheduler::new(&mut self.uc, schedule)?);
+        if let Err(error) = self.restore_scheduler_state() {
+            error!("Could not restore the state of the scheduler: {}", error);
+        }
+        Ok(self)
+    }
+
+    fn restore_scheduler_state(&mut self) -> Result<(), StorageError> {
+        let (Some(scheduler), Some(storage)) = (&mut self.scheduler, &self.storage) else {
+            return Ok(());
+        };
+
+        if let Some(state) = storage.load::<SchedulerState>()? {
+            scheduler.restore_state(&state);
         }
+        Ok(())
+    }
+
+    /// Sets the global quiet hours and the time zone of the watering windows.
+    pub fn with_time_restrictions(
+        mut self,
+        time_restrictions: TimeRestrictions,
+    ) -> Result<Self, TimeRestrictionsError> {
+        self.plant_irrigator_ctrl = self
+            .plant_irrigator_ctrl
+            .with_time_restrictions(time_restrictions)?;
+        Ok(self)
+    }
+
+    /// Resumes watering of the plant named `plant` after its reservoir has been
+    /// refilled. Returns `false` if there is no such plant.
+    pub fn reset_reservoir_alarm(&mut self, plant: &str) -> bool {
+        match self.plant_irrigator_ctrl.plant_irrigator_mut(plant) {
+            Some(plant_irrigator) => {
+                plant_irrigator.reset_reservoir_alarm();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Provides the temperature sensor for a plant configured with
+    /// [`TemperatureSensorConfig::External`](crate::temperature::TemperatureSensorConfig::External).
+    /// Returns `false` if there is no such plant.
+    pub fn set_temperature_sensor(
+        &mut self,
+        plant: &str,
+        temperature_sensor: Box<dyn TemperatureSensor>,
+    ) -> bool {
+        match self.plant_irrigator_ctrl.plant_irrigator_mut(plant) {
+            Some(plant_irrigator) => {
+                plant_irrigator.set_temperature_sensor(temperature_sensor);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces the calibration of the plant named `plant`; see
+    /// [`PlantIrrigator::set_calibration`].
+    pub fn set_calibration(
+        &mut self,
+        plant: &str,
+        calibration: CalibrationCurve,
+    ) -> Result<(), CalibrationUpdateError> {
+        self.plant_irrigator_ctrl
+            .plant_irrigator_mut(plant)
+            .ok_or_else(|| CalibrationUpdateError::UnknownPlant(plant.to_owned()))?
+            .set_calibration(calibration)
+    }
+
+    /// The recent irrigation events of the plant named `plant`, from the
+    /// oldest to the most recent one. `None` if there is no such plant.
+    pub fn event_history(&self, plant: &str) -> Option<&EventHistory> {
+        self.plant_irrigator_ctrl
+            .plant_irrigators()
+            .iter()
+            .find(|plant_irrigator| plant_irrigator.name() == plant)
+            .map(|plant_irrigator| plant_irrigator.event_history())
+    }
+
+    #[inline]
+    pub fn microcontroller(&self) -> &MicrocontrollerImpl {
+        &self.uc
+    }
+
+    #[inline]
+    pub fn plant_irrigators(&self) -> &[PlantIrrigator<MicrocontrollerImpl>] {
+        self.plant_irrigator_ctrl.plant_irrigators()
+    }
+
+    /// Carries out `command` on the plant named `plant`. Manual waterings are
+    /// recorded to the history log right away.
+    pub fn execute_command(&mut self, plant: &str, command: Command) -> Result<(), CommandError> {
+        let plant_irrigator = self
+            .plant_irrigator_ctrl
+            .plant_irrigator_mut(plant)
+            .ok_or_else(|| CommandError::UnknownPlant(plant.to_owned()))?;
+        let result = command.execute(plant_irrigator, &self.uc);
+
+        // A faulted plant refuses the watering before recording any event
+        let watered = matches!(command, Command::Water(_))
+            && !matches!(result, Err(CommandError::Faulted(_)));
+        if let (true, Some(history_log)) = (watered, &mut self.history_log) {
+            let event = plant_irrigator.event_history().latest();
+            if let Some(Err(error)) = event.map(|event| history_log.record(plant, event)) {
+                error!(
+                    "[{}] Could not record the irrigation history: {}",
+                    plant, error
+                );
+            }
+        }
+        result
     }
 
     pub fn run(&mut self) -> ! {
@@ -26,7 +344,515 @@ impl<MicrocontrollerImpl: Microcontroller> Controller<MicrocontrollerImpl> {
     }
 
     pub fn run_cycle(&mut self) {
+        if let Some(scheduler) = &mut self.scheduler {
+            let runs = scheduler.tick(&self.uc);
+            if let (false, Some(storage)) = (runs.is_empty(), &mut self.storage) {
+                if let Err(error) = storage.save(&scheduler.state()) {
+                    error!("Could not save the state of the scheduler: {}", error);
+                }
+            }
+        }
         self.plant_irrigator_ctrl.run_cycle(&self.uc);
+        self.record_history();
+        self.handle_commands();
+        self.serve_http();
+        self.publish_telemetry();
+        self.save_state_if_changed();
         self.uc.wait(Duration::from_millis(1000));
     }
+
+    fn record_history(&mut self) {
+        let Some(history_log) = &mut self.history_log else {
+            return;
+        };
+
+        for plant_irrigator in self.plant_irrigator_ctrl.plant_irrigators() {
+            let Some(event) = plant_irrigator.event_history().latest() else {
+                continue;
+            };
+            if let Err(error) = history_log.record(plant_irrigator.name(), event) {
+                error!(
+                    "[{}] Could not record the irrigation history: {}",
+                    plant_irrigator.name(),
+                    error
+                );
+            }
+        }
+    }
+
+    /// Carries out the commands received since the last cycle, and queues
+    /// their acknowledgements.
+    fn handle_commands(&mut self) {
+        let (Some(commands), Some(telemetry)) = (&mut self.commands, &mut self.telemetry) else {
+            return;
+        };
+        let received: Vec<_> = std::iter::from_fn(|| telemetry.receive())
+            .filter_map(|message| commands.accept(&message))
+            .collect();
+        if let (false, Some(storage)) = (received.is_empty(), &mut self.storage) {
+            if let Err(error) = storage.save(&commands.state()) {
+                error!("Could not save the IDs of the last commands: {}", error);
+            }
+        }
+
+        for received in received {
+            let result = received
+                .command
+                .clone()
+                .and_then(|command| self.execute_command(&received.plant, command));
+            match &result {
+                Ok(()) => info!(
+                    "[{}] Command {:?} carried out: {:?}",
+                    received.plant, received.id, received.command
+                ),
+                Err(error) => warn!(
+                    "[{}] Command {:?} rejected: {}",
+                    received.plant, received.id, error
+                ),
+            }
+            if let (Some(commands), Some(telemetry)) = (&self.commands, &mut self.telemetry) {
+                telemetry.enqueue(commands.response(&received.plant, received.id, &result));
+            }
+        }
+    }
+
+    fn serve_http(&mut self) {
+        let Some((mut server, config)) = self.rest_api.take() else {
+            return;
+        };
+        server.poll(&mut |request| {
+            dashboard::serve(request)
+                .unwrap_or_else(|| rest_api::handle_request(self, &config, request))
+        });
+        self.rest_api = Some((server, config));
+    }
+
+    fn publish_telemetry(&mut self) {
+        let Some(telemetry) = &mut self.telemetry else {
+            return;
+        };
+
+        for plant_irrigator in self.plant_irrigator_ctrl.plant_irrigators() {
+            if let Some(event) = plant_irrigator.event_history().latest() {
+                telemetry.publish_event(plant_irrigator.name(), event);
+            }
+        }
+        telemetry.flush(self.uc.now());
+    }
+
+    /// Saving is skipped when only the moisture history has changed, to spare
+    /// the flash.
+    fn save_state_if_changed(&mut self) {
+        if self.storage.is_none() {
+            return;
+        }
+
+        let changed = self
+            .saved_state
+            .as_ref()
+            .map_or(true, |saved_state| self.state().differs_from(saved_state));
+        if changed {
+            match self.save_state() {
+                Ok(()) => info!("State saved"),
+                Err(error) => error!("Could not save the state: {}", error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::flash::RamFlash;
+    use crate::mock_uc::{MockMicrocontroller, MockMicrocontrollerAction};
+    use crate::mqtt::{InProcessBroker, MqttMessage};
+    use crate::plant_irrigator::{
+        IrrigationStatus, Percentage, SensorCalibrationResult, TargetMoistureLevel,
+    };
+    use crate::reservoir_monitor::ReservoirMonitorConfig;
+    use crate::scheduler::{JobAction, JobConfig, MissedRunPolicy, OutputConfig};
+    use crate::sensor_fault::SensorFaultLimits;
+    use crate::soil::SoilType;
+    use crate::storage::MemoryStorage;
+    use crate::telemetry::TelemetryConfig;
+    use crate::uc::{AnalogValue, GPIO_0, GPIO_1, GPIO_2};
+
+    fn basil_controller() -> Controller<MockMicrocontroller> {
+        basil_controller_calibrated(SensorCalibrationResult::new(
+            AnalogValue::new(500),
+            AnalogValue::new(2200),
+        ))
+    }
+
+    fn basil_controller_calibrated(
+        calibration: SensorCalibrationResult,
+    ) -> Controller<MockMicrocontroller> {
+        let plants = [PlantConfig::new(
+            "basil",
+            GPIO_0,
+            GPIO_1,
+            calibration,
+            TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+        )];
+
+        Controller::new(MockMicrocontroller::new(), &plants).unwrap()
+    }
+
+    #[test_log::test]
+    fn state_survives_restart() {
+        let mut controller = basil_controller()
+            .with_storage(Box::new(MemoryStorage::new()))
+            .unwrap();
+        controller
+            .uc
+            .set_analog_value(GPIO_0, AnalogValue::new(2000));
+        let target = TargetMoistureLevel::new(Percentage::new(30), Percentage::new(50));
+        controller
+            .execute_command("basil", Command::SetTarget(target))
+            .unwrap();
+        controller.run_cycle();
+
+        let saved_state = controller
+            .storage()
+            .unwrap()
+            .load::<ControllerState>()
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved_state.plants[0].counters.waterings, 1);
+        assert_eq!(saved_state.plants[0].pump_runs.len(), 1);
+        assert_eq!(saved_state.plants[0].target, Some(target));
+        assert!(!controller.state().differs_from(&saved_state));
+
+        let mut storage = MemoryStorage::new();
+        storage.save(&saved_state).unwrap();
+        let restarted = basil_controller().with_storage(Box::new(storage)).unwrap();
+        assert_eq!(restarted.state(), saved_state);
+        assert_eq!(
+            restarted.plant_irrigators()[0].target_moisture_level(),
+            target
+        );
+    }
+
+    #[test_log::test]
+    fn configured_calibration_wins_over_saved_one() {
+        let mut controller = basil_controller();
+        let recalibrated = CalibrationCurve::from(SensorCalibrationResult::new(
+            AnalogValue::new(600),
+            AnalogValue::new(2100),
+        ));
+        assert_eq!(
+            controller.set_calibration("basil", recalibrated.clone()),
+            Ok(())
+        );
+        let saved_state = controller.state();
+
+        let mut restarted = basil_controller();
+        restarted.restore_state(&saved_state);
+        assert_eq!(restarted.plant_irrigators()[0].calibration(), &recalibrated);
+
+        let configured =
+            SensorCalibrationResult::new(AnalogValue::new(400), AnalogValue::new(2300));
+        let mut reconfigured = basil_controller_calibrated(configured.clone());
+        reconfigured.restore_state(&saved_state);
+        assert_eq!(
+            reconfigured.plant_irrigators()[0].calibration(),
+            &CalibrationCurve::from(configured)
+        );
+    }
+
+    #[test_log::test]
+    fn calibration_of_vwc_plant_cannot_be_replaced() {
+        let calibration =
+            SensorCalibrationResult::new(AnalogValue::new(500), AnalogValue::new(2200));
+        let target = TargetMoistureLevel::new(Percentage::new(20), Percentage::new(35));
+        let plants = [
+            PlantConfig::new("basil", GPIO_0, GPIO_1, calibration.clone(), target)
+                .with_soil_type(SoilType::PottingMix)
+                .with_vwc_target(target),
+        ];
+        let mut controller = Controller::new(MockMicrocontroller::new(), &plants).unwrap();
+        let vwc_curve = controller.plant_irrigators()[0].calibration().clone();
+
+        let recalibrated = CalibrationCurve::from(SensorCalibrationResult::new(
+            AnalogValue::new(600),
+            AnalogValue::new(2100),
+        ));
+        assert_eq!(
+            controller.set_calibration("basil", recalibrated.clone()),
+            Err(CalibrationUpdateError::VwcTarget("basil".to_owned()))
+        );
+        assert_eq!(controller.plant_irrigators()[0].calibration(), &vwc_curve);
+        assert_eq!(
+            controller.set_calibration("mint", recalibrated),
+            Err(CalibrationUpdateError::UnknownPlant("mint".to_owned()))
+        );
+    }
+
+    #[test]
+    fn schedule_cannot_use_plant_gpios() {
+        let schedule = ScheduleConfig {
+            outputs: vec![
+                OutputConfig::new("fertilizer", GPIO_2),
+                OutputConfig::new("fan", GPIO_1),
+            ],
+            ..ScheduleConfig::default()
+        };
+
+        assert!(matches!(
+            basil_controller().with_schedule(&schedule),
+            Err(ScheduleError::GpioAlreadyUsed { output, gpio }) if output == "fan" && gpio == GPIO_1
+        ));
+    }
+
+    #[test_log::test]
+    fn schedule_survives_restart() {
+        let schedule = ScheduleConfig {
+            outputs: vec![OutputConfig::new("fertilizer", GPIO_2)],
+            jobs: vec![JobConfig::new(
+                "fertilize",
+                "fertilizer",
+                "0 9 * * *".parse().unwrap(),
+                JobAction::Pulse(Duration::from_secs(3)),
+            )
+            .with_missed_run_policy(MissedRunPolicy::RunOnce)],
+            ..ScheduleConfig::default()
+        };
+        // 2023-11-14T09:00:00Z
+        let nine = SystemTime::UNIX_EPOCH + Duration::from_secs(1_699_952_400);
+        let mut controller = basil_controller()
+            .with_storage(Box::new(MemoryStorage::new()))
+            .unwrap()
+            .with_schedule(&schedule)
+            .unwrap();
+        controller.uc.set_wall_clock(nine - Duration::from_secs(60));
+        controller.run_cycle();
+        controller.uc.set_wall_clock(nine + Duration::from_secs(30));
+        controller.run_cycle();
+
+        let saved_state = controller
+            .storage()
+            .unwrap()
+            .load::<SchedulerState>()
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved_state.last_run, Some(nine + Duration::from_secs(30)));
+
+        // Powered off until after the next run; the storage may come second
+        let mut storage = MemoryStorage::new();
+        storage.save(&saved_state).unwrap();
+        let mut restarted = basil_controller()
+            .with_schedule(&schedule)
+            .unwrap()
+            .with_storage(Box::new(storage))
+            .unwrap();
+        restarted
+            .uc
+            .set_wall_clock(nine + Duration::from_secs(86_400 + 3600));
+        restarted.run_cycle();
+        assert!(restarted
+            .uc
+            .actions()
+            .contains(&MockMicrocontrollerAction::DigitalGpioHigh(GPIO_2)));
+    }
+
+    #[test_log::test]
+    fn history_survives_restart() {
+        let flash = RamFlash::new(256, 4);
+        let mut controller =
+            basil_controller().with_history_log(HistoryLog::open(Box::new(flash.clone())).unwrap());
+        controller
+            .uc
+            .set_analog_value(GPIO_0, AnalogValue::new(2000));
+        controller.run_cycle();
+        controller.run_cycle();
+
+        let history_log = HistoryLog::open(Box::new(flash)).unwrap();
+        let entries = history_log.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.plant == "basil" && entry.decision == IrrigationStatus::Watered));
+    }
+
+    #[test_log::test]
+    fn refused_watering_is_not_recorded() {
+        let flash = RamFlash::new(256, 4);
+        let mut controller =
+            basil_controller().with_history_log(HistoryLog::open(Box::new(flash)).unwrap());
+        controller
+            .uc
+            .set_analog_value(GPIO_0, AnalogValue::new(2000));
+        controller.run_cycle();
+
+        let mut state = controller.state();
+        state.plants[0].reservoir.empty_suspected = true;
+        controller.restore_state(&state);
+        assert_eq!(
+            controller.execute_command("basil", Command::Water(Duration::from_secs(1))),
+            Err(CommandError::Faulted(
+                IrrigationStatus::ReservoirSuspectedEmpty
+            ))
+        );
+
+        let entries = controller.history_log().unwrap().entries().unwrap();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test_log::test]
+    fn telemetry_is_published() {
+        let broker = InProcessBroker::new();
+        let telemetry =
+            Telemetry::new(Box::new(broker.client()), TelemetryConfig::default()).unwrap();
+        let mut controller = basil_controller().with_telemetry(telemetry).unwrap();
+        controller
+            .uc
+            .set_analog_value(GPIO_0, AnalogValue::new(2000));
+        controller.run_cycle();
+
+        assert_eq!(
+            broker.retained("plant-wate-rs/basil/status"),
+            Some(b"watered".to_vec())
+        );
+        assert_eq!(controller.telemetry().unwrap().buffered(), 0);
+    }
+
+    #[test_log::test]
+    fn commands_are_carried_out() {
+        let broker = InProcessBroker::new();
+        let telemetry =
+            Telemetry::new(Box::new(broker.client()), TelemetryConfig::default()).unwrap();
+        let mut controller = basil_controller()
+            .with_telemetry(telemetry)
+            .unwrap()
+            .with_commands(CommandConfig::default())
+            .unwrap();
+        controller
+            .uc
+            .set_analog_value(GPIO_0, AnalogValue::new(1000));
+        controller.run_cycle();
+        assert_eq!(controller.state().plants[0].counters.waterings, 0);
+
+        let send = |payload: &str| {
+            broker.publish(MqttMessage::new(
+                "plant-wate-rs/basil/command",
+                payload,
+                false,
+            ))
+        };
+        send(r#"{"id": 1, "action": "water", "duration_ms": 2000}"#);
+        send(r#"{"id": 1, "action": "pause"}"#);
+        send(r#"{"id": 2, "action": "set_target", "min": 30, "max": 50}"#);
+        send("not json");
+        controller.run_cycle();
+
+        let plant_irrigator = &controller.plant_irrigator_ctrl.plant_irrigators()[0];
+        assert_eq!(controller.state().plants[0].counters.waterings, 1);
+        assert!(!plant_irrigator.is_paused());
+        assert_eq!(
+            plant_irrigator.target_moisture_level(),
+            TargetMoistureLevel::new(Percentage::new(30), Percentage::new(50))
+        );
+        let responses: Vec<_> = broker
+            .published()
+            .into_iter()
+            .filter(|message| message.topic == "plant-wate-rs/basil/response")
+            .map(|message| String::from_utf8(message.payload).unwrap())
+            .collect();
+        assert_eq!(
+            responses,
+            vec![
+                r#"{"id":1,"status":"ok"}"#,
+                r#"{"id":1,"status":"rejected","error":"command 1 replayed, last one was 1"}"#,
+                r#"{"id":2,"status":"ok"}"#,
+                r#"{"id":null,"status":"rejected","error":"malformed JSON: unexpected character at offset 0"}"#,
+            ]
+        );
+    }
+
+    #[test_log::test]
+    fn command_ids_survive_restart() {
+        let broker = InProcessBroker::new();
+        let telemetry =
+            Telemetry::new(Box::new(broker.client()), TelemetryConfig::default()).unwrap();
+        let mut controller = basil_controller()
+            .with_storage(Box::new(MemoryStorage::new()))
+            .unwrap()
+            .with_telemetry(telemetry)
+            .unwrap()
+            .with_commands(CommandConfig::default())
+            .unwrap();
+        // Subscribes to the commands
+        controller.run_cycle();
+        broker.publish(MqttMessage::new(
+            "plant-wate-rs/basil/command",
+            r#"{"id": 5, "action": "pause"}"#,
+            false,
+        ));
+        controller.run_cycle();
+
+        let saved_state = controller
+            .storage()
+            .unwrap()
+            .load::<CommandState>()
+            .unwrap()
+            .unwrap();
+        assert_eq!(saved_state.last_ids, [("basil".to_owned(), 5)]);
+
+        let mut storage = MemoryStorage::new();
+        storage.save(&saved_state).unwrap();
+        let mut restarted = basil_controller()
+            .with_commands(CommandConfig::default())
+            .unwrap()
+            .with_storage(Box::new(storage))
+            .unwrap();
+        assert_eq!(restarted.execute_command("basil", Command::Resume), Ok(()));
+        assert_eq!(
+            restarted
+                .commands
+                .as_mut()
+                .unwrap()
+                .accept(&MqttMessage::new(
+                    "plant-wate-rs/basil/command",
+                    r#"{"id": 5, "action": "resume"}"#,
+                    false,
+                ))
+                .unwrap()
+                .command,
+            Err(CommandError::Replayed { id: 5, last_id: 5 })
+        );
+    }
+
+    #[test_log::test]
+    fn reservoir_judged_while_watering_continues() {
+        // A constant reading would otherwise be reported as a stuck sensor
+        let plants = [PlantConfig::new(
+            "basil",
+            GPIO_0,
+            GPIO_1,
+            SensorCalibrationResult::new(AnalogValue::new(500), AnalogValue::new(2200)),
+            TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+        )
+        .with_sensor_fault_limits(SensorFaultLimits {
+            stuck_readings: 0,
+            ..Default::default()
+        })];
+        let mut controller = Controller::new(MockMicrocontroller::new(), &plants).unwrap();
+        controller
+            .microcontroller()
+            .set_analog_value(GPIO_0, AnalogValue::new(2000));
+
+        // The moisture never rises, however often the plant is watered
+        let first_watering = controller.microcontroller().now();
+        let judged_by = first_watering + ReservoirMonitorConfig::default().soak_time;
+        while controller.microcontroller().now() < judged_by {
+            controller.run_cycle();
+        }
+        controller.run_cycle();
+
+        let now = controller.microcontroller().now();
+        let state = controller.plant_irrigators()[0].state(now);
+        assert_eq!(state.reservoir.ineffective_waterings, 1);
+    }
 }
@@ -0,0 +1,320 @@
+use crate::calibration_curve::{CalibrationCurve, CalibrationPoint};
+use crate::plant_irrigator::{Percentage, PlantCounters, TargetMoistureLevel};
+use crate::pump_safety::PumpRunState;
+use crate::reservoir_monitor::ReservoirMonitorState;
+use crate::storage::{DecodeError, Decoder, Encoder, Record};
+use crate::uc::AnalogValue;
+
+/// The part of a plant's state that should survive a reboot.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PlantState {
+    pub name: String,
+    pub calibration: CalibrationCurve,
+    /// The calibration in the configuration when the state was saved; `None`
+    /// in state saved by older firmware.
+    pub configured_calibration: Option<CalibrationCurve>,
+    /// The target if it has been changed from the configured one.
+    pub target: Option<TargetMoistureLevel>,
+    pub counters: PlantCounters,
+    /// The recent pump runs, for the pump safety limits.
+    pub pump_runs: Vec<PumpRunState>,
+    pub reservoir: ReservoirMonitorState,
+    /// Oldest first.
+    pub moisture_history: Vec<Percentage>,
+    pub paused: bool,
+}
+
+impl PlantState {
+    /// Returns `true` if anything but the moisture history, which changes
+    /// every cycle, differs from `other`. The pump runs are not compared
+    /// either: they are relative to the current time, and only really change
+    /// along with the counters.
+    pub fn differs_from(&self, other: &PlantState) -> bool {
+        self.name != other.name
+            || self.calibration != other.calibration
+            || self.configured_calibration != other.configured_calibration
+            || self.target != other.target
+            || self.counters != other.counters
+            || self.reservoir != other.reservoir
+            || self.paused != other.paused
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ControllerState {
+    pub plants: Vec<PlantState>,
+}
+
+impl ControllerState {
+    /// Returns `true` if the state is worth saving again after `saved`; see
+    /// [`PlantState::differs_from`].
+    pub fn differs_from(&self, saved: &ControllerState) -> bool {
+        self.plants.len() != saved.plants.len()
+            || self
+                .plants
+                .iter()
+                .zip(&saved.plants)
+                .any(|(plant, saved_plant)| plant.differs_from(saved_plant))
+    }
+}
+
+impl Record for ControllerState {
+    const KEY: &'static str = "controller_state";
+    /// Version 2 added `paused`, version 3 `configured_calibration` and
+    /// `pump_runs`, version 4 `target`.
+    const VERSION: u16 = 4;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.count(self.plants.len());
+        for plant in &self.plants {
+            encoder.str(&plant.name);
+
+            encode_calibration(encoder, &plant.calibration);
+
+            encoder.u32(plant.counters.waterings);
+            encoder.duration(plant.counters.pump_time);
+
+            encoder.option(
+                plant.reservoir.moisture_before_watering,
+                |encoder, moisture| encoder.u8(moisture.value()),
+            );
+            encoder.u8(plant.reservoir.ineffective_waterings);
+            encoder.bool(plant.reservoir.empty_suspected);
+
+            encoder.count(plant.moisture_history.len());
+            for moisture in &plant.moisture_history {
+                encoder.u8(moisture.value());
+            }
+
+            encoder.bool(plant.paused);
+
+            encoder.option(plant.configured_calibration.as_ref(), encode_calibration);
+            encoder.count(plant.pump_runs.len());
+            for run in &plant.pump_runs {
+                encoder.duration(run.started_ago);
+                encoder.duration(run.duration);
+            }
+
+            encoder.option(plant.target, |encoder, target| {
+                encoder.u8(target.min_value().value());
+                encoder.u8(target.max_value().value());
+            });
+        }
+    }
+
+    fn decode(version: u16, decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let plant_count = decoder.count()?;
+        let mut plants = Vec::with_capacity(plant_count);
+        for _ in 0..plant_count {
+            let name = decoder.str()?;
+
+            let calibration = decode_calibration(decoder)?;
+
+            let counters = PlantCounters {
+                waterings: decoder.u32()?,
+                pump_time: decoder.duration()?,
+            };
+
+            let reservoir = ReservoirMonitorState {
+                moisture_before_watering: decoder.option(decode_percentage)?,
+                ineffective_waterings: decoder.u8()?,
+                empty_suspected: decoder.bool()?,
+            };
+
+            let history_len = decoder.count()?;
+            let moisture_history = (0..history_len)
+                .map(|_| decode_percentage(decoder))
+                .collect::<Result<_, _>>()?;
+
+            let paused = if version >= 2 { decoder.bool()? } else { false };
+
+            let (configured_calibration, pump_runs) = if version >= 3 {
+                let configured_calibration = decoder.option(decode_calibration)?;
+                let run_count = decoder.count()?;
+                let pump_runs = (0..run_count)
+                    .map(|_| {
+                        Ok(PumpRunState {
+                            started_ago: decoder.duration()?,
+                            duration: decoder.duration()?,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                (configured_calibration, pump_runs)
+            } else {
+                (None, Vec::new())
+            };
+
+            let target = if version >= 4 {
+                decoder.option(decode_target)?
+            } else {
+                None
+            };
+
+            plants.push(PlantState {
+                name,
+                calibration,
+                configured_calibration,
+                target,
+                counters,
+                pump_runs,
+                reservoir,
+                moisture_history,
+                paused,
+            });
+        }
+
+        Ok(Self { plants })
+    }
+}
+
+fn encode_calibration(encoder: &mut Encoder, calibration: &CalibrationCurve) {
+    encoder.count(calibration.points().len());
+    for point in calibration.points() {
+        encoder.u16(point.value.value());
+        encoder.u8(point.moisture.value());
+    }
+}
+
+fn decode_calibration(decoder: &mut Decoder) -> Result<CalibrationCurve, DecodeError> {
+    let point_count = decoder.count()?;
+    let mut points = Vec::with_capacity(point_count);
+    for _ in 0..point_count {
+        let value = AnalogValue::new(decoder.u16()?);
+        points.push(CalibrationPoint::new(value, decode_percentage(decoder)?));
+    }
+    CalibrationCurve::new(points).map_err(|_| DecodeError::InvalidValue("calibration curve"))
+}
+
+fn decode_target(decoder: &mut Decoder) -> Result<TargetMoistureLevel, DecodeError> {
+    let min = decode_percentage(decoder)?;
+    let max = decode_percentage(decoder)?;
+    if min >= max {
+        return Err(DecodeError::InvalidValue("target moisture level"));
+    }
+    Ok(TargetMoistureLevel::new(min, max))
+}
+
+pub(crate) fn decode_percentage(decoder: &mut Decoder) -> Result<Percentage, DecodeError> {
+    match decoder.u8()? {
+        value @ 0..=100 => Ok(Percentage::new(value)),
+        _ => Err(DecodeError::InvalidValue("percentage")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::plant_irrigator::SensorCalibrationResult;
+    use crate::storage::{MemoryStorage, RecordStorage, Storage, StorageError};
+
+    fn state() -> ControllerState {
+        ControllerState {
+            plants: vec![PlantState {
+                name: "basil".to_owned(),
+                calibration: SensorCalibrationResult::new(
+                    AnalogValue::new(500),
+                    AnalogValue::new(2200),
+                )
+                .into(),
+                configured_calibration: Some(
+                    SensorCalibrationResult::new(AnalogValue::new(600), AnalogValue::new(2100))
+                        .into(),
+                ),
+                target: Some(TargetMoistureLevel::new(
+                    Percentage::new(40),
+                    Percentage::new(60),
+                )),
+                counters: PlantCounters {
+                    waterings: 12,
+                    pump_time: Duration::from_secs(30),
+                },
+                pump_runs: vec![PumpRunState {
+                    started_ago: Duration::from_secs(3600),
+                    duration: Duration::from_secs(5),
+                }],
+                reservoir: ReservoirMonitorState {
+                    moisture_before_watering: Some(Percentage::new(35)),
+                    ineffective_waterings: 2,
+                    empty_suspected: false,
+                },
+                moisture_history: vec![Percentage::new(35), Percentage::new(42)],
+                paused: true,
+            }],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut storage = MemoryStorage::new();
+
+        storage.save(&state()).unwrap();
+
+        assert_eq!(storage.load(), Ok(Some(state())));
+    }
+
+    /// Length of what version 3 added: the configured calibration with two
+    /// points and a pump run.
+    const VERSION_3_LEN: usize = 1 + 2 + 2 * 3 + 2 + 2 * 8;
+    /// Length of what version 4 added: the target.
+    const VERSION_4_LEN: usize = 1 + 2;
+
+    #[test]
+    fn invalid_percentage() {
+        let mut storage = MemoryStorage::new();
+        storage.save(&state()).unwrap();
+        let mut bytes = storage.read(ControllerState::KEY).unwrap().unwrap();
+        // The last moisture history entry, followed by `paused`
+        let len = bytes.len();
+        bytes[len - VERSION_4_LEN - VERSION_3_LEN - 2] = 101;
+        storage.write(ControllerState::KEY, &bytes).unwrap();
+
+        assert_eq!(
+            storage.load::<ControllerState>(),
+            Err(StorageError::Decode {
+                key: ControllerState::KEY,
+                error: DecodeError::InvalidValue("percentage")
+            })
+        );
+    }
+
+    #[test]
+    fn moisture_history_alone_is_not_a_change() {
+        let saved = state();
+        let mut current = state();
+
+        current.plants[0].moisture_history.push(Percentage::new(50));
+        assert!(!current.differs_from(&saved));
+
+        current.plants[0].counters.waterings += 1;
+        assert!(current.differs_from(&saved));
+    }
+
+    #[test]
+    fn decode_older_versions() {
+        let mut storage = MemoryStorage::new();
+        storage.save(&state()).unwrap();
+        let mut bytes = storage.read(ControllerState::KEY).unwrap().unwrap();
+        let mut expected = state();
+
+        bytes[..2].copy_from_slice(&3u16.to_le_bytes());
+        bytes.truncate(bytes.len() - VERSION_4_LEN);
+        storage.write(ControllerState::KEY, &bytes).unwrap();
+        expected.plants[0].target = None;
+        assert_eq!(storage.load(), Ok(Some(expected.clone())));
+
+        bytes[..2].copy_from_slice(&2u16.to_le_bytes());
+        bytes.truncate(bytes.len() - VERSION_3_LEN);
+        storage.write(ControllerState::KEY, &bytes).unwrap();
+        expected.plants[0].configured_calibration = None;
+        expected.plants[0].pump_runs.clear();
+        assert_eq!(storage.load(), Ok(Some(expected.clone())));
+
+        bytes[..2].copy_from_slice(&1u16.to_le_bytes());
+        bytes.pop();
+        storage.write(ControllerState::KEY, &bytes).unwrap();
+        expected.plants[0].paused = false;
+        assert_eq!(storage.load(), Ok(Some(expected)));
+    }
+}
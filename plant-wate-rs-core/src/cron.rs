@@ -0,0 +1,436 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::time_window::{civil_date, local_epoch_minutes, MINUTES_PER_DAY};
+
+/// How far ahead the next run is looked for. Enough for any valid
+/// expression, e.g. `0 0 29 2 *` matches only every 4 to 8 years.
+const MAX_SEARCH_DAYS: i64 = 8 * 366;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CronField {
+    Minute,
+    Hour,
+    DayOfMonth,
+    Month,
+    /// 0 to 7, both 0 and 7 being Sunday.
+    DayOfWeek,
+}
+
+impl CronField {
+    const ALL: [CronField; 5] = [
+        CronField::Minute,
+        CronField::Hour,
+        CronField::DayOfMonth,
+        CronField::Month,
+        CronField::DayOfWeek,
+    ];
+
+    /// The lowest and the highest allowed value.
+    const fn range(&self) -> (u8, u8) {
+        match self {
+            CronField::Minute => (0, 59),
+            CronField::Hour => (0, 23),
+            CronField::DayOfMonth => (1, 31),
+            CronField::Month => (1, 12),
+            CronField::DayOfWeek => (0, 7),
+        }
+    }
+}
+
+impl Display for CronField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            CronField::Minute => "minute",
+            CronField::Hour => "hour",
+            CronField::DayOfMonth => "day of month",
+            CronField::Month => "month",
+            CronField::DayOfWeek => "day of week",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CronError {
+    /// Five space-separated fields are expected.
+    FieldCount(usize),
+    UnknownMacro(String),
+    InvalidValue {
+        field: CronField,
+        value: String,
+    },
+    OutOfRange {
+        field: CronField,
+        value: u8,
+    },
+    ZeroStep(CronField),
+}
+
+impl Display for CronError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CronError::FieldCount(count) => write!(f, "expected 5 fields, got {}", count),
+            CronError::UnknownMacro(name) => write!(f, "unknown macro `{}`", name),
+            CronError::InvalidValue { field, value } => {
+                write!(f, "invalid {} `{}`", field, value)
+            }
+            CronError::OutOfRange { field, value } => {
+                let (min, max) = field.range();
+                write!(f, "{} {} is not within {}–{}", field, value, min, max)
+            }
+            CronError::ZeroStep(field) => write!(f, "{} step must not be zero", field),
+        }
+    }
+}
+
+impl Error for CronError {}
+
+/// Cron-like schedule: `minute hour day-of-month month day-of-week`.
+///
+/// Each field is `*`, a value, or a range `a-b`, optionally followed by a step
+/// `/n`; several of these can be separated by commas. As in cron, if both the
+/// day of month and the day of week are restricted, a day matching either is
+/// enough. The macros `@yearly`, `@monthly`, `@weekly`, `@daily` and `@hourly`
+/// are supported too.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CronExpression {
+    expression: String,
+    /// One bit per allowed value, indexed by the value.
+    fields: [u64; 5],
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+impl CronExpression {
+    /// The first time matching the expression strictly after `time`, in the
+    /// local time `utc_offset_minutes` ahead of UTC. `None` if there is no such
+    /// time (e.g. `0 0 31 2 *`).
+    pub fn next_after(&self, time: SystemTime, utc_offset_minutes: i16) -> Option<SystemTime> {
+        let minutes_per_day = MINUTES_PER_DAY as i64;
+        let start = local_epoch_minutes(time, utc_offset_minutes) + 1;
+        let first_day = start.div_euclid(minutes_per_day);
+        let start_minute = start.rem_euclid(minutes_per_day);
+
+        for day in first_day..first_day + MAX_SEARCH_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+
+            let from = if day == first_day { start_minute } else { 0 };
+            for hour in from / 60..24 {
+                if !self.matches(CronField::Hour, hour as u8) {
+                    continue;
+                }
+                let from_minute = if hour == from / 60 { from % 60 } else { 0 };
+                if let Some(minute) =
+                    (from_minute..60).find(|&minute| self.matches(CronField::Minute, minute as u8))
+                {
+                    let local_minutes = day * minutes_per_day + hour * 60 + minute;
+                    return epoch_minutes_to_system_time(local_minutes - utc_offset_minutes as i64);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The last time matching the expression at or before `time`; see
+    /// [`CronExpression::next_after`].
+    pub fn last_at_or_before(
+        &self,
+        time: SystemTime,
+        utc_offset_minutes: i16,
+    ) -> Option<SystemTime> {
+        let minutes_per_day = MINUTES_PER_DAY as i64;
+        let end = local_epoch_minutes(time, utc_offset_minutes);
+        let last_day = end.div_euclid(minutes_per_day);
+        let end_minute = end.rem_euclid(minutes_per_day);
+
+        for day in (last_day - MAX_SEARCH_DAYS + 1..=last_day).rev() {
+            if !self.matches_day(day) {
+                continue;
+            }
+
+            let to = if day == last_day {
+                end_minute
+            } else {
+                minutes_per_day - 1
+            };
+            for hour in (0..=to / 60).rev() {
+                if !self.matches(CronField::Hour, hour as u8) {
+                    continue;
+                }
+                let to_minute = if hour == to / 60 { to % 60 } else { 59 };
+                if let Some(minute) = (0..=to_minute)
+                    .rev()
+                    .find(|&minute| self.matches(CronField::Minute, minute as u8))
+                {
+                    let local_minutes = day * minutes_per_day + hour * 60 + minute;
+                    return epoch_minutes_to_system_time(local_minutes - utc_offset_minutes as i64);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn matches(&self, field: CronField, value: u8) -> bool {
+        self.fields[field as usize] & (1 << value) != 0
+    }
+
+    fn matches_day(&self, days_since_epoch: i64) -> bool {
+        let (_, month, day_of_month) = civil_date(days_since_epoch);
+        // 1970-01-01 was a Thursday
+        let day_of_week = (days_since_epoch + 4).rem_euclid(7) as u8;
+
+        if !self.matches(CronField::Month, month) {
+            return false;
+        }
+
+        let day_of_month_matches = self.matches(CronField::DayOfMonth, day_of_month);
+        let day_of_week_matches = self.matches(CronField::DayOfWeek, day_of_week);
+        if self.day_of_month_restricted && self.day_of_week_restricted {
+            day_of_month_matches || day_of_week_matches
+        } else {
+            day_of_month_matches && day_of_week_matches
+        }
+    }
+}
+
+impl FromStr for CronExpression {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let expression = expression.trim();
+        let expanded = match expression {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            name if name.starts_with('@') => return Err(CronError::UnknownMacro(name.to_owned())),
+            expression => expression,
+        };
+
+        let parts: Vec<&str> = expanded.split_whitespace().collect();
+        if parts.len() != CronField::ALL.len() {
+            return Err(CronError::FieldCount(parts.len()));
+        }
+
+        let mut fields = [0; 5];
+        for (field, part) in CronField::ALL.into_iter().zip(&parts) {
+            fields[field as usize] = parse_field(field, part)?;
+        }
+        // Sunday is both 0 and 7
+        let day_of_week = &mut fields[CronField::DayOfWeek as usize];
+        if *day_of_week & (1 << 7) != 0 {
+            *day_of_week = (*day_of_week & !(1 << 7)) | 1;
+        }
+
+        Ok(Self {
+            expression: expression.to_owned(),
+            fields,
+            day_of_month_restricted: !parts[CronField::DayOfMonth as usize].starts_with('*'),
+            day_of_week_restricted: !parts[CronField::DayOfWeek as usize].starts_with('*'),
+        })
+    }
+}
+
+impl Display for CronExpression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+fn parse_field(field: CronField, text: &str) -> Result<u64, CronError> {
+    let (min, max) = field.range();
+    let invalid = || CronError::InvalidValue {
+        field,
+        value: text.to_owned(),
+    };
+    let parse_value = |value: &str| {
+        let value: u8 = value.parse().map_err(|_| invalid())?;
+        if value < min || value > max {
+            return Err(CronError::OutOfRange { field, value });
+        }
+        Ok(value)
+    };
+
+    let mut bits = 0;
+    for item in text.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u8>().map_err(|_| invalid())?)),
+            None => (item, None),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (parse_value(first)?, parse_value(last)?),
+            // `a/n` means from `a` to the end
+            None if step.is_some() => (parse_value(range)?, max),
+            None => {
+                let value = parse_value(range)?;
+                (value, value)
+            }
+        };
+        if first > last {
+            return Err(invalid());
+        }
+
+        let step = match step {
+            Some(0) => return Err(CronError::ZeroStep(field)),
+            Some(step) => step,
+            None => 1,
+        };
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+fn epoch_minutes_to_system_time(minutes: i64) -> Option<SystemTime> {
+    let offset = Duration::from_secs(minutes.unsigned_abs() * 60);
+    if minutes >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(offset)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2023-11-14T22:13:20Z, a Tuesday
+    const NOW: u64 = 1_700_000_000;
+
+    fn next(expression: &str, after: u64, utc_offset_minutes: i16) -> Option<u64> {
+        let expression: CronExpression = expression.parse().unwrap();
+        expression
+            .next_after(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(after),
+                utc_offset_minutes,
+            )
+            .map(|time| {
+                time.duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            })
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            "* * * *".parse::<CronExpression>(),
+            Err(CronError::FieldCount(4))
+        );
+        assert_eq!(
+            "@often".parse::<CronExpression>(),
+            Err(CronError::UnknownMacro("@often".to_owned()))
+        );
+        assert_eq!(
+            "60 * * * *".parse::<CronExpression>(),
+            Err(CronError::OutOfRange {
+                field: CronField::Minute,
+                value: 60
+            })
+        );
+        assert_eq!(
+            "* * 0 * *".parse::<CronExpression>(),
+            Err(CronError::OutOfRange {
+                field: CronField::DayOfMonth,
+                value: 0
+            })
+        );
+        assert_eq!(
+            "* 5-2 * * *".parse::<CronExpression>(),
+            Err(CronError::InvalidValue {
+                field: CronField::Hour,
+                value: "5-2".to_owned()
+            })
+        );
+        assert_eq!(
+            "*/0 * * * *".parse::<CronExpression>(),
+            Err(CronError::ZeroStep(CronField::Minute))
+        );
+    }
+
+    #[test]
+    fn next_run() {
+        // 22:15
+        assert_eq!(next("*/15 * * * *", NOW, 0), Some(NOW + 100));
+        // Weekdays at 06:30; Wednesday
+        assert_eq!(
+            next("30 6 * * 1-5", NOW, 0),
+            Some(NOW + 8 * 3600 + 16 * 60 + 40)
+        );
+        // Saturday 2023-11-18, also as day 7 of the week
+        assert_eq!(
+            next("0 0 * * 6", NOW, 0),
+            Some(NOW + 3 * 86_400 + 3600 + 46 * 60 + 40)
+        );
+        assert_eq!(next("0 0 * * 0", NOW, 0), next("0 0 * * 7", NOW, 0));
+        assert_eq!(next("@weekly", NOW, 0), next("0 0 * * 0", NOW, 0));
+        // Strictly after
+        assert_eq!(next("13 22 * * *", NOW - 20, 0), Some(NOW - 20 + 86_400));
+        // 22:13 UTC is 00:13 local time
+        assert_eq!(next("30 0 * * *", NOW, 120), Some(NOW + 16 * 60 + 40));
+    }
+
+    #[test]
+    fn last_run() {
+        let last = |expression: &str, at: u64, utc_offset_minutes: i16| {
+            let expression: CronExpression = expression.parse().unwrap();
+            expression
+                .last_at_or_before(
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(at),
+                    utc_offset_minutes,
+                )
+                .map(|time| {
+                    time.duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs()
+                })
+        };
+
+        // At or before: 22:13:00 is the start of the current minute
+        assert_eq!(last("13 22 * * *", NOW, 0), Some(NOW - 20));
+        assert_eq!(last("14 22 * * *", NOW, 0), Some(NOW - 20 - 86_400 + 60));
+        assert_eq!(
+            last("0 6 * * 1", NOW, 0),
+            Some(NOW - 16 * 3600 - 13 * 60 - 20 - 86_400)
+        );
+        // 22:13 UTC is 00:13 local time
+        assert_eq!(last("0 0 * * *", NOW, 120), Some(NOW - 13 * 60 - 20));
+        assert_eq!(last("0 0 31 2 *", NOW, 0), None);
+        for expression in ["*/7 3-5 * * *", "@monthly", "0 12 15 * 5"] {
+            let previous = last(expression, NOW, 0).unwrap();
+            assert_eq!(
+                next(expression, previous, 0).map(|next| next > NOW),
+                Some(true)
+            );
+        }
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // The 15th (Wednesday) or any Friday; the 15th comes first
+        assert_eq!(
+            next("0 12 15 * 5", NOW, 0),
+            Some(NOW + 13 * 3600 + 46 * 60 + 40)
+        );
+        // Only the day of month is restricted; 2023-11-17 happens to be a Friday
+        assert_eq!(next("0 12 17 * *", NOW, 0), next("0 12 * * 5", NOW, 0));
+    }
+
+    #[test]
+    fn rare_and_impossible_dates() {
+        // 2024-02-29T00:00:00Z
+        assert_eq!(next("0 0 29 2 *", NOW, 0), Some(1_709_164_800));
+        assert_eq!(next("0 0 31 2 *", NOW, 0), None);
+    }
+}
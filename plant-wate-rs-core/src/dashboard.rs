@@ -0,0 +1,121 @@
+use crate::http::{HttpRequest, HttpResponse, Method};
+
+/// A static file of the web dashboard, compressed with gzip at build time.
+#[derive(Debug)]
+pub struct Asset {
+    /// Where it is served.
+    pub path: &'static str,
+    pub content_type: &'static str,
+    pub gzip: &'static [u8],
+}
+
+/// The files of `assets/dashboard`.
+pub static ASSETS: &[Asset] = include!(concat!(env!("OUT_DIR"), "/dashboard_assets.rs"));
+
+pub fn asset(path: &str) -> Option<&'static Asset> {
+    let path = if path == "/" { "/index.html" } else { path };
+    ASSETS.iter().find(|asset| asset.path == path)
+}
+
+/// Serves the dashboard asset at the path of `request`. Returns `None` if
+/// there is no such asset, for the request to be handled elsewhere.
+///
+/// The assets are only kept compressed, to spare the flash; a client that
+/// does not accept gzip gets a 406 response.
+pub fn serve(request: &HttpRequest) -> Option<HttpResponse> {
+    if request.method != Method::Get {
+        return None;
+    }
+
+    let asset = asset(&request.path)?;
+    if !accepts_gzip(request) {
+        return Some(HttpResponse::error(
+            406,
+            "the dashboard is only available compressed with gzip",
+        ));
+    }
+    Some(
+        HttpResponse::new(200, asset.content_type, asset.gzip)
+            .with_header("Content-Encoding", "gzip")
+            .with_header("Vary", "Accept-Encoding")
+            .with_header("Cache-Control", "no-cache"),
+    )
+}
+
+/// Whether the `Accept-Encoding` header of `request` allows gzip. Without the
+/// header, any encoding is acceptable.
+fn accepts_gzip(request: &HttpRequest) -> bool {
+    let Some(accept_encoding) = request.header("Accept-Encoding") else {
+        return true;
+    };
+
+    accept_encoding.split(',').any(|coding| {
+        let mut parameters = coding.split(';').map(str::trim);
+        let name = parameters.next().unwrap_or_default();
+        let rejected = parameters.any(|parameter| {
+            parameter
+                .strip_prefix("q=")
+                .and_then(|quality| quality.parse::<f32>().ok())
+                == Some(0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !rejected
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn index_is_served() {
+        let response = serve(&HttpRequest::new(Method::Get, "/", "")).unwrap();
+
+        assert_eq!(response.content_type, "text/html; charset=utf-8");
+        assert!(response.headers.contains(&("Content-Encoding", "gzip")));
+        assert!(serve(&HttpRequest::new(Method::Get, "/api/plants", "")).is_none());
+        assert!(serve(&HttpRequest::new(Method::Post, "/app.js", "")).is_none());
+    }
+
+    #[test]
+    fn accept_encoding() {
+        let status = |accept_encoding: &str| {
+            let request = HttpRequest::new(Method::Get, "/app.js", "")
+                .with_header("Accept-Encoding", accept_encoding);
+            serve(&request).unwrap().status
+        };
+
+        assert_eq!(status("gzip, deflate, br"), 200);
+        assert_eq!(status("br;q=1.0, GZIP;q=0.5"), 200);
+        assert_eq!(status("*"), 200);
+        assert_eq!(status("identity"), 406);
+        assert_eq!(status("gzip;q=0, br"), 406);
+        assert_eq!(status(""), 406);
+    }
+
+    #[test]
+    fn assets_are_compressed() {
+        let sources: [(&str, &str); 3] = [
+            (
+                "/index.html",
+                include_str!("../assets/dashboard/index.html"),
+            ),
+            ("/app.js", include_str!("../assets/dashboard/app.js")),
+            ("/style.css", include_str!("../assets/dashboard/style.css")),
+        ];
+        assert_eq!(ASSETS.len(), sources.len());
+
+        for (path, source) in sources {
+            let gzip = asset(path).unwrap().gzip;
+            assert!(gzip.len() < source.len());
+            let mut decompressed = String::new();
+            GzDecoder::new(gzip)
+                .read_to_string(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, source);
+        }
+    }
+}
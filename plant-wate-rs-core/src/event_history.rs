@@ -0,0 +1,178 @@
+use std::time::{Instant, SystemTime};
+
+use crate::plant_irrigator::{IrrigationStatus, Percentage, TargetMoistureLevel};
+use crate::uc::AnalogValue;
+
+/// Number of events kept per plant.
+pub const EVENT_HISTORY_CAPACITY: usize = 64;
+
+/// Fixed-capacity buffer that overwrites its oldest entry when full. Never
+/// allocates.
+#[derive(Debug, Clone)]
+pub struct RingBuffer<T: Copy, const N: usize> {
+    entries: [Option<T>; N],
+    /// Where the next entry is written.
+    next: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    #[inline]
+    pub const fn new() -> Self {
+        assert!(N > 0, "the capacity must not be zero");
+
+        Self {
+            entries: [None; N],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, entry: T) {
+        self.entries[self.next] = Some(entry);
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// The most recent entry.
+    pub fn latest(&self) -> Option<&T> {
+        self.entries[(self.next + N - 1) % N].as_ref()
+    }
+
+    /// The entries from the oldest to the most recent one.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + '_ {
+        let oldest = (self.next + N - self.len) % N;
+        (0..self.len).filter_map(move |index| self.entries[(oldest + index) % N].as_ref())
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl<T: Copy, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why the irrigator made its decision.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DecisionReason {
+    /// The moisture could not be measured.
+    MoistureUnknown,
+    /// The moisture is below the target minimum.
+    BelowTarget,
+    /// The moisture is between the target minimum and maximum.
+    WithinTarget,
+    /// The moisture is at or above the target maximum.
+    AboveTarget,
+    /// Watered outside of the watering hours, as the moisture is below the
+    /// emergency level.
+    EmergencyLevel,
+    /// Watered on request, regardless of the moisture.
+    Manual,
+}
+
+impl DecisionReason {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            DecisionReason::MoistureUnknown => "moisture_unknown",
+            DecisionReason::BelowTarget => "below_target",
+            DecisionReason::WithinTarget => "within_target",
+            DecisionReason::AboveTarget => "above_target",
+            DecisionReason::EmergencyLevel => "emergency_level",
+            DecisionReason::Manual => "manual",
+        }
+    }
+
+    pub fn from_moisture(moisture: Option<Percentage>, target: &TargetMoistureLevel) -> Self {
+        match moisture {
+            None => DecisionReason::MoistureUnknown,
+            Some(moisture) if moisture < target.min_value() => DecisionReason::BelowTarget,
+            Some(moisture) if moisture < target.max_value() => DecisionReason::WithinTarget,
+            Some(_) => DecisionReason::AboveTarget,
+        }
+    }
+}
+
+/// One irrigation cycle of a plant.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct IrrigationEvent {
+    pub time: Instant,
+    /// `None` if the wall clock was not set.
+    pub wall_clock: Option<SystemTime>,
+    /// The last sensor value measured in the cycle, before any compensation.
+    pub sensor_value: Option<AnalogValue>,
+    /// The moisture the decision was based on.
+    pub moisture: Option<Percentage>,
+    pub target: TargetMoistureLevel,
+    pub decision: IrrigationStatus,
+    pub reason: DecisionReason,
+}
+
+pub type EventHistory = RingBuffer<IrrigationEvent, EVENT_HISTORY_CAPACITY>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_buffer_overwrites_oldest() {
+        let mut buffer = RingBuffer::<u32, 3>::new();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.latest(), None);
+
+        buffer.push(1);
+        buffer.push(2);
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![1, 2]);
+
+        buffer.push(3);
+        buffer.push(4);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(buffer.iter().next_back(), Some(&4));
+        assert_eq!(buffer.latest(), Some(&4));
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.capacity(), 3);
+    }
+
+    #[test]
+    fn reason_from_moisture() {
+        let target = TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70));
+
+        assert_eq!(
+            DecisionReason::from_moisture(None, &target),
+            DecisionReason::MoistureUnknown
+        );
+        assert_eq!(
+            DecisionReason::from_moisture(Some(Percentage::new(39)), &target),
+            DecisionReason::BelowTarget
+        );
+        assert_eq!(
+            DecisionReason::from_moisture(Some(Percentage::new(40)), &target),
+            DecisionReason::WithinTarget
+        );
+        assert_eq!(
+            DecisionReason::from_moisture(Some(Percentage::new(70)), &target),
+            DecisionReason::AboveTarget
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Value of every byte of an erased page.
+pub const ERASED: u8 = 0xFF;
+
+/// Raw NOR-like flash memory, divided into equally sized pages.
+///
+/// Erasing a page sets all its bytes to [`ERASED`]; writing can only clear
+/// bits, so a byte can be written once between erases.
+pub trait Flash: Debug {
+    fn page_size(&self) -> usize;
+    fn page_count(&self) -> usize;
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError>;
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError>;
+    fn erase_page(&mut self, page: usize) -> Result<(), FlashError>;
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FlashError {
+    OutOfBounds {
+        offset: usize,
+        len: usize,
+    },
+    /// The power was lost during the operation, which may have been done only
+    /// partially.
+    PowerLoss,
+    /// A flash image does not have the size of the flash.
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    Io(io::ErrorKind),
+}
+
+impl Display for FlashError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlashError::OutOfBounds { offset, len } => {
+                write!(f, "{} bytes at offset {} are out of bounds", len, offset)
+            }
+            FlashError::PowerLoss => write!(f, "power lost"),
+            FlashError::SizeMismatch { expected, actual } => write!(
+                f,
+                "flash image of {} bytes, expected {} bytes",
+                actual, expected
+            ),
+            FlashError::Io(kind) => write!(f, "flash I/O error: {}", kind),
+        }
+    }
+}
+
+impl Error for FlashError {}
+
+impl From<io::Error> for FlashError {
+    fn from(error: io::Error) -> Self {
+        FlashError::Io(error.kind())
+    }
+}
+
+fn check_bounds(flash: &dyn Flash, offset: usize, len: usize) -> Result<(), FlashError> {
+    let size = flash.page_size() * flash.page_count();
+    match offset.checked_add(len) {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(FlashError::OutOfBounds { offset, len }),
+    }
+}
+
+#[derive(Debug)]
+struct RamFlashState {
+    data: Vec<u8>,
+    erase_counts: Vec<u32>,
+    /// Bytes that can still be written or erased before the power is cut.
+    power_budget: Option<usize>,
+    powered: bool,
+}
+
+impl RamFlashState {
+    fn check_power(&self) -> Result<(), FlashError> {
+        if self.powered {
+            Ok(())
+        } else {
+            Err(FlashError::PowerLoss)
+        }
+    }
+
+    /// How many of `len` bytes can be modified before the power is cut.
+    fn consume_budget(&mut self, len: usize) -> usize {
+        match &mut self.power_budget {
+            Some(budget) => {
+                let allowed = len.min(*budget);
+                *budget -= allowed;
+                allowed
+            }
+            None => len,
+        }
+    }
+
+    fn cut_power_if(&mut self, partial: bool) -> Result<(), FlashError> {
+        if partial {
+            self.powered = false;
+            self.power_budget = None;
+            return Err(FlashError::PowerLoss);
+        }
+        Ok(())
+    }
+}
+
+/// Flash kept in memory, for tests. Clones share the memory, so a clone can
+/// be used to inspect the flash or to "reboot" with the same contents.
+#[derive(Debug, Clone)]
+pub struct RamFlash {
+    page_size: usize,
+    page_count: usize,
+    state: Rc<RefCell<RamFlashState>>,
+}
+
+impl RamFlash {
+    pub fn new(page_size: usize, page_count: usize) -> Self {
+        Self {
+            page_size,
+            page_count,
+            state: Rc::new(RefCell::new(RamFlashState {
+                data: vec![ERASED; page_size * page_count],
+                erase_counts: vec![0; page_count],
+                power_budget: None,
+                powered: true,
+            })),
+        }
+    }
+
+    /// Cuts the power once `bytes` more bytes have been written or erased.
+    /// The operation that runs out of the budget is only done partially, and
+    /// every operation fails from then on until [`RamFlash::restore_power`].
+    pub fn cut_power_after(&self, bytes: usize) {
+        self.state.borrow_mut().power_budget = Some(bytes);
+    }
+
+    pub fn restore_power(&self) {
+        let mut state = self.state.borrow_mut();
+        state.powered = true;
+        state.power_budget = None;
+    }
+
+    pub fn erase_count(&self, page: usize) -> u32 {
+        self.state.borrow().erase_counts[page]
+    }
+}
+
+impl Flash for RamFlash {
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn page_count(&self) -> usize {
+        self.page_count
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError> {
+        check_bounds(self, offset, buffer.len())?;
+        let state = self.state.borrow();
+        state.check_power()?;
+
+        buffer.copy_from_slice(&state.data[offset..offset + buffer.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        check_bounds(self, offset, data.len())?;
+        let mut state = self.state.borrow_mut();
+        state.check_power()?;
+
+        let allowed = state.consume_budget(data.len());
+        for (byte, new) in state.data[offset..].iter_mut().zip(&data[..allowed]) {
+            *byte &= new;
+        }
+        state.cut_power_if(allowed < data.len())
+    }
+
+    fn erase_page(&mut self, page: usize) -> Result<(), FlashError> {
+        check_bounds(self, page * self.page_size, self.page_size)?;
+        let mut state = self.state.borrow_mut();
+        state.check_power()?;
+
+        let allowed = state.consume_budget(self.page_size);
+        let start = page * self.page_size;
+        state.data[start..start + allowed].fill(ERASED);
+        state.erase_counts[page] += 1;
+        state.cut_power_if(allowed < self.page_size)
+    }
+}
+
+/// Flash backed by a file, e.g. to run the firmware logic on a Linux host.
+#[derive(Debug)]
+pub struct FileFlash {
+    file: File,
+    page_size: usize,
+    page_count: usize,
+}
+
+impl FileFlash {
+    /// Opens the flash image at `path`, creating an erased one if it does not
+    /// exist or is empty. An image of a different size is reported as
+    /// [`FlashError::SizeMismatch`] rather than erased, as it may hold data
+    /// written with another configuration.
+    pub fn open(
+        path: impl AsRef<Path>,
+        page_size: usize,
+        page_count: usize,
+    ) -> Result<Self, FlashError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let size = page_size * page_count;
+        match file.metadata()?.len() {
+            0 => {
+                file.write_all(&vec![ERASED; size])?;
+                file.sync_all()?;
+            }
+            len if len != size as u64 => {
+                return Err(FlashError::SizeMismatch {
+                    expected: size as u64,
+                    actual: len,
+                })
+            }
+            _ => {}
+        }
+
+        Ok(Self {
+            file,
+            page_size,
+            page_count,
+        })
+    }
+}
+
+impl Flash for FileFlash {
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn page_count(&self) -> usize {
+        self.page_count
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError> {
+        check_bounds(self, offset, buffer.len())?;
+
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(buffer)?;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        let mut current = vec![0; data.len()];
+        self.read(offset, &mut current)?;
+        for (byte, new) in current.iter_mut().zip(data) {
+            *byte &= new;
+        }
+
+        self.file.seek(SeekFrom::Start(offset as u64))?;
+        self.file.write_all(&current)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn erase_page(&mut self, page: usize) -> Result<(), FlashError> {
+        check_bounds(self, page * self.page_size, self.page_size)?;
+
+        self.file
+            .seek(SeekFrom::Start((page * self.page_size) as u64))?;
+        self.file.write_all(&vec![ERASED; self.page_size])?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_only_clear_bits() {
+        let mut flash = RamFlash::new(16, 2);
+        let mut buffer = [0; 2];
+
+        flash.write(16, &[0b1010_1010, 0x00]).unwrap();
+        flash.write(16, &[0b0110_0110, 0xFF]).unwrap();
+        flash.read(16, &mut buffer).unwrap();
+        assert_eq!(buffer, [0b0010_0010, 0x00]);
+
+        flash.erase_page(1).unwrap();
+        flash.read(16, &mut buffer).unwrap();
+        assert_eq!(buffer, [ERASED, ERASED]);
+        assert_eq!(flash.erase_count(1), 1);
+
+        assert_eq!(
+            flash.write(31, &[0, 0]),
+            Err(FlashError::OutOfBounds { offset: 31, len: 2 })
+        );
+    }
+
+    #[test]
+    fn power_cut() {
+        let mut flash = RamFlash::new(16, 1);
+        let mut buffer = [0; 4];
+
+        flash.cut_power_after(2);
+        assert_eq!(flash.write(0, &[1, 2, 3, 4]), Err(FlashError::PowerLoss));
+        assert_eq!(flash.read(0, &mut buffer), Err(FlashError::PowerLoss));
+
+        flash.restore_power();
+        flash.read(0, &mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2, ERASED, ERASED]);
+    }
+
+    #[test]
+    fn file_flash() {
+        let path = std::env::temp_dir().join(format!(
+            "plant-wate-rs-flash-test-{}.bin",
+            std::process::id()
+        ));
+        let mut flash = FileFlash::open(&path, 16, 2).unwrap();
+        let mut buffer = [0; 2];
+
+        flash.write(20, &[0x0F, 0xF0]).unwrap();
+        drop(flash);
+        let mut flash = FileFlash::open(&path, 16, 2).unwrap();
+        flash.read(20, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x0F, 0xF0]);
+
+        flash.erase_page(1).unwrap();
+        flash.read(20, &mut buffer).unwrap();
+        assert_eq!(buffer, [ERASED, ERASED]);
+        drop(flash);
+
+        assert_eq!(
+            FileFlash::open(&path, 16, 4).err(),
+            Some(FlashError::SizeMismatch {
+                expected: 64,
+                actual: 32
+            })
+        );
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 32);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,605 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use log::{info, warn};
+
+use crate::flash::{Flash, FlashError, ERASED};
+
+const PAGE_MAGIC: u32 = 0x504C_4F47;
+/// Magic, sequence number and CRC.
+const PAGE_HEADER_LEN: usize = 12;
+/// Length, id and CRC.
+const RECORD_HEADER_LEN: usize = 10;
+/// The length of a record that has not been written; marks the end of a page.
+const END_OF_PAGE: u16 = u16::MAX;
+
+/// CRC-32 (IEEE 802.3), as used by zlib.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FlashLogError {
+    Flash(FlashError),
+    /// The log needs at least two pages, each large enough for some records.
+    UnsupportedGeometry {
+        page_size: usize,
+        page_count: usize,
+    },
+    RecordTooLarge {
+        len: usize,
+        max_len: usize,
+    },
+}
+
+impl Display for FlashLogError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlashLogError::Flash(error) => write!(f, "{}", error),
+            FlashLogError::UnsupportedGeometry {
+                page_size,
+                page_count,
+            } => write!(
+                f,
+                "unsupported flash geometry: {} pages of {} bytes",
+                page_count, page_size
+            ),
+            FlashLogError::RecordTooLarge { len, max_len } => {
+                write!(f, "record of {} bytes exceeds {} bytes", len, max_len)
+            }
+        }
+    }
+}
+
+impl Error for FlashLogError {}
+
+impl From<FlashError> for FlashLogError {
+    fn from(error: FlashError) -> Self {
+        FlashLogError::Flash(error)
+    }
+}
+
+/// Decides which records are carried over when the page holding them is
+/// reclaimed; all the others are dropped.
+pub trait RetentionPolicy {
+    fn keep(&self, record: &[u8]) -> bool;
+}
+
+impl<F: Fn(&[u8]) -> bool> RetentionPolicy for F {
+    fn keep(&self, record: &[u8]) -> bool {
+        self(record)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LogRecord {
+    /// Increases with every appended record.
+    pub id: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Records of one page, and where the next record goes; `None` if the page
+/// ends with a torn record, in which case nothing more can be written to it.
+struct PageContents {
+    records: Vec<LogRecord>,
+    end: Option<usize>,
+}
+
+/// Append-only log of records on a [`Flash`], spreading the wear over all the
+/// pages.
+///
+/// Records are appended to the head page; once it is full, the log moves on
+/// to the next page, which is always kept erased. The page after that, holding
+/// the oldest records, is then reclaimed: the records the [`RetentionPolicy`]
+/// keeps are copied to the new head page, up to half of it, and the page is
+/// erased to become the next spare one.
+///
+/// Every page and record is protected by a CRC. A record torn by a power loss
+/// is ignored, as are the records after it in the same page; an interrupted
+/// reclaim is finished when the log is opened again.
+pub struct FlashLog {
+    flash: Box<dyn Flash>,
+    retention: Box<dyn RetentionPolicy>,
+    head: usize,
+    head_sequence: u32,
+    /// Where the next record goes in the head page; `None` if the page ends
+    /// with a torn record.
+    write_offset: Option<usize>,
+    next_id: u32,
+}
+
+impl FlashLog {
+    /// Opens the log, formatting the flash if it does not contain one, and
+    /// recovers from any interrupted operation.
+    pub fn open(
+        flash: Box<dyn Flash>,
+        retention: impl RetentionPolicy + 'static,
+    ) -> Result<Self, FlashLogError> {
+        let (page_size, page_count) = (flash.page_size(), flash.page_count());
+        if page_count < 2 || page_size < 2 * (PAGE_HEADER_LEN + RECORD_HEADER_LEN + 1) {
+            return Err(FlashLogError::UnsupportedGeometry {
+                page_size,
+                page_count,
+            });
+        }
+
+        let mut log = Self {
+            flash,
+            retention: Box::new(retention),
+            head: 0,
+            head_sequence: 0,
+            write_offset: None,
+            next_id: 0,
+        };
+
+        let sequences = (0..page_count)
+            .map(|page| log.page_sequence(page))
+            .collect::<Result<Vec<_>, _>>()?;
+        let head = sequences
+            .iter()
+            .enumerate()
+            .filter_map(|(page, sequence)| sequence.map(|sequence| (sequence, page)))
+            .max();
+        match head {
+            Some((sequence, page)) => {
+                log.head = page;
+                log.head_sequence = sequence;
+                log.write_offset = log.read_page(page)?.end;
+            }
+            None => {
+                info!("No log found in the flash, formatting it");
+                log.format_page(0, 0)?;
+                log.write_offset = Some(PAGE_HEADER_LEN);
+            }
+        }
+
+        let mut last_id = None;
+        log.for_each_record(|record| {
+            last_id = Some(last_id.map_or(record.id, |last_id: u32| last_id.max(record.id)));
+        })?;
+        log.next_id = last_id.map_or(0, |id| id.wrapping_add(1));
+
+        let spare = log.spare_page();
+        if log.write_offset.is_none() && log.page_sequence(spare)?.is_some() {
+            // A rotation was interrupted while copying the retained records,
+            // which are all still in the page being reclaimed
+            info!("Restarting an interrupted log rotation");
+            log.format_page(log.head, log.head_sequence)?;
+            log.write_offset = Some(PAGE_HEADER_LEN);
+        }
+        log.reclaim(spare)?;
+
+        Ok(log)
+    }
+
+    /// The largest payload a record can have.
+    pub fn max_record_len(&self) -> usize {
+        self.carry_over_capacity() - RECORD_HEADER_LEN
+    }
+
+    pub fn append(&mut self, payload: &[u8]) -> Result<(), FlashLogError> {
+        if payload.len() > self.max_record_len() {
+            return Err(FlashLogError::RecordTooLarge {
+                len: payload.len(),
+                max_len: self.max_record_len(),
+            });
+        }
+
+        if !self.fits(payload.len()) {
+            self.rotate()?;
+        }
+        self.write_record(self.next_id, payload)?;
+        self.next_id = self.next_id.wrapping_add(1);
+        Ok(())
+    }
+
+    /// All the records, oldest first.
+    pub fn records(&self) -> Result<Vec<LogRecord>, FlashLogError> {
+        let mut records = BTreeMap::new();
+        self.for_each_record(|record| {
+            records.insert(record.id, record.payload);
+        })?;
+
+        Ok(records
+            .into_iter()
+            .map(|(id, payload)| LogRecord { id, payload })
+            .collect())
+    }
+
+    /// Calls `f` with every record, reading one page at a time from the
+    /// oldest one. Unlike with [`records`](Self::records), the records
+    /// retained by a reclaim come after newer ones.
+    pub fn for_each_record(&self, mut f: impl FnMut(LogRecord)) -> Result<(), FlashLogError> {
+        let page_count = self.flash.page_count();
+        // Records copied by an unfinished reclaim are also in the head page
+        let copied: HashSet<u32> = self
+            .read_page(self.head)?
+            .records
+            .iter()
+            .map(|record| record.id)
+            .collect();
+
+        // From the oldest page to the head
+        for page in (1..=page_count).map(|index| (self.head + index) % page_count) {
+            if self.page_sequence(page)?.is_none() {
+                continue;
+            }
+            for record in self.read_page(page)?.records {
+                if page == self.head || !copied.contains(&record.id) {
+                    f(record);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Erases the whole log.
+    pub fn clear(&mut self) -> Result<(), FlashLogError> {
+        for page in 0..self.flash.page_count() {
+            if page != self.head {
+                self.erase_if_needed(page)?;
+            }
+        }
+        self.head_sequence = self.head_sequence.wrapping_add(1);
+        self.format_page(self.head, self.head_sequence)?;
+        self.write_offset = Some(PAGE_HEADER_LEN);
+        Ok(())
+    }
+
+    fn spare_page(&self) -> usize {
+        (self.head + 1) % self.flash.page_count()
+    }
+
+    /// Space for the records carried over by a reclaim, which leaves room for
+    /// a record of the maximum length after them.
+    fn carry_over_capacity(&self) -> usize {
+        (self.flash.page_size() - PAGE_HEADER_LEN) / 2
+    }
+
+    fn fits(&self, len: usize) -> bool {
+        self.write_offset.map_or(false, |offset| {
+            offset + RECORD_HEADER_LEN + len <= self.flash.page_size()
+        })
+    }
+
+    /// Moves the head to the spare page and reclaims the oldest one.
+    fn rotate(&mut self) -> Result<(), FlashLogError> {
+        let new_head = self.spare_page();
+        let sequence = self.head_sequence.wrapping_add(1);
+        self.format_page(new_head, sequence)?;
+        self.head = new_head;
+        self.head_sequence = sequence;
+        self.write_offset = Some(PAGE_HEADER_LEN);
+
+        self.reclaim(self.spare_page())
+    }
+
+    /// Copies the records to retain from `page` to the head page and erases
+    /// `page`. Safe to repeat if interrupted.
+    fn reclaim(&mut self, page: usize) -> Result<(), FlashLogError> {
+        if self.page_sequence(page)?.is_some() && page != self.head {
+            let copied: HashSet<u32> = self
+                .read_page(self.head)?
+                .records
+                .iter()
+                .map(|record| record.id)
+                .collect();
+            let limit = PAGE_HEADER_LEN + self.carry_over_capacity();
+
+            for record in self.read_page(page)?.records {
+                if copied.contains(&record.id) || !self.retention.keep(&record.payload) {
+                    continue;
+                }
+                let fits = self.write_offset.map_or(false, |offset| {
+                    offset + RECORD_HEADER_LEN + record.payload.len() <= limit
+                });
+                if !fits {
+                    warn!("No room left to retain older log records, dropping them");
+                    break;
+                }
+                self.write_record(record.id, &record.payload)?;
+            }
+        }
+
+        self.erase_if_needed(page)
+    }
+
+    fn erase_if_needed(&mut self, page: usize) -> Result<(), FlashLogError> {
+        let mut data = vec![0; self.flash.page_size()];
+        self.flash.read(page * self.flash.page_size(), &mut data)?;
+        if data.iter().any(|&byte| byte != ERASED) {
+            self.flash.erase_page(page)?;
+        }
+        Ok(())
+    }
+
+    fn format_page(&mut self, page: usize, sequence: u32) -> Result<(), FlashLogError> {
+        self.erase_if_needed(page)?;
+
+        let mut header = Vec::with_capacity(PAGE_HEADER_LEN);
+        header.extend_from_slice(&PAGE_MAGIC.to_le_bytes());
+        header.extend_from_slice(&sequence.to_le_bytes());
+        header.extend_from_slice(&crc32(&header).to_le_bytes());
+        self.flash.write(page * self.flash.page_size(), &header)?;
+        Ok(())
+    }
+
+    /// `None` if the page has no valid header, e.g. it is erased.
+    fn page_sequence(&self, page: usize) -> Result<Option<u32>, FlashLogError> {
+        let mut header = [0; PAGE_HEADER_LEN];
+        self.flash
+            .read(page * self.flash.page_size(), &mut header)?;
+
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let sequence = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        Ok((magic == PAGE_MAGIC && crc == crc32(&header[..8])).then_some(sequence))
+    }
+
+    fn read_page(&self, page: usize) -> Result<PageContents, FlashLogError> {
+        let page_size = self.flash.page_size();
+        let mut data = vec![0; page_size];
+        self.flash.read(page * page_size, &mut data)?;
+
+        let mut records = Vec::new();
+        let mut offset = PAGE_HEADER_LEN;
+        while offset + RECORD_HEADER_LEN <= page_size {
+            let header = &data[offset..offset + RECORD_HEADER_LEN];
+            let len = u16::from_le_bytes(header[0..2].try_into().unwrap());
+            if len == END_OF_PAGE {
+                break;
+            }
+            let id = u32::from_le_bytes(header[2..6].try_into().unwrap());
+            let crc = u32::from_le_bytes(header[6..10].try_into().unwrap());
+
+            let start = offset + RECORD_HEADER_LEN;
+            let end = start + len as usize;
+            if end > page_size || crc != record_crc(len, id, &data[start..end]) {
+                warn!("Torn log record in page {} at offset {}", page, offset);
+                return Ok(PageContents { records, end: None });
+            }
+
+            records.push(LogRecord {
+                id,
+                payload: data[start..end].to_vec(),
+            });
+            offset = end;
+        }
+
+        Ok(PageContents {
+            records,
+            end: Some(offset),
+        })
+    }
+
+    fn write_record(&mut self, id: u32, payload: &[u8]) -> Result<(), FlashLogError> {
+        let offset = self
+            .write_offset
+            .expect("the head page is checked to have room");
+        let len = payload.len() as u16;
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&id.to_le_bytes());
+        record.extend_from_slice(&record_crc(len, id, payload).to_le_bytes());
+        record.extend_from_slice(payload);
+
+        let address = self.head * self.flash.page_size() + offset;
+        if let Err(error) = self.flash.write(address, &record) {
+            // The record may have been written partially
+            self.write_offset = None;
+            return Err(error.into());
+        }
+        self.write_offset = Some(offset + record.len());
+        Ok(())
+    }
+}
+
+fn record_crc(len: u16, id: u32, payload: &[u8]) -> u32 {
+    let mut data = Vec::with_capacity(6 + payload.len());
+    data.extend_from_slice(&len.to_le_bytes());
+    data.extend_from_slice(&id.to_le_bytes());
+    data.extend_from_slice(payload);
+    crc32(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::RamFlash;
+
+    const PAGE_SIZE: usize = 128;
+    const PAGE_COUNT: usize = 4;
+
+    fn drop_all(_: &[u8]) -> bool {
+        false
+    }
+
+    fn payloads(log: &FlashLog) -> Vec<Vec<u8>> {
+        log.records()
+            .unwrap()
+            .into_iter()
+            .map(|record| record.payload)
+            .collect()
+    }
+
+    /// 30-byte records; 3 fit in a page
+    fn record(index: u8) -> Vec<u8> {
+        vec![index; 20]
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn append_and_reopen() {
+        let flash = RamFlash::new(PAGE_SIZE, PAGE_COUNT);
+        let mut log = FlashLog::open(Box::new(flash.clone()), drop_all).unwrap();
+        assert!(log.records().unwrap().is_empty());
+
+        log.append(b"first").unwrap();
+        log.append(b"second").unwrap();
+
+        let log = FlashLog::open(Box::new(flash), drop_all).unwrap();
+        assert_eq!(payloads(&log), vec![b"first".to_vec(), b"second".to_vec()]);
+        assert_eq!(log.records().unwrap()[1].id, 1);
+    }
+
+    #[test]
+    fn rotation_spreads_wear() {
+        let flash = RamFlash::new(PAGE_SIZE, PAGE_COUNT);
+        let mut log = FlashLog::open(Box::new(flash.clone()), drop_all).unwrap();
+
+        for index in 0..60 {
+            log.append(&record(index)).unwrap();
+        }
+
+        // Two pages of records (the third one is the spare) plus the head
+        let expected: Vec<_> = (51..60).map(record).collect();
+        assert_eq!(payloads(&log), expected);
+        let erase_counts: Vec<_> = (0..PAGE_COUNT)
+            .map(|page| flash.erase_count(page))
+            .collect();
+        assert!(erase_counts.iter().all(|&count| (4..=5).contains(&count)));
+    }
+
+    #[test]
+    fn compaction_retains_records() {
+        let flash = RamFlash::new(PAGE_SIZE, PAGE_COUNT);
+        let keep_first = |record: &[u8]| record[0] == 0;
+        let mut log = FlashLog::open(Box::new(flash), keep_first).unwrap();
+
+        for index in 0..30 {
+            log.append(&record(index)).unwrap();
+        }
+
+        // Record 0 is carried over from page to page, each time taking the
+        // place of a new record
+        let payloads = payloads(&log);
+        assert_eq!(payloads[0], record(0));
+        assert_eq!(payloads[1..], (22..30).map(record).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn for_each_record() {
+        let flash = RamFlash::new(PAGE_SIZE, PAGE_COUNT);
+        let keep_first = |record: &[u8]| record[0] == 0;
+        let mut log = FlashLog::open(Box::new(flash), keep_first).unwrap();
+        for index in 0..10 {
+            log.append(&record(index)).unwrap();
+        }
+
+        let mut visited = Vec::new();
+        log.for_each_record(|record| visited.push((record.id, record.payload[0])))
+            .unwrap();
+        // Record 0 has been carried over to the head page
+        let mut expected: Vec<_> = (3..9).map(|index| (index, index as u8)).collect();
+        expected.extend([(0, 0), (9, 9)]);
+        assert_eq!(visited, expected);
+    }
+
+    #[test]
+    fn too_large_record() {
+        let mut log =
+            FlashLog::open(Box::new(RamFlash::new(PAGE_SIZE, PAGE_COUNT)), drop_all).unwrap();
+
+        assert_eq!(
+            log.append(&[0; 49]),
+            Err(FlashLogError::RecordTooLarge {
+                len: 49,
+                max_len: 48
+            })
+        );
+        log.append(&[0; 48]).unwrap();
+    }
+
+    #[test]
+    fn unsupported_geometry() {
+        assert!(matches!(
+            FlashLog::open(Box::new(RamFlash::new(PAGE_SIZE, 1)), drop_all),
+            Err(FlashLogError::UnsupportedGeometry { .. })
+        ));
+    }
+
+    #[test]
+    fn torn_record() {
+        let flash = RamFlash::new(PAGE_SIZE, PAGE_COUNT);
+        let mut log = FlashLog::open(Box::new(flash.clone()), drop_all).unwrap();
+        log.append(&record(0)).unwrap();
+
+        flash.cut_power_after(15);
+        assert_eq!(
+            log.append(&record(1)),
+            Err(FlashLogError::Flash(FlashError::PowerLoss))
+        );
+        flash.restore_power();
+
+        let mut log = FlashLog::open(Box::new(flash.clone()), drop_all).unwrap();
+        assert_eq!(payloads(&log), vec![record(0)]);
+        // The torn record closes the page
+        log.append(&record(2)).unwrap();
+        let log = FlashLog::open(Box::new(flash), drop_all).unwrap();
+        assert_eq!(payloads(&log), vec![record(0), record(2)]);
+        assert_eq!(log.records().unwrap()[1].id, 1);
+    }
+
+    /// Cuts the power at every possible point of a rotation and checks that
+    /// reopening the log always gives a consistent result.
+    #[test]
+    fn power_loss_during_rotation() {
+        let keep_even = |record: &[u8]| record[0] % 2 == 0;
+
+        for budget in 0..PAGE_SIZE * 3 {
+            let flash = RamFlash::new(PAGE_SIZE, PAGE_COUNT);
+            let mut log = FlashLog::open(Box::new(flash.clone()), keep_even).unwrap();
+            // Fill all but the spare page
+            for index in 0..9 {
+                log.append(&record(index)).unwrap();
+            }
+
+            flash.cut_power_after(budget);
+            let result = log.append(&record(9));
+            flash.restore_power();
+
+            let mut log = FlashLog::open(Box::new(flash.clone()), keep_even).unwrap();
+            let actual = payloads(&log);
+            // Only one record fits in the space kept for the retained ones
+            let mut retained = vec![record(0)];
+            retained.extend((3..9).map(record));
+            if result.is_ok() {
+                retained.push(record(9));
+                assert_eq!(actual, retained, "power cut after {} bytes", budget);
+            } else {
+                // Records 1 and 2 may be gone already
+                assert!(
+                    retained.iter().all(|record| actual.contains(record)),
+                    "power cut after {} bytes: {:?}",
+                    budget,
+                    actual
+                );
+                assert!(
+                    actual.iter().all(|record| record[0] < 9),
+                    "power cut after {} bytes: {:?}",
+                    budget,
+                    actual
+                );
+            }
+
+            // The log remains usable
+            log.append(&record(100)).unwrap();
+            assert_eq!(payloads(&log).last(), Some(&record(100)));
+        }
+    }
+}
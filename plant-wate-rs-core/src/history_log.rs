@@ -0,0 +1,667 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+use crate::controller_state::decode_percentage;
+use crate::event_history::{DecisionReason, IrrigationEvent};
+use crate::flash::Flash;
+use crate::flash_log::{FlashLog, FlashLogError, LogRecord};
+use crate::plant_irrigator::{IrrigationStatus, Percentage, TargetMoistureLevel};
+use crate::pump_safety::SafetyLimit;
+use crate::sensor_fault::SensorFaultKind;
+use crate::storage::{DecodeError, Decoder, Encoder};
+use crate::uc::{AnalogValue, GpioId, UcError};
+
+const ENTRY_VERSION: u16 = 1;
+
+/// Repeated events are still recorded this often, so that the moisture can be
+/// charted.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long the [`HistoryLog`] keeps the hourly moisture in memory.
+pub const HOURLY_MOISTURE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const SECONDS_PER_HOUR: u64 = SAMPLE_INTERVAL.as_secs();
+
+/// An irrigation event of a plant, as kept in the [`HistoryLog`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HistoryEntry {
+    pub plant: String,
+    /// Truncated to seconds; `None` if the wall clock was not set.
+    pub wall_clock: Option<SystemTime>,
+    pub sensor_value: Option<AnalogValue>,
+    pub moisture: Option<Percentage>,
+    pub target: TargetMoistureLevel,
+    pub decision: IrrigationStatus,
+    pub reason: DecisionReason,
+}
+
+impl HistoryEntry {
+    pub fn new(plant: &str, event: &IrrigationEvent) -> Self {
+        Self {
+            plant: plant.to_owned(),
+            wall_clock: event.wall_clock.map(truncate_to_seconds),
+            sensor_value: event.sensor_value,
+            moisture: event.moisture,
+            target: event.target,
+            decision: event.decision,
+            reason: event.reason,
+        }
+    }
+
+    /// Whether the entry is kept when its flash page is reclaimed: only the
+    /// entries where something happened are.
+    pub fn is_notable(&self) -> bool {
+        !matches!(
+            self.decision,
+            IrrigationStatus::NotWatered
+                | IrrigationStatus::TargetReached
+                | IrrigationStatus::OutsideWateringWindow
+                | IrrigationStatus::Paused
+        )
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.u16(ENTRY_VERSION);
+        encoder.str(&self.plant);
+        encoder.option(self.wall_clock, |encoder, wall_clock| {
+            encoder.u64(seconds_since_epoch(wall_clock))
+        });
+        encoder.option(self.sensor_value, |encoder, value| {
+            encoder.u16(value.value())
+        });
+        encoder.option(self.moisture, |encoder, moisture| {
+            encoder.u8(moisture.value())
+        });
+        encoder.u8(self.target.min_value().value());
+        encoder.u8(self.target.max_value().value());
+        encode_status(&mut encoder, self.decision);
+        encoder.u8(match self.reason {
+            DecisionReason::MoistureUnknown => 0,
+            DecisionReason::BelowTarget => 1,
+            DecisionReason::WithinTarget => 2,
+            DecisionReason::AboveTarget => 3,
+            DecisionReason::EmergencyLevel => 4,
+            DecisionReason::Manual => 5,
+        });
+        encoder.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut decoder = Decoder::new(bytes);
+        if decoder.u16()? != ENTRY_VERSION {
+            return Err(DecodeError::InvalidValue("history entry version"));
+        }
+
+        let plant = decoder.str()?;
+        let wall_clock = decoder.option(|decoder| {
+            SystemTime::UNIX_EPOCH
+                .checked_add(Duration::from_secs(decoder.u64()?))
+                .ok_or(DecodeError::InvalidValue("time"))
+        })?;
+        let sensor_value = decoder.option(|decoder| Ok(AnalogValue::new(decoder.u16()?)))?;
+        let moisture = decoder.option(decode_percentage)?;
+        let min_value = decode_percentage(&mut decoder)?;
+        let max_value = decode_percentage(&mut decoder)?;
+        if min_value >= max_value {
+            return Err(DecodeError::InvalidValue("target moisture level"));
+        }
+        let decision = decode_status(&mut decoder)?;
+        let reason = decode_reason(&mut decoder)?;
+        decoder.finish()?;
+
+        Ok(Self {
+            plant,
+            wall_clock,
+            sensor_value,
+            moisture,
+            target: TargetMoistureLevel::new(min_value, max_value),
+            decision,
+            reason,
+        })
+    }
+}
+
+/// Irrigation history of all the plants, kept on flash across reboots.
+///
+/// When space runs out, the oldest entries are dropped, except the
+/// [notable](HistoryEntry::is_notable) ones, which are kept as long as
+/// possible.
+///
+/// The hourly moisture of the last [`HOURLY_MOISTURE_PERIOD`] is also kept in
+/// memory, so that charting it does not read the whole log.
+pub struct HistoryLog {
+    log: FlashLog,
+    /// The decision, reason and time of the last entry recorded for each
+    /// plant.
+    last_recorded: HashMap<String, (IrrigationStatus, DecisionReason, Option<SystemTime>)>,
+    hourly: HourlyTotals,
+}
+
+impl HistoryLog {
+    /// Reads the whole log once, one flash page at a time, to aggregate the
+    /// hourly moisture.
+    pub fn open(flash: Box<dyn Flash>) -> Result<Self, FlashLogError> {
+        let retention =
+            |record: &[u8]| HistoryEntry::decode(record).map_or(false, |entry| entry.is_notable());
+
+        let log = FlashLog::open(flash, retention)?;
+        let mut hourly = HourlyTotals::default();
+        log.for_each_record(|record| {
+            if let Some(entry) = decode_record(&record) {
+                hourly.add_recent(&entry);
+            }
+        })?;
+        Ok(Self {
+            log,
+            last_recorded: HashMap::new(),
+            hourly,
+        })
+    }
+
+    pub fn append(&mut self, entry: &HistoryEntry) -> Result<(), FlashLogError> {
+        self.log.append(&entry.encode())?;
+        self.last_recorded.insert(
+            entry.plant.clone(),
+            (entry.decision, entry.reason, entry.wall_clock),
+        );
+        self.hourly.add_recent(entry);
+        Ok(())
+    }
+
+    /// The moisture of `plant` by hour, from the hour of `since` on, oldest
+    /// first; at most over the last [`HOURLY_MOISTURE_PERIOD`]. Hours without
+    /// any entries are left out.
+    pub fn hourly_moisture(&self, plant: &str, since: SystemTime) -> Vec<HourlyMoisture> {
+        self.hourly.hours(plant, since)
+    }
+
+    /// Appends the event unless it repeats the decision and reason of the
+    /// previous one of the plant, recorded less than [`SAMPLE_INTERVAL`] ago,
+    /// to spare the flash. Waterings are always recorded.
+    pub fn record(&mut self, plant: &str, event: &IrrigationEvent) -> Result<(), FlashLogError> {
+        let repeated = event.decision != IrrigationStatus::Watered
+            && self
+                .last_recorded
+                .get(plant)
+                .map_or(false, |&(decision, reason, wall_clock)| {
+                    let sample_due = match (wall_clock, event.wall_clock) {
+                        (Some(last), Some(time)) => time
+                            .duration_since(last)
+                            .map_or(false, |elapsed| elapsed >= SAMPLE_INTERVAL),
+                        _ => false,
+                    };
+                    (decision, reason) == (event.decision, event.reason) && !sample_due
+                });
+        if repeated {
+            return Ok(());
+        }
+
+        self.append(&HistoryEntry::new(plant, event))
+    }
+
+    /// All the entries, oldest first. Entries that cannot be decoded are
+    /// skipped.
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>, FlashLogError> {
+        Ok(self
+            .log
+            .records()?
+            .iter()
+            .filter_map(decode_record)
+            .collect())
+    }
+
+    pub fn clear(&mut self) -> Result<(), FlashLogError> {
+        self.last_recorded.clear();
+        self.hourly = HourlyTotals::default();
+        self.log.clear()
+    }
+}
+
+fn decode_record(record: &LogRecord) -> Option<HistoryEntry> {
+    match HistoryEntry::decode(&record.payload) {
+        Ok(entry) => Some(entry),
+        Err(error) => {
+            warn!("Skipping history entry {}: {}", record.id, error);
+            None
+        }
+    }
+}
+
+/// The moisture of a plant over one hour.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct HourlyMoisture {
+    pub hour: SystemTime,
+    /// `None` if the moisture was not measured in that hour.
+    pub min: Option<Percentage>,
+    pub max: Option<Percentage>,
+    pub mean: Option<Percentage>,
+    pub waterings: u32,
+}
+
+/// Aggregates the entries of `plant` recorded since `since` by hour, oldest
+/// first. Hours without any entries are left out.
+pub fn hourly_moisture<'a>(
+    entries: impl IntoIterator<Item = &'a HistoryEntry>,
+    plant: &str,
+    since: SystemTime,
+) -> Vec<HourlyMoisture> {
+    let mut hourly = HourlyTotals::default();
+    for entry in entries {
+        if entry.plant == plant && entry.wall_clock.map_or(false, |time| time >= since) {
+            hourly.add(entry);
+        }
+    }
+    hourly.hours(plant, SystemTime::UNIX_EPOCH)
+}
+
+/// The running totals of the entries of one hour.
+#[derive(Debug, Copy, Clone, Default)]
+struct HourTotals {
+    min: Option<Percentage>,
+    max: Option<Percentage>,
+    sum: u32,
+    count: u32,
+    waterings: u32,
+}
+
+/// The hourly totals of each plant, by hours since the epoch.
+#[derive(Debug, Default)]
+struct HourlyTotals {
+    plants: HashMap<String, BTreeMap<u64, HourTotals>>,
+}
+
+impl HourlyTotals {
+    /// Entries without a wall clock time are left out.
+    fn add(&mut self, entry: &HistoryEntry) {
+        let Some(wall_clock) = entry.wall_clock else {
+            return;
+        };
+
+        let hour = self
+            .plants
+            .entry(entry.plant.clone())
+            .or_default()
+            .entry(seconds_since_epoch(wall_clock) / SECONDS_PER_HOUR)
+            .or_default();
+        if let Some(moisture) = entry.moisture {
+            hour.min = Some(hour.min.map_or(moisture, |min| min.min(moisture)));
+            hour.max = Some(hour.max.map_or(moisture, |max| max.max(moisture)));
+            hour.sum += u32::from(moisture.value());
+            hour.count += 1;
+        }
+        if entry.decision == IrrigationStatus::Watered {
+            hour.waterings += 1;
+        }
+    }
+
+    /// Adds the entry unless it is older than [`HOURLY_MOISTURE_PERIOD`] before
+    /// the latest one of its plant, and drops the hours that are. The entries
+    /// do not need to be in order.
+    fn add_recent(&mut self, entry: &HistoryEntry) {
+        self.add(entry);
+
+        let latest = self
+            .plants
+            .get(&entry.plant)
+            .and_then(|hours| hours.keys().next_back().copied());
+        if let Some(latest) = latest {
+            let since = (SystemTime::UNIX_EPOCH + Duration::from_secs(latest * SECONDS_PER_HOUR))
+                .checked_sub(HOURLY_MOISTURE_PERIOD)
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            self.prune(&entry.plant, since);
+        }
+    }
+
+    /// Drops the hours of `plant` before the one of `since`.
+    fn prune(&mut self, plant: &str, since: SystemTime) {
+        if let Some(hours) = self.plants.get_mut(plant) {
+            *hours = hours.split_off(&(seconds_since_epoch(since) / SECONDS_PER_HOUR));
+        }
+    }
+
+    fn hours(&self, plant: &str, since: SystemTime) -> Vec<HourlyMoisture> {
+        let Some(hours) = self.plants.get(plant) else {
+            return Vec::new();
+        };
+
+        hours
+            .range(seconds_since_epoch(since) / SECONDS_PER_HOUR..)
+            .map(|(&index, hour)| HourlyMoisture {
+                hour: SystemTime::UNIX_EPOCH + Duration::from_secs(index * SECONDS_PER_HOUR),
+                min: hour.min,
+                max: hour.max,
+                mean: (hour.count > 0)
+                    .then(|| Percentage::new(((hour.sum + hour.count / 2) / hour.count) as u8)),
+                waterings: hour.waterings,
+            })
+            .collect()
+    }
+}
+
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(seconds_since_epoch(time))
+}
+
+fn seconds_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn encode_status(encoder: &mut Encoder, status: IrrigationStatus) {
+    match status {
+        IrrigationStatus::Watered => encoder.u8(0),
+        IrrigationStatus::TargetReached => encoder.u8(1),
+        IrrigationStatus::NotWatered => encoder.u8(2),
+        IrrigationStatus::SafetyLimitReached(limit) => {
+            encoder.u8(3);
+            encoder.u8(match limit {
+                SafetyLimit::Cooldown => 0,
+                SafetyLimit::HourlyRuntime => 1,
+                SafetyLimit::DailyRuntime => 2,
+                SafetyLimit::DailyWaterBudget => 3,
+            });
+        }
+        IrrigationStatus::SensorFault(kind) => {
+            encoder.u8(4);
+            encoder.u8(match kind {
+                SensorFaultKind::OpenCircuit => 0,
+                SensorFaultKind::ShortCircuit => 1,
+                SensorFaultKind::Stuck => 2,
+            });
+        }
+        IrrigationStatus::ReservoirSuspectedEmpty => encoder.u8(5),
+        IrrigationStatus::HardwareError(error) => {
+            encoder.u8(6);
+            let (kind, gpio) = match error {
+                UcError::GpioInUse(gpio) => (0, gpio),
+                UcError::GpioNotSupported(gpio) => (1, gpio),
+                UcError::GpioInitFailed(gpio) => (2, gpio),
+                UcError::AnalogReadFailed(gpio) => (3, gpio),
+                UcError::DigitalWriteFailed(gpio) => (4, gpio),
+            };
+            encoder.u8(kind);
+            encoder.u8(gpio.value());
+        }
+        IrrigationStatus::OutsideWateringWindow => encoder.u8(7),
+        IrrigationStatus::Paused => encoder.u8(8),
+    }
+}
+
+fn decode_status(decoder: &mut Decoder) -> Result<IrrigationStatus, DecodeError> {
+    let status = match decoder.u8()? {
+        0 => IrrigationStatus::Watered,
+        1 => IrrigationStatus::TargetReached,
+        2 => IrrigationStatus::NotWatered,
+        3 => IrrigationStatus::SafetyLimitReached(match decoder.u8()? {
+            0 => SafetyLimit::Cooldown,
+            1 => SafetyLimit::HourlyRuntime,
+            2 => SafetyLimit::DailyRuntime,
+            3 => SafetyLimit::DailyWaterBudget,
+            _ => return Err(DecodeError::InvalidValue("safety limit")),
+        }),
+        4 => IrrigationStatus::SensorFault(match decoder.u8()? {
+            0 => SensorFaultKind::OpenCircuit,
+            1 => SensorFaultKind::ShortCircuit,
+            2 => SensorFaultKind::Stuck,
+            _ => return Err(DecodeError::InvalidValue("sensor fault")),
+        }),
+        5 => IrrigationStatus::ReservoirSuspectedEmpty,
+        6 => {
+            let kind = decoder.u8()?;
+            let gpio = GpioId::new(decoder.u8()?);
+            IrrigationStatus::HardwareError(match kind {
+                0 => UcError::GpioInUse(gpio),
+                1 => UcError::GpioNotSupported(gpio),
+                2 => UcError::GpioInitFailed(gpio),
+                3 => UcError::AnalogReadFailed(gpio),
+                4 => UcError::DigitalWriteFailed(gpio),
+                _ => return Err(DecodeError::InvalidValue("hardware error")),
+            })
+        }
+        7 => IrrigationStatus::OutsideWateringWindow,
+        8 => IrrigationStatus::Paused,
+        _ => return Err(DecodeError::InvalidValue("irrigation status")),
+    };
+    Ok(status)
+}
+
+fn decode_reason(decoder: &mut Decoder) -> Result<DecisionReason, DecodeError> {
+    match decoder.u8()? {
+        0 => Ok(DecisionReason::MoistureUnknown),
+        1 => Ok(DecisionReason::BelowTarget),
+        2 => Ok(DecisionReason::WithinTarget),
+        3 => Ok(DecisionReason::AboveTarget),
+        4 => Ok(DecisionReason::EmergencyLevel),
+        5 => Ok(DecisionReason::Manual),
+        _ => Err(DecodeError::InvalidValue("decision reason")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::flash::RamFlash;
+    use crate::uc::GPIO_1;
+
+    fn event(decision: IrrigationStatus) -> IrrigationEvent {
+        IrrigationEvent {
+            time: Instant::now(),
+            wall_clock: Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_500)),
+            sensor_value: Some(AnalogValue::new(1800)),
+            moisture: Some(Percentage::new(35)),
+            target: TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+            decision,
+            reason: DecisionReason::BelowTarget,
+        }
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let statuses = [
+            IrrigationStatus::Watered,
+            IrrigationStatus::SafetyLimitReached(SafetyLimit::DailyWaterBudget),
+            IrrigationStatus::SensorFault(SensorFaultKind::Stuck),
+            IrrigationStatus::HardwareError(UcError::DigitalWriteFailed(GPIO_1)),
+            IrrigationStatus::OutsideWateringWindow,
+            IrrigationStatus::Paused,
+        ];
+
+        for status in statuses {
+            let entry = HistoryEntry::new("basil", &event(status));
+            assert_eq!(HistoryEntry::decode(&entry.encode()), Ok(entry));
+        }
+
+        let entry = HistoryEntry::new("basil", &event(IrrigationStatus::Watered));
+        assert_eq!(
+            entry.wall_clock,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        let mut bytes = entry.encode();
+        bytes.push(0);
+        assert_eq!(
+            HistoryEntry::decode(&bytes),
+            Err(DecodeError::TrailingBytes)
+        );
+    }
+
+    #[test]
+    fn time_out_of_range() {
+        let mut encoder = Encoder::new();
+        encoder.u16(ENTRY_VERSION);
+        encoder.str("basil");
+        encoder.option(Some(u64::MAX), |encoder, seconds| encoder.u64(seconds));
+
+        assert_eq!(
+            HistoryEntry::decode(&encoder.into_bytes()),
+            Err(DecodeError::InvalidValue("time"))
+        );
+    }
+
+    #[test]
+    fn repeated_events_are_recorded_once() {
+        let flash = RamFlash::new(256, 4);
+        let mut history = HistoryLog::open(Box::new(flash.clone())).unwrap();
+
+        history
+            .record("basil", &event(IrrigationStatus::NotWatered))
+            .unwrap();
+        history
+            .record("basil", &event(IrrigationStatus::NotWatered))
+            .unwrap();
+        history
+            .record("mint", &event(IrrigationStatus::NotWatered))
+            .unwrap();
+        history
+            .record("basil", &event(IrrigationStatus::Watered))
+            .unwrap();
+        history
+            .record("basil", &event(IrrigationStatus::Watered))
+            .unwrap();
+
+        let history = HistoryLog::open(Box::new(flash)).unwrap();
+        let entries: Vec<_> = history
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.plant, entry.decision))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("basil".to_owned(), IrrigationStatus::NotWatered),
+                ("mint".to_owned(), IrrigationStatus::NotWatered),
+                ("basil".to_owned(), IrrigationStatus::Watered),
+                ("basil".to_owned(), IrrigationStatus::Watered),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_events_are_sampled() {
+        let mut history = HistoryLog::open(Box::new(RamFlash::new(256, 4))).unwrap();
+        let mut not_watered = event(IrrigationStatus::NotWatered);
+
+        for _ in 0..4 {
+            history.record("basil", &not_watered).unwrap();
+            not_watered.wall_clock = not_watered
+                .wall_clock
+                .map(|time| time + SAMPLE_INTERVAL / 2);
+        }
+
+        assert_eq!(history.entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn hourly_aggregates() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_699_999_200);
+        let entry = |minutes: u64, moisture: Option<u8>, decision: IrrigationStatus| HistoryEntry {
+            wall_clock: Some(start + Duration::from_secs(minutes * 60)),
+            moisture: moisture.map(Percentage::new),
+            ..HistoryEntry::new("basil", &event(decision))
+        };
+        let entries = [
+            entry(0, Some(35), IrrigationStatus::Watered),
+            entry(10, Some(50), IrrigationStatus::NotWatered),
+            entry(20, Some(46), IrrigationStatus::NotWatered),
+            HistoryEntry {
+                plant: "mint".to_owned(),
+                ..entry(30, Some(10), IrrigationStatus::Watered)
+            },
+            entry(
+                190,
+                None,
+                IrrigationStatus::SensorFault(SensorFaultKind::OpenCircuit),
+            ),
+        ];
+
+        assert_eq!(
+            hourly_moisture(&entries, "basil", start),
+            vec![
+                HourlyMoisture {
+                    hour: start,
+                    min: Some(Percentage::new(35)),
+                    max: Some(Percentage::new(50)),
+                    mean: Some(Percentage::new(44)),
+                    waterings: 1,
+                },
+                HourlyMoisture {
+                    hour: start + 3 * SAMPLE_INTERVAL,
+                    min: None,
+                    max: None,
+                    mean: None,
+                    waterings: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            hourly_moisture(&entries, "basil", start + SAMPLE_INTERVAL).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn hourly_moisture_is_kept_in_memory() {
+        let flash = RamFlash::new(256, 4);
+        let mut history = HistoryLog::open(Box::new(flash.clone())).unwrap();
+        let mut watered = event(IrrigationStatus::Watered);
+        let start = watered.wall_clock.unwrap();
+
+        history.record("basil", &watered).unwrap();
+        let hours = history.hourly_moisture("basil", start);
+        assert_eq!(hours.len(), 1);
+        assert_eq!(hours[0].waterings, 1);
+        assert_eq!(hours[0].mean, Some(Percentage::new(35)));
+        assert!(history.hourly_moisture("mint", start).is_empty());
+
+        // Aggregated again from the flash after a reboot
+        let mut history = HistoryLog::open(Box::new(flash)).unwrap();
+        assert_eq!(history.hourly_moisture("basil", start), hours);
+
+        // Hours older than the period are dropped
+        watered.wall_clock = Some(start + HOURLY_MOISTURE_PERIOD + SAMPLE_INTERVAL);
+        history.record("basil", &watered).unwrap();
+        let hours = history.hourly_moisture("basil", SystemTime::UNIX_EPOCH);
+        assert_eq!(hours.len(), 1);
+        assert!(hours[0].hour > start);
+    }
+
+    #[test]
+    fn hourly_moisture_ignores_older_entries_out_of_order() {
+        let mut history = HistoryLog::open(Box::new(RamFlash::new(256, 4))).unwrap();
+        let watered = HistoryEntry::new("basil", &event(IrrigationStatus::Watered));
+        let start = watered.wall_clock.unwrap();
+        let later = HistoryEntry {
+            wall_clock: Some(start + HOURLY_MOISTURE_PERIOD + SAMPLE_INTERVAL),
+            ..watered.clone()
+        };
+
+        // As when the watering is carried over to a newer flash page
+        history.append(&later).unwrap();
+        history.append(&watered).unwrap();
+
+        let hours = history.hourly_moisture("basil", SystemTime::UNIX_EPOCH);
+        assert_eq!(hours.len(), 1);
+        assert!(hours[0].hour > start);
+    }
+
+    #[test]
+    fn notable_entries_outlive_others() {
+        let mut history = HistoryLog::open(Box::new(RamFlash::new(256, 4))).unwrap();
+
+        let watered = HistoryEntry::new("basil", &event(IrrigationStatus::Watered));
+        history.append(&watered).unwrap();
+        let not_watered = HistoryEntry::new("basil", &event(IrrigationStatus::NotWatered));
+        for _ in 0..100 {
+            history.append(&not_watered).unwrap();
+        }
+
+        let entries = history.entries().unwrap();
+        assert_eq!(entries[0], watered);
+        assert!(entries[1..].iter().all(|entry| *entry == not_watered));
+    }
+}
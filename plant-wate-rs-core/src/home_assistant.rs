@@ -0,0 +1,392 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::json::JsonObject;
+use crate::mqtt::MqttMessage;
+use crate::telemetry::{Metric, Telemetry, TelemetryConfig, PAYLOAD_OFFLINE, PAYLOAD_ONLINE};
+
+const MANUFACTURER: &str = "plant-wate-rs";
+
+/// Where the plants are announced to Home Assistant, through
+/// [MQTT discovery](https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DiscoveryConfig {
+    /// As configured in Home Assistant.
+    pub discovery_prefix: String,
+    /// Identifies this controller; the unique IDs of the entities of its plants
+    /// are derived from it.
+    pub node_id: String,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            discovery_prefix: "homeassistant".to_owned(),
+            node_id: "plant-wate-rs".to_owned(),
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    pub fn validate(&self) -> Result<(), DiscoveryError> {
+        if self.discovery_prefix.is_empty() {
+            return Err(DiscoveryError::EmptyDiscoveryPrefix);
+        }
+        if self.node_id.is_empty() || !is_id(&self.node_id) {
+            return Err(DiscoveryError::InvalidNodeId(self.node_id.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DiscoveryError {
+    EmptyDiscoveryPrefix,
+    InvalidNodeId(String),
+    /// The names of two plants map to the same IDs once the characters not
+    /// allowed in IDs are replaced.
+    IdCollision {
+        plant: String,
+        other: String,
+    },
+}
+
+impl Display for DiscoveryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiscoveryError::EmptyDiscoveryPrefix => {
+                write!(f, "the discovery prefix must not be empty")
+            }
+            DiscoveryError::InvalidNodeId(node_id) => write!(
+                f,
+                "invalid node ID `{}`: only ASCII letters, digits, `_` and `-` are allowed",
+                node_id
+            ),
+            DiscoveryError::IdCollision { plant, other } => write!(
+                f,
+                "plants `{}` and `{}` would have the same Home Assistant IDs",
+                other, plant
+            ),
+        }
+    }
+}
+
+impl Error for DiscoveryError {}
+
+/// An entity created in Home Assistant for each plant.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Entity {
+    Moisture,
+    LastWatered,
+    /// On if the pump ran in the last cycle. Read-only: watering on demand
+    /// goes through the [commands](crate::commands).
+    Pump,
+    /// On when the sensor, the hardware or the reservoir needs attention.
+    Fault,
+}
+
+impl Entity {
+    pub const ALL: [Entity; 4] = [
+        Entity::Moisture,
+        Entity::LastWatered,
+        Entity::Pump,
+        Entity::Fault,
+    ];
+
+    /// The Home Assistant integration of the entity.
+    pub const fn component(&self) -> &'static str {
+        match self {
+            Entity::Moisture | Entity::LastWatered => "sensor",
+            Entity::Pump | Entity::Fault => "binary_sensor",
+        }
+    }
+
+    /// The telemetry metric holding the state of the entity.
+    pub const fn metric(&self) -> Metric {
+        match self {
+            Entity::Moisture => Metric::Moisture,
+            Entity::LastWatered => Metric::LastWatered,
+            Entity::Pump => Metric::Pump,
+            Entity::Fault => Metric::Fault,
+        }
+    }
+
+    const fn name(&self) -> &'static str {
+        match self {
+            Entity::Moisture => "Moisture",
+            Entity::LastWatered => "Last watered",
+            Entity::Pump => "Pump",
+            Entity::Fault => "Fault",
+        }
+    }
+}
+
+pub fn discovery_topic(config: &DiscoveryConfig, plant: &str, entity: Entity) -> String {
+    format!(
+        "{}/{}/{}/{}/config",
+        config.discovery_prefix,
+        entity.component(),
+        config.node_id,
+        object_id(plant, entity)
+    )
+}
+
+/// The JSON configuration of `entity` of the plant named `plant`.
+pub fn discovery_payload(
+    config: &DiscoveryConfig,
+    telemetry: &TelemetryConfig,
+    plant: &str,
+    entity: Entity,
+) -> String {
+    let device_id = format!("{}_{}", config.node_id, sanitize(plant));
+    let device = JsonObject::new()
+        .strings("identifiers", [device_id.as_str()])
+        .string("name", plant)
+        .string("manufacturer", MANUFACTURER)
+        .string("model", "Plant irrigator")
+        .string("sw_version", env!("CARGO_PKG_VERSION"))
+        .finish();
+
+    let mut payload = JsonObject::new()
+        .string("name", entity.name())
+        .string(
+            "unique_id",
+            &format!("{}_{}", config.node_id, object_id(plant, entity)),
+        )
+        .string("state_topic", &telemetry.topic(plant, entity.metric()))
+        .raw("device", &device);
+    if let Some(topic) = &telemetry.availability_topic {
+        payload = payload
+            .string("availability_topic", topic)
+            .string("payload_available", PAYLOAD_ONLINE)
+            .string("payload_not_available", PAYLOAD_OFFLINE);
+    }
+
+    match entity {
+        Entity::Moisture => payload
+            .string("device_class", "moisture")
+            .string("state_class", "measurement")
+            .string("unit_of_measurement", "%"),
+        Entity::LastWatered => payload.string("device_class", "timestamp"),
+        Entity::Pump => payload
+            .string("device_class", "running")
+            .string("icon", "mdi:water-pump"),
+        Entity::Fault => payload
+            .string("device_class", "problem")
+            .string("entity_category", "diagnostic"),
+    }
+    .finish()
+}
+
+/// The retained discovery messages of all the entities of the plants named
+/// `plants`.
+pub fn discovery_messages(
+    config: &DiscoveryConfig,
+    telemetry: &TelemetryConfig,
+    plants: &[&str],
+) -> Result<Vec<MqttMessage>, DiscoveryError> {
+    config.validate()?;
+    for (index, plant) in plants.iter().enumerate() {
+        if let Some(other) = plants[..index]
+            .iter()
+            .find(|other| sanitize(other) == sanitize(plant))
+        {
+            return Err(DiscoveryError::IdCollision {
+                plant: (*plant).to_owned(),
+                other: (*other).to_owned(),
+            });
+        }
+    }
+
+    Ok(plants
+        .iter()
+        .flat_map(|plant| {
+            Entity::ALL.into_iter().map(move |entity| {
+                MqttMessage::new(
+                    discovery_topic(config, plant, entity),
+                    discovery_payload(config, telemetry, plant, entity),
+                    true,
+                )
+            })
+        })
+        .collect())
+}
+
+/// Has `telemetry` publish the discovery messages of the plants named
+/// `plants` on every connection, so that they are back after the broker has
+/// lost them.
+pub fn announce(
+    telemetry: &mut Telemetry,
+    config: &DiscoveryConfig,
+    plants: &[&str],
+) -> Result<(), DiscoveryError> {
+    for message in discovery_messages(config, telemetry.config(), plants)? {
+        telemetry.publish_on_connect(message);
+    }
+    Ok(())
+}
+
+fn object_id(plant: &str, entity: Entity) -> String {
+    format!("{}_{}", sanitize(plant), entity.metric().name())
+}
+
+fn is_id(id: &str) -> bool {
+    id.chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Replaces the characters not allowed in IDs.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::mqtt::InProcessBroker;
+
+    const PLANTS: [&str; 2] = ["Basil", "Mint \"2\""];
+
+    fn telemetry_config() -> TelemetryConfig {
+        TelemetryConfig {
+            availability_topic: Some("plant-wate-rs/availability".to_owned()),
+            ..TelemetryConfig::default()
+        }
+    }
+
+    #[test]
+    fn moisture_sensor() {
+        let payload = discovery_payload(
+            &DiscoveryConfig::default(),
+            &telemetry_config(),
+            PLANTS[0],
+            Entity::Moisture,
+        );
+
+        assert_eq!(
+            payload,
+            concat!(
+                r#"{"name":"Moisture","unique_id":"plant-wate-rs_basil_moisture","#,
+                r#""state_topic":"plant-wate-rs/Basil/moisture","#,
+                r#""device":{"identifiers":["plant-wate-rs_basil"],"name":"Basil","#,
+                r#""manufacturer":"plant-wate-rs","model":"Plant irrigator","#,
+                r#""sw_version":""#,
+                env!("CARGO_PKG_VERSION"),
+                r#""},"#,
+                r#""availability_topic":"plant-wate-rs/availability","#,
+                r#""payload_available":"online","payload_not_available":"offline","#,
+                r#""device_class":"moisture","state_class":"measurement","#,
+                r#""unit_of_measurement":"%"}"#
+            )
+        );
+    }
+
+    #[test]
+    fn pump_is_read_only() {
+        let payload = discovery_payload(
+            &DiscoveryConfig::default(),
+            &TelemetryConfig::default(),
+            PLANTS[0],
+            Entity::Pump,
+        );
+
+        assert!(payload.contains(r#""state_topic":"plant-wate-rs/Basil/pump""#));
+        assert!(payload.contains(r#""device_class":"running""#));
+        assert!(!payload.contains("command_topic"));
+        assert!(!payload.contains("availability_topic"));
+    }
+
+    #[test]
+    fn messages_for_all_plants() {
+        let config = DiscoveryConfig {
+            discovery_prefix: "ha".to_owned(),
+            node_id: "greenhouse".to_owned(),
+        };
+        let messages = discovery_messages(&config, &telemetry_config(), &PLANTS).unwrap();
+
+        let topics: Vec<_> = messages
+            .iter()
+            .map(|message| message.topic.as_str())
+            .collect();
+        assert_eq!(
+            topics,
+            vec![
+                "ha/sensor/greenhouse/basil_moisture/config",
+                "ha/sensor/greenhouse/basil_last_watered/config",
+                "ha/binary_sensor/greenhouse/basil_pump/config",
+                "ha/binary_sensor/greenhouse/basil_fault/config",
+                "ha/sensor/greenhouse/mint__2__moisture/config",
+                "ha/sensor/greenhouse/mint__2__last_watered/config",
+                "ha/binary_sensor/greenhouse/mint__2__pump/config",
+                "ha/binary_sensor/greenhouse/mint__2__fault/config",
+            ]
+        );
+        assert!(messages.iter().all(|message| message.retain));
+        let mint = String::from_utf8(messages[7].payload.clone()).unwrap();
+        assert!(mint.contains(r#""name":"Mint \"2\"""#));
+        assert!(mint.contains(r#""device_class":"problem""#));
+    }
+
+    #[test]
+    fn invalid_node_id() {
+        let config = DiscoveryConfig {
+            node_id: "green house".to_owned(),
+            ..DiscoveryConfig::default()
+        };
+
+        assert_eq!(
+            config.validate(),
+            Err(DiscoveryError::InvalidNodeId("green house".to_owned()))
+        );
+    }
+
+    #[test]
+    fn colliding_ids() {
+        assert_eq!(
+            discovery_messages(
+                &DiscoveryConfig::default(),
+                &TelemetryConfig::default(),
+                &["Basil", "Lemon balm", "lemon_balm"]
+            ),
+            Err(DiscoveryError::IdCollision {
+                plant: "lemon_balm".to_owned(),
+                other: "Lemon balm".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn announce_plants() {
+        let broker = InProcessBroker::new();
+        let mut telemetry = Telemetry::new(Box::new(broker.client()), telemetry_config()).unwrap();
+
+        announce(&mut telemetry, &DiscoveryConfig::default(), &PLANTS).unwrap();
+        telemetry.flush(Instant::now());
+
+        assert_eq!(broker.published().len(), 1 + 2 * Entity::ALL.len());
+        assert!(broker
+            .retained("homeassistant/binary_sensor/plant-wate-rs/basil_pump/config")
+            .is_some());
+
+        // Announced again after reconnecting
+        broker.drop_connections();
+        telemetry.flush(Instant::now() + Duration::from_secs(1));
+        let announcements = broker
+            .published()
+            .iter()
+            .filter(|message| message.topic.ends_with("/config"))
+            .count();
+        assert_eq!(announcements, 2 * 2 * Entity::ALL.len());
+    }
+}
@@ -0,0 +1,400 @@
+use std::fmt::Debug;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use crate::json::JsonObject;
+
+/// Largest request body accepted.
+pub const MAX_BODY_LEN: usize = 16 * 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+impl Method {
+    pub fn parse(method: &str) -> Option<Self> {
+        match method {
+            "GET" => Some(Method::Get),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "PATCH" => Some(Method::Patch),
+            "DELETE" => Some(Method::Delete),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HttpRequest {
+    pub method: Method,
+    /// Without the query string, still percent-encoded.
+    pub path: String,
+    pub query: Option<String>,
+    /// As received; a server may pass on only those the handlers look at.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Splits the query string off `uri`.
+    pub fn new(method: Method, uri: &str, body: impl Into<Vec<u8>>) -> Self {
+        let (path, query) = match uri.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_owned())),
+            None => (uri, None),
+        };
+        Self {
+            method,
+            path: path.to_owned(),
+            query,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    /// Besides the content type and length.
+    pub headers: Vec<(&'static str, &'static str)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_header(mut self, name: &'static str, value: &'static str) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    pub fn json(status: u16, json: String) -> Self {
+        Self::new(status, "application/json", json)
+    }
+
+    /// A JSON object with the error `message`.
+    pub fn error(status: u16, message: &str) -> Self {
+        Self::json(status, JsonObject::new().string("error", message).finish())
+    }
+}
+
+pub const fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// HTTP server polled from the control loop, so that the requests are handled
+/// between the irrigation cycles.
+pub trait HttpServer: Debug {
+    /// Handles the requests received since the last call with `handler`.
+    /// Does not wait for new requests.
+    fn poll(&mut self, handler: &mut dyn FnMut(&HttpRequest) -> HttpResponse);
+}
+
+/// HTTP/1.1 server on a TCP socket, for running on a host. Connections are
+/// closed after every response.
+#[derive(Debug)]
+pub struct TcpHttpServer {
+    listener: TcpListener,
+}
+
+impl TcpHttpServer {
+    const MAX_HEADERS: usize = 32;
+    const MAX_LINE_LEN: usize = 4096;
+    /// How long a client has to send its whole request.
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn handle_connection(
+        stream: TcpStream,
+        handler: &mut dyn FnMut(&HttpRequest) -> HttpResponse,
+    ) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        let mut reader = BufReader::new(DeadlineReader {
+            stream: &stream,
+            deadline: Instant::now() + Self::REQUEST_TIMEOUT,
+        });
+
+        let response = match Self::read_request(&mut reader)? {
+            Ok(request) => {
+                debug!("{:?} {}", request.method, request.path);
+                handler(&request)
+            }
+            Err(response) => response,
+        };
+        Self::write_response(&stream, &response)
+    }
+
+    /// The request, or the response to send if it is not acceptable.
+    fn read_request(reader: &mut impl BufRead) -> io::Result<Result<HttpRequest, HttpResponse>> {
+        let Some(request_line) = Self::read_line(reader)? else {
+            return Ok(Err(HttpResponse::error(414, "request line too long")));
+        };
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(uri), Some(_version)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Ok(Err(HttpResponse::error(400, "malformed request line")));
+        };
+
+        let mut headers = Vec::new();
+        let mut content_length = 0;
+        for line in 0.. {
+            if line == Self::MAX_HEADERS {
+                return Ok(Err(HttpResponse::error(431, "too many headers")));
+            }
+            let Some(header) = Self::read_line(reader)? else {
+                return Ok(Err(HttpResponse::error(431, "header too long")));
+            };
+            if header.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    match value.trim().parse() {
+                        Ok(length) => content_length = length,
+                        Err(_) => {
+                            return Ok(Err(HttpResponse::error(400, "invalid content length")))
+                        }
+                    }
+                }
+                headers.push((name.to_owned(), value.trim().to_owned()));
+            }
+        }
+        if content_length > MAX_BODY_LEN {
+            return Ok(Err(HttpResponse::error(413, "request body too large")));
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        Ok(match Method::parse(method) {
+            Some(method) => Ok(HttpRequest {
+                headers,
+                ..HttpRequest::new(method, uri, body)
+            }),
+            None => Err(HttpResponse::error(501, "method not supported")),
+        })
+    }
+
+    /// A line of at most [`Self::MAX_LINE_LEN`] bytes, with its line ending;
+    /// `None` if it is longer.
+    fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        reader
+            .take(Self::MAX_LINE_LEN as u64)
+            .read_line(&mut line)?;
+        if line.len() == Self::MAX_LINE_LEN && !line.ends_with('\n') {
+            return Ok(None);
+        }
+        Ok(Some(line))
+    }
+
+    fn write_response(mut stream: &TcpStream, response: &HttpResponse) -> io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+            response.status,
+            reason_phrase(response.status),
+            response.content_type,
+            response.body.len()
+        )?;
+        for (name, value) in &response.headers {
+            write!(stream, "{}: {}\r\n", name, value)?;
+        }
+        write!(stream, "Connection: close\r\n\r\n")?;
+        stream.write_all(&response.body)?;
+        stream.flush()
+    }
+}
+
+/// Fails the reads once `deadline` has passed, so that a slow client cannot
+/// hold up the control loop.
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buffer)
+    }
+}
+
+impl HttpServer for TcpHttpServer {
+    fn poll(&mut self, handler: &mut dyn FnMut(&HttpRequest) -> HttpResponse) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    if let Err(error) = Self::handle_connection(stream, handler) {
+                        warn!("HTTP connection from {} failed: {}", peer, error);
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                Err(error) => {
+                    warn!("Could not accept an HTTP connection: {}", error);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread;
+
+    use super::*;
+
+    /// Sends `request` to `server` and returns the whole response.
+    fn exchange(
+        server: &mut TcpHttpServer,
+        request: &str,
+        handler: &mut dyn FnMut(&HttpRequest) -> HttpResponse,
+    ) -> String {
+        let address = server.local_addr().unwrap();
+        let request = request.to_owned();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        while !client.is_finished() {
+            server.poll(handler);
+            thread::sleep(Duration::from_millis(1));
+        }
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn request_and_response() {
+        let mut server = TcpHttpServer::bind("127.0.0.1:0").unwrap();
+        let mut received = Vec::new();
+
+        let response = exchange(
+            &mut server,
+            "POST /api/plants/basil/water?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\n{}{}",
+            &mut |request| {
+                received.push(request.clone());
+                HttpResponse::json(200, "{}".to_owned()).with_header("Cache-Control", "no-cache")
+            },
+        );
+
+        assert_eq!(
+            received,
+            vec![HttpRequest {
+                method: Method::Post,
+                path: "/api/plants/basil/water".to_owned(),
+                query: Some("x=1".to_owned()),
+                headers: vec![
+                    ("Host".to_owned(), "localhost".to_owned()),
+                    ("Content-Length".to_owned(), "4".to_owned()),
+                ],
+                body: b"{}{}".to_vec(),
+            }]
+        );
+        assert_eq!(
+            response,
+            concat!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n",
+                "Cache-Control: no-cache\r\nConnection: close\r\n\r\n{}"
+            )
+        );
+    }
+
+    #[test]
+    fn headers() {
+        let request = HttpRequest::new(Method::Get, "/", "").with_header("Accept-Encoding", "gzip");
+
+        assert_eq!(request.header("accept-encoding"), Some("gzip"));
+        assert_eq!(request.header("Accept"), None);
+    }
+
+    #[test]
+    fn rejected_requests() {
+        let mut server = TcpHttpServer::bind("127.0.0.1:0").unwrap();
+        let mut handler = |_: &HttpRequest| -> HttpResponse { unreachable!() };
+
+        let response = exchange(&mut server, "BREW /pot HTTP/1.1\r\n\r\n", &mut handler);
+        assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+
+        let response = exchange(
+            &mut server,
+            "POST / HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n",
+            &mut handler,
+        );
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(5000));
+        let response = exchange(&mut server, &request, &mut handler);
+        assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+
+        let request = format!("GET / HTTP/1.1\r\n{}\r\n", "X-Header: 1\r\n".repeat(40));
+        let response = exchange(&mut server, &request, &mut handler);
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+}
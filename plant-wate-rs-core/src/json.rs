@@ -0,0 +1,460 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Write};
+
+/// Writes `value` as a JSON string literal.
+pub fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Joins already serialized values into a JSON array.
+pub fn array(values: impl IntoIterator<Item = String>) -> String {
+    let mut json = String::from("[");
+    for (index, value) in values.into_iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        json.push_str(&value);
+    }
+    json.push(']');
+    json
+}
+
+/// Builds a JSON object, member by member.
+#[derive(Debug, Clone)]
+pub struct JsonObject {
+    json: String,
+}
+
+impl JsonObject {
+    pub fn new() -> Self {
+        Self {
+            json: String::from("{"),
+        }
+    }
+
+    #[must_use]
+    pub fn string(mut self, key: &str, value: &str) -> Self {
+        self.key(key);
+        write_string(&mut self.json, value);
+        self
+    }
+
+    /// A number, or any other value whose [`Display`] output is valid JSON.
+    #[must_use]
+    pub fn number(mut self, key: &str, value: impl Display) -> Self {
+        self.key(key);
+        write!(self.json, "{}", value).unwrap();
+        self
+    }
+
+    /// A number, or `null` if there is no value.
+    #[must_use]
+    pub fn number_or_null(self, key: &str, value: Option<impl Display>) -> Self {
+        match value {
+            Some(value) => self.number(key, value),
+            None => self.null(key),
+        }
+    }
+
+    #[must_use]
+    pub fn bool(self, key: &str, value: bool) -> Self {
+        self.number(key, value)
+    }
+
+    #[must_use]
+    pub fn null(self, key: &str) -> Self {
+        self.number(key, "null")
+    }
+
+    #[must_use]
+    pub fn strings<'a>(mut self, key: &str, values: impl IntoIterator<Item = &'a str>) -> Self {
+        self.key(key);
+        self.json.push('[');
+        for (index, value) in values.into_iter().enumerate() {
+            if index > 0 {
+                self.json.push(',');
+            }
+            write_string(&mut self.json, value);
+        }
+        self.json.push(']');
+        self
+    }
+
+    /// A member whose value is already serialized, e.g. a nested object.
+    #[must_use]
+    pub fn raw(self, key: &str, json: &str) -> Self {
+        self.number(key, json)
+    }
+
+    pub fn finish(mut self) -> String {
+        self.json.push('}');
+        self.json
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.json.len() > 1 {
+            self.json.push(',');
+        }
+        write_string(&mut self.json, key);
+        self.json.push(':');
+    }
+}
+
+impl Default for JsonObject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl JsonValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The value if it is a non-negative integer that fits in a `u64`
+    /// without losing precision.
+    pub fn as_u64(&self) -> Option<u64> {
+        const MAX_EXACT: f64 = (1u64 << f64::MANTISSA_DIGITS) as f64;
+        match self {
+            JsonValue::Number(value)
+                if value.fract() == 0.0 && (0.0..=MAX_EXACT).contains(value) =>
+            {
+                Some(*value as u64)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum JsonError {
+    UnexpectedEnd,
+    /// At the given byte offset.
+    UnexpectedCharacter(usize),
+    InvalidNumber(usize),
+    InvalidEscape(usize),
+    DuplicateKey(usize),
+    /// Nested objects and arrays are not supported.
+    Unsupported(usize),
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::UnexpectedEnd => write!(f, "unexpected end of JSON"),
+            JsonError::UnexpectedCharacter(offset) => {
+                write!(f, "unexpected character at offset {}", offset)
+            }
+            JsonError::InvalidNumber(offset) => write!(f, "invalid number at offset {}", offset),
+            JsonError::InvalidEscape(offset) => {
+                write!(f, "invalid escape sequence at offset {}", offset)
+            }
+            JsonError::DuplicateKey(offset) => write!(f, "duplicate key at offset {}", offset),
+            JsonError::Unsupported(offset) => {
+                write!(f, "nested value at offset {} not supported", offset)
+            }
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+/// Parses a JSON object whose members are all strings, numbers, booleans or
+/// `null`.
+pub fn parse_flat_object(input: &str) -> Result<BTreeMap<String, JsonValue>, JsonError> {
+    let mut parser = Parser { input, offset: 0 };
+    let object = parser.object()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(object),
+        Some(_) => Err(JsonError::UnexpectedCharacter(parser.offset)),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.offset).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.offset += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(byte) if byte == expected => {
+                self.offset += 1;
+                Ok(())
+            }
+            Some(_) => Err(JsonError::UnexpectedCharacter(self.offset)),
+            None => Err(JsonError::UnexpectedEnd),
+        }
+    }
+
+    fn object(&mut self) -> Result<BTreeMap<String, JsonValue>, JsonError> {
+        let mut object = BTreeMap::new();
+        self.expect(b'{')?;
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.offset += 1;
+            return Ok(object);
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key_offset = self.offset;
+            self.expect(b'"')?;
+            let key = self.string()?;
+            self.expect(b':')?;
+            let value = self.value()?;
+            if object.insert(key, value).is_some() {
+                return Err(JsonError::DuplicateKey(key_offset));
+            }
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b'}') => {
+                    self.offset += 1;
+                    return Ok(object);
+                }
+                Some(_) => return Err(JsonError::UnexpectedCharacter(self.offset)),
+                None => return Err(JsonError::UnexpectedEnd),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, JsonError> {
+        self.skip_whitespace();
+        let rest = &self.input[self.offset..];
+        for (literal, value) in [
+            ("null", JsonValue::Null),
+            ("true", JsonValue::Bool(true)),
+            ("false", JsonValue::Bool(false)),
+        ] {
+            if rest.starts_with(literal) {
+                self.offset += literal.len();
+                return Ok(value);
+            }
+        }
+
+        match self.peek() {
+            Some(b'"') => {
+                self.offset += 1;
+                self.string().map(JsonValue::String)
+            }
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'{' | b'[') => Err(JsonError::Unsupported(self.offset)),
+            Some(_) => Err(JsonError::UnexpectedCharacter(self.offset)),
+            None => Err(JsonError::UnexpectedEnd),
+        }
+    }
+
+    fn number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.offset;
+        while matches!(
+            self.peek(),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.offset += 1;
+        }
+        self.input[start..self.offset]
+            .parse()
+            .map(JsonValue::Number)
+            .map_err(|_| JsonError::InvalidNumber(start))
+    }
+
+    /// Parses the rest of a string, after the opening quote.
+    fn string(&mut self) -> Result<String, JsonError> {
+        let mut value = String::new();
+        loop {
+            let rest = &self.input[self.offset..];
+            let Some(special) = rest.find(['"', '\\']) else {
+                return Err(JsonError::UnexpectedEnd);
+            };
+            value.push_str(&rest[..special]);
+            self.offset += special;
+
+            if self.peek() == Some(b'"') {
+                self.offset += 1;
+                return Ok(value);
+            }
+            value.push(self.escape()?);
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, JsonError> {
+        let start = self.offset;
+        let escaped = match self.input.as_bytes().get(start + 1) {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.offset += 2;
+                let high = self.hex_code_unit(start)?;
+                let code_point = if (0xD800..0xDC00).contains(&high) {
+                    if !self.input[self.offset..].starts_with("\\u") {
+                        return Err(JsonError::InvalidEscape(start));
+                    }
+                    self.offset += 2;
+                    let low = self.hex_code_unit(start)?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(JsonError::InvalidEscape(start));
+                    }
+                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    high
+                };
+                return char::from_u32(code_point).ok_or(JsonError::InvalidEscape(start));
+            }
+            Some(_) => return Err(JsonError::InvalidEscape(start)),
+            None => return Err(JsonError::UnexpectedEnd),
+        };
+        self.offset += 2;
+        Ok(escaped)
+    }
+
+    fn hex_code_unit(&mut self, escape_start: usize) -> Result<u32, JsonError> {
+        let digits = self
+            .input
+            .get(self.offset..self.offset + 4)
+            .ok_or(JsonError::UnexpectedEnd)?;
+        let code_unit = u32::from_str_radix(digits, 16)
+            .ok()
+            .filter(|_| digits.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .ok_or(JsonError::InvalidEscape(escape_start))?;
+        self.offset += 4;
+        Ok(code_unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object() {
+        let inner = JsonObject::new().number("n", 1.5).finish();
+        let json = JsonObject::new()
+            .string("name", "say \"hi\"\\\n\u{1}")
+            .bool("ok", true)
+            .null("none")
+            .strings("list", ["a", "b"])
+            .raw("inner", &inner)
+            .finish();
+
+        assert_eq!(
+            json,
+            r#"{"name":"say \"hi\"\\\n\u0001","ok":true,"none":null,"list":["a","b"],"inner":{"n":1.5}}"#
+        );
+        assert_eq!(JsonObject::new().finish(), "{}");
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(
+            array([inner_object(1), inner_object(2)]),
+            r#"[{"n":1},{"n":2}]"#
+        );
+        assert_eq!(array([]), "[]");
+    }
+
+    fn inner_object(n: u8) -> String {
+        JsonObject::new().number("n", n).finish()
+    }
+
+    #[test]
+    fn parse() {
+        let object = parse_flat_object(
+            r#" { "id": 17, "action" : "water", "ok": true, "none": null,
+                "text": "a\"\\\/\né🌱", "x": -1.5e2 } "#,
+        )
+        .unwrap();
+
+        assert_eq!(object["id"].as_u64(), Some(17));
+        assert_eq!(object["action"].as_str(), Some("water"));
+        assert_eq!(object["ok"].as_bool(), Some(true));
+        assert_eq!(object["none"], JsonValue::Null);
+        assert_eq!(object["text"].as_str(), Some("a\"\\/\né🌱"));
+        assert_eq!(object["x"], JsonValue::Number(-150.0));
+        assert_eq!(object["x"].as_u64(), None);
+        assert_eq!(parse_flat_object("{}"), Ok(BTreeMap::new()));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_flat_object(""), Err(JsonError::UnexpectedEnd));
+        assert_eq!(
+            parse_flat_object(r#"{"a": 1"#),
+            Err(JsonError::UnexpectedEnd)
+        );
+        assert_eq!(
+            parse_flat_object(r#"{"a": 1} x"#),
+            Err(JsonError::UnexpectedCharacter(9))
+        );
+        assert_eq!(
+            parse_flat_object(r#"{"a": 1, "a": 2}"#),
+            Err(JsonError::DuplicateKey(9))
+        );
+        assert_eq!(
+            parse_flat_object(r#"{"a": [1]}"#),
+            Err(JsonError::Unsupported(6))
+        );
+        assert_eq!(
+            parse_flat_object(r#"{"a": 1.2.3}"#),
+            Err(JsonError::InvalidNumber(6))
+        );
+        assert_eq!(
+            parse_flat_object(r#"{"a": "\x"}"#),
+            Err(JsonError::InvalidEscape(7))
+        );
+        assert_eq!(
+            parse_flat_object(r#"{"a": "\ud83c"}"#),
+            Err(JsonError::InvalidEscape(7))
+        );
+    }
+}
@@ -1,6 +1,34 @@
+pub mod calibration;
+pub mod calibration_curve;
+pub mod commands;
 pub mod controller;
-mod mock_uc;
-mod plant_irrigator;
-mod plant_irrigator_controller;
+pub mod controller_state;
+pub mod cron;
+pub mod dashboard;
+pub mod event_history;
+pub mod flash;
+pub mod flash_log;
+pub mod history_log;
+pub mod home_assistant;
+pub mod http;
+pub mod json;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock_uc;
+pub mod moisture_smoothing;
+pub mod mqtt;
+pub mod plant_config;
+pub mod plant_irrigator;
+pub mod plant_irrigator_controller;
+pub mod pump_safety;
+pub mod reservoir_monitor;
+pub mod rest_api;
+pub mod scheduler;
+pub mod sensor_fault;
+pub mod soil;
+pub mod storage;
+pub mod telemetry;
+pub mod temperature;
+pub mod time_window;
 pub mod uc;
-mod uc_utils;
+pub mod uc_utils;
+pub mod watering_strategy;
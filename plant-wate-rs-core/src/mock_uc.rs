@@ -1,32 +1,51 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ops::Deref;
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::uc::{AnalogInput, AnalogValue, DigitalOutput, GpioId, Microcontroller};
+use crate::uc::{AnalogInput, AnalogValue, DigitalOutput, GpioId, Microcontroller, UcError};
 
 #[derive(Debug)]
 pub struct MockDigitalOutput {
     id: GpioId,
+    failures: Rc<Cell<u32>>,
     action_log: ActionLog,
 }
 
 impl DigitalOutput for MockDigitalOutput {
-    fn set_high(&mut self) {
+    fn set_high(&mut self) -> Result<(), UcError> {
+        if take_failure(&self.failures) {
+            self.action_log
+                .add(MockMicrocontrollerAction::DigitalGpioHighFailed(self.id));
+            return Err(UcError::DigitalWriteFailed(self.id));
+        }
+
         self.action_log
             .add(MockMicrocontrollerAction::DigitalGpioHigh(self.id));
+        Ok(())
     }
 
-    fn set_low(&mut self) {
+    fn set_low(&mut self) -> Result<(), UcError> {
+        if take_failure(&self.failures) {
+            self.action_log
+                .add(MockMicrocontrollerAction::DigitalGpioLowFailed(self.id));
+            return Err(UcError::DigitalWriteFailed(self.id));
+        }
+
         self.action_log
             .add(MockMicrocontrollerAction::DigitalGpioLow(self.id));
+        Ok(())
     }
 }
 
 impl MockDigitalOutput {
-    fn new(id: GpioId, action_log: ActionLog) -> Self {
-        Self { id, action_log }
+    fn new(id: GpioId, failures: Rc<Cell<u32>>, action_log: ActionLog) -> Self {
+        Self {
+            id,
+            failures,
+            action_log,
+        }
     }
 }
 
@@ -34,40 +53,123 @@ impl MockDigitalOutput {
 pub struct MockAnalogInput {
     id: GpioId,
     value: Rc<RefCell<AnalogValue>>,
+    queued_values: Rc<RefCell<VecDeque<AnalogValue>>>,
+    failures: Rc<Cell<u32>>,
     action_log: ActionLog,
 }
 
 impl AnalogInput for MockAnalogInput {
-    fn get_value(&mut self) -> AnalogValue {
-        let value = *(*self.value).borrow();
+    fn get_value(&mut self) -> Result<AnalogValue, UcError> {
+        if take_failure(&self.failures) {
+            self.action_log
+                .add(MockMicrocontrollerAction::AnalogGpioGetValueFailed(self.id));
+            return Err(UcError::AnalogReadFailed(self.id));
+        }
+
+        let queued_value = self.queued_values.borrow_mut().pop_front();
+        let value = queued_value.unwrap_or_else(|| *(*self.value).borrow());
         self.action_log
             .add(MockMicrocontrollerAction::AnalogGpioGetValue(
                 self.id, value,
             ));
-        value
+        Ok(value)
     }
 }
 
 impl MockAnalogInput {
-    fn new(id: GpioId, value: Rc<RefCell<AnalogValue>>, action_log: ActionLog) -> Self {
+    fn new(
+        id: GpioId,
+        value: Rc<RefCell<AnalogValue>>,
+        queued_values: Rc<RefCell<VecDeque<AnalogValue>>>,
+        failures: Rc<Cell<u32>>,
+        action_log: ActionLog,
+    ) -> Self {
         Self {
             id,
             value,
+            queued_values,
+            failures,
             action_log,
         }
     }
 }
 
+/// Consumes one of the injected failures, if there are any left.
+fn take_failure(failures: &Cell<u32>) -> bool {
+    let remaining = failures.get();
+    if remaining > 0 {
+        failures.set(remaining - 1);
+        true
+    } else {
+        false
+    }
+}
+
 #[derive(Debug)]
 enum MockGpio {
-    AnalogInput(Rc<RefCell<AnalogValue>>),
-    DigitalOutput,
+    AnalogInput {
+        value: Rc<RefCell<AnalogValue>>,
+        queued_values: Rc<RefCell<VecDeque<AnalogValue>>>,
+        failures: Rc<Cell<u32>>,
+    },
+    DigitalOutput {
+        failures: Rc<Cell<u32>>,
+    },
+}
+
+impl MockGpio {
+    fn failures(&self) -> &Rc<Cell<u32>> {
+        match self {
+            MockGpio::AnalogInput { failures, .. } => failures,
+            MockGpio::DigitalOutput { failures } => failures,
+        }
+    }
+}
+
+/// Time that only moves forward when the microcontroller waits (or the clock is
+/// advanced explicitly).
+#[derive(Debug)]
+struct VirtualClock {
+    start: Instant,
+    elapsed: Cell<Duration>,
+    /// Wall clock time at `start`, if set.
+    wall_clock_start: Cell<Option<SystemTime>>,
+}
+
+impl VirtualClock {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Cell::new(Duration::ZERO),
+            wall_clock_start: Cell::new(None),
+        }
+    }
+
+    fn advance(&self, duration: Duration) {
+        self.elapsed.set(self.elapsed.get() + duration);
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+
+    fn wall_clock(&self) -> Option<SystemTime> {
+        self.wall_clock_start
+            .get()
+            .map(|start| start + self.elapsed.get())
+    }
+
+    fn set_wall_clock(&self, time: SystemTime) {
+        self.wall_clock_start.set(Some(time - self.elapsed.get()));
+    }
 }
 
 #[derive(Debug)]
 pub struct MockMicrocontroller {
     action_log: ActionLog,
+    clock: VirtualClock,
     gpio: HashMap<GpioId, MockGpio>,
+    unsupported_gpio: HashSet<GpioId>,
 }
 
 impl Microcontroller for MockMicrocontroller {
@@ -77,25 +179,65 @@ impl Microcontroller for MockMicrocontroller {
     fn wait(&self, duration: Duration) {
         self.action_log
             .add(MockMicrocontrollerAction::Wait(duration));
+        self.clock.advance(duration);
     }
 
-    fn get_analog_input(&mut self, id: GpioId) -> Self::AnalogInput {
-        self.check_gpio_none(id);
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    fn wall_clock(&self) -> Option<SystemTime> {
+        self.clock.wall_clock()
+    }
+
+    fn get_analog_input(&mut self, id: GpioId) -> Result<Self::AnalogInput, UcError> {
+        self.check_gpio_available(id)?;
         let value = Rc::new(RefCell::new(AnalogValue::new(0)));
-        self.gpio.insert(id, MockGpio::AnalogInput(value.clone()));
+        let queued_values = Rc::new(RefCell::new(VecDeque::new()));
+        let failures = Rc::new(Cell::new(0));
+        self.gpio.insert(
+            id,
+            MockGpio::AnalogInput {
+                value: value.clone(),
+                queued_values: queued_values.clone(),
+                failures: failures.clone(),
+            },
+        );
         self.action_log
             .add(MockMicrocontrollerAction::GpioSetAsAnalogInput(id));
 
-        MockAnalogInput::new(id, value, self.action_log.clone())
+        Ok(MockAnalogInput::new(
+            id,
+            value,
+            queued_values,
+            failures,
+            self.action_log.clone(),
+        ))
     }
 
-    fn get_digital_output(&mut self, id: GpioId) -> Self::DigitalOutput {
-        self.check_gpio_none(id);
-        self.gpio.insert(id, MockGpio::DigitalOutput);
+    fn get_digital_output(&mut self, id: GpioId) -> Result<Self::DigitalOutput, UcError> {
+        self.check_gpio_available(id)?;
+        let failures = Rc::new(Cell::new(0));
+        self.gpio.insert(
+            id,
+            MockGpio::DigitalOutput {
+                failures: failures.clone(),
+            },
+        );
         self.action_log
             .add(MockMicrocontrollerAction::GpioSetAsDigitalOutput(id));
 
-        MockDigitalOutput::new(id, self.action_log.clone())
+        Ok(MockDigitalOutput::new(
+            id,
+            failures,
+            self.action_log.clone(),
+        ))
+    }
+}
+
+impl Default for MockMicrocontroller {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -103,25 +245,65 @@ impl MockMicrocontroller {
     pub fn new() -> Self {
         Self {
             action_log: ActionLog::new(),
+            clock: VirtualClock::new(),
             gpio: Default::default(),
+            unsupported_gpio: Default::default(),
         }
     }
 
-    fn check_gpio_none(&self, id: GpioId) {
-        if self.gpio.contains_key(&id) {
-            panic!("{} already enabled!", id);
+    fn check_gpio_available(&self, id: GpioId) -> Result<(), UcError> {
+        if self.unsupported_gpio.contains(&id) {
+            Err(UcError::GpioNotSupported(id))
+        } else if self.gpio.contains_key(&id) {
+            Err(UcError::GpioInUse(id))
+        } else {
+            Ok(())
         }
     }
 
     pub fn set_analog_value(&self, id: GpioId, value: AnalogValue) {
         let gpio = &self.gpio[&id];
-        if let MockGpio::AnalogInput(val) = gpio {
+        if let MockGpio::AnalogInput { value: val, .. } = gpio {
             *val.deref().borrow_mut() = value;
         } else {
             panic!("{} is not analog input!", id);
         }
     }
 
+    /// Moves the virtual clock forward without recording a wait.
+    pub fn advance_clock(&self, duration: Duration) {
+        self.clock.advance(duration);
+    }
+
+    /// Sets the wall clock; it advances along with the virtual clock from now
+    /// on.
+    pub fn set_wall_clock(&self, time: SystemTime) {
+        self.clock.set_wall_clock(time);
+    }
+
+    /// Makes the next reads of the analog input return `values`, one per read,
+    /// before falling back to the value set with
+    /// [`MockMicrocontroller::set_analog_value`].
+    pub fn queue_analog_values(&self, id: GpioId, values: impl IntoIterator<Item = AnalogValue>) {
+        let gpio = &self.gpio[&id];
+        if let MockGpio::AnalogInput { queued_values, .. } = gpio {
+            queued_values.borrow_mut().extend(values);
+        } else {
+            panic!("{} is not analog input!", id);
+        }
+    }
+
+    /// Makes the next `count` reads (for analog inputs) or writes (for digital
+    /// outputs) of the GPIO fail.
+    pub fn inject_failures(&self, id: GpioId, count: u32) {
+        self.gpio[&id].failures().set(count);
+    }
+
+    /// Makes the GPIO unavailable for both analog input and digital output.
+    pub fn set_gpio_unsupported(&mut self, id: GpioId) {
+        self.unsupported_gpio.insert(id);
+    }
+
     pub fn actions(&self) -> Vec<MockMicrocontrollerAction> {
         self.action_log.actions()
     }
@@ -154,6 +336,34 @@ pub enum MockMicrocontrollerAction {
     GpioSetAsDigitalOutput(GpioId),
     GpioSetAsAnalogInput(GpioId),
     DigitalGpioHigh(GpioId),
+    DigitalGpioHighFailed(GpioId),
     DigitalGpioLow(GpioId),
+    DigitalGpioLowFailed(GpioId),
     AnalogGpioGetValue(GpioId, AnalogValue),
+    AnalogGpioGetValueFailed(GpioId),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::mock_uc::MockMicrocontroller;
+    use crate::uc::Microcontroller;
+
+    #[test]
+    fn virtual_clock() {
+        let mock_uc = MockMicrocontroller::new();
+        let start = mock_uc.now();
+        assert_eq!(mock_uc.wall_clock(), None);
+
+        mock_uc.wait(Duration::from_secs(5));
+        mock_uc.advance_clock(Duration::from_secs(10));
+        assert_eq!(mock_uc.now() - start, Duration::from_secs(15));
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        mock_uc.set_wall_clock(time);
+        assert_eq!(mock_uc.wall_clock(), Some(time));
+        mock_uc.wait(Duration::from_secs(60));
+        assert_eq!(mock_uc.wall_clock(), Some(time + Duration::from_secs(60)));
+    }
 }
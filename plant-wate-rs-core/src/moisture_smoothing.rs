@@ -0,0 +1,170 @@
+use crate::plant_irrigator::Percentage;
+
+/// Smoothing applied to the moisture percentage across the cycles.
+///
+/// The smoothed value lags behind the actual moisture, so a heavily smoothed
+/// plant reacts slower both to drying out and to being watered.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum MoistureSmoothingConfig {
+    #[default]
+    None,
+    /// Exponential moving average; `alpha` is the weight of the newest
+    /// reading, in `(0, 1]`.
+    Ema { alpha: f32 },
+    /// One-dimensional Kalman filter assuming the moisture stays constant
+    /// between the readings.
+    Kalman {
+        /// Variance of the moisture change between two readings, in
+        /// percentage points squared.
+        process_noise: f32,
+        /// Variance of the sensor noise, in percentage points squared.
+        measurement_noise: f32,
+    },
+}
+
+impl MoistureSmoothingConfig {
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            MoistureSmoothingConfig::None => true,
+            MoistureSmoothingConfig::Ema { alpha } => alpha > 0.0 && alpha <= 1.0,
+            MoistureSmoothingConfig::Kalman {
+                process_noise,
+                measurement_noise,
+            } => {
+                process_noise.is_finite()
+                    && process_noise >= 0.0
+                    && measurement_noise.is_finite()
+                    && measurement_noise > 0.0
+            }
+        }
+    }
+}
+
+/// A single moisture reading, before and after smoothing.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MoistureReading {
+    pub raw: Percentage,
+    pub filtered: Percentage,
+}
+
+/// Keeps the smoothing state of a single plant between the cycles.
+#[derive(Debug, Clone)]
+pub struct MoistureSmoother {
+    config: MoistureSmoothingConfig,
+    estimate: Option<f32>,
+    /// Variance of the estimate; only used by the Kalman filter.
+    estimate_variance: f32,
+}
+
+impl MoistureSmoother {
+    #[inline]
+    pub fn new(config: MoistureSmoothingConfig) -> Self {
+        debug_assert!(config.is_valid());
+
+        Self {
+            config,
+            estimate: None,
+            estimate_variance: 0.0,
+        }
+    }
+
+    #[inline]
+    pub const fn config(&self) -> &MoistureSmoothingConfig {
+        &self.config
+    }
+
+    /// Feeds a new reading to the filter and returns the smoothed value.
+    pub fn update(&mut self, raw: Percentage) -> MoistureReading {
+        let measurement = raw.value() as f32;
+        let estimate = match (self.config, self.estimate) {
+            (MoistureSmoothingConfig::None, _) | (_, None) => {
+                if let MoistureSmoothingConfig::Kalman {
+                    measurement_noise, ..
+                } = self.config
+                {
+                    self.estimate_variance = measurement_noise;
+                }
+                measurement
+            }
+            (MoistureSmoothingConfig::Ema { alpha }, Some(estimate)) => {
+                alpha * measurement + (1.0 - alpha) * estimate
+            }
+            (
+                MoistureSmoothingConfig::Kalman {
+                    process_noise,
+                    measurement_noise,
+                },
+                Some(estimate),
+            ) => {
+                let predicted_variance = self.estimate_variance + process_noise;
+                let gain = predicted_variance / (predicted_variance + measurement_noise);
+                self.estimate_variance = (1.0 - gain) * predicted_variance;
+                estimate + gain * (measurement - estimate)
+            }
+        };
+        self.estimate = Some(estimate);
+
+        MoistureReading {
+            raw,
+            filtered: Percentage::new(estimate.round().clamp(0.0, 100.0) as u8),
+        }
+    }
+
+    /// Forgets the past readings.
+    pub fn reset(&mut self) {
+        self.estimate = None;
+        self.estimate_variance = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(smoother: &mut MoistureSmoother, raw: u8) -> u8 {
+        smoother.update(Percentage::new(raw)).filtered.value()
+    }
+
+    #[test]
+    fn no_smoothing() {
+        let mut smoother = MoistureSmoother::new(MoistureSmoothingConfig::None);
+
+        assert_eq!(update(&mut smoother, 40), 40);
+        assert_eq!(update(&mut smoother, 60), 60);
+    }
+
+    #[test]
+    fn ema() {
+        let mut smoother = MoistureSmoother::new(MoistureSmoothingConfig::Ema { alpha: 0.25 });
+
+        assert_eq!(update(&mut smoother, 40), 40);
+        assert_eq!(update(&mut smoother, 80), 50);
+        assert_eq!(update(&mut smoother, 50), 50);
+        assert_eq!(
+            smoother.update(Percentage::new(30)),
+            MoistureReading {
+                raw: Percentage::new(30),
+                filtered: Percentage::new(45),
+            }
+        );
+
+        smoother.reset();
+        assert_eq!(update(&mut smoother, 30), 30);
+    }
+
+    #[test]
+    fn kalman_converges() {
+        let mut smoother = MoistureSmoother::new(MoistureSmoothingConfig::Kalman {
+            process_noise: 0.1,
+            measurement_noise: 4.0,
+        });
+
+        assert_eq!(update(&mut smoother, 40), 40);
+        assert_eq!(update(&mut smoother, 60), 50);
+        for raw in [41, 39, 40, 41, 39, 40, 40, 41, 39, 40] {
+            update(&mut smoother, raw);
+        }
+        // Once the estimate has settled, an outlier barely moves it
+        assert!(update(&mut smoother, 60) <= 45);
+    }
+}
@@ -0,0 +1,360 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// Whether the broker keeps the message for clients subscribing later.
+    pub retain: bool,
+}
+
+impl MqttMessage {
+    pub fn new(topic: impl Into<String>, payload: impl Into<Vec<u8>>, retain: bool) -> Self {
+        Self {
+            topic: topic.into(),
+            payload: payload.into(),
+            retain,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TransportError {
+    NotConnected,
+    ConnectionFailed(String),
+    PublishFailed(String),
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportError::NotConnected => write!(f, "not connected to the MQTT broker"),
+            TransportError::ConnectionFailed(reason) => {
+                write!(f, "could not connect to the MQTT broker: {}", reason)
+            }
+            TransportError::PublishFailed(reason) => write!(f, "could not publish: {}", reason),
+        }
+    }
+}
+
+impl Error for TransportError {}
+
+/// Connection to an MQTT broker.
+pub trait MqttTransport: Debug {
+    /// Connects to the broker, if not connected already.
+    fn connect(&mut self) -> Result<(), TransportError>;
+    fn is_connected(&self) -> bool;
+    /// Sets the message the broker publishes when the connection is lost
+    /// without disconnecting properly. Used from the next connection on.
+    fn set_last_will(&mut self, will: MqttMessage);
+    fn publish(&mut self, message: &MqttMessage) -> Result<(), TransportError>;
+    /// Subscribes to the topics matching `filter`, which may contain the `+`
+    /// and `#` wildcards. Subscriptions last until the connection is lost.
+    fn subscribe(&mut self, filter: &str) -> Result<(), TransportError>;
+    /// The next message received on a subscribed topic, if any. `retain` is
+    /// set on messages the broker had retained before subscribing.
+    fn receive(&mut self) -> Option<MqttMessage>;
+}
+
+/// Returns `true` if `topic` matches the subscription `filter`.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split('/');
+    for filter_level in filter.split('/') {
+        match (filter_level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (filter_level, Some(topic_level)) if filter_level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+#[derive(Debug, Default)]
+struct BrokerState {
+    online: bool,
+    /// Incremented every time the broker goes offline, which drops the
+    /// connections made before.
+    generation: u32,
+    published: Vec<MqttMessage>,
+    retained: BTreeMap<String, Vec<u8>>,
+    /// Last wills of the clients connected in the current generation.
+    wills: Vec<MqttMessage>,
+    next_client_id: u32,
+    /// Client IDs and filters of the subscriptions of the current
+    /// generation.
+    subscriptions: Vec<(u32, String)>,
+    inboxes: HashMap<u32, VecDeque<MqttMessage>>,
+}
+
+impl BrokerState {
+    fn deliver(&mut self, message: MqttMessage) {
+        if message.retain {
+            self.retained
+                .insert(message.topic.clone(), message.payload.clone());
+        }
+
+        let mut subscribers: Vec<u32> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, filter)| topic_matches(filter, &message.topic))
+            .map(|(client, _)| *client)
+            .collect();
+        subscribers.sort_unstable();
+        subscribers.dedup();
+        for client in subscribers {
+            self.inboxes
+                .entry(client)
+                .or_default()
+                .push_back(MqttMessage {
+                    retain: false,
+                    ..message.clone()
+                });
+        }
+
+        self.published.push(message);
+    }
+
+    /// Drops the connections of all the clients.
+    fn next_generation(&mut self) {
+        self.generation += 1;
+        self.subscriptions.clear();
+        self.inboxes.clear();
+    }
+}
+
+/// Broker running in the same process, for tests. Clones share the broker.
+#[derive(Debug, Clone)]
+pub struct InProcessBroker {
+    state: Rc<RefCell<BrokerState>>,
+}
+
+impl InProcessBroker {
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(BrokerState {
+                online: true,
+                ..BrokerState::default()
+            })),
+        }
+    }
+
+    pub fn client(&self) -> InProcessClient {
+        let mut state = self.state.borrow_mut();
+        let id = state.next_client_id;
+        state.next_client_id += 1;
+
+        InProcessClient {
+            id,
+            broker: self.clone(),
+            connection: None,
+            will: None,
+        }
+    }
+
+    /// Taking the broker offline disconnects all the clients.
+    pub fn set_online(&self, online: bool) {
+        let mut state = self.state.borrow_mut();
+        if state.online && !online {
+            state.next_generation();
+            state.wills.clear();
+        }
+        state.online = online;
+    }
+
+    /// Drops the connections of all the clients, as if the network failed,
+    /// and publishes their last wills.
+    pub fn drop_connections(&self) {
+        let mut state = self.state.borrow_mut();
+        state.next_generation();
+        for will in std::mem::take(&mut state.wills) {
+            state.deliver(will);
+        }
+    }
+
+    /// Publishes a message as another client would.
+    pub fn publish(&self, message: MqttMessage) {
+        self.state.borrow_mut().deliver(message);
+    }
+
+    /// Every message published so far, in order.
+    pub fn published(&self) -> Vec<MqttMessage> {
+        self.state.borrow().published.clone()
+    }
+
+    pub fn retained(&self, topic: &str) -> Option<Vec<u8>> {
+        self.state.borrow().retained.get(topic).cloned()
+    }
+}
+
+impl Default for InProcessBroker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct InProcessClient {
+    id: u32,
+    broker: InProcessBroker,
+    /// The broker generation the client has connected in.
+    connection: Option<u32>,
+    will: Option<MqttMessage>,
+}
+
+impl MqttTransport for InProcessClient {
+    fn connect(&mut self) -> Result<(), TransportError> {
+        if self.is_connected() {
+            return Ok(());
+        }
+
+        let mut state = self.broker.state.borrow_mut();
+        if !state.online {
+            return Err(TransportError::ConnectionFailed(
+                "broker offline".to_owned(),
+            ));
+        }
+        self.connection = Some(state.generation);
+        state.wills.extend(self.will.clone());
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        let state = self.broker.state.borrow();
+        state.online && self.connection == Some(state.generation)
+    }
+
+    fn set_last_will(&mut self, will: MqttMessage) {
+        self.will = Some(will);
+    }
+
+    fn publish(&mut self, message: &MqttMessage) -> Result<(), TransportError> {
+        if !self.is_connected() {
+            return Err(TransportError::NotConnected);
+        }
+
+        self.broker.state.borrow_mut().deliver(message.clone());
+        Ok(())
+    }
+
+    fn subscribe(&mut self, filter: &str) -> Result<(), TransportError> {
+        if !self.is_connected() {
+            return Err(TransportError::NotConnected);
+        }
+
+        let mut state = self.broker.state.borrow_mut();
+        state.subscriptions.push((self.id, filter.to_owned()));
+        let retained: Vec<_> = state
+            .retained
+            .iter()
+            .filter(|(topic, _)| topic_matches(filter, topic))
+            .map(|(topic, payload)| MqttMessage::new(topic.as_str(), payload.as_slice(), true))
+            .collect();
+        state.inboxes.entry(self.id).or_default().extend(retained);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<MqttMessage> {
+        if !self.is_connected() {
+            return None;
+        }
+        self.broker
+            .state
+            .borrow_mut()
+            .inboxes
+            .get_mut(&self.id)?
+            .pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broker_going_offline_disconnects_clients() {
+        let broker = InProcessBroker::new();
+        let mut client = broker.client();
+        let message = MqttMessage::new("plants/basil/moisture", "42", true);
+
+        assert_eq!(client.publish(&message), Err(TransportError::NotConnected));
+        client.connect().unwrap();
+        client.publish(&message).unwrap();
+
+        broker.set_online(false);
+        assert!(!client.is_connected());
+        assert!(client.connect().is_err());
+
+        broker.set_online(true);
+        assert!(!client.is_connected());
+        client.connect().unwrap();
+        client
+            .publish(&MqttMessage::new("plants/basil/status", "watered", false))
+            .unwrap();
+
+        assert_eq!(broker.published().len(), 2);
+        assert_eq!(
+            broker.retained("plants/basil/moisture"),
+            Some(b"42".to_vec())
+        );
+        assert_eq!(broker.retained("plants/basil/status"), None);
+    }
+
+    #[test]
+    fn last_will() {
+        let broker = InProcessBroker::new();
+        let mut client = broker.client();
+        client.set_last_will(MqttMessage::new("plants/availability", "offline", true));
+        client.connect().unwrap();
+
+        broker.drop_connections();
+
+        assert!(!client.is_connected());
+        assert_eq!(
+            broker.retained("plants/availability"),
+            Some(b"offline".to_vec())
+        );
+    }
+
+    #[test]
+    fn topic_filters() {
+        assert!(topic_matches("plants/+/command", "plants/basil/command"));
+        assert!(!topic_matches("plants/+/command", "plants/basil/status"));
+        assert!(!topic_matches("plants/+/command", "plants/basil/command/x"));
+        assert!(topic_matches("plants/#", "plants/basil/command"));
+        assert!(topic_matches("plants/#", "plants"));
+        assert!(!topic_matches("plants/basil", "plants"));
+    }
+
+    #[test]
+    fn subscriptions() {
+        let broker = InProcessBroker::new();
+        broker.publish(MqttMessage::new("plants/basil/command", "old", true));
+        let mut client = broker.client();
+        client.connect().unwrap();
+
+        client.subscribe("plants/+/command").unwrap();
+        broker.publish(MqttMessage::new("plants/mint/command", "new", true));
+        broker.publish(MqttMessage::new("plants/mint/status", "ignored", false));
+
+        assert_eq!(
+            client.receive(),
+            Some(MqttMessage::new("plants/basil/command", "old", true))
+        );
+        assert_eq!(
+            client.receive(),
+            Some(MqttMessage::new("plants/mint/command", "new", false))
+        );
+        assert_eq!(client.receive(), None);
+
+        // Subscriptions do not survive a reconnection
+        broker.drop_connections();
+        client.connect().unwrap();
+        broker.publish(MqttMessage::new("plants/mint/command", "lost", false));
+        assert_eq!(client.receive(), None);
+    }
+}
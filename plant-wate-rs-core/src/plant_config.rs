@@ -0,0 +1,888 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::calibration_curve::{CalibrationCurve, CalibrationCurveError};
+use crate::moisture_smoothing::MoistureSmoothingConfig;
+use crate::plant_irrigator::{Percentage, TargetMoistureLevel};
+use crate::pump_safety::PumpSafetyLimits;
+use crate::reservoir_monitor::ReservoirMonitorConfig;
+use crate::sensor_fault::SensorFaultLimits;
+use crate::soil::SoilType;
+use crate::temperature::TemperatureCompensationConfig;
+use crate::time_window::TimeWindow;
+use crate::uc::{GpioId, UcError};
+use crate::uc_utils::SamplingConfig;
+use crate::watering_strategy::WateringStrategyConfig;
+
+/// Unit the target moisture level is expressed in.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MoistureTarget {
+    /// Percent of the sensor calibration range.
+    Relative(TargetMoistureLevel),
+    /// Volumetric water content; requires the soil type to be set. The
+    /// moisture of the plant is then expressed in VWC as well.
+    Vwc(TargetMoistureLevel),
+}
+
+impl MoistureTarget {
+    #[inline]
+    pub const fn level(&self) -> TargetMoistureLevel {
+        match self {
+            MoistureTarget::Relative(level) | MoistureTarget::Vwc(level) => *level,
+        }
+    }
+}
+
+/// Declarative description of a single plant handled by the
+/// [`PlantIrrigatorController`](crate::plant_irrigator_controller::PlantIrrigatorController).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlantConfig {
+    name: String,
+    sensor_gpio: GpioId,
+    pump_gpio: GpioId,
+    calibration: CalibrationCurve,
+    target: MoistureTarget,
+    soil_type: Option<SoilType>,
+    sampling: SamplingConfig,
+    temperature_compensation: Option<TemperatureCompensationConfig>,
+    watering_windows: Vec<TimeWindow>,
+    emergency_moisture_level: Option<Percentage>,
+    moisture_smoothing: MoistureSmoothingConfig,
+    watering_strategy: WateringStrategyConfig,
+    pump_safety_limits: PumpSafetyLimits,
+    sensor_fault_limits: SensorFaultLimits,
+    reservoir_monitor: ReservoirMonitorConfig,
+}
+
+impl PlantConfig {
+    #[inline]
+    pub fn new(
+        name: impl Into<String>,
+        sensor_gpio: GpioId,
+        pump_gpio: GpioId,
+        calibration: impl Into<CalibrationCurve>,
+        target_moisture_level: TargetMoistureLevel,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            sensor_gpio,
+            pump_gpio,
+            calibration: calibration.into(),
+            target: MoistureTarget::Relative(target_moisture_level),
+            soil_type: None,
+            sampling: SamplingConfig::default(),
+            temperature_compensation: None,
+            watering_windows: Vec::new(),
+            emergency_moisture_level: None,
+            moisture_smoothing: MoistureSmoothingConfig::default(),
+            watering_strategy: WateringStrategyConfig::default(),
+            pump_safety_limits: PumpSafetyLimits::default(),
+            sensor_fault_limits: SensorFaultLimits::default(),
+            reservoir_monitor: ReservoirMonitorConfig::default(),
+        }
+    }
+
+    /// Sets the soil the sensor is in, which allows estimating the volumetric
+    /// water content. The calibration has to include the readings in the air
+    /// (0%) and in water (100%), as the two-point calibration does.
+    #[inline]
+    #[must_use]
+    pub fn with_soil_type(mut self, soil_type: SoilType) -> Self {
+        self.soil_type = Some(soil_type);
+        self
+    }
+
+    /// Replaces the target moisture level with one expressed in volumetric
+    /// water content. The soil type has to be set as well.
+    #[inline]
+    #[must_use]
+    pub fn with_vwc_target(mut self, target: TargetMoistureLevel) -> Self {
+        self.target = MoistureTarget::Vwc(target);
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_sampling(mut self, sampling: SamplingConfig) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_temperature_compensation(
+        mut self,
+        temperature_compensation: TemperatureCompensationConfig,
+    ) -> Self {
+        self.temperature_compensation = Some(temperature_compensation);
+        self
+    }
+
+    /// Restricts watering to the given times of day; no windows means any
+    /// time.
+    #[inline]
+    #[must_use]
+    pub fn with_watering_windows(mut self, watering_windows: Vec<TimeWindow>) -> Self {
+        self.watering_windows = watering_windows;
+        self
+    }
+
+    /// Moisture below which the plant is watered even outside of its watering
+    /// windows and during the quiet hours.
+    #[inline]
+    #[must_use]
+    pub fn with_emergency_moisture_level(mut self, emergency_moisture_level: Percentage) -> Self {
+        self.emergency_moisture_level = Some(emergency_moisture_level);
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_moisture_smoothing(mut self, moisture_smoothing: MoistureSmoothingConfig) -> Self {
+        self.moisture_smoothing = moisture_smoothing;
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_watering_strategy(mut self, watering_strategy: WateringStrategyConfig) -> Self {
+        self.watering_strategy = watering_strategy;
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_pump_safety_limits(mut self, pump_safety_limits: PumpSafetyLimits) -> Self {
+        self.pump_safety_limits = pump_safety_limits;
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_sensor_fault_limits(mut self, sensor_fault_limits: SensorFaultLimits) -> Self {
+        self.sensor_fault_limits = sensor_fault_limits;
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_reservoir_monitor(mut self, reservoir_monitor: ReservoirMonitorConfig) -> Self {
+        self.reservoir_monitor = reservoir_monitor;
+        self
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub const fn sensor_gpio(&self) -> GpioId {
+        self.sensor_gpio
+    }
+
+    #[inline]
+    pub const fn pump_gpio(&self) -> GpioId {
+        self.pump_gpio
+    }
+
+    #[inline]
+    pub const fn calibration(&self) -> &CalibrationCurve {
+        &self.calibration
+    }
+
+    #[inline]
+    pub const fn target(&self) -> &MoistureTarget {
+        &self.target
+    }
+
+    #[inline]
+    pub const fn soil_type(&self) -> Option<SoilType> {
+        self.soil_type
+    }
+
+    /// Maps the sensor values to the volumetric water content, based on the
+    /// soil type and on the readings of the sensor in the air and in water.
+    /// `None` if the soil type is not set.
+    pub fn vwc_curve(&self) -> Result<Option<CalibrationCurve>, PlantConfigError> {
+        let Some(soil_type) = self.soil_type else {
+            return Ok(None);
+        };
+        let (Some(air), Some(water)) = (
+            self.calibration.value_at(Percentage::new(0)),
+            self.calibration.value_at(Percentage::new(100)),
+        ) else {
+            return Err(PlantConfigError::NoAirAndWaterReadings(self.name.clone()));
+        };
+
+        soil_type.vwc_curve(air, water).map(Some).map_err(|error| {
+            PlantConfigError::InvalidCalibration {
+                plant: self.name.clone(),
+                error,
+            }
+        })
+    }
+
+    /// The calibration the irrigator works with: the configured one for a
+    /// relative target, the [VWC curve](Self::vwc_curve) for a VWC target.
+    pub fn irrigator_calibration(&self) -> Result<CalibrationCurve, PlantConfigError> {
+        match self.target {
+            MoistureTarget::Relative(_) => Ok(self.calibration.clone()),
+            MoistureTarget::Vwc(_) => self
+                .vwc_curve()?
+                .ok_or_else(|| PlantConfigError::VwcTargetWithoutSoilType(self.name.clone())),
+        }
+    }
+
+    #[inline]
+    pub const fn sampling(&self) -> &SamplingConfig {
+        &self.sampling
+    }
+
+    #[inline]
+    pub const fn temperature_compensation(&self) -> Option<&TemperatureCompensationConfig> {
+        self.temperature_compensation.as_ref()
+    }
+
+    #[inline]
+    pub fn watering_windows(&self) -> &[TimeWindow] {
+        &self.watering_windows
+    }
+
+    #[inline]
+    pub const fn emergency_moisture_level(&self) -> Option<Percentage> {
+        self.emergency_moisture_level
+    }
+
+    /// All the GPIOs used by the plant.
+    pub fn gpios(&self) -> impl Iterator<Item = GpioId> {
+        [self.sensor_gpio, self.pump_gpio].into_iter().chain(
+            self.temperature_compensation
+                .and_then(|config| config.gpio()),
+        )
+    }
+
+    #[inline]
+    pub const fn moisture_smoothing(&self) -> &MoistureSmoothingConfig {
+        &self.moisture_smoothing
+    }
+
+    #[inline]
+    pub const fn watering_strategy(&self) -> &WateringStrategyConfig {
+        &self.watering_strategy
+    }
+
+    #[inline]
+    pub const fn pump_safety_limits(&self) -> &PumpSafetyLimits {
+        &self.pump_safety_limits
+    }
+
+    #[inline]
+    pub const fn sensor_fault_limits(&self) -> &SensorFaultLimits {
+        &self.sensor_fault_limits
+    }
+
+    #[inline]
+    pub const fn reservoir_monitor(&self) -> &ReservoirMonitorConfig {
+        &self.reservoir_monitor
+    }
+
+    /// Checks a single plant entry for errors that do not depend on the other
+    /// entries.
+    pub fn validate(&self) -> Result<(), PlantConfigError> {
+        if self.name.trim().is_empty() {
+            return Err(PlantConfigError::EmptyName);
+        }
+
+        let mut gpios = HashSet::new();
+        for gpio in self.gpios() {
+            if !gpios.insert(gpio) {
+                return Err(PlantConfigError::GpioAlreadyUsed {
+                    plant: self.name.clone(),
+                    gpio,
+                });
+            }
+        }
+
+        let calibration = &self.calibration;
+        if let Err(error) = calibration.validate() {
+            return Err(PlantConfigError::InvalidCalibration {
+                plant: self.name.clone(),
+                error,
+            });
+        }
+
+        self.vwc_curve()?;
+        let (target, max_target) = match (&self.target, self.soil_type) {
+            (MoistureTarget::Relative(target), _) => (target, Percentage::new(100)),
+            (MoistureTarget::Vwc(target), Some(soil_type)) => (target, soil_type.saturation()),
+            (MoistureTarget::Vwc(_), None) => {
+                return Err(PlantConfigError::VwcTargetWithoutSoilType(
+                    self.name.clone(),
+                ))
+            }
+        };
+        if target.min_value() >= target.max_value() || target.max_value() > max_target {
+            return Err(PlantConfigError::InvalidTargetMoistureLevel {
+                plant: self.name.clone(),
+                target: *target,
+            });
+        }
+
+        if !self.sampling.is_valid() {
+            return Err(PlantConfigError::InvalidSampling {
+                plant: self.name.clone(),
+                sampling: self.sampling,
+            });
+        }
+
+        if let Some(temperature_compensation) = self.temperature_compensation {
+            if !temperature_compensation.is_valid() {
+                return Err(PlantConfigError::InvalidTemperatureCompensation {
+                    plant: self.name.clone(),
+                    temperature_compensation,
+                });
+            }
+        }
+
+        if let Some(level) = self.emergency_moisture_level {
+            if level.value() > 100 {
+                return Err(PlantConfigError::InvalidEmergencyMoistureLevel {
+                    plant: self.name.clone(),
+                    level,
+                });
+            }
+        }
+
+        if let Some(window) = self
+            .watering_windows
+            .iter()
+            .find(|window| !window.is_valid())
+        {
+            return Err(PlantConfigError::InvalidWateringWindow {
+                plant: self.name.clone(),
+                window: *window,
+            });
+        }
+
+        if !self.moisture_smoothing.is_valid() {
+            return Err(PlantConfigError::InvalidMoistureSmoothing {
+                plant: self.name.clone(),
+                moisture_smoothing: self.moisture_smoothing,
+            });
+        }
+
+        if !self.watering_strategy.is_valid() {
+            return Err(PlantConfigError::InvalidWateringStrategy {
+                plant: self.name.clone(),
+                watering_strategy: self.watering_strategy.clone(),
+            });
+        }
+
+        if !self.pump_safety_limits.is_valid() {
+            return Err(PlantConfigError::InvalidPumpSafetyLimits {
+                plant: self.name.clone(),
+                pump_safety_limits: self.pump_safety_limits,
+            });
+        }
+
+        // Readings within the calibration range must not be mistaken for faults
+        let limits = &self.sensor_fault_limits;
+        if !limits.is_valid()
+            || calibration.min_value() <= limits.open_circuit_max
+            || calibration.max_value() >= limits.short_circuit_min
+        {
+            return Err(PlantConfigError::InvalidSensorFaultLimits {
+                plant: self.name.clone(),
+                sensor_fault_limits: *limits,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the whole plant list, including the conflicts between the entries
+/// (duplicate names, GPIOs used more than once).
+pub fn validate_plant_configs(plants: &[PlantConfig]) -> Result<(), PlantConfigError> {
+    if plants.is_empty() {
+        return Err(PlantConfigError::NoPlants);
+    }
+
+    let mut names = HashSet::new();
+    let mut gpios = HashSet::new();
+    for plant in plants {
+        plant.validate()?;
+
+        if !names.insert(plant.name()) {
+            return Err(PlantConfigError::DuplicateName(plant.name.clone()));
+        }
+        for gpio in plant.gpios() {
+            if !gpios.insert(gpio) {
+                return Err(PlantConfigError::GpioAlreadyUsed {
+                    plant: plant.name.clone(),
+                    gpio,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlantConfigError {
+    NoPlants,
+    EmptyName,
+    DuplicateName(String),
+    GpioAlreadyUsed {
+        plant: String,
+        gpio: GpioId,
+    },
+    /// The microcontroller could not provide a GPIO requested by the plant.
+    Gpio {
+        plant: String,
+        error: UcError,
+    },
+    InvalidCalibration {
+        plant: String,
+        error: CalibrationCurveError,
+    },
+    InvalidTargetMoistureLevel {
+        plant: String,
+        target: TargetMoistureLevel,
+    },
+    VwcTargetWithoutSoilType(String),
+    /// The soil type is set, but the calibration lacks the readings in the air
+    /// (0%) or in water (100%) that the VWC is estimated from.
+    NoAirAndWaterReadings(String),
+    InvalidSampling {
+        plant: String,
+        sampling: SamplingConfig,
+    },
+    InvalidTemperatureCompensation {
+        plant: String,
+        temperature_compensation: TemperatureCompensationConfig,
+    },
+    InvalidEmergencyMoistureLevel {
+        plant: String,
+        level: Percentage,
+    },
+    /// The window is empty.
+    InvalidWateringWindow {
+        plant: String,
+        window: TimeWindow,
+    },
+    InvalidMoistureSmoothing {
+        plant: String,
+        moisture_smoothing: MoistureSmoothingConfig,
+    },
+    InvalidWateringStrategy {
+        plant: String,
+        watering_strategy: WateringStrategyConfig,
+    },
+    InvalidPumpSafetyLimits {
+        plant: String,
+        pump_safety_limits: PumpSafetyLimits,
+    },
+    InvalidSensorFaultLimits {
+        plant: String,
+        sensor_fault_limits: SensorFaultLimits,
+    },
+}
+
+impl Display for PlantConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlantConfigError::NoPlants => write!(f, "no plants configured"),
+            PlantConfigError::EmptyName => write!(f, "plant name cannot be empty"),
+            PlantConfigError::DuplicateName(name) => {
+                write!(f, "plant name `{}` used more than once", name)
+            }
+            PlantConfigError::GpioAlreadyUsed { plant, gpio } => {
+                write!(f, "plant `{}`: {} is already in use", plant, gpio)
+            }
+            PlantConfigError::Gpio { plant, error } => write!(f, "plant `{}`: {}", plant, error),
+            PlantConfigError::InvalidCalibration { plant, error } => {
+                write!(f, "plant `{}`: invalid calibration: {}", plant, error)
+            }
+            PlantConfigError::InvalidTargetMoistureLevel { plant, target } => write!(
+                f,
+                "plant `{}`: invalid target moisture level {}",
+                plant, target
+            ),
+            PlantConfigError::VwcTargetWithoutSoilType(plant) => write!(
+                f,
+                "plant `{}`: target set in VWC, but the soil type is unknown",
+                plant
+            ),
+            PlantConfigError::NoAirAndWaterReadings(plant) => write!(
+                f,
+                "plant `{}`: soil type set, but the calibration lacks the 0% and 100% points",
+                plant
+            ),
+            PlantConfigError::InvalidSampling { plant, sampling } => {
+                write!(f, "plant `{}`: invalid sampling {:?}", plant, sampling)
+            }
+            PlantConfigError::InvalidTemperatureCompensation {
+                plant,
+                temperature_compensation,
+            } => write!(
+                f,
+                "plant `{}`: invalid temperature compensation {:?}",
+                plant, temperature_compensation
+            ),
+            PlantConfigError::InvalidEmergencyMoistureLevel { plant, level } => write!(
+                f,
+                "plant `{}`: invalid emergency moisture level {}",
+                plant, level
+            ),
+            PlantConfigError::InvalidWateringWindow { plant, window } => {
+                write!(f, "plant `{}`: empty watering window {}", plant, window)
+            }
+            PlantConfigError::InvalidMoistureSmoothing {
+                plant,
+                moisture_smoothing,
+            } => write!(
+                f,
+                "plant `{}`: invalid moisture smoothing {:?}",
+                plant, moisture_smoothing
+            ),
+            PlantConfigError::InvalidWateringStrategy {
+                plant,
+                watering_strategy,
+            } => write!(
+                f,
+                "plant `{}`: invalid watering strategy {:?}",
+                plant, watering_strategy
+            ),
+            PlantConfigError::InvalidPumpSafetyLimits {
+                plant,
+                pump_safety_limits,
+            } => write!(
+                f,
+                "plant `{}`: invalid pump safety limits {:?}",
+                plant, pump_safety_limits
+            ),
+            PlantConfigError::InvalidSensorFaultLimits {
+                plant,
+                sensor_fault_limits,
+            } => write!(
+                f,
+                "plant `{}`: invalid sensor fault limits {:?}",
+                plant, sensor_fault_limits
+            ),
+        }
+    }
+}
+
+impl Error for PlantConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::calibration_curve::CalibrationPoint;
+    use crate::moisture_smoothing::MoistureSmoothingConfig;
+    use crate::plant_irrigator::{Percentage, SensorCalibrationResult};
+    use crate::temperature::{
+        Temperature, TemperatureCompensation, TemperatureSensorConfig, ThermistorConfig,
+    };
+    use crate::uc::{AnalogValue, GPIO_0, GPIO_1, GPIO_2, GPIO_3};
+
+    #[test]
+    fn valid_plant_list() {
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1),
+            plant_config("mint", GPIO_2, GPIO_3),
+        ];
+
+        assert_eq!(validate_plant_configs(&plants), Ok(()));
+    }
+
+    #[test]
+    fn empty_plant_list() {
+        assert_eq!(validate_plant_configs(&[]), Err(PlantConfigError::NoPlants));
+    }
+
+    #[test]
+    fn empty_name() {
+        let plants = [plant_config(" ", GPIO_0, GPIO_1)];
+
+        assert_eq!(
+            validate_plant_configs(&plants),
+            Err(PlantConfigError::EmptyName)
+        );
+    }
+
+    #[test]
+    fn duplicate_name() {
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1),
+            plant_config("basil", GPIO_2, GPIO_3),
+        ];
+
+        assert_eq!(
+            validate_plant_configs(&plants),
+            Err(PlantConfigError::DuplicateName("basil".to_owned()))
+        );
+    }
+
+    #[test]
+    fn sensor_and_pump_on_same_gpio() {
+        let plants = [plant_config("basil", GPIO_0, GPIO_0)];
+
+        assert_eq!(
+            validate_plant_configs(&plants),
+            Err(PlantConfigError::GpioAlreadyUsed {
+                plant: "basil".to_owned(),
+                gpio: GPIO_0,
+            })
+        );
+    }
+
+    #[test]
+    fn gpio_shared_between_plants() {
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1),
+            plant_config("mint", GPIO_2, GPIO_1),
+        ];
+
+        assert_eq!(
+            validate_plant_configs(&plants),
+            Err(PlantConfigError::GpioAlreadyUsed {
+                plant: "mint".to_owned(),
+                gpio: GPIO_1,
+            })
+        );
+    }
+
+    #[test]
+    fn thermistor_gpio_conflict() {
+        let temperature_compensation = TemperatureCompensationConfig {
+            sensor: TemperatureSensorConfig::Thermistor {
+                gpio: GPIO_2,
+                thermistor: ThermistorConfig::default(),
+            },
+            compensation: TemperatureCompensation {
+                reference: Temperature::from_celsius(20.0),
+                linear_coefficient: 3.0,
+                quadratic_coefficient: 0.0,
+            },
+        };
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1)
+                .with_temperature_compensation(temperature_compensation),
+            plant_config("mint", GPIO_2, GPIO_3),
+        ];
+
+        assert_eq!(plants[0].validate(), Ok(()));
+        assert_eq!(
+            validate_plant_configs(&plants),
+            Err(PlantConfigError::GpioAlreadyUsed {
+                plant: "mint".to_owned(),
+                gpio: GPIO_2,
+            })
+        );
+    }
+
+    #[test]
+    fn out_of_range_values() {
+        let target = TargetMoistureLevel::new(Percentage::new(70), Percentage::new(40));
+        let plant = PlantConfig::new(
+            "basil",
+            GPIO_0,
+            GPIO_1,
+            SensorCalibrationResult::new(AnalogValue::new(500), AnalogValue::new(2200)),
+            target,
+        );
+        assert_eq!(
+            plant.validate(),
+            Err(PlantConfigError::InvalidTargetMoistureLevel {
+                plant: "basil".to_owned(),
+                target,
+            })
+        );
+
+        let plant = PlantConfig::new(
+            "basil",
+            GPIO_0,
+            GPIO_1,
+            SensorCalibrationResult::new(AnalogValue::new(2200), AnalogValue::new(500)),
+            TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+        );
+        assert_eq!(
+            plant.validate(),
+            Err(PlantConfigError::InvalidCalibration {
+                plant: "basil".to_owned(),
+                error: CalibrationCurveError::UnsortedValues,
+            })
+        );
+
+        let plant = plant_config("basil", GPIO_0, GPIO_1)
+            .with_emergency_moisture_level(Percentage::new(120));
+        assert_eq!(
+            plant.validate(),
+            Err(PlantConfigError::InvalidEmergencyMoistureLevel {
+                plant: "basil".to_owned(),
+                level: Percentage::new(120),
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_moisture_smoothing() {
+        let moisture_smoothing = MoistureSmoothingConfig::Ema { alpha: 0.0 };
+        let plants =
+            [plant_config("basil", GPIO_0, GPIO_1).with_moisture_smoothing(moisture_smoothing)];
+
+        assert_eq!(
+            validate_plant_configs(&plants),
+            Err(PlantConfigError::InvalidMoistureSmoothing {
+                plant: "basil".to_owned(),
+                moisture_smoothing,
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_watering_strategy() {
+        let watering_strategy = WateringStrategyConfig::FixedDose {
+            dose: Duration::ZERO,
+        };
+        let plants =
+            [plant_config("basil", GPIO_0, GPIO_1)
+                .with_watering_strategy(watering_strategy.clone())];
+
+        assert_eq!(
+            validate_plant_configs(&plants),
+            Err(PlantConfigError::InvalidWateringStrategy {
+                plant: "basil".to_owned(),
+                watering_strategy,
+            })
+        );
+    }
+
+    #[test]
+    fn vwc_target() {
+        let plant = plant_config("basil", GPIO_0, GPIO_1)
+            .with_soil_type(SoilType::PottingMix)
+            .with_vwc_target(TargetMoistureLevel::new(
+                Percentage::new(25),
+                Percentage::new(42),
+            ));
+
+        assert_eq!(plant.validate(), Ok(()));
+        assert_eq!(
+            plant.target().level(),
+            TargetMoistureLevel::new(Percentage::new(25), Percentage::new(42))
+        );
+        // 500 mV in water, 2200 mV in the air
+        let calibration = plant.irrigator_calibration().unwrap();
+        assert_eq!(
+            calibration.moisture(AnalogValue::new(1350)),
+            Percentage::new(25)
+        );
+        assert_eq!(
+            calibration.moisture(AnalogValue::new(500)),
+            Percentage::new(55)
+        );
+    }
+
+    #[test]
+    fn soil_type_without_air_and_water_readings() {
+        let calibration = CalibrationCurve::new(vec![
+            CalibrationPoint::new(AnalogValue::new(1000), Percentage::new(90)),
+            CalibrationPoint::new(AnalogValue::new(2500), Percentage::new(0)),
+        ])
+        .unwrap();
+        let plant = PlantConfig::new(
+            "basil",
+            GPIO_0,
+            GPIO_1,
+            calibration,
+            TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+        )
+        .with_soil_type(SoilType::Clay);
+
+        assert_eq!(
+            plant.validate(),
+            Err(PlantConfigError::NoAirAndWaterReadings("basil".to_owned()))
+        );
+        assert_eq!(
+            plant.vwc_curve(),
+            Err(PlantConfigError::NoAirAndWaterReadings("basil".to_owned()))
+        );
+    }
+
+    #[test]
+    fn invalid_vwc_target() {
+        let target = TargetMoistureLevel::new(Percentage::new(20), Percentage::new(40));
+        let plant = plant_config("basil", GPIO_0, GPIO_1).with_vwc_target(target);
+        assert_eq!(
+            plant.validate(),
+            Err(PlantConfigError::VwcTargetWithoutSoilType(
+                "basil".to_owned()
+            ))
+        );
+
+        // Cactus mix never gets wetter than 35% VWC
+        let plant = plant.with_soil_type(SoilType::CactusMix);
+        assert_eq!(
+            plant.validate(),
+            Err(PlantConfigError::InvalidTargetMoistureLevel {
+                plant: "basil".to_owned(),
+                target,
+            })
+        );
+    }
+
+    #[test]
+    fn multi_point_calibration() {
+        let calibration = CalibrationCurve::new(vec![
+            CalibrationPoint::new(AnalogValue::new(1000), Percentage::new(100)),
+            CalibrationPoint::new(AnalogValue::new(1300), Percentage::new(50)),
+            CalibrationPoint::new(AnalogValue::new(2500), Percentage::new(0)),
+        ])
+        .unwrap();
+        let plants = [PlantConfig::new(
+            "basil",
+            GPIO_0,
+            GPIO_1,
+            calibration,
+            TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+        )];
+
+        assert_eq!(validate_plant_configs(&plants), Ok(()));
+    }
+
+    #[test]
+    fn sensor_fault_limits_overlapping_calibration() {
+        let sensor_fault_limits = SensorFaultLimits {
+            short_circuit_min: AnalogValue::new(2000),
+            ..Default::default()
+        };
+        let plants =
+            [plant_config("basil", GPIO_0, GPIO_1).with_sensor_fault_limits(sensor_fault_limits)];
+
+        assert_eq!(
+            validate_plant_configs(&plants),
+            Err(PlantConfigError::InvalidSensorFaultLimits {
+                plant: "basil".to_owned(),
+                sensor_fault_limits,
+            })
+        );
+    }
+
+    fn plant_config(name: &str, sensor_gpio: GpioId, pump_gpio: GpioId) -> PlantConfig {
+        PlantConfig::new(
+            name,
+            sensor_gpio,
+            pump_gpio,
+            SensorCalibrationResult::new(AnalogValue::new(500), AnalogValue::new(2200)),
+            TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+        )
+    }
+}
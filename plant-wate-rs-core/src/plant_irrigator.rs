@@ -1,10 +1,25 @@
+use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use log::info;
+use log::{error, info, warn};
 
-use crate::uc::{AnalogInput, AnalogValue, DigitalOutput, Microcontroller};
-use crate::uc_utils::AnalogValueMean;
+use crate::calibration_curve::{CalibrationCurve, CalibrationCurveError};
+use crate::controller_state::PlantState;
+use crate::event_history::{DecisionReason, EventHistory, IrrigationEvent};
+use crate::moisture_smoothing::{MoistureReading, MoistureSmoother, MoistureSmoothingConfig};
+use crate::pump_safety::{PumpSafetyLimits, PumpSafetyMonitor, SafetyLimit};
+use crate::reservoir_monitor::{ReservoirMonitor, ReservoirMonitorConfig};
+use crate::sensor_fault::{SensorFaultDetector, SensorFaultKind, SensorFaultLimits};
+use crate::temperature::{
+    Temperature, TemperatureCompensation, TemperatureInput, TemperatureSensor,
+};
+use crate::time_window::{TimeOfDay, TimeWindow};
+use crate::uc::{AnalogInput, AnalogValue, DigitalOutput, Microcontroller, UcError};
+use crate::uc_utils::{
+    retry, SampleFilter, SamplingConfig, HARDWARE_ATTEMPTS, HARDWARE_RETRY_DELAY,
+};
+use crate::watering_strategy::{MoistureHistory, WateringAction, WateringStrategy, MAX_PULSES};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct SensorCalibrationResult {
@@ -13,25 +28,39 @@ pub struct SensorCalibrationResult {
 }
 
 impl SensorCalibrationResult {
+    /// The values are not checked here; a calibration with `min_value` not
+    /// below `max_value` is reported by [`PlantConfig::validate`].
+    ///
+    /// [`PlantConfig::validate`]: crate::plant_config::PlantConfig::validate
     #[inline]
-    pub fn new(min_value: AnalogValue, max_value: AnalogValue) -> Self {
-        debug_assert!(min_value < max_value);
-
+    pub const fn new(min_value: AnalogValue, max_value: AnalogValue) -> Self {
         Self {
             min_value,
             max_value,
         }
     }
+
+    #[inline]
+    pub const fn min_value(&self) -> AnalogValue {
+        self.min_value
+    }
+
+    #[inline]
+    pub const fn max_value(&self) -> AnalogValue {
+        self.max_value
+    }
 }
 
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Percentage(u8);
 
 impl Percentage {
+    /// Values above 100 are not rejected here, so that configuration errors
+    /// can be reported by [`PlantConfig::validate`].
+    ///
+    /// [`PlantConfig::validate`]: crate::plant_config::PlantConfig::validate
     #[inline]
-    pub fn new(value: u8) -> Self {
-        debug_assert!(value <= 100);
-
+    pub const fn new(value: u8) -> Self {
         Self(value)
     }
 
@@ -57,17 +86,19 @@ impl Display for Percentage {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct TargetMoistureLevel {
     min_value: Percentage,
     max_value: Percentage,
 }
 
 impl TargetMoistureLevel {
+    /// The range is not checked here; an empty one is reported by
+    /// [`PlantConfig::validate`].
+    ///
+    /// [`PlantConfig::validate`]: crate::plant_config::PlantConfig::validate
     #[inline]
-    pub fn new(min_value: Percentage, max_value: Percentage) -> Self {
-        debug_assert!(min_value < max_value);
-
+    pub const fn new(min_value: Percentage, max_value: Percentage) -> Self {
         Self {
             min_value,
             max_value,
@@ -93,88 +124,811 @@ impl Display for TargetMoistureLevel {
 
 #[derive(Debug)]
 pub struct PlantIrrigator<MicrocontrollerImpl: Microcontroller> {
+    name: String,
     soil_moisture_sensor: MicrocontrollerImpl::AnalogInput,
     pump_enabled: MicrocontrollerImpl::DigitalOutput,
 
-    calibration_result: SensorCalibrationResult,
+    calibration: CalibrationCurve,
+    /// The calibration given on creation, before any recalibration.
+    configured_calibration: CalibrationCurve,
     target_moisture_level: TargetMoistureLevel,
+    /// The target given on creation, before any
+    /// [`PlantIrrigator::set_target_moisture_level`].
+    configured_target_moisture_level: TargetMoistureLevel,
+    /// Maps the sensor values to the volumetric water content.
+    vwc_curve: Option<CalibrationCurve>,
+    /// Whether the target, and so the calibration, are in VWC.
+    vwc_target: bool,
+    last_vwc: Option<Percentage>,
+    watering_windows: Vec<TimeWindow>,
+    emergency_moisture_level: Option<Percentage>,
+
+    watering_strategy: Box<dyn WateringStrategy>,
+    sampling: SamplingConfig,
+    sample_filter: Box<dyn SampleFilter>,
+    temperature_input: Option<TemperatureInput<MicrocontrollerImpl::AnalogInput>>,
+    temperature_compensation: Option<TemperatureCompensation>,
+    last_temperature: Option<Temperature>,
+    moisture_smoother: MoistureSmoother,
+    last_reading: Option<MoistureReading>,
+    moisture_history: MoistureHistory,
+    pump_safety_monitor: PumpSafetyMonitor,
+    sensor_fault_detector: SensorFaultDetector,
+    reservoir_monitor: ReservoirMonitor,
+    /// Set when the pump could not be turned off.
+    pump_off_pending: bool,
+    /// The sensor or hardware fault of a past cycle, until
+    /// [`PlantIrrigator::reset_faults`].
+    fault: Option<IrrigationStatus>,
+    paused: bool,
+    event_history: EventHistory,
+    cycle: CycleRecord,
+    counters: PlantCounters,
+}
+
+/// Running totals of a plant's waterings.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct PlantCounters {
+    /// Number of pump runs.
+    pub waterings: u32,
+    pub pump_time: Duration,
 }
 
-const PUMP_ON_TIME: Duration = Duration::from_millis(500);
-const MEASUREMENT_DELAY_TIME: Duration = Duration::from_millis(500);
+/// What has been observed during the current cycle, for the event history.
+#[derive(Debug, Copy, Clone, Default)]
+struct CycleRecord {
+    sensor_value: Option<AnalogValue>,
+    moisture: Option<Percentage>,
+    emergency: bool,
+}
 
 impl<MicrocontrollerImpl: Microcontroller> PlantIrrigator<MicrocontrollerImpl> {
     #[inline]
     pub fn new(
+        name: impl Into<String>,
         soil_moisture_sensor: MicrocontrollerImpl::AnalogInput,
         pump_enabled: MicrocontrollerImpl::DigitalOutput,
-        calibration_result: SensorCalibrationResult,
+        calibration: impl Into<CalibrationCurve>,
         target_moisture_level: TargetMoistureLevel,
+        watering_strategy: Box<dyn WateringStrategy>,
     ) -> Self {
+        let calibration = calibration.into();
         Self {
+            name: name.into(),
             soil_moisture_sensor,
             pump_enabled,
-            calibration_result,
+            configured_calibration: calibration.clone(),
+            calibration,
             target_moisture_level,
+            configured_target_moisture_level: target_moisture_level,
+            vwc_curve: None,
+            vwc_target: false,
+            last_vwc: None,
+            watering_windows: Vec::new(),
+            emergency_moisture_level: None,
+            watering_strategy,
+            sampling: SamplingConfig::default(),
+            sample_filter: SamplingConfig::default().filter.build(),
+            temperature_input: None,
+            temperature_compensation: None,
+            last_temperature: None,
+            moisture_smoother: MoistureSmoother::new(MoistureSmoothingConfig::default()),
+            last_reading: None,
+            moisture_history: MoistureHistory::new(),
+            pump_safety_monitor: PumpSafetyMonitor::new(PumpSafetyLimits::default()),
+            sensor_fault_detector: SensorFaultDetector::new(SensorFaultLimits::default()),
+            reservoir_monitor: ReservoirMonitor::new(ReservoirMonitorConfig::default()),
+            pump_off_pending: false,
+            fault: None,
+            paused: false,
+            event_history: EventHistory::new(),
+            cycle: CycleRecord::default(),
+            counters: PlantCounters::default(),
         }
     }
 
-    pub fn execute(&mut self, microcontroller: &MicrocontrollerImpl) -> IrrigationStatus {
-        let moisture = self.avg_moisture_sensor_value(microcontroller);
+    /// Restricts watering to the given times of day; no windows means any
+    /// time.
+    #[inline]
+    #[must_use]
+    pub fn with_watering_windows(mut self, watering_windows: Vec<TimeWindow>) -> Self {
+        self.watering_windows = watering_windows;
+        self
+    }
+
+    /// Moisture below which the plant is watered even when watering is
+    /// otherwise not allowed.
+    #[inline]
+    #[must_use]
+    pub fn with_emergency_moisture_level(mut self, emergency_moisture_level: Percentage) -> Self {
+        self.emergency_moisture_level = Some(emergency_moisture_level);
+        self
+    }
+
+    /// Enables estimating the volumetric water content of the soil; see
+    /// [`SoilType::vwc_curve`](crate::soil::SoilType::vwc_curve).
+    #[inline]
+    #[must_use]
+    pub fn with_vwc_curve(mut self, vwc_curve: CalibrationCurve) -> Self {
+        self.vwc_curve = Some(vwc_curve);
+        self
+    }
+
+    /// Marks the calibration as the VWC curve of the soil, for a target in VWC.
+    /// It then cannot be replaced by [`PlantIrrigator::set_calibration`].
+    #[inline]
+    #[must_use]
+    pub fn with_vwc_target(mut self) -> Self {
+        self.vwc_target = true;
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_sampling(mut self, sampling: SamplingConfig) -> Self {
+        self.sample_filter = sampling.filter.build();
+        self.sampling = sampling;
+        self
+    }
+
+    /// Compensates the soil moisture sensor readings for the temperature read
+    /// from `temperature_input`.
+    #[inline]
+    #[must_use]
+    pub fn with_temperature_compensation(
+        mut self,
+        temperature_input: Option<TemperatureInput<MicrocontrollerImpl::AnalogInput>>,
+        temperature_compensation: TemperatureCompensation,
+    ) -> Self {
+        self.temperature_input = temperature_input;
+        self.temperature_compensation = Some(temperature_compensation);
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_moisture_smoothing(mut self, moisture_smoothing: MoistureSmoothingConfig) -> Self {
+        self.moisture_smoother = MoistureSmoother::new(moisture_smoothing);
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_pump_safety_limits(mut self, pump_safety_limits: PumpSafetyLimits) -> Self {
+        self.pump_safety_monitor = PumpSafetyMonitor::new(pump_safety_limits);
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_sensor_fault_limits(mut self, sensor_fault_limits: SensorFaultLimits) -> Self {
+        self.sensor_fault_detector = SensorFaultDetector::new(sensor_fault_limits);
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_reservoir_monitor_config(
+        mut self,
+        reservoir_monitor_config: ReservoirMonitorConfig,
+    ) -> Self {
+        self.reservoir_monitor = ReservoirMonitor::new(reservoir_monitor_config);
+        self
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns `true` if the irrigator is in the middle of a watering that
+    /// spans multiple cycles (e.g. bringing the moisture up to the target
+    /// maximum).
+    #[inline]
+    pub fn is_watering(&self) -> bool {
+        self.watering_strategy.is_watering()
+    }
 
-        let min_val = self.calibration_result.min_value;
-        let max_val = self.calibration_result.max_value;
-        let moisture_clamped = moisture.value().clamp(min_val.value(), max_val.value());
-        let moisture_ratio = (moisture_clamped - min_val.value()) as f32
-            / (max_val.value() - min_val.value()) as f32;
-        let moisture_percentage: Percentage = (1.0 - moisture_ratio).into();
+    #[inline]
+    pub const fn target_moisture_level(&self) -> TargetMoistureLevel {
+        self.target_moisture_level
+    }
 
+    pub fn set_target_moisture_level(&mut self, target_moisture_level: TargetMoistureLevel) {
         info!(
-            "Moisture value: {}; min: {}, max: {}, percentage: {}",
-            moisture, min_val, max_val, moisture_percentage
+            "[{}] New target level: {}",
+            self.name, target_moisture_level
         );
-        info!("Target level: {}", self.target_moisture_level);
+        self.target_moisture_level = target_moisture_level;
+    }
 
-        if moisture_percentage < self.target_moisture_level.min_value {
-            info!("Actual level below target, watering...");
+    /// Stops watering automatically until [`PlantIrrigator::resume`]. The
+    /// moisture is still measured.
+    pub fn pause(&mut self) {
+        info!("[{}] Watering paused", self.name);
+        self.paused = true;
+    }
 
-            self.pump_enabled.set_high();
-            microcontroller.wait(PUMP_ON_TIME);
-            self.pump_enabled.set_low();
+    pub fn resume(&mut self) {
+        info!("[{}] Watering resumed", self.name);
+        self.paused = false;
+    }
 
-            IrrigationStatus::Watered
+    #[inline]
+    pub const fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The most recent moisture reading, both raw and smoothed.
+    #[inline]
+    pub const fn last_reading(&self) -> Option<MoistureReading> {
+        self.last_reading
+    }
+
+    /// Replaces the temperature input, e.g. with a digital sensor that cannot
+    /// be described in the [`PlantConfig`](crate::plant_config::PlantConfig).
+    pub fn set_temperature_sensor(&mut self, temperature_sensor: Box<dyn TemperatureSensor>) {
+        self.temperature_input = Some(TemperatureInput::Sensor(temperature_sensor));
+    }
+
+    /// The temperature read during the most recent measurement.
+    #[inline]
+    pub const fn last_temperature(&self) -> Option<Temperature> {
+        self.last_temperature
+    }
+
+    /// Estimated volumetric water content at the most recent measurement.
+    /// Requires the VWC curve to be set.
+    #[inline]
+    pub const fn last_vwc(&self) -> Option<Percentage> {
+        self.last_vwc
+    }
+
+    /// History of the smoothed moisture readings.
+    #[inline]
+    pub fn moisture_history(&self) -> &MoistureHistory {
+        &self.moisture_history
+    }
+
+    #[inline]
+    pub const fn is_reservoir_suspected_empty(&self) -> bool {
+        self.reservoir_monitor.is_empty_suspected()
+    }
+
+    #[inline]
+    pub const fn calibration(&self) -> &CalibrationCurve {
+        &self.calibration
+    }
+
+    /// Replaces the calibration, e.g. after recalibrating the sensor. Not
+    /// possible with a target in VWC, as the calibration is then derived from
+    /// the soil type.
+    pub fn set_calibration(
+        &mut self,
+        calibration: CalibrationCurve,
+    ) -> Result<(), CalibrationUpdateError> {
+        if self.vwc_target {
+            return Err(CalibrationUpdateError::VwcTarget(self.name.clone()));
+        }
+        calibration
+            .validate()
+            .map_err(CalibrationUpdateError::InvalidCalibration)?;
+
+        info!("[{}] New calibration: {}", self.name, calibration);
+        self.calibration = calibration;
+        Ok(())
+    }
+
+    #[inline]
+    pub const fn counters(&self) -> &PlantCounters {
+        &self.counters
+    }
+
+    /// The state that should survive a reboot, at `now`.
+    pub fn state(&self, now: Instant) -> PlantState {
+        PlantState {
+            name: self.name.clone(),
+            calibration: self.calibration.clone(),
+            configured_calibration: Some(self.configured_calibration.clone()),
+            target: (self.target_moisture_level != self.configured_target_moisture_level)
+                .then_some(self.target_moisture_level),
+            counters: self.counters,
+            pump_runs: self.pump_safety_monitor.state(now),
+            reservoir: self.reservoir_monitor.state(),
+            moisture_history: self.moisture_history.iter().collect(),
+            paused: self.paused,
+        }
+    }
+
+    /// Restores the state saved with [`PlantIrrigator::state`], as if it had
+    /// been saved at `now`.
+    ///
+    /// The saved calibration is only restored if the configured one has not
+    /// changed since; a new calibration in the configuration wins over an
+    /// earlier recalibration. A target set since creation is restored.
+    pub fn restore_state(&mut self, state: &PlantState, now: Instant) {
+        debug_assert_eq!(state.name, self.name);
+
+        let configuration_changed = state
+            .configured_calibration
+            .as_ref()
+            .map_or(false, |calibration| {
+                *calibration != self.configured_calibration
+            });
+        if configuration_changed {
+            info!(
+                "[{}] Calibration changed in the configuration, not restoring the saved one",
+                self.name
+            );
         } else {
-            info!("Actual level above target, not watering");
+            self.calibration = state.calibration.clone();
+        }
+        if let Some(target) = state.target {
+            self.target_moisture_level = target;
+        }
+        self.counters = state.counters;
+        self.pump_safety_monitor.restore(&state.pump_runs, now);
+        self.reservoir_monitor.restore(state.reservoir);
+        self.paused = state.paused;
+        self.moisture_history = MoistureHistory::new();
+        for &moisture in &state.moisture_history {
+            self.moisture_history.push(moisture);
+        }
+    }
 
-            IrrigationStatus::NotWatered
+    /// Resumes watering after the reservoir has been suspected empty, e.g. once
+    /// it has been refilled.
+    pub fn reset_reservoir_alarm(&mut self) {
+        info!("[{}] Reservoir alarm reset", self.name);
+        self.reservoir_monitor.reset();
+    }
+
+    /// Clears the latched faults: the reservoir alarm, the sensor and hardware
+    /// faults of past cycles, and the count of identical readings of a sensor
+    /// suspected to be stuck.
+    pub fn reset_faults(&mut self) {
+        self.reset_reservoir_alarm();
+        self.sensor_fault_detector.reset();
+        self.fault = None;
+    }
+
+    /// The fault that stops manual watering until
+    /// [`PlantIrrigator::reset_faults`], if any.
+    pub fn latched_fault(&self) -> Option<IrrigationStatus> {
+        if self.reservoir_monitor.is_empty_suspected() {
+            Some(IrrigationStatus::ReservoirSuspectedEmpty)
+        } else {
+            self.fault
         }
     }
 
-    fn avg_moisture_sensor_value(&mut self, microcontroller: &MicrocontrollerImpl) -> AnalogValue {
-        const MEASUREMENTS: usize = 3;
+    /// Returns `true` if the plant's own watering windows allow watering at
+    /// `time`.
+    pub fn is_within_watering_window(&self, time: TimeOfDay) -> bool {
+        self.watering_windows.is_empty()
+            || self
+                .watering_windows
+                .iter()
+                .any(|window| window.contains(time))
+    }
+
+    pub fn execute(&mut self, microcontroller: &MicrocontrollerImpl) -> IrrigationStatus {
+        self.execute_with_permission(microcontroller, WateringPermission::Allowed)
+    }
+
+    pub fn execute_with_permission(
+        &mut self,
+        microcontroller: &MicrocontrollerImpl,
+        permission: WateringPermission,
+    ) -> IrrigationStatus {
+        info!(
+            "[{}] Target level: {}",
+            self.name, self.target_moisture_level
+        );
 
-        let mut moisture_levels = [AnalogValue::new(0); MEASUREMENTS];
-        moisture_levels[0] = self.soil_moisture_sensor.get_value();
-        for val in moisture_levels.iter_mut().take(MEASUREMENTS).skip(1) {
-            microcontroller.wait(MEASUREMENT_DELAY_TIME);
-            *val = self.soil_moisture_sensor.get_value();
+        self.cycle = CycleRecord::default();
+        let time = microcontroller.now();
+        let wall_clock = microcontroller.wall_clock();
+        let status = match self.execute_inner(microcontroller, permission) {
+            Ok(status) | Err(status) => status,
+        };
+        if let IrrigationStatus::SensorFault(_) | IrrigationStatus::HardwareError(_) = status {
+            self.fault = Some(status);
         }
 
-        moisture_levels.iter().mean()
+        let reason = if self.cycle.emergency {
+            DecisionReason::EmergencyLevel
+        } else {
+            DecisionReason::from_moisture(self.cycle.moisture, &self.target_moisture_level)
+        };
+        self.event_history.push(IrrigationEvent {
+            time,
+            wall_clock,
+            sensor_value: self.cycle.sensor_value,
+            moisture: self.cycle.moisture,
+            target: self.target_moisture_level,
+            decision: status,
+            reason,
+        });
+
+        status
+    }
+
+    /// Runs the pump for `pump_time` right away, regardless of the moisture,
+    /// the watering windows or a pause. The pump safety limits still apply,
+    /// and a [latched fault](PlantIrrigator::latched_fault) prevents it.
+    pub fn water_manually(
+        &mut self,
+        microcontroller: &MicrocontrollerImpl,
+        pump_time: Duration,
+    ) -> IrrigationStatus {
+        info!("[{}] Manual watering requested", self.name);
+
+        let time = microcontroller.now();
+        let wall_clock = microcontroller.wall_clock();
+        let mut run = || {
+            if let Some(fault) = self.latched_fault() {
+                warn!("[{}] Not watering manually: {:?}", self.name, fault);
+                return Err(fault);
+            }
+            if self.pump_off_pending {
+                self.stop_pump(microcontroller)?;
+            }
+            let moisture = self.last_reading.map(|reading| reading.raw);
+            self.run_pump(microcontroller, pump_time, moisture)?;
+            Ok(IrrigationStatus::Watered)
+        };
+        let status = match run() {
+            Ok(status) | Err(status) => status,
+        };
+
+        self.event_history.push(IrrigationEvent {
+            time,
+            wall_clock,
+            sensor_value: None,
+            moisture: None,
+            target: self.target_moisture_level,
+            decision: status,
+            reason: DecisionReason::Manual,
+        });
+        status
+    }
+
+    /// The outcomes of the recent cycles.
+    #[inline]
+    pub const fn event_history(&self) -> &EventHistory {
+        &self.event_history
+    }
+
+    /// Returns `Err` with the status explaining why the plant could not be
+    /// watered as needed.
+    fn execute_inner(
+        &mut self,
+        microcontroller: &MicrocontrollerImpl,
+        permission: WateringPermission,
+    ) -> Result<IrrigationStatus, IrrigationStatus> {
+        if self.pump_off_pending {
+            self.stop_pump(microcontroller)?;
+        }
+
+        self.sensor_fault_detector.start_cycle();
+        let mut watered = false;
+        // Pulse actions re-measure within the same cycle; make sure a
+        // misbehaving strategy cannot keep the pump going forever
+        for _ in 0..=MAX_PULSES {
+            let raw_moisture = self.measure_moisture(microcontroller)?;
+            let reading = self.moisture_smoother.update(raw_moisture);
+            self.last_reading = Some(reading);
+            if reading.filtered != reading.raw {
+                info!(
+                    "[{}] Smoothed moisture: {} (raw: {})",
+                    self.name, reading.filtered, reading.raw
+                );
+            }
+            if let Some(vwc) = self.last_vwc() {
+                info!("[{}] Estimated VWC: {}", self.name, vwc);
+            }
+            let moisture_percentage = reading.filtered;
+            self.cycle.moisture = Some(moisture_percentage);
+            self.moisture_history.push(moisture_percentage);
+
+            // Whether a watering was effective is judged by the actual
+            // readings, which the smoothing would make lag behind
+            self.reservoir_monitor
+                .record_reading(reading.raw, microcontroller.now());
+            if self.reservoir_monitor.is_empty_suspected() {
+                warn!(
+                    "[{}] Moisture does not rise after watering, reservoir suspected empty",
+                    self.name
+                );
+                return Err(IrrigationStatus::ReservoirSuspectedEmpty);
+            }
+
+            if self.paused {
+                info!("[{}] Watering paused", self.name);
+                return Ok(if watered {
+                    IrrigationStatus::Watered
+                } else {
+                    IrrigationStatus::Paused
+                });
+            }
+
+            if permission == WateringPermission::EmergencyOnly {
+                let emergency = self
+                    .emergency_moisture_level
+                    .map_or(false, |level| moisture_percentage < level);
+                if !emergency {
+                    info!("[{}] Outside of the watering hours", self.name);
+                    return if watered {
+                        Ok(IrrigationStatus::Watered)
+                    } else {
+                        Err(IrrigationStatus::OutsideWateringWindow)
+                    };
+                }
+                warn!(
+                    "[{}] Moisture critically low, watering outside of the watering hours",
+                    self.name
+                );
+                self.cycle.emergency = true;
+            }
+
+            let action = self.watering_strategy.next_action(
+                moisture_percentage,
+                &self.target_moisture_level,
+                &self.moisture_history,
+            );
+            match action {
+                WateringAction::Skip => {
+                    info!("[{}] Not watering", self.name);
+                    break;
+                }
+                WateringAction::Finish => {
+                    info!("[{}] Target level reached, stopping watering", self.name);
+                    return Ok(IrrigationStatus::TargetReached);
+                }
+                WateringAction::Water(pump_time) => {
+                    self.run_pump(microcontroller, pump_time, Some(reading.raw))?;
+                    return Ok(IrrigationStatus::Watered);
+                }
+                WateringAction::Pulse {
+                    pump_time,
+                    soak_time,
+                } => {
+                    self.run_pump(microcontroller, pump_time, Some(reading.raw))?;
+                    info!("[{}] Soaking for {:?}...", self.name, soak_time);
+                    microcontroller.wait(soak_time);
+                    watered = true;
+                }
+            }
+        }
+
+        if watered {
+            Ok(IrrigationStatus::Watered)
+        } else {
+            Ok(IrrigationStatus::NotWatered)
+        }
+    }
+
+    fn measure_moisture(
+        &mut self,
+        microcontroller: &MicrocontrollerImpl,
+    ) -> Result<Percentage, IrrigationStatus> {
+        let moisture = self
+            .filtered_moisture_sensor_value(microcontroller)
+            .map_err(|error| {
+                error!("[{}] Could not read the moisture: {}", self.name, error);
+                IrrigationStatus::HardwareError(error)
+            })?;
+        self.cycle.sensor_value = Some(moisture);
+        if let Some(fault) = self.sensor_fault_detector.check(moisture) {
+            warn!("[{}] Sensor fault: {}, not watering", self.name, fault);
+            return Err(IrrigationStatus::SensorFault(fault));
+        }
+        let moisture = self.compensate_temperature(moisture);
+
+        let moisture_percentage = self.calibration.moisture(moisture);
+        self.last_vwc = self
+            .vwc_curve
+            .as_ref()
+            .map(|vwc_curve| vwc_curve.moisture(moisture));
+
+        info!(
+            "[{}] Moisture value: {}; calibration: {}; percentage: {}",
+            self.name, moisture, self.calibration, moisture_percentage
+        );
+
+        Ok(moisture_percentage)
+    }
+
+    /// Runs the pump for `pump_time`, shortened if needed to stay within the
+    /// pump safety limits. `moisture` is the raw moisture before watering, if
+    /// known.
+    fn run_pump(
+        &mut self,
+        microcontroller: &MicrocontrollerImpl,
+        pump_time: Duration,
+        moisture: Option<Percentage>,
+    ) -> Result<(), IrrigationStatus> {
+        let now = microcontroller.now();
+        let pump_time = self
+            .pump_safety_monitor
+            .allowed_pump_time(now, pump_time)
+            .map_err(|limit| {
+                warn!("[{}] {} limit reached, not watering", self.name, limit);
+                IrrigationStatus::SafetyLimitReached(limit)
+            })?;
+
+        info!("[{}] Watering for {:?}...", self.name, pump_time);
+        let pump_enabled = &mut self.pump_enabled;
+        if let Err(error) = retry(
+            microcontroller,
+            HARDWARE_ATTEMPTS,
+            HARDWARE_RETRY_DELAY,
+            || pump_enabled.set_high(),
+        ) {
+            error!("[{}] Could not start the pump: {}", self.name, error);
+            // The pin state is unknown at this point
+            self.pump_off_pending = true;
+            let _ = self.stop_pump(microcontroller);
+            return Err(IrrigationStatus::HardwareError(error));
+        }
+        microcontroller.wait(pump_time);
+        let stop_result = self.stop_pump(microcontroller);
+        self.pump_safety_monitor.record_pump_run(now, pump_time);
+        self.counters.waterings = self.counters.waterings.saturating_add(1);
+        self.counters.pump_time += pump_time;
+        if let Some(moisture) = moisture {
+            self.reservoir_monitor.record_watering(moisture, now);
+        }
+
+        stop_result
+    }
+
+    /// Turns the pump off. If that fails, the next cycles will try again before
+    /// doing anything else.
+    fn stop_pump(&mut self, microcontroller: &MicrocontrollerImpl) -> Result<(), IrrigationStatus> {
+        let pump_enabled = &mut self.pump_enabled;
+        match retry(
+            microcontroller,
+            HARDWARE_ATTEMPTS,
+            HARDWARE_RETRY_DELAY,
+            || pump_enabled.set_low(),
+        ) {
+            Ok(()) => {
+                self.pump_off_pending = false;
+                Ok(())
+            }
+            Err(error) => {
+                error!("[{}] Could not stop the pump: {}", self.name, error);
+                self.pump_off_pending = true;
+                Err(IrrigationStatus::HardwareError(error))
+            }
+        }
+    }
+
+    /// Returns `value` unchanged if there is no temperature compensation or the
+    /// temperature could not be read.
+    fn compensate_temperature(&mut self, value: AnalogValue) -> AnalogValue {
+        let (Some(temperature_input), Some(compensation)) = (
+            self.temperature_input.as_mut(),
+            self.temperature_compensation,
+        ) else {
+            return value;
+        };
+
+        match temperature_input.read_temperature() {
+            Ok(temperature) => {
+                self.last_temperature = Some(temperature);
+                let compensated = compensation.apply(value, temperature);
+                info!(
+                    "[{}] Temperature: {}; compensated moisture value: {}",
+                    self.name, temperature, compensated
+                );
+                compensated
+            }
+            Err(error) => {
+                warn!(
+                    "[{}] Could not read the temperature, not compensating: {}",
+                    self.name, error
+                );
+                self.last_temperature = None;
+                value
+            }
+        }
+    }
+
+    fn filtered_moisture_sensor_value(
+        &mut self,
+        microcontroller: &MicrocontrollerImpl,
+    ) -> Result<AnalogValue, UcError> {
+        let sensor = &mut self.soil_moisture_sensor;
+        let mut samples = Vec::with_capacity(self.sampling.samples as usize);
+        for sample in 0..self.sampling.samples {
+            if sample > 0 {
+                microcontroller.wait(self.sampling.sample_delay);
+            }
+            samples.push(retry(
+                microcontroller,
+                HARDWARE_ATTEMPTS,
+                HARDWARE_RETRY_DELAY,
+                || sensor.get_value(),
+            )?);
+        }
+
+        Ok(self.sample_filter.filter(&samples))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CalibrationUpdateError {
+    UnknownPlant(String),
+    /// The calibration of a plant with a target in VWC is derived from its
+    /// soil type.
+    VwcTarget(String),
+    InvalidCalibration(CalibrationCurveError),
+}
+
+impl Display for CalibrationUpdateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalibrationUpdateError::UnknownPlant(plant) => write!(f, "unknown plant '{}'", plant),
+            CalibrationUpdateError::VwcTarget(plant) => write!(
+                f,
+                "the calibration of '{}' is derived from its soil type, as its target is in VWC",
+                plant
+            ),
+            CalibrationUpdateError::InvalidCalibration(error) => {
+                write!(f, "invalid calibration: {}", error)
+            }
+        }
+    }
+}
+
+impl Error for CalibrationUpdateError {}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum IrrigationStatus {
+    /// The pump was run. The irrigator keeps watering in the following cycles
+    /// until the target maximum is reached.
     Watered,
+    /// The moisture reached the target maximum and the watering has stopped.
+    TargetReached,
     NotWatered,
+    /// The plant needs water, but the pump has hit one of its safety limits.
+    SafetyLimitReached(SafetyLimit),
+    /// The soil moisture sensor readings cannot be trusted; not watering.
+    SensorFault(SensorFaultKind),
+    /// Recent waterings did not raise the moisture; not watering until the
+    /// alarm is reset manually.
+    ReservoirSuspectedEmpty,
+    /// Communication with the sensor or the pump failed, even after retrying.
+    HardwareError(UcError),
+    /// Watering is not allowed at this time of day and the moisture is not
+    /// critically low.
+    OutsideWateringWindow,
+    /// Watering has been paused; see [`PlantIrrigator::pause`].
+    Paused,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WateringPermission {
+    Allowed,
+    /// Only water if the moisture is below the emergency level.
+    EmergencyOnly,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::mock_uc::{MockMicrocontroller, MockMicrocontrollerAction};
-    use crate::uc::{GpioId, GPIO_0, GPIO_1};
+    use crate::soil::SoilType;
+    use crate::temperature::{Thermistor, ThermistorConfig};
+    use crate::uc::{GpioId, GPIO_0, GPIO_1, GPIO_2};
+    use crate::uc_utils::SampleFilterConfig;
+    use crate::watering_strategy::{PidGains, WateringStrategyConfig};
+
+    const PUMP_ON_TIME: Duration = Duration::from_millis(500);
+    const MEASUREMENT_DELAY_TIME: Duration = Duration::from_millis(500);
 
     #[test_log::test]
     fn water_when_below_target() {
@@ -207,20 +961,615 @@ mod tests {
         assert_eq!(actual_actions, expected_actions);
     }
 
+    #[test_log::test]
+    fn sample_filter_ignores_spike() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let sample_delay = Duration::from_millis(100);
+        let mut plant_irrigator = plant_irrigator.with_sampling(SamplingConfig {
+            samples: 5,
+            sample_delay,
+            filter: SampleFilterConfig::Median,
+        });
+
+        // The mean (1760 mV = 26%) would be below the target
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(1500));
+        mock_uc.queue_analog_values(GPIO_1, [1500, 1500, 2800].map(AnalogValue::new));
+
+        assert_eq!(
+            plant_irrigator.execute(&mock_uc),
+            IrrigationStatus::NotWatered
+        );
+        let waits = mock_uc
+            .actions()
+            .into_iter()
+            .filter(|action| *action == MockMicrocontrollerAction::Wait(sample_delay))
+            .count();
+        assert_eq!(waits, 4);
+    }
+
+    #[test_log::test]
+    fn smoothing_ignores_single_low_reading() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let mut plant_irrigator =
+            plant_irrigator.with_moisture_smoothing(MoistureSmoothingConfig::Ema { alpha: 0.5 });
+
+        // 500 mV = 100%, 2200 mV = 0%
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(1435));
+        assert_eq!(
+            plant_irrigator.execute(&mock_uc),
+            IrrigationStatus::NotWatered
+        );
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(1605));
+        assert_eq!(
+            plant_irrigator.execute(&mock_uc),
+            IrrigationStatus::NotWatered
+        );
+        assert_eq!(
+            plant_irrigator.last_reading(),
+            Some(MoistureReading {
+                raw: Percentage::new(35),
+                filtered: Percentage::new(40),
+            })
+        );
+    }
+
+    #[test_log::test]
+    fn temperature_compensation() {
+        let (mut mock_uc, plant_irrigator) = create_test_data();
+        let thermistor = Thermistor::new(
+            mock_uc.get_analog_input(GPIO_2).unwrap(),
+            ThermistorConfig::default(),
+        );
+        let mut plant_irrigator = plant_irrigator.with_temperature_compensation(
+            Some(TemperatureInput::Thermistor(thermistor)),
+            TemperatureCompensation {
+                reference: Temperature::from_celsius(25.0),
+                linear_coefficient: 10.0,
+                quadratic_coefficient: 0.0,
+            },
+        );
+
+        // 45 °C; 2000 mV - 200 mV = 1800 mV
+        mock_uc.set_analog_value(GPIO_2, AnalogValue::new(1000));
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        plant_irrigator.execute(&mock_uc);
+        assert!(plant_irrigator
+            .last_temperature()
+            .map_or(false, |temperature| (temperature.celsius() - 45.0).abs()
+                < 0.01));
+        assert_eq!(
+            plant_irrigator.moisture_history().latest(),
+            Some(Percentage::new(24))
+        );
+
+        // Without the temperature, the raw reading is used
+        mock_uc.inject_failures(GPIO_2, 1);
+        plant_irrigator.execute(&mock_uc);
+        assert_eq!(plant_irrigator.last_temperature(), None);
+        assert_eq!(
+            plant_irrigator.moisture_history().latest(),
+            Some(Percentage::new(12))
+        );
+    }
+
+    #[test_log::test]
+    fn emergency_watering() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let mut plant_irrigator =
+            plant_irrigator.with_emergency_moisture_level(Percentage::new(20));
+
+        // 500 mV = 100%, 2200 mV = 0%; 29%
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(1700));
+        assert_eq!(
+            plant_irrigator.execute_with_permission(&mock_uc, WateringPermission::EmergencyOnly),
+            IrrigationStatus::OutsideWateringWindow
+        );
+
+        // 12%
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        assert_eq!(
+            plant_irrigator.execute_with_permission(&mock_uc, WateringPermission::EmergencyOnly),
+            IrrigationStatus::Watered
+        );
+    }
+
+    #[test_log::test]
+    fn event_history() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let mut plant_irrigator =
+            plant_irrigator.with_emergency_moisture_level(Percentage::new(20));
+        let start = mock_uc.now();
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(1700));
+        plant_irrigator.execute_with_permission(&mock_uc, WateringPermission::EmergencyOnly);
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        plant_irrigator.execute_with_permission(&mock_uc, WateringPermission::EmergencyOnly);
+        mock_uc.inject_failures(GPIO_1, 3);
+        plant_irrigator.execute(&mock_uc);
+
+        let history = plant_irrigator.event_history();
+        let events: Vec<_> = history
+            .iter()
+            .map(|event| {
+                (
+                    event.sensor_value.map(|value| value.value()),
+                    event.moisture.map(|moisture| moisture.value()),
+                    event.decision,
+                    event.reason,
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (
+                    Some(1700),
+                    Some(29),
+                    IrrigationStatus::OutsideWateringWindow,
+                    DecisionReason::BelowTarget
+                ),
+                (
+                    Some(2000),
+                    Some(12),
+                    IrrigationStatus::Watered,
+                    DecisionReason::EmergencyLevel
+                ),
+                (
+                    None,
+                    None,
+                    IrrigationStatus::HardwareError(UcError::AnalogReadFailed(GPIO_1)),
+                    DecisionReason::MoistureUnknown
+                ),
+            ]
+        );
+        let first = history.iter().next().unwrap();
+        assert_eq!(first.time, start);
+        assert_eq!(first.wall_clock, None);
+        assert_eq!(first.target, plant_irrigator.target_moisture_level);
+        assert!(history.latest().unwrap().time > first.time);
+    }
+
+    #[test_log::test]
+    fn pause_and_resume() {
+        let (mock_uc, mut plant_irrigator) = create_test_data();
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+
+        plant_irrigator.pause();
+        assert_eq!(plant_irrigator.execute(&mock_uc), IrrigationStatus::Paused);
+        assert_eq!(
+            plant_irrigator.event_history().latest().unwrap().moisture,
+            Some(Percentage::new(12))
+        );
+        assert!(plant_irrigator.state(mock_uc.now()).paused);
+
+        plant_irrigator.resume();
+        assert_eq!(plant_irrigator.execute(&mock_uc), IrrigationStatus::Watered);
+    }
+
+    #[test_log::test]
+    fn manual_watering() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let mut plant_irrigator = plant_irrigator.with_pump_safety_limits(PumpSafetyLimits {
+            max_on_time_per_hour: Duration::from_secs(5),
+            ..PumpSafetyLimits::default()
+        });
+        plant_irrigator.pause();
+        let actions_before = mock_uc.actions().len();
+
+        assert_eq!(
+            plant_irrigator.water_manually(&mock_uc, Duration::from_secs(3)),
+            IrrigationStatus::Watered
+        );
+        assert_eq!(
+            mock_uc.actions()[actions_before..],
+            [
+                MockMicrocontrollerAction::DigitalGpioHigh(GPIO_0),
+                MockMicrocontrollerAction::Wait(Duration::from_secs(3)),
+                MockMicrocontrollerAction::DigitalGpioLow(GPIO_0),
+            ]
+        );
+        let event = plant_irrigator.event_history().latest().unwrap();
+        assert_eq!(event.reason, DecisionReason::Manual);
+        assert_eq!(plant_irrigator.counters().waterings, 1);
+
+        // Shortened to the remaining hourly runtime
+        plant_irrigator.water_manually(&mock_uc, Duration::from_secs(3));
+        assert_eq!(
+            mock_uc.actions().iter().rev().nth(1),
+            Some(&MockMicrocontrollerAction::Wait(Duration::from_secs(2)))
+        );
+        assert_eq!(
+            plant_irrigator.water_manually(&mock_uc, Duration::from_secs(3)),
+            IrrigationStatus::SafetyLimitReached(SafetyLimit::HourlyRuntime)
+        );
+    }
+
+    #[test_log::test]
+    fn no_manual_watering_with_latched_fault() {
+        let (mock_uc, mut plant_irrigator) = create_test_data();
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(0));
+        plant_irrigator.execute(&mock_uc);
+        // The sensor has been reconnected since
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        let actions_before = mock_uc.actions().len();
+
+        let fault = IrrigationStatus::SensorFault(SensorFaultKind::OpenCircuit);
+        assert_eq!(plant_irrigator.latched_fault(), Some(fault));
+        assert_eq!(
+            plant_irrigator.water_manually(&mock_uc, Duration::from_secs(3)),
+            fault
+        );
+        assert_eq!(mock_uc.actions().len(), actions_before);
+
+        let mut state = plant_irrigator.state(mock_uc.now());
+        state.reservoir.empty_suspected = true;
+        plant_irrigator.restore_state(&state, mock_uc.now());
+        assert_eq!(
+            plant_irrigator.latched_fault(),
+            Some(IrrigationStatus::ReservoirSuspectedEmpty)
+        );
+
+        plant_irrigator.reset_faults();
+        assert_eq!(plant_irrigator.latched_fault(), None);
+        assert_eq!(
+            plant_irrigator.water_manually(&mock_uc, Duration::from_secs(3)),
+            IrrigationStatus::Watered
+        );
+    }
+
+    #[test_log::test]
+    fn estimated_vwc() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let vwc_curve = SoilType::CocoCoir
+            .vwc_curve(AnalogValue::new(2200), AnalogValue::new(500))
+            .unwrap();
+        let mut plant_irrigator = plant_irrigator.with_vwc_curve(vwc_curve);
+        assert_eq!(plant_irrigator.last_vwc(), None);
+
+        // 50%
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(1350));
+        plant_irrigator.execute(&mock_uc);
+
+        assert_eq!(plant_irrigator.last_vwc(), Some(Percentage::new(30)));
+    }
+
+    #[test_log::test]
+    fn keep_watering_until_max_reached() {
+        let (mock_uc, mut plant_irrigator) = create_test_data();
+
+        // 500 mV = 100%, 2200 mV = 0%
+        let statuses: Vec<IrrigationStatus> = [2000, 1350, 1350, 840, 1350]
+            .into_iter()
+            .map(|value| {
+                mock_uc.set_analog_value(GPIO_1, AnalogValue::new(value));
+                plant_irrigator.execute(&mock_uc)
+            })
+            .collect();
+
+        assert_eq!(
+            statuses,
+            vec![
+                IrrigationStatus::Watered,
+                IrrigationStatus::Watered,
+                IrrigationStatus::Watered,
+                IrrigationStatus::TargetReached,
+                IrrigationStatus::NotWatered,
+            ]
+        );
+        assert!(!plant_irrigator.is_watering());
+    }
+
+    #[test_log::test]
+    fn dont_start_watering_between_min_and_max() {
+        let (mock_uc, mut plant_irrigator) = create_test_data();
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(1350));
+
+        assert_eq!(
+            plant_irrigator.execute(&mock_uc),
+            IrrigationStatus::NotWatered
+        );
+        assert!(!plant_irrigator.is_watering());
+    }
+
+    #[test_log::test]
+    fn pulse_and_soak_measures_between_pulses() {
+        let pulse_time = Duration::from_millis(200);
+        let soak_time = Duration::from_secs(10);
+        let (mock_uc, mut plant_irrigator) =
+            create_test_data_with_strategy(WateringStrategyConfig::PulseAndSoak {
+                pulses: 2,
+                pulse_time,
+                soak_time,
+            });
+
+        let sensor_value = AnalogValue::new(2000);
+        mock_uc.set_analog_value(GPIO_1, sensor_value);
+        let status = plant_irrigator.execute(&mock_uc);
+
+        let pulse_actions = vec![
+            MockMicrocontrollerAction::DigitalGpioHigh(GPIO_0),
+            MockMicrocontrollerAction::Wait(pulse_time),
+            MockMicrocontrollerAction::DigitalGpioLow(GPIO_0),
+            MockMicrocontrollerAction::Wait(soak_time),
+        ];
+        let mut expected_actions = Vec::new();
+        expected_actions.extend(mock_uc_irrigator_init_actions(GPIO_0, GPIO_1));
+        for _ in 0..2 {
+            expected_actions.extend(mock_uc_irrigator_measure_actions(GPIO_1, sensor_value));
+            expected_actions.extend(pulse_actions.clone());
+        }
+        expected_actions.extend(mock_uc_irrigator_measure_actions(GPIO_1, sensor_value));
+        assert_eq!(mock_uc.actions(), expected_actions);
+        assert_eq!(status, IrrigationStatus::Watered);
+    }
+
+    #[test_log::test]
+    fn pid_pump_time_follows_moisture() {
+        let (mock_uc, mut plant_irrigator) =
+            create_test_data_with_strategy(WateringStrategyConfig::Pid {
+                gains: PidGains::new(20.0, 2.0, 10.0),
+                min_pump_time: Duration::from_millis(50),
+                max_pump_time: Duration::from_secs(2),
+                integral_limit: 200.0,
+            });
+
+        // 25%, 35%, 50%, 60%; the target midpoint is 55%
+        let pump_times: Vec<Option<Duration>> = [1775, 1605, 1350, 1180]
+            .into_iter()
+            .map(|value| {
+                mock_uc.set_analog_value(GPIO_1, AnalogValue::new(value));
+                let actions_before = mock_uc.actions().len();
+                plant_irrigator.execute(&mock_uc);
+                mock_uc.actions()[actions_before..]
+                    .windows(2)
+                    .find_map(|actions| match actions {
+                        [MockMicrocontrollerAction::DigitalGpioHigh(_), MockMicrocontrollerAction::Wait(pump_time)] => {
+                            Some(*pump_time)
+                        }
+                        _ => None,
+                    })
+            })
+            .collect();
+
+        assert_eq!(
+            pump_times,
+            vec![
+                Some(Duration::from_millis(660)),
+                Some(Duration::from_millis(400)),
+                Some(Duration::from_millis(60)),
+                None,
+            ]
+        );
+    }
+
+    #[test_log::test]
+    fn stop_watering_at_safety_limit() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let mut plant_irrigator = plant_irrigator.with_pump_safety_limits(PumpSafetyLimits {
+            max_on_time_per_hour: Duration::from_millis(800),
+            ..Default::default()
+        });
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        let statuses: Vec<IrrigationStatus> =
+            (0..3).map(|_| plant_irrigator.execute(&mock_uc)).collect();
+
+        assert_eq!(
+            statuses,
+            vec![
+                IrrigationStatus::Watered,
+                IrrigationStatus::Watered,
+                IrrigationStatus::SafetyLimitReached(SafetyLimit::HourlyRuntime),
+            ]
+        );
+        // The second run is cut short to stay within the limit
+        let pump_times: Vec<Duration> = mock_uc
+            .actions()
+            .windows(2)
+            .filter_map(|actions| match actions {
+                [MockMicrocontrollerAction::DigitalGpioHigh(_), MockMicrocontrollerAction::Wait(pump_time)] => {
+                    Some(*pump_time)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            pump_times,
+            vec![Duration::from_millis(500), Duration::from_millis(300)]
+        );
+    }
+
+    #[test_log::test]
+    fn dont_water_during_cooldown() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let mut plant_irrigator = plant_irrigator.with_pump_safety_limits(PumpSafetyLimits {
+            min_cooldown: Duration::from_secs(60 * 60),
+            ..Default::default()
+        });
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+
+        assert_eq!(plant_irrigator.execute(&mock_uc), IrrigationStatus::Watered);
+        assert_eq!(
+            plant_irrigator.execute(&mock_uc),
+            IrrigationStatus::SafetyLimitReached(SafetyLimit::Cooldown)
+        );
+    }
+
+    #[test_log::test]
+    fn dont_water_with_disconnected_sensor() {
+        let (mock_uc, mut plant_irrigator) = create_test_data();
+
+        let sensor_value = AnalogValue::new(0);
+        mock_uc.set_analog_value(GPIO_1, sensor_value);
+
+        assert_eq!(
+            plant_irrigator.execute(&mock_uc),
+            IrrigationStatus::SensorFault(SensorFaultKind::OpenCircuit)
+        );
+        let mut expected_actions = Vec::new();
+        expected_actions.extend(mock_uc_irrigator_init_actions(GPIO_0, GPIO_1));
+        expected_actions.extend(mock_uc_irrigator_measure_actions(GPIO_1, sensor_value));
+        assert_eq!(mock_uc.actions(), expected_actions);
+    }
+
+    #[test_log::test]
+    fn dont_water_with_stuck_sensor() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let mut plant_irrigator = plant_irrigator.with_sensor_fault_limits(SensorFaultLimits {
+            stuck_readings: 3,
+            ..Default::default()
+        });
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        let statuses: Vec<IrrigationStatus> =
+            (0..3).map(|_| plant_irrigator.execute(&mock_uc)).collect();
+
+        assert_eq!(
+            statuses,
+            vec![
+                IrrigationStatus::Watered,
+                IrrigationStatus::Watered,
+                IrrigationStatus::SensorFault(SensorFaultKind::Stuck),
+            ]
+        );
+    }
+
+    #[test_log::test]
+    fn stop_watering_when_reservoir_empty() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let mut plant_irrigator = plant_irrigator
+            .with_reservoir_monitor_config(ReservoirMonitorConfig {
+                min_moisture_rise: 1,
+                max_ineffective_waterings: 2,
+                soak_time: Duration::from_secs(60),
+            })
+            .with_sensor_fault_limits(SensorFaultLimits {
+                stuck_readings: 0,
+                ..Default::default()
+            });
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        let statuses: Vec<IrrigationStatus> = (0..4)
+            .map(|_| {
+                let status = plant_irrigator.execute(&mock_uc);
+                mock_uc.advance_clock(Duration::from_secs(60));
+                status
+            })
+            .collect();
+
+        assert_eq!(
+            statuses,
+            vec![
+                IrrigationStatus::Watered,
+                IrrigationStatus::Watered,
+                IrrigationStatus::ReservoirSuspectedEmpty,
+                IrrigationStatus::ReservoirSuspectedEmpty,
+            ]
+        );
+        assert!(plant_irrigator.is_reservoir_suspected_empty());
+
+        plant_irrigator.reset_reservoir_alarm();
+        assert_eq!(plant_irrigator.execute(&mock_uc), IrrigationStatus::Watered);
+    }
+
+    #[test_log::test]
+    fn judge_waterings_after_soak_time() {
+        let (mock_uc, plant_irrigator) = create_test_data();
+        let mut plant_irrigator = plant_irrigator
+            .with_reservoir_monitor_config(ReservoirMonitorConfig {
+                min_moisture_rise: 1,
+                max_ineffective_waterings: 1,
+                soak_time: Duration::from_secs(600),
+            })
+            .with_sensor_fault_limits(SensorFaultLimits {
+                stuck_readings: 0,
+                ..Default::default()
+            });
+
+        // The moisture rises slowly, only after the water has soaked in
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        for _ in 0..3 {
+            assert_eq!(plant_irrigator.execute(&mock_uc), IrrigationStatus::Watered);
+        }
+        mock_uc.advance_clock(Duration::from_secs(600));
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(1900));
+        plant_irrigator.execute(&mock_uc);
+
+        assert!(!plant_irrigator.is_reservoir_suspected_empty());
+    }
+
+    #[test_log::test]
+    fn retry_transient_sensor_errors() {
+        let (mock_uc, mut plant_irrigator) = create_test_data();
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        mock_uc.inject_failures(GPIO_1, 2);
+
+        assert_eq!(plant_irrigator.execute(&mock_uc), IrrigationStatus::Watered);
+    }
+
+    #[test_log::test]
+    fn dont_water_when_sensor_fails() {
+        let (mock_uc, mut plant_irrigator) = create_test_data();
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        mock_uc.inject_failures(GPIO_1, 3);
+
+        assert_eq!(
+            plant_irrigator.execute(&mock_uc),
+            IrrigationStatus::HardwareError(UcError::AnalogReadFailed(GPIO_1))
+        );
+        assert!(!mock_uc
+            .actions()
+            .contains(&MockMicrocontrollerAction::DigitalGpioHigh(GPIO_0)));
+    }
+
+    #[test_log::test]
+    fn turn_pump_off_in_next_cycle_after_failure() {
+        let (mock_uc, mut plant_irrigator) = create_test_data();
+
+        mock_uc.set_analog_value(GPIO_1, AnalogValue::new(2000));
+        // Both turning the pump on and the attempts to turn it back off fail
+        mock_uc.inject_failures(GPIO_0, 6);
+
+        assert_eq!(
+            plant_irrigator.execute(&mock_uc),
+            IrrigationStatus::HardwareError(UcError::DigitalWriteFailed(GPIO_0))
+        );
+
+        let actions_before = mock_uc.actions().len();
+        assert_eq!(plant_irrigator.execute(&mock_uc), IrrigationStatus::Watered);
+        assert_eq!(
+            mock_uc.actions()[actions_before],
+            MockMicrocontrollerAction::DigitalGpioLow(GPIO_0)
+        );
+    }
+
     fn create_test_data() -> (MockMicrocontroller, PlantIrrigator<MockMicrocontroller>) {
+        create_test_data_with_strategy(WateringStrategyConfig::default())
+    }
+
+    fn create_test_data_with_strategy(
+        watering_strategy: WateringStrategyConfig,
+    ) -> (MockMicrocontroller, PlantIrrigator<MockMicrocontroller>) {
         let mut mock_uc = MockMicrocontroller::new();
-        let pumb_enabled = mock_uc.get_digital_output(GPIO_0);
-        let soil_sensor = mock_uc.get_analog_input(GPIO_1);
+        let pumb_enabled = mock_uc.get_digital_output(GPIO_0).unwrap();
+        let soil_sensor = mock_uc.get_analog_input(GPIO_1).unwrap();
 
         let calibration_result =
             SensorCalibrationResult::new(AnalogValue::new(500), AnalogValue::new(2200));
         let target_moisture_level =
             TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70));
         let plant_irrigator: PlantIrrigator<MockMicrocontroller> = PlantIrrigator::new(
+            "test_plant",
             soil_sensor,
             pumb_enabled,
             calibration_result,
             target_moisture_level,
+            watering_strategy.build(),
         );
 
         (mock_uc, plant_irrigator)
@@ -1,35 +1,337 @@
 use std::time::Duration;
 
-use crate::plant_irrigator::{
-    Percentage, PlantIrrigator, SensorCalibrationResult, TargetMoistureLevel,
-};
-use crate::uc::{AnalogValue, Microcontroller, GPIO_0, GPIO_2};
+use log::warn;
+
+use crate::plant_config::{validate_plant_configs, MoistureTarget, PlantConfig, PlantConfigError};
+use crate::plant_irrigator::{PlantIrrigator, WateringPermission};
+use crate::temperature::{TemperatureInput, TemperatureSensorConfig, Thermistor};
+use crate::time_window::{TimeRestrictions, TimeRestrictionsError};
+use crate::uc::Microcontroller;
 
 pub struct PlantIrrigatorController<MicrocontrollerImpl: Microcontroller> {
-    plant_irrigator_1: PlantIrrigator<MicrocontrollerImpl>,
+    plant_irrigators: Vec<PlantIrrigator<MicrocontrollerImpl>>,
+    time_restrictions: TimeRestrictions,
 }
 
 impl<MicrocontrollerImpl: Microcontroller> PlantIrrigatorController<MicrocontrollerImpl> {
-    pub fn new(microcontroller: &mut MicrocontrollerImpl) -> Self {
-        let sensor_1 = microcontroller.get_analog_input(GPIO_0);
-        let pump_1 = microcontroller.get_digital_output(GPIO_2);
-        let calibration_result =
-            SensorCalibrationResult::new(AnalogValue::new(1027), AnalogValue::new(2526));
-        let target_moisture_level =
-            TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70));
-
-        Self {
-            plant_irrigator_1: PlantIrrigator::new(
-                sensor_1,
-                pump_1,
-                calibration_result,
-                target_moisture_level,
-            ),
-        }
+    /// Creates a plant irrigator for each of the entries in `plants`.
+    ///
+    /// The whole list is validated before any GPIO is acquired, so an invalid
+    /// entry leaves the microcontroller untouched. A GPIO that the
+    /// microcontroller cannot provide is reported as
+    /// [`PlantConfigError::Gpio`].
+    pub fn new(
+        microcontroller: &mut MicrocontrollerImpl,
+        plants: &[PlantConfig],
+    ) -> Result<Self, PlantConfigError> {
+        validate_plant_configs(plants)?;
+
+        let plant_irrigators = plants
+            .iter()
+            .map(|plant| {
+                let gpio_error = |error| PlantConfigError::Gpio {
+                    plant: plant.name().to_owned(),
+                    error,
+                };
+                let sensor = microcontroller
+                    .get_analog_input(plant.sensor_gpio())
+                    .map_err(gpio_error)?;
+                let pump = microcontroller
+                    .get_digital_output(plant.pump_gpio())
+                    .map_err(gpio_error)?;
+
+                let mut plant_irrigator = PlantIrrigator::new(
+                    plant.name(),
+                    sensor,
+                    pump,
+                    plant.irrigator_calibration()?,
+                    plant.target().level(),
+                    plant.watering_strategy().build(),
+                )
+                .with_sampling(*plant.sampling())
+                .with_moisture_smoothing(*plant.moisture_smoothing())
+                .with_pump_safety_limits(*plant.pump_safety_limits())
+                .with_sensor_fault_limits(*plant.sensor_fault_limits())
+                .with_reservoir_monitor_config(*plant.reservoir_monitor())
+                .with_watering_windows(plant.watering_windows().to_vec());
+
+                if let Some(emergency_moisture_level) = plant.emergency_moisture_level() {
+                    plant_irrigator =
+                        plant_irrigator.with_emergency_moisture_level(emergency_moisture_level);
+                }
+
+                if let Some(config) = plant.temperature_compensation() {
+                    let temperature_input = match config.sensor {
+                        TemperatureSensorConfig::Thermistor { gpio, thermistor } => {
+                            let input =
+                                microcontroller.get_analog_input(gpio).map_err(gpio_error)?;
+                            Some(TemperatureInput::Thermistor(Thermistor::new(
+                                input, thermistor,
+                            )))
+                        }
+                        TemperatureSensorConfig::External => None,
+                    };
+                    plant_irrigator = plant_irrigator
+                        .with_temperature_compensation(temperature_input, config.compensation);
+                }
+
+                if let MoistureTarget::Vwc(_) = plant.target() {
+                    plant_irrigator = plant_irrigator.with_vwc_target();
+                }
+
+                Ok(match plant.vwc_curve()? {
+                    Some(vwc_curve) => plant_irrigator.with_vwc_curve(vwc_curve),
+                    None => plant_irrigator,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            plant_irrigators,
+            time_restrictions: TimeRestrictions::default(),
+        })
+    }
+
+    /// Sets the global quiet hours and the time zone used to interpret both
+    /// them and the per-plant watering windows.
+    pub fn with_time_restrictions(
+        mut self,
+        time_restrictions: TimeRestrictions,
+    ) -> Result<Self, TimeRestrictionsError> {
+        time_restrictions.validate()?;
+
+        self.time_restrictions = time_restrictions;
+        Ok(self)
+    }
+
+    #[inline]
+    pub fn plant_irrigators(&self) -> &[PlantIrrigator<MicrocontrollerImpl>] {
+        &self.plant_irrigators
+    }
+
+    #[inline]
+    pub fn plant_irrigator_mut(
+        &mut self,
+        name: &str,
+    ) -> Option<&mut PlantIrrigator<MicrocontrollerImpl>> {
+        self.plant_irrigators
+            .iter_mut()
+            .find(|plant_irrigator| plant_irrigator.name() == name)
     }
 
+    /// Waters the plants that need it. Outside of the watering windows and
+    /// during the quiet hours, only the plants with critically low moisture are
+    /// watered. If the wall clock has not been set, the time restrictions are
+    /// not enforced.
     pub fn run_cycle(&mut self, microcontroller: &MicrocontrollerImpl) {
-        self.plant_irrigator_1.execute(microcontroller);
+        let time_of_day = self
+            .time_restrictions
+            .time_of_day(microcontroller.wall_clock());
+        if time_of_day.is_none() {
+            warn!("Wall clock not set, ignoring the watering hours");
+        }
+        let quiet = time_of_day.map_or(false, |time| self.time_restrictions.is_quiet(time));
+
+        for plant_irrigator in &mut self.plant_irrigators {
+            let allowed = time_of_day.map_or(true, |time| {
+                !quiet && plant_irrigator.is_within_watering_window(time)
+            });
+            let permission = if allowed {
+                WateringPermission::Allowed
+            } else {
+                WateringPermission::EmergencyOnly
+            };
+            plant_irrigator.execute_with_permission(microcontroller, permission);
+        }
         microcontroller.wait(Duration::from_secs(5));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::mock_uc::{MockMicrocontroller, MockMicrocontrollerAction};
+    use crate::plant_irrigator::{Percentage, SensorCalibrationResult, TargetMoistureLevel};
+    use crate::soil::SoilType;
+    use crate::time_window::{TimeOfDay, TimeWindow};
+    use crate::uc::{AnalogValue, GpioId, UcError, GPIO_0, GPIO_1, GPIO_2, GPIO_3};
+
+    #[test_log::test]
+    fn run_cycle_executes_all_plants() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1),
+            plant_config("mint", GPIO_2, GPIO_3),
+        ];
+        let mut controller = PlantIrrigatorController::new(&mut mock_uc, &plants).unwrap();
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(2000));
+        mock_uc.set_analog_value(GPIO_2, AnalogValue::new(1000));
+
+        controller.run_cycle(&mock_uc);
+
+        assert_eq!(pumps_enabled(&mock_uc), vec![GPIO_1]);
+        assert_eq!(
+            mock_uc.actions().last(),
+            Some(&MockMicrocontrollerAction::Wait(Duration::from_secs(5)))
+        );
+    }
+
+    #[test_log::test]
+    fn quiet_hours() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1),
+            plant_config("mint", GPIO_2, GPIO_3).with_emergency_moisture_level(Percentage::new(20)),
+        ];
+        let mut controller = PlantIrrigatorController::new(&mut mock_uc, &plants)
+            .unwrap()
+            .with_time_restrictions(TimeRestrictions {
+                utc_offset_minutes: 60,
+                quiet_hours: vec![TimeWindow::new(TimeOfDay::new(22, 0), TimeOfDay::new(7, 0))],
+            })
+            .unwrap();
+        // 12% and 6%
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(2000));
+        mock_uc.set_analog_value(GPIO_2, AnalogValue::new(2100));
+
+        // 23:13 local time
+        mock_uc.set_wall_clock(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        controller.run_cycle(&mock_uc);
+        assert_eq!(pumps_enabled(&mock_uc), vec![GPIO_3]);
+
+        // Without the wall clock, nothing is restricted
+        let mut mock_uc = MockMicrocontroller::new();
+        let mut controller = PlantIrrigatorController::new(&mut mock_uc, &plants)
+            .unwrap()
+            .with_time_restrictions(TimeRestrictions {
+                utc_offset_minutes: 0,
+                quiet_hours: vec![TimeWindow::new(TimeOfDay::MIDNIGHT, TimeOfDay::new(23, 59))],
+            })
+            .unwrap();
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(2000));
+        controller.run_cycle(&mock_uc);
+        assert_eq!(pumps_enabled(&mock_uc), vec![GPIO_1]);
+    }
+
+    #[test_log::test]
+    fn plant_watering_windows() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1).with_watering_windows(vec![
+                TimeWindow::new(TimeOfDay::new(6, 0), TimeOfDay::new(9, 0)),
+                TimeWindow::new(TimeOfDay::new(18, 0), TimeOfDay::new(21, 0)),
+            ]),
+        ];
+        let mut controller = PlantIrrigatorController::new(&mut mock_uc, &plants).unwrap();
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(2000));
+
+        // 22:13 UTC
+        mock_uc.set_wall_clock(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        controller.run_cycle(&mock_uc);
+        assert!(pumps_enabled(&mock_uc).is_empty());
+
+        // 20:13 UTC the next day
+        mock_uc.advance_clock(Duration::from_secs(22 * 60 * 60));
+        controller.run_cycle(&mock_uc);
+        assert_eq!(pumps_enabled(&mock_uc), vec![GPIO_1]);
+    }
+
+    #[test]
+    fn invalid_config_does_not_acquire_gpio() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1),
+            plant_config("mint", GPIO_1, GPIO_2),
+        ];
+
+        let result = PlantIrrigatorController::new(&mut mock_uc, &plants);
+
+        assert_eq!(
+            result.err(),
+            Some(PlantConfigError::GpioAlreadyUsed {
+                plant: "mint".to_owned(),
+                gpio: GPIO_1,
+            })
+        );
+        assert!(mock_uc.actions().is_empty());
+    }
+
+    #[test]
+    fn unsupported_gpio() {
+        let mut mock_uc = MockMicrocontroller::new();
+        mock_uc.set_gpio_unsupported(GPIO_3);
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1),
+            plant_config("mint", GPIO_2, GPIO_3),
+        ];
+
+        let result = PlantIrrigatorController::new(&mut mock_uc, &plants);
+
+        assert_eq!(
+            result.err(),
+            Some(PlantConfigError::Gpio {
+                plant: "mint".to_owned(),
+                error: UcError::GpioNotSupported(GPIO_3),
+            })
+        );
+    }
+
+    #[test_log::test]
+    fn vwc_target_is_the_same_for_all_sensors() {
+        let target = TargetMoistureLevel::new(Percentage::new(20), Percentage::new(30));
+        let plants = [
+            plant_config("basil", GPIO_0, GPIO_1)
+                .with_soil_type(SoilType::PottingMix)
+                .with_vwc_target(target),
+            PlantConfig::new(
+                "mint",
+                GPIO_2,
+                GPIO_3,
+                SensorCalibrationResult::new(AnalogValue::new(1000), AnalogValue::new(2000)),
+                target,
+            )
+            .with_soil_type(SoilType::PottingMix)
+            .with_vwc_target(target),
+        ];
+
+        // Both sensors halfway between the air and water: 25% VWC
+        let mut mock_uc = MockMicrocontroller::new();
+        let mut controller = PlantIrrigatorController::new(&mut mock_uc, &plants).unwrap();
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(1350));
+        mock_uc.set_analog_value(GPIO_2, AnalogValue::new(1500));
+        controller.run_cycle(&mock_uc);
+        assert!(pumps_enabled(&mock_uc).is_empty());
+
+        // Both at 35% of the way from the air to water: 17% VWC
+        mock_uc.set_analog_value(GPIO_0, AnalogValue::new(1605));
+        mock_uc.set_analog_value(GPIO_2, AnalogValue::new(1650));
+        controller.run_cycle(&mock_uc);
+        assert_eq!(pumps_enabled(&mock_uc), vec![GPIO_1, GPIO_3]);
+        for plant_irrigator in controller.plant_irrigators() {
+            assert_eq!(plant_irrigator.last_vwc(), Some(Percentage::new(17)));
+        }
+    }
+
+    fn pumps_enabled(mock_uc: &MockMicrocontroller) -> Vec<GpioId> {
+        mock_uc
+            .actions()
+            .into_iter()
+            .filter_map(|action| match action {
+                MockMicrocontrollerAction::DigitalGpioHigh(id) => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn plant_config(name: &str, sensor_gpio: GpioId, pump_gpio: GpioId) -> PlantConfig {
+        PlantConfig::new(
+            name,
+            sensor_gpio,
+            pump_gpio,
+            SensorCalibrationResult::new(AnalogValue::new(500), AnalogValue::new(2200)),
+            TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+        )
+    }
+}
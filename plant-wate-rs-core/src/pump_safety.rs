@@ -0,0 +1,389 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Amount of water a pump may deliver within a rolling day.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WaterBudget {
+    /// Millilitres per rolling day.
+    pub millilitres: u32,
+    /// Pump flow rate, in millilitres per second.
+    pub pump_flow_rate: f32,
+}
+
+impl WaterBudget {
+    #[inline]
+    pub const fn new(millilitres: u32, pump_flow_rate: f32) -> Self {
+        Self {
+            millilitres,
+            pump_flow_rate,
+        }
+    }
+
+    /// Pump on-time corresponding to the whole budget; `None` if it cannot be
+    /// represented, e.g. for a flow rate of zero.
+    pub fn max_on_time(&self) -> Option<Duration> {
+        Duration::try_from_secs_f32(self.millilitres as f32 / self.pump_flow_rate).ok()
+    }
+}
+
+/// Limits enforced on every pump run, regardless of what the watering strategy
+/// asks for.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PumpSafetyLimits {
+    /// Maximum cumulative pump on-time within a rolling hour.
+    pub max_on_time_per_hour: Duration,
+    /// Maximum cumulative pump on-time within a rolling day.
+    pub max_on_time_per_day: Duration,
+    /// Minimum time between the end of a pump run and the start of the next
+    /// one.
+    pub min_cooldown: Duration,
+    pub daily_water_budget: Option<WaterBudget>,
+}
+
+impl Default for PumpSafetyLimits {
+    fn default() -> Self {
+        Self {
+            max_on_time_per_hour: Duration::from_secs(60),
+            max_on_time_per_day: Duration::from_secs(5 * 60),
+            min_cooldown: Duration::ZERO,
+            daily_water_budget: None,
+        }
+    }
+}
+
+impl PumpSafetyLimits {
+    pub fn is_valid(&self) -> bool {
+        let budget_valid = self.daily_water_budget.map_or(true, |budget| {
+            budget.millilitres > 0
+                && budget.pump_flow_rate.is_finite()
+                && budget.pump_flow_rate > 0.0
+                && budget.max_on_time().is_some()
+        });
+
+        !self.max_on_time_per_hour.is_zero()
+            && self.max_on_time_per_hour <= self.max_on_time_per_day
+            && budget_valid
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SafetyLimit {
+    Cooldown,
+    HourlyRuntime,
+    DailyRuntime,
+    DailyWaterBudget,
+}
+
+impl Display for SafetyLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            SafetyLimit::Cooldown => "pump cooldown",
+            SafetyLimit::HourlyRuntime => "hourly pump runtime",
+            SafetyLimit::DailyRuntime => "daily pump runtime",
+            SafetyLimit::DailyWaterBudget => "daily water budget",
+        };
+        f.write_str(description)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PumpRun {
+    start: Instant,
+    duration: Duration,
+}
+
+impl PumpRun {
+    fn end(&self) -> Instant {
+        self.start + self.duration
+    }
+
+    /// Part of the run that falls into the window ending at `now`.
+    fn time_within(&self, now: Instant, window: Duration) -> Duration {
+        let window_start = now.checked_sub(window);
+        match window_start {
+            Some(window_start) if self.end() <= window_start => Duration::ZERO,
+            Some(window_start) if self.start < window_start => self.end() - window_start,
+            _ => self.duration,
+        }
+    }
+}
+
+/// A pump run as saved across reboots, relative to when it was saved, as
+/// [`Instant`]s do not survive a reboot.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PumpRunState {
+    /// From the start of the run to when it was saved.
+    pub started_ago: Duration,
+    pub duration: Duration,
+}
+
+/// Keeps track of the pump runs of a single pump and enforces the
+/// [`PumpSafetyLimits`].
+#[derive(Debug, Clone)]
+pub struct PumpSafetyMonitor {
+    limits: PumpSafetyLimits,
+    runs: VecDeque<PumpRun>,
+}
+
+impl PumpSafetyMonitor {
+    #[inline]
+    pub fn new(limits: PumpSafetyLimits) -> Self {
+        Self {
+            limits,
+            runs: VecDeque::new(),
+        }
+    }
+
+    #[inline]
+    pub const fn limits(&self) -> &PumpSafetyLimits {
+        &self.limits
+    }
+
+    /// Returns the pump time that can be used at `now`: `requested`, shortened
+    /// if needed so that no limit is exceeded, or the limit that prevents the
+    /// pump from running at all.
+    pub fn allowed_pump_time(
+        &self,
+        now: Instant,
+        requested: Duration,
+    ) -> Result<Duration, SafetyLimit> {
+        if let Some(last_run) = self.runs.back() {
+            if now.saturating_duration_since(last_run.end()) < self.limits.min_cooldown {
+                return Err(SafetyLimit::Cooldown);
+            }
+        }
+
+        let hourly = self.remaining(now, HOUR, self.limits.max_on_time_per_hour);
+        let daily = self.remaining(now, DAY, self.limits.max_on_time_per_day);
+        let budget = self
+            .limits
+            .daily_water_budget
+            // Never `None` with valid limits
+            .map(|budget| self.remaining(now, DAY, budget.max_on_time().unwrap_or_default()));
+
+        let mut allowed = requested;
+        for (remaining, limit) in [
+            (Some(hourly), SafetyLimit::HourlyRuntime),
+            (Some(daily), SafetyLimit::DailyRuntime),
+            (budget, SafetyLimit::DailyWaterBudget),
+        ] {
+            match remaining {
+                Some(remaining) if remaining.is_zero() => return Err(limit),
+                Some(remaining) => allowed = allowed.min(remaining),
+                None => {}
+            }
+        }
+
+        Ok(allowed)
+    }
+
+    pub fn record_pump_run(&mut self, start: Instant, duration: Duration) {
+        while let Some(run) = self.runs.front() {
+            if run.time_within(start, DAY).is_zero() {
+                self.runs.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        self.runs.push_back(PumpRun { start, duration });
+    }
+
+    /// Cumulative pump on-time within the window ending at `now`.
+    pub fn on_time_within(&self, now: Instant, window: Duration) -> Duration {
+        self.runs
+            .iter()
+            .map(|run| run.time_within(now, window))
+            .sum()
+    }
+
+    /// The runs that may still count towards a limit, relative to `now`.
+    pub fn state(&self, now: Instant) -> Vec<PumpRunState> {
+        self.runs
+            .iter()
+            .map(|run| PumpRunState {
+                started_ago: now.saturating_duration_since(run.start),
+                duration: run.duration,
+            })
+            .collect()
+    }
+
+    /// Restores the runs saved with [`PumpSafetyMonitor::state`] as if they
+    /// had been saved at `now`. How long the controller was off is not known,
+    /// so the limits may be enforced for longer than needed, but never for
+    /// shorter.
+    pub fn restore(&mut self, runs: &[PumpRunState], now: Instant) {
+        self.runs = runs
+            .iter()
+            .map(|run| PumpRun {
+                // Shortly after booting, an `Instant` may not reach that far back
+                start: now.checked_sub(run.started_ago).unwrap_or(now),
+                duration: run.duration,
+            })
+            .collect();
+    }
+
+    fn remaining(&self, now: Instant, window: Duration, limit: Duration) -> Duration {
+        limit.saturating_sub(self.on_time_within(now, window))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn cooldown() {
+        let limits = PumpSafetyLimits {
+            min_cooldown: 60 * SECOND,
+            ..Default::default()
+        };
+        let mut monitor = PumpSafetyMonitor::new(limits);
+        let start = Instant::now();
+
+        monitor.record_pump_run(start, SECOND);
+
+        assert_eq!(
+            monitor.allowed_pump_time(start + 30 * SECOND, SECOND),
+            Err(SafetyLimit::Cooldown)
+        );
+        assert_eq!(
+            monitor.allowed_pump_time(start + 61 * SECOND, SECOND),
+            Ok(SECOND)
+        );
+    }
+
+    #[test]
+    fn hourly_runtime_rolls_over() {
+        let limits = PumpSafetyLimits {
+            max_on_time_per_hour: 10 * SECOND,
+            ..Default::default()
+        };
+        let mut monitor = PumpSafetyMonitor::new(limits);
+        let start = Instant::now();
+
+        monitor.record_pump_run(start, 6 * SECOND);
+        assert_eq!(
+            monitor.allowed_pump_time(start + 60 * SECOND, 6 * SECOND),
+            Ok(4 * SECOND)
+        );
+
+        monitor.record_pump_run(start + 60 * SECOND, 4 * SECOND);
+        assert_eq!(
+            monitor.allowed_pump_time(start + 120 * SECOND, SECOND),
+            Err(SafetyLimit::HourlyRuntime)
+        );
+
+        // The first run has left the window
+        assert_eq!(
+            monitor.allowed_pump_time(start + HOUR + 10 * SECOND, 10 * SECOND),
+            Ok(6 * SECOND)
+        );
+    }
+
+    #[test]
+    fn daily_runtime() {
+        let limits = PumpSafetyLimits {
+            max_on_time_per_hour: 30 * SECOND,
+            max_on_time_per_day: 60 * SECOND,
+            ..Default::default()
+        };
+        let mut monitor = PumpSafetyMonitor::new(limits);
+        let start = Instant::now();
+
+        monitor.record_pump_run(start, 30 * SECOND);
+        monitor.record_pump_run(start + 2 * HOUR, 30 * SECOND);
+
+        assert_eq!(
+            monitor.allowed_pump_time(start + 4 * HOUR, SECOND),
+            Err(SafetyLimit::DailyRuntime)
+        );
+        assert_eq!(
+            monitor.allowed_pump_time(start + DAY + HOUR, 10 * SECOND),
+            Ok(10 * SECOND)
+        );
+    }
+
+    #[test]
+    fn daily_water_budget() {
+        let limits = PumpSafetyLimits {
+            daily_water_budget: Some(WaterBudget::new(100, 20.0)),
+            ..Default::default()
+        };
+        let mut monitor = PumpSafetyMonitor::new(limits);
+        let start = Instant::now();
+
+        assert_eq!(
+            monitor.allowed_pump_time(start, 10 * SECOND),
+            Ok(5 * SECOND)
+        );
+        monitor.record_pump_run(start, 5 * SECOND);
+        assert_eq!(
+            monitor.allowed_pump_time(start + 2 * HOUR, SECOND),
+            Err(SafetyLimit::DailyWaterBudget)
+        );
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let start = Instant::now();
+        let mut monitor = PumpSafetyMonitor::new(PumpSafetyLimits {
+            min_cooldown: Duration::from_secs(10 * 60),
+            ..PumpSafetyLimits::default()
+        });
+        monitor.record_pump_run(start, Duration::from_secs(40));
+        let now = start + Duration::from_secs(5 * 60);
+        let state = monitor.state(now);
+        assert_eq!(
+            state,
+            vec![PumpRunState {
+                started_ago: Duration::from_secs(5 * 60),
+                duration: Duration::from_secs(40),
+            }]
+        );
+
+        // After a reboot, in which the clock starts over
+        let mut restored = PumpSafetyMonitor::new(*monitor.limits());
+        let reboot = now + Duration::from_secs(3600);
+        restored.restore(&state, reboot);
+        assert_eq!(restored.state(reboot), state);
+        assert_eq!(
+            restored.allowed_pump_time(reboot, Duration::from_secs(30)),
+            Err(SafetyLimit::Cooldown)
+        );
+        assert_eq!(
+            restored.allowed_pump_time(
+                reboot + Duration::from_secs(6 * 60),
+                Duration::from_secs(30)
+            ),
+            Ok(Duration::from_secs(20))
+        );
+    }
+
+    #[test]
+    fn limits_validation() {
+        assert!(PumpSafetyLimits::default().is_valid());
+        assert!(!PumpSafetyLimits {
+            max_on_time_per_hour: Duration::ZERO,
+            ..Default::default()
+        }
+        .is_valid());
+        assert!(!PumpSafetyLimits {
+            daily_water_budget: Some(WaterBudget::new(100, 0.0)),
+            ..Default::default()
+        }
+        .is_valid());
+        assert!(!PumpSafetyLimits {
+            daily_water_budget: Some(WaterBudget::new(1, 1e-39)),
+            ..Default::default()
+        }
+        .is_valid());
+        assert_eq!(WaterBudget::new(1, 1e-39).max_on_time(), None);
+    }
+}
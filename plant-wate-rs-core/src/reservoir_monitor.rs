@@ -0,0 +1,217 @@
+use std::time::{Duration, Instant};
+
+use crate::plant_irrigator::Percentage;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ReservoirMonitorConfig {
+    /// Minimum moisture rise, in percentage points, for a watering to count as
+    /// effective.
+    pub min_moisture_rise: u8,
+    /// Number of ineffective waterings in a row after which the reservoir is
+    /// suspected to be empty; `0` disables the check.
+    pub max_ineffective_waterings: u8,
+    /// How long the water is given to soak in after the first of a series of
+    /// waterings before the moisture rise is measured.
+    pub soak_time: Duration,
+}
+
+impl Default for ReservoirMonitorConfig {
+    fn default() -> Self {
+        Self {
+            min_moisture_rise: 1,
+            max_ineffective_waterings: 5,
+            soak_time: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// The part of [`ReservoirMonitor`] that should survive a reboot.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ReservoirMonitorState {
+    pub moisture_before_watering: Option<Percentage>,
+    pub ineffective_waterings: u8,
+    pub empty_suspected: bool,
+}
+
+/// Detects an empty water reservoir by watching whether the moisture rises
+/// after each watering.
+///
+/// Once the reservoir is suspected to be empty, the state is latched until
+/// [`ReservoirMonitor::reset`] is called.
+#[derive(Debug, Clone)]
+pub struct ReservoirMonitor {
+    config: ReservoirMonitorConfig,
+    moisture_before_watering: Option<Percentage>,
+    /// When the first watering being judged has been made; `None` if the soak
+    /// time is known to be over.
+    first_watering: Option<Instant>,
+    ineffective_waterings: u8,
+    empty_suspected: bool,
+}
+
+impl ReservoirMonitor {
+    #[inline]
+    pub fn new(config: ReservoirMonitorConfig) -> Self {
+        Self {
+            config,
+            moisture_before_watering: None,
+            first_watering: None,
+            ineffective_waterings: 0,
+            empty_suspected: false,
+        }
+    }
+
+    #[inline]
+    pub const fn config(&self) -> &ReservoirMonitorConfig {
+        &self.config
+    }
+
+    #[inline]
+    pub const fn is_empty_suspected(&self) -> bool {
+        self.empty_suspected
+    }
+
+    #[inline]
+    pub const fn ineffective_waterings(&self) -> u8 {
+        self.ineffective_waterings
+    }
+
+    /// Records that the plant has been watered at `time` when the moisture was
+    /// `moisture_before`. Waterings within the soak time of the first one are
+    /// judged together, against the moisture before it, so that watering on
+    /// every cycle does not postpone the judgement.
+    pub fn record_watering(&mut self, moisture_before: Percentage, time: Instant) {
+        self.moisture_before_watering.get_or_insert(moisture_before);
+        self.first_watering.get_or_insert(time);
+    }
+
+    /// Records a moisture reading taken at `time`; the first reading once the
+    /// soak time has passed after the first watering is used to tell whether
+    /// the watering was effective.
+    pub fn record_reading(&mut self, moisture: Percentage, time: Instant) {
+        let soaking = self.first_watering.map_or(false, |first_watering| {
+            time.saturating_duration_since(first_watering) < self.config.soak_time
+        });
+        if soaking {
+            return;
+        }
+        self.first_watering = None;
+        let Some(moisture_before) = self.moisture_before_watering.take() else {
+            return;
+        };
+
+        let rise = moisture.value().saturating_sub(moisture_before.value());
+        if rise >= self.config.min_moisture_rise {
+            self.ineffective_waterings = 0;
+        } else {
+            self.ineffective_waterings = self.ineffective_waterings.saturating_add(1);
+            if self.config.max_ineffective_waterings > 0
+                && self.ineffective_waterings >= self.config.max_ineffective_waterings
+            {
+                self.empty_suspected = true;
+            }
+        }
+    }
+
+    pub fn state(&self) -> ReservoirMonitorState {
+        ReservoirMonitorState {
+            moisture_before_watering: self.moisture_before_watering,
+            ineffective_waterings: self.ineffective_waterings,
+            empty_suspected: self.empty_suspected,
+        }
+    }
+
+    /// The soak time of a watering recorded in `state` is considered over.
+    pub fn restore(&mut self, state: ReservoirMonitorState) {
+        self.moisture_before_watering = state.moisture_before_watering;
+        self.first_watering = None;
+        self.ineffective_waterings = state.ineffective_waterings;
+        self.empty_suspected = state.empty_suspected;
+    }
+
+    /// Clears the latched state, e.g. after the reservoir has been refilled.
+    pub fn reset(&mut self) {
+        self.moisture_before_watering = None;
+        self.first_watering = None;
+        self.ineffective_waterings = 0;
+        self.empty_suspected = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOAK_TIME: Duration = Duration::from_secs(600);
+
+    fn monitor(min_moisture_rise: u8, max_ineffective_waterings: u8) -> ReservoirMonitor {
+        ReservoirMonitor::new(ReservoirMonitorConfig {
+            min_moisture_rise,
+            max_ineffective_waterings,
+            soak_time: SOAK_TIME,
+        })
+    }
+
+    #[test]
+    fn latch_after_ineffective_waterings() {
+        let mut monitor = monitor(2, 3);
+        let start = Instant::now();
+
+        for moisture in [30, 31, 31] {
+            monitor.record_watering(Percentage::new(moisture), start);
+            assert!(!monitor.is_empty_suspected());
+            monitor.record_reading(Percentage::new(moisture + 1), start + SOAK_TIME);
+        }
+
+        assert!(monitor.is_empty_suspected());
+
+        // Stays latched even if the moisture goes up afterwards
+        monitor.record_watering(Percentage::new(32), start);
+        monitor.record_reading(Percentage::new(50), start + SOAK_TIME);
+        assert!(monitor.is_empty_suspected());
+
+        monitor.reset();
+        assert!(!monitor.is_empty_suspected());
+    }
+
+    #[test]
+    fn effective_watering_resets_counter() {
+        let mut monitor = monitor(2, 2);
+        let start = Instant::now();
+
+        monitor.record_watering(Percentage::new(30), start);
+        monitor.record_reading(Percentage::new(30), start + SOAK_TIME);
+        assert_eq!(monitor.ineffective_waterings(), 1);
+
+        monitor.record_watering(Percentage::new(30), start);
+        monitor.record_reading(Percentage::new(35), start + SOAK_TIME);
+        assert_eq!(monitor.ineffective_waterings(), 0);
+
+        // Readings without a watering in between are ignored
+        monitor.record_reading(Percentage::new(35), start + SOAK_TIME);
+        assert_eq!(monitor.ineffective_waterings(), 0);
+        assert!(!monitor.is_empty_suspected());
+    }
+
+    #[test]
+    fn judge_after_soak_time() {
+        let mut monitor = monitor(2, 1);
+        let start = Instant::now();
+
+        // The water has not reached the sensor yet
+        monitor.record_watering(Percentage::new(30), start);
+        monitor.record_reading(Percentage::new(30), start + Duration::from_secs(5));
+        assert_eq!(monitor.ineffective_waterings(), 0);
+
+        // Watering again while soaking keeps the moisture before, and the time
+        // of, the first one
+        let second_watering = start + Duration::from_secs(60);
+        monitor.record_watering(Percentage::new(31), second_watering);
+        monitor.record_reading(Percentage::new(31), start + SOAK_TIME / 2);
+        assert_eq!(monitor.ineffective_waterings(), 0);
+
+        monitor.record_reading(Percentage::new(32), start + SOAK_TIME);
+        assert_eq!(monitor.ineffective_waterings(), 0);
+        assert!(!monitor.is_empty_suspected());
+    }
+}
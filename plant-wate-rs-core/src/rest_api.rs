@@ -0,0 +1,613 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, SystemTime};
+
+use crate::commands::{parse_fields, parse_percentage, u64_field, Command, CommandError, Fields};
+use crate::controller::Controller;
+use crate::event_history::IrrigationEvent;
+use crate::history_log::{hourly_moisture, HistoryEntry, HourlyMoisture, HOURLY_MOISTURE_PERIOD};
+use crate::http::{HttpRequest, HttpResponse, Method};
+use crate::json::{self, JsonObject};
+use crate::plant_irrigator::{IrrigationStatus, PlantIrrigator, TargetMoistureLevel};
+use crate::telemetry::status_payload;
+use crate::time_window::format_rfc3339;
+use crate::uc::Microcontroller;
+
+/// Limits of the requests to the REST API.
+///
+/// The endpoints are:
+/// * `GET /api/plants`: the live state of all the plants;
+/// * `GET /api/plants/{plant}`: the live state of one plant;
+/// * `GET /api/plants/{plant}/history`: its recent irrigation events;
+/// * `GET /api/plants/{plant}/chart`: its hourly moisture over the last
+///   [`CHART_PERIOD`], from the history log if there is one;
+/// * `GET /api/plants/{plant}/config` and `PATCH /api/plants/{plant}/config`:
+///   its target moisture level and whether it is paused, e.g. `{"target_min":
+///   40, "target_max": 70, "paused": false}`;
+/// * `POST /api/plants/{plant}/water`: a manual watering, e.g. `{"duration_ms":
+///   3000}`.
+///
+/// The bodies of the requests changing the state must be sent as
+/// `application/json`, so that browsers do not let other sites send them
+/// without a CORS preflight.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RestApiConfig {
+    /// Longest watering that can be requested.
+    pub max_manual_watering: Duration,
+}
+
+impl Default for RestApiConfig {
+    fn default() -> Self {
+        Self {
+            max_manual_watering: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RestApiConfig {
+    pub fn validate(&self) -> Result<(), RestApiConfigError> {
+        if self.max_manual_watering.is_zero() {
+            return Err(RestApiConfigError::ZeroMaxManualWatering);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RestApiConfigError {
+    ZeroMaxManualWatering,
+}
+
+impl Display for RestApiConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestApiConfigError::ZeroMaxManualWatering => {
+                write!(f, "the longest manual watering must not be zero")
+            }
+        }
+    }
+}
+
+impl Error for RestApiConfigError {}
+
+pub const CHART_PERIOD: Duration = HOURLY_MOISTURE_PERIOD;
+
+/// Routes `request` to its handler.
+pub fn handle_request<MicrocontrollerImpl: Microcontroller>(
+    controller: &mut Controller<MicrocontrollerImpl>,
+    config: &RestApiConfig,
+    request: &HttpRequest,
+) -> HttpResponse {
+    let Some(path) = request.path.strip_prefix("/api/") else {
+        return HttpResponse::error(404, "not found");
+    };
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    let (plant, resource) = match segments.as_slice() {
+        ["plants"] => {
+            return match request.method {
+                Method::Get => list_plants(controller),
+                _ => method_not_allowed(),
+            }
+        }
+        ["plants", plant] => (*plant, None),
+        ["plants", plant, resource] => (*plant, Some(*resource)),
+        _ => return HttpResponse::error(404, "not found"),
+    };
+
+    let Some(plant) = percent_decode(plant) else {
+        return HttpResponse::error(400, "invalid plant name");
+    };
+    let Some(plant_irrigator) = controller
+        .plant_irrigators()
+        .iter()
+        .find(|plant_irrigator| plant_irrigator.name() == plant)
+    else {
+        return error_response(&CommandError::UnknownPlant(plant));
+    };
+
+    match (request.method, resource) {
+        (Method::Get, None) => HttpResponse::json(200, plant_json(plant_irrigator)),
+        (Method::Get, Some("history")) => HttpResponse::json(
+            200,
+            json::array(plant_irrigator.event_history().iter().map(event_json)),
+        ),
+        (Method::Get, Some("chart")) => chart(controller, plant_irrigator),
+        (Method::Get, Some("config")) => HttpResponse::json(200, config_json(plant_irrigator)),
+        (Method::Patch, Some("config")) | (Method::Post, Some("water")) if !is_json(request) => {
+            HttpResponse::error(415, "the body must be application/json")
+        }
+        (Method::Patch, Some("config")) => update_config(controller, &plant, &request.body),
+        (Method::Post, Some("water")) => water(controller, config, &plant, &request.body),
+        (_, None | Some("history" | "chart" | "config" | "water")) => method_not_allowed(),
+        _ => HttpResponse::error(404, "not found"),
+    }
+}
+
+fn list_plants<MicrocontrollerImpl: Microcontroller>(
+    controller: &Controller<MicrocontrollerImpl>,
+) -> HttpResponse {
+    HttpResponse::json(
+        200,
+        json::array(controller.plant_irrigators().iter().map(plant_json)),
+    )
+}
+
+fn chart<MicrocontrollerImpl: Microcontroller>(
+    controller: &Controller<MicrocontrollerImpl>,
+    plant_irrigator: &PlantIrrigator<MicrocontrollerImpl>,
+) -> HttpResponse {
+    let Some(now) = controller.microcontroller().wall_clock() else {
+        return HttpResponse::json(200, json::array([]));
+    };
+    let since = now
+        .checked_sub(CHART_PERIOD)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    let hours = match controller.history_log() {
+        Some(history_log) => history_log.hourly_moisture(plant_irrigator.name(), since),
+        None => {
+            let entries: Vec<_> = plant_irrigator
+                .event_history()
+                .iter()
+                .map(|event| HistoryEntry::new(plant_irrigator.name(), event))
+                .collect();
+            hourly_moisture(&entries, plant_irrigator.name(), since)
+        }
+    };
+    HttpResponse::json(200, json::array(hours.iter().map(hour_json)))
+}
+
+fn update_config<MicrocontrollerImpl: Microcontroller>(
+    controller: &mut Controller<MicrocontrollerImpl>,
+    plant: &str,
+    body: &[u8],
+) -> HttpResponse {
+    let current = controller
+        .plant_irrigators()
+        .iter()
+        .find(|plant_irrigator| plant_irrigator.name() == plant)
+        .map(|plant_irrigator| plant_irrigator.target_moisture_level());
+    let Some(current) = current else {
+        return error_response(&CommandError::UnknownPlant(plant.to_owned()));
+    };
+
+    let commands = match parse_fields(body).and_then(|fields| config_commands(&fields, current)) {
+        Ok(commands) => commands,
+        Err(error) => return error_response(&error),
+    };
+    for command in commands {
+        if let Err(error) = controller.execute_command(plant, command) {
+            return error_response(&error);
+        }
+    }
+
+    let plant_irrigator = controller
+        .plant_irrigators()
+        .iter()
+        .find(|plant_irrigator| plant_irrigator.name() == plant)
+        .expect("the plant has been found before");
+    HttpResponse::json(200, config_json(plant_irrigator))
+}
+
+/// The commands applying the members of a configuration update. Members left
+/// out are not changed.
+fn config_commands(
+    fields: &Fields,
+    current: TargetMoistureLevel,
+) -> Result<Vec<Command>, CommandError> {
+    let mut commands = Vec::new();
+
+    let min = match fields.get("target_min") {
+        Some(_) => Some(parse_percentage(fields, "target_min")?),
+        None => None,
+    };
+    let max = match fields.get("target_max") {
+        Some(_) => Some(parse_percentage(fields, "target_max")?),
+        None => None,
+    };
+    if min.is_some() || max.is_some() {
+        let min = min.unwrap_or(current.min_value());
+        let max = max.unwrap_or(current.max_value());
+        if min >= max {
+            return Err(CommandError::InvalidTarget);
+        }
+        commands.push(Command::SetTarget(TargetMoistureLevel::new(min, max)));
+    }
+
+    if let Some(paused) = fields.get("paused") {
+        match paused.as_bool() {
+            Some(true) => commands.push(Command::Pause),
+            Some(false) => commands.push(Command::Resume),
+            None => return Err(CommandError::InvalidField("paused")),
+        }
+    }
+    Ok(commands)
+}
+
+fn water<MicrocontrollerImpl: Microcontroller>(
+    controller: &mut Controller<MicrocontrollerImpl>,
+    config: &RestApiConfig,
+    plant: &str,
+    body: &[u8],
+) -> HttpResponse {
+    let pump_time = parse_fields(body)
+        .and_then(|fields| u64_field(&fields, "duration_ms"))
+        .map(Duration::from_millis)
+        .and_then(|requested| {
+            let max = config.max_manual_watering;
+            if requested.is_zero() {
+                Err(CommandError::InvalidField("duration_ms"))
+            } else if requested > max {
+                Err(CommandError::WateringTooLong { requested, max })
+            } else {
+                Ok(requested)
+            }
+        });
+
+    match pump_time
+        .and_then(|pump_time| controller.execute_command(plant, Command::Water(pump_time)))
+    {
+        Ok(()) => HttpResponse::json(
+            200,
+            JsonObject::new()
+                .string("status", status_payload(&IrrigationStatus::Watered))
+                .finish(),
+        ),
+        Err(error) => error_response(&error),
+    }
+}
+
+fn error_response(error: &CommandError) -> HttpResponse {
+    let status = match error {
+        CommandError::UnknownPlant(_) => 404,
+        CommandError::Faulted(_) | CommandError::Failed(_) => 409,
+        _ => 400,
+    };
+    HttpResponse::error(status, &error.to_string())
+}
+
+/// Whether the body of `request` is declared as JSON; parameters such as the
+/// charset are ignored.
+fn is_json(request: &HttpRequest) -> bool {
+    request
+        .header("Content-Type")
+        .map_or(false, |content_type| {
+            let media_type = content_type.split(';').next().unwrap_or_default();
+            media_type.trim().eq_ignore_ascii_case("application/json")
+        })
+}
+
+fn method_not_allowed() -> HttpResponse {
+    HttpResponse::error(405, "method not allowed")
+}
+
+fn plant_json<MicrocontrollerImpl: Microcontroller>(
+    plant_irrigator: &PlantIrrigator<MicrocontrollerImpl>,
+) -> String {
+    let reading = plant_irrigator.last_reading();
+    let target = plant_irrigator.target_moisture_level();
+    let event_history = plant_irrigator.event_history();
+    let last_watered = event_history
+        .iter()
+        .rev()
+        .find(|event| event.decision == IrrigationStatus::Watered)
+        .and_then(|event| event.wall_clock);
+
+    let object = JsonObject::new()
+        .string("name", plant_irrigator.name())
+        .number_or_null("moisture", reading.map(|reading| reading.filtered.value()))
+        .number_or_null("raw_moisture", reading.map(|reading| reading.raw.value()))
+        .number("target_min", target.min_value().value())
+        .number("target_max", target.max_value().value())
+        .bool("paused", plant_irrigator.is_paused())
+        .bool("watering", plant_irrigator.is_watering())
+        .bool(
+            "reservoir_suspected_empty",
+            plant_irrigator.is_reservoir_suspected_empty(),
+        );
+    let object = match event_history.latest() {
+        Some(event) => object.string("status", status_payload(&event.decision)),
+        None => object.null("status"),
+    };
+    match last_watered {
+        Some(time) => object.string("last_watered", &format_rfc3339(time)),
+        None => object.null("last_watered"),
+    }
+    .finish()
+}
+
+fn event_json(event: &IrrigationEvent) -> String {
+    let object = match event.wall_clock {
+        Some(time) => JsonObject::new().string("time", &format_rfc3339(time)),
+        None => JsonObject::new().null("time"),
+    };
+    object
+        .number_or_null(
+            "sensor_value",
+            event.sensor_value.map(|value| value.value()),
+        )
+        .number_or_null("moisture", event.moisture.map(|moisture| moisture.value()))
+        .number("target_min", event.target.min_value().value())
+        .number("target_max", event.target.max_value().value())
+        .string("decision", status_payload(&event.decision))
+        .string("reason", event.reason.name())
+        .finish()
+}
+
+fn hour_json(hour: &HourlyMoisture) -> String {
+    JsonObject::new()
+        .string("time", &format_rfc3339(hour.hour))
+        .number_or_null("min", hour.min.map(|moisture| moisture.value()))
+        .number_or_null("max", hour.max.map(|moisture| moisture.value()))
+        .number_or_null("mean", hour.mean.map(|moisture| moisture.value()))
+        .number("waterings", hour.waterings)
+        .finish()
+}
+
+fn config_json<MicrocontrollerImpl: Microcontroller>(
+    plant_irrigator: &PlantIrrigator<MicrocontrollerImpl>,
+) -> String {
+    let target = plant_irrigator.target_moisture_level();
+    JsonObject::new()
+        .number("target_min", target.min_value().value())
+        .number("target_max", target.max_value().value())
+        .bool("paused", plant_irrigator.is_paused())
+        .finish()
+}
+
+/// Decodes the `%XX` escapes of a path segment.
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            if !hex.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+                return None;
+            }
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::RamFlash;
+    use crate::history_log::HistoryLog;
+    use crate::mock_uc::MockMicrocontroller;
+    use crate::plant_config::PlantConfig;
+    use crate::plant_irrigator::{Percentage, SensorCalibrationResult};
+    use crate::uc::{AnalogValue, GPIO_0, GPIO_1, GPIO_2, GPIO_3};
+
+    fn controller() -> Controller<MockMicrocontroller> {
+        let calibration =
+            SensorCalibrationResult::new(AnalogValue::new(500), AnalogValue::new(2200));
+        let target = TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70));
+        let plants = [
+            PlantConfig::new("basil", GPIO_0, GPIO_1, calibration.clone(), target),
+            PlantConfig::new("sweet mint", GPIO_2, GPIO_3, calibration, target),
+        ];
+
+        Controller::new(MockMicrocontroller::new(), &plants).unwrap()
+    }
+
+    fn request(
+        controller: &mut Controller<MockMicrocontroller>,
+        method: Method,
+        uri: &str,
+        body: &str,
+    ) -> (u16, String) {
+        let response = handle_request(
+            controller,
+            &RestApiConfig::default(),
+            &HttpRequest::new(method, uri, body).with_header("Content-Type", "application/json"),
+        );
+        assert_eq!(response.content_type, "application/json");
+        (response.status, String::from_utf8(response.body).unwrap())
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert_eq!(RestApiConfig::default().validate(), Ok(()));
+        let config = RestApiConfig {
+            max_manual_watering: Duration::ZERO,
+        };
+        assert_eq!(
+            config.validate(),
+            Err(RestApiConfigError::ZeroMaxManualWatering)
+        );
+    }
+
+    #[test_log::test]
+    fn plants_and_history() {
+        let mut controller = controller();
+        controller
+            .microcontroller()
+            .set_wall_clock(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        controller
+            .microcontroller()
+            .set_analog_value(GPIO_0, AnalogValue::new(2000));
+        controller
+            .microcontroller()
+            .set_analog_value(GPIO_2, AnalogValue::new(1000));
+        controller.run_cycle();
+
+        let (status, body) = request(&mut controller, Method::Get, "/api/plants", "");
+        assert_eq!(status, 200);
+        assert!(body.starts_with(r#"[{"name":"basil","moisture":12,"raw_moisture":12,"#));
+        assert!(body.contains(r#""status":"watered","last_watered":"2023-11-14T22:13:20+00:00"}"#));
+        assert!(body.contains(r#"{"name":"sweet mint","moisture":71,"#));
+
+        let (status, body) = request(&mut controller, Method::Get, "/api/plants/sweet%20mint", "");
+        assert_eq!(status, 200);
+        assert!(body.contains(r#""last_watered":null"#));
+
+        let (status, body) = request(
+            &mut controller,
+            Method::Get,
+            "/api/plants/basil/history",
+            "",
+        );
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            concat!(
+                r#"[{"time":"2023-11-14T22:13:20+00:00","sensor_value":2000,"moisture":12,"#,
+                r#""target_min":40,"target_max":70,"decision":"watered","reason":"below_target"}]"#
+            )
+        );
+    }
+
+    #[test_log::test]
+    fn chart() {
+        let mut controller = controller()
+            .with_history_log(HistoryLog::open(Box::new(RamFlash::new(1024, 4))).unwrap());
+        let (_, body) = request(&mut controller, Method::Get, "/api/plants/basil/chart", "");
+        assert_eq!(body, "[]");
+
+        let uc = controller.microcontroller();
+        uc.set_wall_clock(SystemTime::UNIX_EPOCH + Duration::from_secs(1_699_999_200));
+        uc.set_analog_value(GPIO_0, AnalogValue::new(1000));
+        uc.set_analog_value(GPIO_2, AnalogValue::new(1000));
+        controller.run_cycle();
+        controller
+            .microcontroller()
+            .advance_clock(Duration::from_secs(2 * 60 * 60));
+        controller.run_cycle();
+
+        let (status, body) = request(&mut controller, Method::Get, "/api/plants/basil/chart", "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            concat!(
+                r#"[{"time":"2023-11-14T22:00:00+00:00","min":71,"max":71,"mean":71,"waterings":0},"#,
+                r#"{"time":"2023-11-15T00:00:00+00:00","min":71,"max":71,"mean":71,"waterings":0}]"#
+            )
+        );
+    }
+
+    #[test_log::test]
+    fn update_config() {
+        let mut controller = controller();
+
+        let (status, body) = request(
+            &mut controller,
+            Method::Patch,
+            "/api/plants/basil/config",
+            r#"{"target_max": 60, "paused": true}"#,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"target_min":40,"target_max":60,"paused":true}"#);
+        assert!(controller.plant_irrigators()[0].is_paused());
+
+        let (status, body) = request(
+            &mut controller,
+            Method::Patch,
+            "/api/plants/basil/config",
+            r#"{"target_min": 60}"#,
+        );
+        assert_eq!(status, 400);
+        assert_eq!(body, r#"{"error":"invalid target moisture level"}"#);
+
+        let (_, body) = request(&mut controller, Method::Get, "/api/plants/basil/config", "");
+        assert_eq!(body, r#"{"target_min":40,"target_max":60,"paused":true}"#);
+    }
+
+    #[test_log::test]
+    fn manual_watering() {
+        let mut controller = controller();
+
+        let (status, body) = request(
+            &mut controller,
+            Method::Post,
+            "/api/plants/basil/water",
+            r#"{"duration_ms": 1500}"#,
+        );
+        assert_eq!((status, body.as_str()), (200, r#"{"status":"watered"}"#));
+        assert_eq!(controller.plant_irrigators()[0].counters().waterings, 1);
+
+        let (status, body) = request(
+            &mut controller,
+            Method::Post,
+            "/api/plants/basil/water",
+            r#"{"duration_ms": 60000}"#,
+        );
+        assert_eq!(status, 400);
+        assert_eq!(
+            body,
+            r#"{"error":"watering of 60000 ms requested, at most 30000 ms allowed"}"#
+        );
+    }
+
+    #[test_log::test]
+    fn rejects_non_json_bodies() {
+        let mut controller = controller();
+        let config = RestApiConfig::default();
+
+        let request = HttpRequest::new(
+            Method::Post,
+            "/api/plants/basil/water",
+            r#"{"duration_ms": 1500}"#,
+        );
+        let response = handle_request(&mut controller, &config, &request);
+        assert_eq!(response.status, 415);
+        let response = handle_request(
+            &mut controller,
+            &config,
+            &request.with_header("Content-Type", "text/plain"),
+        );
+        assert_eq!(response.status, 415);
+        assert_eq!(controller.plant_irrigators()[0].counters().waterings, 0);
+
+        let request = HttpRequest::new(
+            Method::Patch,
+            "/api/plants/basil/config",
+            r#"{"paused": true}"#,
+        )
+        .with_header("Content-Type", "text/plain;charset=UTF-8");
+        let response = handle_request(&mut controller, &config, &request);
+        assert_eq!(response.status, 415);
+        assert!(!controller.plant_irrigators()[0].is_paused());
+
+        let request = HttpRequest::new(request.method, &request.path, request.body)
+            .with_header("Content-Type", "Application/JSON; charset=UTF-8");
+        let response = handle_request(&mut controller, &config, &request);
+        assert_eq!(response.status, 200);
+        assert!(controller.plant_irrigators()[0].is_paused());
+    }
+
+    #[test_log::test]
+    fn errors() {
+        let mut controller = controller();
+
+        assert_eq!(
+            request(&mut controller, Method::Get, "/api/plants/rose", ""),
+            (404, r#"{"error":"unknown plant 'rose'"}"#.to_owned())
+        );
+        assert_eq!(
+            request(&mut controller, Method::Get, "/api/pumps", "").0,
+            404
+        );
+        assert_eq!(
+            request(&mut controller, Method::Delete, "/api/plants/basil", "").0,
+            405
+        );
+        assert_eq!(
+            request(
+                &mut controller,
+                Method::Post,
+                "/api/plants/basil/water",
+                "{"
+            )
+            .0,
+            400
+        );
+    }
+}
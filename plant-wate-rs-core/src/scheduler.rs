@@ -0,0 +1,808 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{debug, error, info, warn};
+
+use crate::cron::CronExpression;
+use crate::storage::{DecodeError, Decoder, Encoder, Record};
+use crate::time_window::MINUTES_PER_DAY;
+use crate::uc::{DigitalOutput, GpioId, Microcontroller, UcError};
+use crate::uc_utils::{retry, HARDWARE_ATTEMPTS, HARDWARE_RETRY_DELAY};
+
+/// What a job does with its output when it runs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum JobAction {
+    TurnOn,
+    TurnOff,
+    /// Turns the output on for the given time, e.g. to dose fertilizer. The
+    /// output is turned off by the first [`Scheduler::tick`] after that time,
+    /// so the pulse lasts up to a control cycle longer.
+    Pulse(Duration),
+}
+
+impl Display for JobAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobAction::TurnOn => write!(f, "turn on"),
+            JobAction::TurnOff => write!(f, "turn off"),
+            JobAction::Pulse(duration) => write!(f, "pulse for {:?}", duration),
+        }
+    }
+}
+
+/// What to do with a run that did not happen on time, e.g. because watering
+/// took too long or the wall clock has jumped forward.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum MissedRunPolicy {
+    /// Wait for the next scheduled run.
+    #[default]
+    Skip,
+    /// Run once as soon as possible, no matter how many runs were missed.
+    RunOnce,
+}
+
+/// A digital output the jobs refer to by name.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OutputConfig {
+    pub name: String,
+    pub gpio: GpioId,
+}
+
+impl OutputConfig {
+    #[inline]
+    pub fn new(name: &str, gpio: GpioId) -> Self {
+        Self {
+            name: name.to_owned(),
+            gpio,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct JobConfig {
+    pub name: String,
+    /// Name of the [`OutputConfig`] the job acts on.
+    pub output: String,
+    pub schedule: CronExpression,
+    pub action: JobAction,
+    pub missed_run_policy: MissedRunPolicy,
+}
+
+impl JobConfig {
+    #[inline]
+    pub fn new(name: &str, output: &str, schedule: CronExpression, action: JobAction) -> Self {
+        Self {
+            name: name.to_owned(),
+            output: output.to_owned(),
+            schedule,
+            action,
+            missed_run_policy: MissedRunPolicy::default(),
+        }
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn with_missed_run_policy(mut self, missed_run_policy: MissedRunPolicy) -> Self {
+        self.missed_run_policy = missed_run_policy;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ScheduleConfig {
+    pub outputs: Vec<OutputConfig>,
+    pub jobs: Vec<JobConfig>,
+    /// Offset of the local time the schedules are in from UTC.
+    pub utc_offset_minutes: i16,
+    /// How late a run may start and still be considered on time.
+    pub grace_period: Duration,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            outputs: Vec::new(),
+            jobs: Vec::new(),
+            utc_offset_minutes: 0,
+            grace_period: Duration::from_secs(2 * 60),
+        }
+    }
+}
+
+impl ScheduleConfig {
+    pub fn validate(&self) -> Result<(), ScheduleError> {
+        if self.utc_offset_minutes.unsigned_abs() >= MINUTES_PER_DAY {
+            return Err(ScheduleError::InvalidUtcOffset(self.utc_offset_minutes));
+        }
+
+        let mut names = HashSet::new();
+        let mut gpios = HashSet::new();
+        for output in &self.outputs {
+            if !names.insert(output.name.as_str()) {
+                return Err(ScheduleError::DuplicateOutput(output.name.clone()));
+            }
+            if !gpios.insert(output.gpio) {
+                return Err(ScheduleError::GpioAlreadyUsed {
+                    output: output.name.clone(),
+                    gpio: output.gpio,
+                });
+            }
+        }
+
+        let mut jobs = HashSet::new();
+        for job in &self.jobs {
+            if !jobs.insert(job.name.as_str()) {
+                return Err(ScheduleError::DuplicateJob(job.name.clone()));
+            }
+            if !names.contains(job.output.as_str()) {
+                return Err(ScheduleError::UnknownOutput {
+                    job: job.name.clone(),
+                    output: job.output.clone(),
+                });
+            }
+            if job.action == JobAction::Pulse(Duration::ZERO) {
+                return Err(ScheduleError::EmptyPulse(job.name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ScheduleError {
+    InvalidUtcOffset(i16),
+    DuplicateOutput(String),
+    DuplicateJob(String),
+    GpioAlreadyUsed {
+        output: String,
+        gpio: GpioId,
+    },
+    UnknownOutput {
+        job: String,
+        output: String,
+    },
+    EmptyPulse(String),
+    /// The microcontroller could not provide the GPIO of the output.
+    Gpio {
+        output: String,
+        error: UcError,
+    },
+}
+
+impl Display for ScheduleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleError::InvalidUtcOffset(offset) => {
+                write!(f, "invalid UTC offset of {} minutes", offset)
+            }
+            ScheduleError::DuplicateOutput(output) => {
+                write!(f, "output `{}` is defined more than once", output)
+            }
+            ScheduleError::DuplicateJob(job) => {
+                write!(f, "job `{}` is defined more than once", job)
+            }
+            ScheduleError::GpioAlreadyUsed { output, gpio } => {
+                write!(f, "output `{}`: GPIO {} is already used", output, gpio)
+            }
+            ScheduleError::UnknownOutput { job, output } => {
+                write!(f, "job `{}`: unknown output `{}`", job, output)
+            }
+            ScheduleError::EmptyPulse(job) => {
+                write!(f, "job `{}`: pulse duration must not be zero", job)
+            }
+            ScheduleError::Gpio { output, error } => write!(f, "output `{}`: {}", output, error),
+        }
+    }
+}
+
+impl Error for ScheduleError {}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum JobOutcome {
+    Ran,
+    /// The run was missed and made up for as per [`MissedRunPolicy::RunOnce`].
+    RanLate(Duration),
+    /// The run was missed and skipped as per [`MissedRunPolicy::Skip`].
+    Skipped(Duration),
+    Failed(UcError),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct JobRun {
+    pub job: String,
+    pub scheduled: SystemTime,
+    pub outcome: JobOutcome,
+}
+
+/// The part of the scheduler's state that should survive a reboot.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SchedulerState {
+    /// When jobs were last run or skipped; the runs due after that are missed
+    /// ones.
+    pub last_run: Option<SystemTime>,
+}
+
+impl Record for SchedulerState {
+    const KEY: &'static str = "scheduler_state";
+    const VERSION: u16 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.option(self.last_run, |encoder, time| {
+            let since_epoch = time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            encoder.u64(since_epoch.as_secs());
+        });
+    }
+
+    fn decode(_version: u16, decoder: &mut Decoder) -> Result<Self, DecodeError> {
+        let last_run = decoder.option(|decoder| {
+            SystemTime::UNIX_EPOCH
+                .checked_add(Duration::from_secs(decoder.u64()?))
+                .ok_or(DecodeError::InvalidValue("time"))
+        })?;
+
+        Ok(Self { last_run })
+    }
+}
+
+struct NamedOutput<DigitalOutputImpl: DigitalOutput> {
+    name: String,
+    output: DigitalOutputImpl,
+    /// When the running pulse ends.
+    pulse_end: Option<Instant>,
+}
+
+struct ScheduledJob {
+    config: JobConfig,
+    /// Index into [`Scheduler::outputs`].
+    output: usize,
+    next_run: Option<SystemTime>,
+}
+
+/// Runs timed jobs, such as grow light switching or fertilizer pulses, on
+/// named digital outputs.
+///
+/// The schedules are in wall clock time; until the wall clock is set, no job
+/// runs. Once it is, the first tick sets each output to the level the last
+/// scheduled turn on or turn off job would have left it at, e.g. to turn a grow
+/// light back on after a reboot.
+pub struct Scheduler<MicrocontrollerImpl: Microcontroller> {
+    outputs: Vec<NamedOutput<MicrocontrollerImpl::DigitalOutput>>,
+    jobs: Vec<ScheduledJob>,
+    utc_offset_minutes: i16,
+    grace_period: Duration,
+    last_tick: Option<SystemTime>,
+    last_run: Option<SystemTime>,
+}
+
+impl<MicrocontrollerImpl: Microcontroller> Scheduler<MicrocontrollerImpl> {
+    /// Validates `config` and acquires the outputs.
+    pub fn new(
+        microcontroller: &mut MicrocontrollerImpl,
+        config: &ScheduleConfig,
+    ) -> Result<Self, ScheduleError> {
+        config.validate()?;
+
+        let outputs = config
+            .outputs
+            .iter()
+            .map(|output| {
+                Ok(NamedOutput {
+                    name: output.name.clone(),
+                    output: microcontroller
+                        .get_digital_output(output.gpio)
+                        .map_err(|error| ScheduleError::Gpio {
+                            output: output.name.clone(),
+                            error,
+                        })?,
+                    pulse_end: None,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let jobs = config
+            .jobs
+            .iter()
+            .map(|job| ScheduledJob {
+                config: job.clone(),
+                output: outputs
+                    .iter()
+                    .position(|output| output.name == job.output)
+                    .expect("outputs are validated"),
+                next_run: None,
+            })
+            .collect();
+
+        Ok(Self {
+            outputs,
+            jobs,
+            utc_offset_minutes: config.utc_offset_minutes,
+            grace_period: config.grace_period,
+            last_tick: None,
+            last_run: None,
+        })
+    }
+
+    pub fn state(&self) -> SchedulerState {
+        SchedulerState {
+            last_run: self.last_run,
+        }
+    }
+
+    /// Restores the state saved before a reboot, so that the first tick
+    /// treats the pulses due since [`SchedulerState::last_run`] as missed. The
+    /// runs of turn on and turn off jobs are not, as the first tick sets the
+    /// levels of the outputs anyway. Must be called before the first tick.
+    pub fn restore_state(&mut self, state: &SchedulerState) {
+        self.last_run = state.last_run;
+    }
+
+    /// When the job named `job` runs next; `None` if it is not scheduled (yet).
+    pub fn next_run(&self, job: &str) -> Option<SystemTime> {
+        self.jobs
+            .iter()
+            .find(|scheduled_job| scheduled_job.config.name == job)
+            .and_then(|scheduled_job| scheduled_job.next_run)
+    }
+
+    /// Ends the pulses that are over, runs the jobs that are due and schedules
+    /// their next runs. Meant to be called regularly, more often than the
+    /// grace period.
+    pub fn tick(&mut self, microcontroller: &MicrocontrollerImpl) -> Vec<JobRun> {
+        self.end_pulses(microcontroller);
+
+        let Some(now) = microcontroller.wall_clock() else {
+            debug!("Wall clock not set, not running any jobs");
+            return Vec::new();
+        };
+        let first_tick = self.last_tick.is_none();
+        let clock_went_back = self.last_tick.map_or(false, |last_tick| now < last_tick);
+        if clock_went_back {
+            warn!("Wall clock went back, rescheduling all the jobs");
+        }
+        self.last_tick = Some(now);
+
+        // Pulses due after the last run before a reboot were missed
+        let pulses_after = match self.last_run {
+            Some(last_run) if first_tick && last_run <= now => last_run,
+            _ => now,
+        };
+        if first_tick {
+            self.set_scheduled_levels(microcontroller, now);
+        }
+
+        let mut runs = Vec::new();
+        for job in &mut self.jobs {
+            let schedule = &job.config.schedule;
+            if job.next_run.is_none() || clock_went_back {
+                let after = match job.config.action {
+                    JobAction::Pulse(_) => pulses_after,
+                    JobAction::TurnOn | JobAction::TurnOff => now,
+                };
+                job.next_run = schedule.next_after(after, self.utc_offset_minutes);
+            }
+            let Some(scheduled) = job.next_run.filter(|&scheduled| scheduled <= now) else {
+                continue;
+            };
+
+            let late = now.duration_since(scheduled).unwrap_or_default();
+            let output = &mut self.outputs[job.output];
+            let outcome = if late <= self.grace_period {
+                run_job(microcontroller, output, &job.config)
+                    .map_or_else(JobOutcome::Failed, |()| JobOutcome::Ran)
+            } else {
+                match job.config.missed_run_policy {
+                    MissedRunPolicy::Skip => {
+                        warn!(
+                            "[{}] Missed the run by {:?}, skipping it",
+                            job.config.name, late
+                        );
+                        JobOutcome::Skipped(late)
+                    }
+                    MissedRunPolicy::RunOnce => {
+                        warn!(
+                            "[{}] Missed the run by {:?}, running it now",
+                            job.config.name, late
+                        );
+                        run_job(microcontroller, output, &job.config)
+                            .map_or_else(JobOutcome::Failed, |()| JobOutcome::RanLate(late))
+                    }
+                }
+            };
+
+            job.next_run = schedule.next_after(now, self.utc_offset_minutes);
+            self.last_run = Some(now);
+            runs.push(JobRun {
+                job: job.config.name.clone(),
+                scheduled,
+                outcome,
+            });
+        }
+
+        runs
+    }
+
+    /// Sets each output to the level left by the last turn on or turn off job
+    /// scheduled at or before `time`, if any.
+    fn set_scheduled_levels(&mut self, microcontroller: &MicrocontrollerImpl, time: SystemTime) {
+        for (index, output) in self.outputs.iter_mut().enumerate() {
+            let level = self
+                .jobs
+                .iter()
+                .filter(|job| job.output == index)
+                .filter_map(|job| {
+                    let level = match job.config.action {
+                        JobAction::TurnOn => true,
+                        JobAction::TurnOff => false,
+                        JobAction::Pulse(_) => return None,
+                    };
+                    let scheduled = job
+                        .config
+                        .schedule
+                        .last_at_or_before(time, self.utc_offset_minutes)?;
+                    Some((scheduled, level))
+                })
+                .max_by_key(|&(scheduled, _)| scheduled)
+                .map(|(_, level)| level);
+            let Some(level) = level else {
+                continue;
+            };
+
+            info!(
+                "Turning `{}` {} as scheduled",
+                output.name,
+                if level { "on" } else { "off" }
+            );
+            let result = retry(
+                microcontroller,
+                HARDWARE_ATTEMPTS,
+                HARDWARE_RETRY_DELAY,
+                || {
+                    if level {
+                        output.output.set_high()
+                    } else {
+                        output.output.set_low()
+                    }
+                },
+            );
+            if let Err(error) = result {
+                error!("Could not set the level of `{}`: {}", output.name, error);
+            }
+        }
+    }
+
+    /// Turns off the outputs whose pulse is over. An output that cannot be
+    /// turned off is tried again on the next tick.
+    fn end_pulses(&mut self, microcontroller: &MicrocontrollerImpl) {
+        let now = microcontroller.now();
+        for output in &mut self.outputs {
+            if output.pulse_end.map_or(true, |pulse_end| pulse_end > now) {
+                continue;
+            }
+
+            let result = retry(
+                microcontroller,
+                HARDWARE_ATTEMPTS,
+                HARDWARE_RETRY_DELAY,
+                || output.output.set_low(),
+            );
+            match result {
+                Ok(()) => output.pulse_end = None,
+                Err(error) => error!("Could not end the pulse of `{}`: {}", output.name, error),
+            }
+        }
+    }
+}
+
+fn run_job<MicrocontrollerImpl: Microcontroller>(
+    microcontroller: &MicrocontrollerImpl,
+    output: &mut NamedOutput<MicrocontrollerImpl::DigitalOutput>,
+    job: &JobConfig,
+) -> Result<(), UcError> {
+    info!("[{}] {} `{}`", job.name, job.action, output.name);
+
+    let pin = &mut output.output;
+    let result = match job.action {
+        JobAction::TurnOn => retry(
+            microcontroller,
+            HARDWARE_ATTEMPTS,
+            HARDWARE_RETRY_DELAY,
+            || pin.set_high(),
+        ),
+        JobAction::TurnOff => retry(
+            microcontroller,
+            HARDWARE_ATTEMPTS,
+            HARDWARE_RETRY_DELAY,
+            || pin.set_low(),
+        ),
+        JobAction::Pulse(duration) => {
+            let started = retry(
+                microcontroller,
+                HARDWARE_ATTEMPTS,
+                HARDWARE_RETRY_DELAY,
+                || pin.set_high(),
+            );
+            // Ended by the tick after it is over; right away if starting
+            // failed, as the pin state is unknown
+            output.pulse_end = Some(match started {
+                Ok(()) => microcontroller.now() + duration,
+                Err(_) => microcontroller.now(),
+            });
+            started
+        }
+    };
+
+    if let Err(error) = result {
+        error!("[{}] Could not {}: {}", job.name, job.action, error);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_uc::{MockMicrocontroller, MockMicrocontrollerAction};
+    use crate::storage::{MemoryStorage, RecordStorage};
+    use crate::uc::{GPIO_0, GPIO_1, GPIO_2};
+
+    // 2023-11-14T22:13:20Z, a Tuesday
+    const NOW: u64 = 1_700_000_000;
+
+    fn schedule_config(jobs: Vec<JobConfig>) -> ScheduleConfig {
+        ScheduleConfig {
+            outputs: vec![
+                OutputConfig::new("light", GPIO_0),
+                OutputConfig::new("fertilizer", GPIO_1),
+            ],
+            jobs,
+            ..ScheduleConfig::default()
+        }
+    }
+
+    fn job(name: &str, output: &str, schedule: &str, action: JobAction) -> JobConfig {
+        JobConfig::new(name, output, schedule.parse().unwrap(), action)
+    }
+
+    fn mock_uc_at(seconds_since_epoch: u64) -> MockMicrocontroller {
+        let mock_uc = MockMicrocontroller::new();
+        mock_uc.set_wall_clock(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds_since_epoch));
+        mock_uc
+    }
+
+    fn outcomes(runs: Vec<JobRun>) -> Vec<(String, JobOutcome)> {
+        runs.into_iter().map(|run| (run.job, run.outcome)).collect()
+    }
+
+    #[test_log::test]
+    fn runs_jobs_on_time() {
+        let mut mock_uc = mock_uc_at(NOW);
+        let config = schedule_config(vec![
+            job("lights off", "light", "15 22 * * *", JobAction::TurnOff),
+            job(
+                "fertilize",
+                "fertilizer",
+                "20 22 * * 2",
+                JobAction::Pulse(Duration::from_secs(3)),
+            ),
+        ]);
+        let mut scheduler = Scheduler::new(&mut mock_uc, &config).unwrap();
+
+        assert!(scheduler.tick(&mock_uc).is_empty());
+        assert_eq!(
+            scheduler.next_run("fertilize"),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(NOW + 400))
+        );
+
+        // 22:15:30
+        mock_uc.advance_clock(Duration::from_secs(130));
+        assert_eq!(
+            outcomes(scheduler.tick(&mock_uc)),
+            vec![("lights off".to_owned(), JobOutcome::Ran)]
+        );
+        assert!(scheduler.tick(&mock_uc).is_empty());
+
+        // 22:20:30
+        mock_uc.advance_clock(Duration::from_secs(300));
+        assert_eq!(
+            outcomes(scheduler.tick(&mock_uc)),
+            vec![("fertilize".to_owned(), JobOutcome::Ran)]
+        );
+        // The pulse does not block, and ends with the first tick after it
+        mock_uc.advance_clock(Duration::from_secs(2));
+        scheduler.tick(&mock_uc);
+        mock_uc.advance_clock(Duration::from_secs(1));
+        scheduler.tick(&mock_uc);
+        assert_eq!(
+            mock_uc.actions(),
+            vec![
+                MockMicrocontrollerAction::GpioSetAsDigitalOutput(GPIO_0),
+                MockMicrocontrollerAction::GpioSetAsDigitalOutput(GPIO_1),
+                // The level left by last night's run
+                MockMicrocontrollerAction::DigitalGpioLow(GPIO_0),
+                MockMicrocontrollerAction::DigitalGpioLow(GPIO_0),
+                MockMicrocontrollerAction::DigitalGpioHigh(GPIO_1),
+                MockMicrocontrollerAction::DigitalGpioLow(GPIO_1),
+            ]
+        );
+        assert_eq!(
+            scheduler.next_run("fertilize"),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(NOW + 400 + 7 * 86_400))
+        );
+    }
+
+    #[test_log::test]
+    fn missed_runs() {
+        let mut mock_uc = mock_uc_at(NOW);
+        let config = schedule_config(vec![
+            job("lights off", "light", "15 22 * * *", JobAction::TurnOff),
+            job("lights on", "light", "0 6 * * *", JobAction::TurnOn)
+                .with_missed_run_policy(MissedRunPolicy::RunOnce),
+        ]);
+        let mut scheduler = Scheduler::new(&mut mock_uc, &config).unwrap();
+        scheduler.tick(&mock_uc);
+
+        // Two days later, at 12:00
+        mock_uc.advance_clock(Duration::from_secs(2 * 86_400 - 10 * 3600 - 13 * 60 - 20));
+        let late_on = Duration::from_secs(6 * 3600 + 86_400);
+        let late_off = Duration::from_secs(13 * 3600 + 45 * 60 + 86_400);
+        assert_eq!(
+            outcomes(scheduler.tick(&mock_uc)),
+            vec![
+                ("lights off".to_owned(), JobOutcome::Skipped(late_off)),
+                ("lights on".to_owned(), JobOutcome::RanLate(late_on)),
+            ]
+        );
+        assert_eq!(
+            mock_uc.actions().last(),
+            Some(&MockMicrocontrollerAction::DigitalGpioHigh(GPIO_0))
+        );
+        assert!(scheduler.tick(&mock_uc).is_empty());
+    }
+
+    fn lights_config() -> ScheduleConfig {
+        schedule_config(vec![
+            job("lights on", "light", "0 6 * * *", JobAction::TurnOn),
+            job("lights off", "light", "0 22 * * *", JobAction::TurnOff),
+            job(
+                "fertilize",
+                "fertilizer",
+                "0 9 * * *",
+                JobAction::Pulse(Duration::from_secs(3)),
+            )
+            .with_missed_run_policy(MissedRunPolicy::RunOnce),
+        ])
+    }
+
+    #[test_log::test]
+    fn levels_are_set_on_boot() {
+        // 12:13:20, while the lights are scheduled to be on
+        let mut mock_uc = mock_uc_at(NOW - 10 * 3600);
+        let mut scheduler = Scheduler::new(&mut mock_uc, &lights_config()).unwrap();
+
+        assert!(scheduler.tick(&mock_uc).is_empty());
+        assert_eq!(
+            mock_uc.actions()[2..],
+            [MockMicrocontrollerAction::DigitalGpioHigh(GPIO_0)]
+        );
+    }
+
+    #[test_log::test]
+    fn state_survives_reboot() {
+        // 08:13:20
+        let mut mock_uc = mock_uc_at(NOW - 14 * 3600);
+        let mut scheduler = Scheduler::new(&mut mock_uc, &lights_config()).unwrap();
+        scheduler.tick(&mock_uc);
+        assert_eq!(scheduler.state().last_run, None);
+
+        // The fertilizer pulse is cut short by a power loss at 09:00:01
+        mock_uc.advance_clock(Duration::from_secs(46 * 60 + 40));
+        assert_eq!(
+            outcomes(scheduler.tick(&mock_uc)),
+            vec![("fertilize".to_owned(), JobOutcome::Ran)]
+        );
+        let state = scheduler.state();
+        let mut storage = MemoryStorage::new();
+        storage.save(&state).unwrap();
+        assert_eq!(storage.load(), Ok(Some(state.clone())));
+
+        // Back on the next day at 10:00; the lights are turned on right away, as
+        // scheduled
+        let mut mock_uc = mock_uc_at(NOW - 12 * 3600 - 13 * 60 - 20 + 86_400);
+        let mut scheduler = Scheduler::new(&mut mock_uc, &lights_config()).unwrap();
+        scheduler.restore_state(&state);
+        assert_eq!(
+            outcomes(scheduler.tick(&mock_uc)),
+            vec![(
+                "fertilize".to_owned(),
+                JobOutcome::RanLate(Duration::from_secs(3600))
+            )]
+        );
+        assert_eq!(
+            mock_uc.actions()[2..],
+            [
+                MockMicrocontrollerAction::DigitalGpioHigh(GPIO_0),
+                MockMicrocontrollerAction::DigitalGpioHigh(GPIO_1),
+            ]
+        );
+    }
+
+    #[test_log::test]
+    fn clock_going_back_reschedules() {
+        let mut mock_uc = mock_uc_at(NOW);
+        let config = schedule_config(vec![job(
+            "lights off",
+            "light",
+            "15 22 * * *",
+            JobAction::TurnOff,
+        )]);
+        let mut scheduler = Scheduler::new(&mut mock_uc, &config).unwrap();
+        scheduler.tick(&mock_uc);
+
+        // The clock is synchronized after running fast for a day
+        mock_uc.set_wall_clock(SystemTime::UNIX_EPOCH + Duration::from_secs(NOW - 86_400));
+        scheduler.tick(&mock_uc);
+        assert_eq!(
+            scheduler.next_run("lights off"),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(NOW - 86_400 + 100))
+        );
+    }
+
+    #[test_log::test]
+    fn no_wall_clock() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let config = schedule_config(vec![job(
+            "lights off",
+            "light",
+            "* * * * *",
+            JobAction::TurnOff,
+        )]);
+        let mut scheduler = Scheduler::new(&mut mock_uc, &config).unwrap();
+
+        mock_uc.advance_clock(Duration::from_secs(3600));
+        assert!(scheduler.tick(&mock_uc).is_empty());
+        assert_eq!(scheduler.next_run("lights off"), None);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let unknown_output = schedule_config(vec![job(
+            "water",
+            "pump",
+            "@daily",
+            JobAction::Pulse(Duration::from_secs(1)),
+        )]);
+        let mut shared_gpio = schedule_config(Vec::new());
+        shared_gpio.outputs.push(OutputConfig::new("fan", GPIO_1));
+
+        assert_eq!(
+            Scheduler::new(&mut mock_uc, &unknown_output).err(),
+            Some(ScheduleError::UnknownOutput {
+                job: "water".to_owned(),
+                output: "pump".to_owned()
+            })
+        );
+        assert_eq!(
+            Scheduler::new(&mut mock_uc, &shared_gpio).err(),
+            Some(ScheduleError::GpioAlreadyUsed {
+                output: "fan".to_owned(),
+                gpio: GPIO_1
+            })
+        );
+        assert!(mock_uc.actions().is_empty());
+
+        mock_uc.set_gpio_unsupported(GPIO_2);
+        let mut unsupported_gpio = schedule_config(Vec::new());
+        unsupported_gpio.outputs[0].gpio = GPIO_2;
+        assert_eq!(
+            Scheduler::new(&mut mock_uc, &unsupported_gpio).err(),
+            Some(ScheduleError::Gpio {
+                output: "light".to_owned(),
+                error: UcError::GpioNotSupported(GPIO_2)
+            })
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt::{Display, Formatter};
+
+use crate::uc::AnalogValue;
+
+/// Thresholds used to tell a faulty soil moisture sensor from a working one.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SensorFaultLimits {
+    /// Readings at or below this value mean the sensor is disconnected.
+    pub open_circuit_max: AnalogValue,
+    /// Readings at or above this value mean the sensor is shorted.
+    pub short_circuit_min: AnalogValue,
+    /// Number of consecutive irrigation cycles reading the same value after
+    /// which the sensor is considered stuck; `0` disables the check.
+    pub stuck_readings: u16,
+}
+
+impl Default for SensorFaultLimits {
+    fn default() -> Self {
+        Self {
+            open_circuit_max: AnalogValue::new(50),
+            short_circuit_min: AnalogValue::new(2900),
+            stuck_readings: 20,
+        }
+    }
+}
+
+impl SensorFaultLimits {
+    pub fn is_valid(&self) -> bool {
+        self.open_circuit_max < self.short_circuit_min
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SensorFaultKind {
+    OpenCircuit,
+    ShortCircuit,
+    Stuck,
+}
+
+impl Display for SensorFaultKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            SensorFaultKind::OpenCircuit => "open circuit",
+            SensorFaultKind::ShortCircuit => "short circuit",
+            SensorFaultKind::Stuck => "stuck reading",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Classifies the readings of a single soil moisture sensor.
+#[derive(Debug, Clone)]
+pub struct SensorFaultDetector {
+    limits: SensorFaultLimits,
+    last_value: Option<AnalogValue>,
+    identical_readings: u16,
+    /// Whether a reading of the current cycle has been counted already.
+    cycle_counted: bool,
+}
+
+impl SensorFaultDetector {
+    #[inline]
+    pub fn new(limits: SensorFaultLimits) -> Self {
+        Self {
+            limits,
+            last_value: None,
+            identical_readings: 0,
+            cycle_counted: false,
+        }
+    }
+
+    #[inline]
+    pub const fn limits(&self) -> &SensorFaultLimits {
+        &self.limits
+    }
+
+    /// Forgets the previous readings.
+    pub fn reset(&mut self) {
+        self.last_value = None;
+        self.identical_readings = 0;
+        self.cycle_counted = false;
+    }
+
+    /// Marks the start of an irrigation cycle. Only one identical reading is
+    /// counted per cycle, so that re-measuring within a cycle does not make the
+    /// sensor look stuck sooner.
+    pub fn start_cycle(&mut self) {
+        self.cycle_counted = false;
+    }
+
+    /// Records a new reading and returns the fault it indicates, if any.
+    pub fn check(&mut self, value: AnalogValue) -> Option<SensorFaultKind> {
+        if self.last_value != Some(value) {
+            self.last_value = Some(value);
+            self.identical_readings = 1;
+        } else if !self.cycle_counted {
+            self.identical_readings = self.identical_readings.saturating_add(1);
+        }
+        self.cycle_counted = true;
+
+        if value <= self.limits.open_circuit_max {
+            Some(SensorFaultKind::OpenCircuit)
+        } else if value >= self.limits.short_circuit_min {
+            Some(SensorFaultKind::ShortCircuit)
+        } else if self.limits.stuck_readings > 0
+            && self.identical_readings >= self.limits.stuck_readings
+        {
+            Some(SensorFaultKind::Stuck)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_and_short_circuit() {
+        let mut detector = SensorFaultDetector::new(SensorFaultLimits::default());
+
+        assert_eq!(
+            detector.check(AnalogValue::new(0)),
+            Some(SensorFaultKind::OpenCircuit)
+        );
+        assert_eq!(
+            detector.check(AnalogValue::new(3100)),
+            Some(SensorFaultKind::ShortCircuit)
+        );
+        assert_eq!(detector.check(AnalogValue::new(1500)), None);
+    }
+
+    #[test]
+    fn stuck_sensor() {
+        let mut detector = SensorFaultDetector::new(SensorFaultLimits {
+            stuck_readings: 3,
+            ..Default::default()
+        });
+
+        for _ in 0..2 {
+            detector.start_cycle();
+            assert_eq!(detector.check(AnalogValue::new(1500)), None);
+        }
+        detector.start_cycle();
+        assert_eq!(
+            detector.check(AnalogValue::new(1500)),
+            Some(SensorFaultKind::Stuck)
+        );
+        assert_eq!(detector.check(AnalogValue::new(1501)), None);
+    }
+
+    #[test]
+    fn readings_counted_once_per_cycle() {
+        let mut detector = SensorFaultDetector::new(SensorFaultLimits {
+            stuck_readings: 3,
+            ..Default::default()
+        });
+
+        detector.start_cycle();
+        for _ in 0..10 {
+            assert_eq!(detector.check(AnalogValue::new(1500)), None);
+        }
+        detector.start_cycle();
+        for _ in 0..10 {
+            assert_eq!(detector.check(AnalogValue::new(1500)), None);
+        }
+        detector.start_cycle();
+        assert_eq!(
+            detector.check(AnalogValue::new(1500)),
+            Some(SensorFaultKind::Stuck)
+        );
+    }
+
+    #[test]
+    fn stuck_check_disabled() {
+        let mut detector = SensorFaultDetector::new(SensorFaultLimits {
+            stuck_readings: 0,
+            ..Default::default()
+        });
+
+        for _ in 0..100 {
+            detector.start_cycle();
+            assert_eq!(detector.check(AnalogValue::new(1500)), None);
+        }
+    }
+}
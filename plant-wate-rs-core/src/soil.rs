@@ -0,0 +1,161 @@
+use std::fmt::{Display, Formatter};
+
+use crate::calibration_curve::{CalibrationCurve, CalibrationCurveError, CalibrationPoint};
+use crate::plant_irrigator::Percentage;
+use crate::uc::AnalogValue;
+
+/// Soil the sensor is put in. Determines how the sensor readings translate to
+/// volumetric water content (VWC).
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum SoilType {
+    PottingMix,
+    CactusMix,
+    Clay,
+    CocoCoir,
+}
+
+impl Display for SoilType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SoilType::PottingMix => "potting mix",
+            SoilType::CactusMix => "cactus mix",
+            SoilType::Clay => "clay",
+            SoilType::CocoCoir => "coco coir",
+        };
+        f.write_str(name)
+    }
+}
+
+impl SoilType {
+    /// Approximate (scaled reading, VWC) pairs, both in percent. The scaled
+    /// reading is 0% for the sensor in the air and 100% in water. The last
+    /// point is the VWC of saturated soil.
+    const fn curve(&self) -> &'static [(u8, u8)] {
+        match self {
+            SoilType::PottingMix => &[(0, 0), (20, 8), (50, 25), (80, 42), (100, 55)],
+            SoilType::CactusMix => &[(0, 0), (20, 5), (50, 14), (80, 26), (100, 35)],
+            SoilType::Clay => &[(0, 0), (20, 15), (50, 32), (80, 42), (100, 48)],
+            SoilType::CocoCoir => &[(0, 0), (20, 10), (50, 30), (80, 52), (100, 65)],
+        }
+    }
+
+    /// VWC of the saturated soil; no reading maps to a higher value.
+    pub fn saturation(&self) -> Percentage {
+        let (_, vwc) = self.curve()[self.curve().len() - 1];
+        Percentage::new(vwc)
+    }
+
+    /// Estimates the VWC from the sensor reading scaled between the air (0%)
+    /// and water (100%).
+    pub fn vwc(&self, scaled_reading: Percentage) -> Percentage {
+        Percentage::new(interpolate(
+            self.curve().iter().copied(),
+            scaled_reading.value(),
+        ))
+    }
+
+    /// Maps the values of a sensor that reads `air` in the air and `water` in
+    /// water straight to VWC. Scaling by the sensor's own readings makes the
+    /// VWC comparable across sensors.
+    pub fn vwc_curve(
+        &self,
+        air: AnalogValue,
+        water: AnalogValue,
+    ) -> Result<CalibrationCurve, CalibrationCurveError> {
+        if water >= air {
+            return Err(CalibrationCurveError::UnsortedValues);
+        }
+
+        let range = (air.value() - water.value()) as u32;
+        // Lower values mean wetter soil, so the wettest point goes first
+        let points = self
+            .curve()
+            .iter()
+            .rev()
+            .map(|&(scaled_reading, vwc)| {
+                let offset = (range * (100 - scaled_reading as u32) + 50) / 100;
+                CalibrationPoint::new(
+                    AnalogValue::new(water.value() + offset as u16),
+                    Percentage::new(vwc),
+                )
+            })
+            .collect();
+        CalibrationCurve::new(points)
+    }
+}
+
+/// Linear interpolation over `points` sorted by `x`; `x` outside the points is
+/// clamped to them.
+fn interpolate(points: impl Iterator<Item = (u8, u8)>, x: u8) -> u8 {
+    let mut previous = None;
+    for (x1, y1) in points {
+        if x <= x1 {
+            let Some((x0, y0)) = previous else {
+                return y1;
+            };
+            let ratio = (x - x0) as f32 / (x1 - x0) as f32;
+            return (y0 as f32 + ratio * (y1 as f32 - y0 as f32)).round() as u8;
+        }
+        previous = Some((x1, y1));
+    }
+
+    previous.map_or(0, |(_, y)| y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vwc_presets() {
+        assert_eq!(
+            SoilType::PottingMix.vwc(Percentage::new(0)),
+            Percentage::new(0)
+        );
+        assert_eq!(
+            SoilType::PottingMix.vwc(Percentage::new(35)),
+            Percentage::new(17)
+        );
+        assert_eq!(
+            SoilType::CactusMix.vwc(Percentage::new(100)),
+            Percentage::new(35)
+        );
+        assert_eq!(SoilType::Clay.vwc(Percentage::new(80)), Percentage::new(42));
+        assert_eq!(
+            SoilType::CocoCoir.saturation(),
+            SoilType::CocoCoir.vwc(Percentage::new(100))
+        );
+    }
+
+    #[test]
+    fn vwc_curve_scales_by_sensor_readings() {
+        // Two sensors with different ranges in the same soil
+        let narrow = SoilType::PottingMix
+            .vwc_curve(AnalogValue::new(2000), AnalogValue::new(1000))
+            .unwrap();
+        let wide = SoilType::PottingMix
+            .vwc_curve(AnalogValue::new(2600), AnalogValue::new(600))
+            .unwrap();
+
+        for (narrow_value, wide_value, vwc) in [(2000, 2600, 0), (1500, 1600, 25), (1000, 600, 55)]
+        {
+            assert_eq!(
+                narrow.moisture(AnalogValue::new(narrow_value)),
+                Percentage::new(vwc)
+            );
+            assert_eq!(
+                wide.moisture(AnalogValue::new(wide_value)),
+                Percentage::new(vwc)
+            );
+        }
+        assert_eq!(
+            narrow.moisture(AnalogValue::new(1500)),
+            SoilType::PottingMix.vwc(Percentage::new(50))
+        );
+
+        assert_eq!(
+            SoilType::Clay.vwc_curve(AnalogValue::new(1000), AnalogValue::new(2000)),
+            Err(CalibrationCurveError::UnsortedValues)
+        );
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Byte store addressed by keys made of ASCII letters, digits, `-` and `_`.
+pub trait Storage {
+    /// Returns `None` if nothing is stored under `key`.
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError>;
+    fn write(&mut self, key: &str, data: &[u8]) -> Result<(), StorageError>;
+    /// Removing a missing key is not an error.
+    fn remove(&mut self, key: &str) -> Result<(), StorageError>;
+}
+
+/// A value that can be kept in a [`Storage`].
+///
+/// The stored bytes start with the version the record was encoded with, so
+/// that [`Record::decode`] can still read records written by older firmware.
+pub trait Record: Sized {
+    const KEY: &'static str;
+    /// Increment on every change of the encoding.
+    const VERSION: u16;
+
+    fn encode(&self, encoder: &mut Encoder);
+    /// `version` is the one the record was encoded with; never higher than
+    /// [`Record::VERSION`].
+    fn decode(version: u16, decoder: &mut Decoder) -> Result<Self, DecodeError>;
+}
+
+/// Typed access to the records of a [`Storage`].
+pub trait RecordStorage {
+    /// Returns `None` if the record has never been saved.
+    fn load<R: Record>(&self) -> Result<Option<R>, StorageError>;
+    fn save<R: Record>(&mut self, record: &R) -> Result<(), StorageError>;
+}
+
+impl<StorageImpl: Storage + ?Sized> RecordStorage for StorageImpl {
+    fn load<R: Record>(&self) -> Result<Option<R>, StorageError> {
+        let Some(bytes) = self.read(R::KEY)? else {
+            return Ok(None);
+        };
+
+        let decode_error = |error| StorageError::Decode { key: R::KEY, error };
+        let mut decoder = Decoder::new(&bytes);
+        let version = decoder.u16().map_err(decode_error)?;
+        if version > R::VERSION {
+            return Err(StorageError::UnsupportedVersion {
+                key: R::KEY,
+                version,
+            });
+        }
+        let record = R::decode(version, &mut decoder).map_err(decode_error)?;
+        decoder.finish().map_err(decode_error)?;
+
+        Ok(Some(record))
+    }
+
+    fn save<R: Record>(&mut self, record: &R) -> Result<(), StorageError> {
+        let mut encoder = Encoder::new();
+        encoder.u16(R::VERSION);
+        record.encode(&mut encoder);
+
+        self.write(R::KEY, &encoder.into_bytes())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum StorageError {
+    InvalidKey(String),
+    Io(io::ErrorKind),
+    /// The record was written by a newer firmware.
+    UnsupportedVersion {
+        key: &'static str,
+        version: u16,
+    },
+    Decode {
+        key: &'static str,
+        error: DecodeError,
+    },
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::InvalidKey(key) => write!(f, "invalid storage key `{}`", key),
+            StorageError::Io(kind) => write!(f, "storage I/O error: {}", kind),
+            StorageError::UnsupportedVersion { key, version } => {
+                write!(f, "`{}`: unsupported record version {}", key, version)
+            }
+            StorageError::Decode { key, error } => write!(f, "`{}`: {}", key, error),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(error: io::Error) -> Self {
+        StorageError::Io(error.kind())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    TrailingBytes,
+    /// The bytes do not form a valid value of the named type.
+    InvalidValue(&'static str),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of record"),
+            DecodeError::TrailingBytes => write!(f, "unexpected bytes at the end of record"),
+            DecodeError::InvalidValue(name) => write!(f, "invalid {}", name),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Writes values in a compact little-endian binary format.
+#[derive(Debug, Clone, Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    /// Millisecond precision.
+    pub fn duration(&mut self, value: Duration) {
+        self.u64(value.as_millis().try_into().unwrap_or(u64::MAX));
+    }
+
+    /// Length-prefixed; at most `u16::MAX` bytes.
+    pub fn str(&mut self, value: &str) {
+        debug_assert!(value.len() <= u16::MAX as usize);
+
+        self.count(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    /// Length of a sequence that follows; at most `u16::MAX`.
+    pub fn count(&mut self, count: usize) {
+        debug_assert!(count <= u16::MAX as usize);
+
+        self.u16(count as u16);
+    }
+
+    pub fn option<T>(&mut self, value: Option<T>, encode: impl FnOnce(&mut Self, T)) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            encode(self, value);
+        }
+    }
+}
+
+/// Reads values written by an [`Encoder`].
+#[derive(Debug, Clone)]
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    #[inline]
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Fails if not all the bytes have been read.
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes)
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        if self.bytes.len() < N {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        self.take().map(u8::from_le_bytes)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    pub fn f32(&mut self) -> Result<f32, DecodeError> {
+        self.take().map(f32::from_le_bytes)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::InvalidValue("bool")),
+        }
+    }
+
+    pub fn duration(&mut self) -> Result<Duration, DecodeError> {
+        self.u64().map(Duration::from_millis)
+    }
+
+    pub fn str(&mut self) -> Result<String, DecodeError> {
+        let len = self.count()?;
+        if self.bytes.len() < len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidValue("string"))
+    }
+
+    pub fn count(&mut self) -> Result<usize, DecodeError> {
+        self.u16().map(usize::from)
+    }
+
+    pub fn option<T>(
+        &mut self,
+        decode: impl FnOnce(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<Option<T>, DecodeError> {
+        if self.bool()? {
+            decode(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn validate_key(key: &str) -> Result<(), StorageError> {
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(StorageError::InvalidKey(key.to_owned()))
+    }
+}
+
+/// Keeps the data in memory only; meant for tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    entries: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        validate_key(key)?;
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn write(&mut self, key: &str, data: &[u8]) -> Result<(), StorageError> {
+        validate_key(key)?;
+        self.entries.insert(key.to_owned(), data.to_vec());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), StorageError> {
+        validate_key(key)?;
+        self.entries.remove(key);
+        Ok(())
+    }
+}
+
+/// Keeps each key in its own file in a directory.
+///
+/// Writes go to a temporary file first, which then replaces the old one, so
+/// a crash never leaves a partially written record behind.
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    directory: PathBuf,
+}
+
+impl FileStorage {
+    /// Creates `directory` if it does not exist yet.
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        Ok(Self { directory })
+    }
+
+    fn path(&self, key: &str, extension: &str) -> Result<PathBuf, StorageError> {
+        validate_key(key)?;
+        Ok(self.directory.join(key).with_extension(extension))
+    }
+}
+
+impl Storage for FileStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match fs::read(self.path(key, "bin")?) {
+            Ok(data) => Ok(Some(data)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn write(&mut self, key: &str, data: &[u8]) -> Result<(), StorageError> {
+        let temporary_path = self.path(key, "tmp")?;
+        let mut file = fs::File::create(&temporary_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, self.path(key, "bin")?)?;
+
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), StorageError> {
+        match fs::remove_file(self.path(key, "bin")?) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Counter {
+        name: String,
+        count: u32,
+        limit: Option<f32>,
+    }
+
+    impl Record for Counter {
+        const KEY: &'static str = "counter";
+        const VERSION: u16 = 2;
+
+        fn encode(&self, encoder: &mut Encoder) {
+            encoder.str(&self.name);
+            encoder.u32(self.count);
+            encoder.option(self.limit, Encoder::f32);
+        }
+
+        fn decode(version: u16, decoder: &mut Decoder) -> Result<Self, DecodeError> {
+            let name = decoder.str()?;
+            // Version 1 used a 16-bit count and had no limit
+            let (count, limit) = if version == 1 {
+                (decoder.u16()?.into(), None)
+            } else {
+                (decoder.u32()?, decoder.option(Decoder::f32)?)
+            };
+
+            Ok(Self { name, count, limit })
+        }
+    }
+
+    fn counter() -> Counter {
+        Counter {
+            name: "waterings".to_owned(),
+            count: 70_000,
+            limit: Some(1.5),
+        }
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let mut storage = MemoryStorage::new();
+        assert_eq!(storage.load::<Counter>(), Ok(None));
+
+        storage.save(&counter()).unwrap();
+        assert_eq!(storage.load(), Ok(Some(counter())));
+
+        storage.remove(Counter::KEY).unwrap();
+        assert_eq!(storage.load::<Counter>(), Ok(None));
+    }
+
+    #[test]
+    fn record_versions() {
+        let mut storage = MemoryStorage::new();
+
+        storage
+            .write("counter", &[1, 0, 2, 0, b'a', b'b', 7, 0])
+            .unwrap();
+        assert_eq!(
+            storage.load(),
+            Ok(Some(Counter {
+                name: "ab".to_owned(),
+                count: 7,
+                limit: None,
+            }))
+        );
+
+        storage.write("counter", &[3, 0]).unwrap();
+        assert_eq!(
+            storage.load::<Counter>(),
+            Err(StorageError::UnsupportedVersion {
+                key: "counter",
+                version: 3
+            })
+        );
+    }
+
+    #[test]
+    fn corrupted_records() {
+        let mut storage = MemoryStorage::new();
+        let mut encoder = Encoder::new();
+        encoder.u16(Counter::VERSION);
+        counter().encode(&mut encoder);
+        let bytes = encoder.into_bytes();
+
+        storage.write("counter", &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(
+            storage.load::<Counter>(),
+            Err(StorageError::Decode {
+                key: "counter",
+                error: DecodeError::UnexpectedEnd
+            })
+        );
+
+        storage
+            .write("counter", &[bytes.as_slice(), &[0]].concat())
+            .unwrap();
+        assert_eq!(
+            storage.load::<Counter>(),
+            Err(StorageError::Decode {
+                key: "counter",
+                error: DecodeError::TrailingBytes
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let mut storage = MemoryStorage::new();
+
+        assert_eq!(
+            storage.write("../etc/passwd", &[]),
+            Err(StorageError::InvalidKey("../etc/passwd".to_owned()))
+        );
+        assert!(storage.read("").is_err());
+    }
+
+    #[test]
+    fn file_storage() {
+        let directory =
+            std::env::temp_dir().join(format!("plant-wate-rs-storage-test-{}", std::process::id()));
+        let mut storage = FileStorage::new(&directory).unwrap();
+
+        storage.save(&counter()).unwrap();
+        assert_eq!(
+            FileStorage::new(&directory).unwrap().load(),
+            Ok(Some(counter()))
+        );
+        storage.remove("counter").unwrap();
+        storage.remove("counter").unwrap();
+        assert_eq!(storage.read("counter"), Ok(None));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,694 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::event_history::IrrigationEvent;
+use crate::home_assistant::{DiscoveryConfig, DiscoveryError};
+use crate::mqtt::{MqttMessage, MqttTransport, TransportError};
+use crate::plant_irrigator::IrrigationStatus;
+use crate::time_window::format_rfc3339;
+
+/// Published to the availability topic once connected.
+pub const PAYLOAD_ONLINE: &str = "online";
+/// Published by the broker to the availability topic when the connection is
+/// lost.
+pub const PAYLOAD_OFFLINE: &str = "offline";
+
+/// A value published for each plant.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Metric {
+    /// Soil moisture percentage; not published while unknown.
+    Moisture,
+    /// Raw sensor value; not published while unknown.
+    SensorValue,
+    TargetMin,
+    TargetMax,
+    /// See [`status_payload`].
+    Status,
+    /// `ON` if the pump ran in the last cycle, `OFF` otherwise.
+    Pump,
+    /// `ON` if the sensor, the hardware or the reservoir needs attention,
+    /// `OFF` otherwise.
+    Fault,
+    /// RFC 3339 timestamp of the last watering; only published when watering
+    /// with the wall clock set.
+    LastWatered,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 8] = [
+        Metric::Moisture,
+        Metric::SensorValue,
+        Metric::TargetMin,
+        Metric::TargetMax,
+        Metric::Status,
+        Metric::Pump,
+        Metric::Fault,
+        Metric::LastWatered,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Metric::Moisture => "moisture",
+            Metric::SensorValue => "sensor_value",
+            Metric::TargetMin => "target_min",
+            Metric::TargetMax => "target_max",
+            Metric::Status => "status",
+            Metric::Pump => "pump",
+            Metric::Fault => "fault",
+            Metric::LastWatered => "last_watered",
+        }
+    }
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The payload published to the [`Metric::Status`] topic.
+pub const fn status_payload(status: &IrrigationStatus) -> &'static str {
+    match status {
+        IrrigationStatus::Watered => "watered",
+        IrrigationStatus::TargetReached => "target_reached",
+        IrrigationStatus::NotWatered => "not_watered",
+        IrrigationStatus::SafetyLimitReached(_) => "safety_limit_reached",
+        IrrigationStatus::SensorFault(_) => "sensor_fault",
+        IrrigationStatus::ReservoirSuspectedEmpty => "reservoir_suspected_empty",
+        IrrigationStatus::HardwareError(_) => "hardware_error",
+        IrrigationStatus::OutsideWateringWindow => "outside_watering_window",
+        IrrigationStatus::Paused => "paused",
+    }
+}
+
+const fn on_off(on: bool) -> &'static str {
+    if on {
+        "ON"
+    } else {
+        "OFF"
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TelemetryConfig {
+    /// Topic of each metric of each plant; `{plant}` and `{metric}` are
+    /// replaced with the plant name and the [metric name](Metric::name).
+    pub topic_template: String,
+    pub retain: bool,
+    /// Topic set to [`PAYLOAD_ONLINE`] on connecting, and to
+    /// [`PAYLOAD_OFFLINE`] by the broker when the connection is lost.
+    pub availability_topic: Option<String>,
+    /// Minimum time between publications of a plant's readings. A change of
+    /// the irrigation status is published right away.
+    pub publish_interval: Duration,
+    /// Messages kept while the broker cannot be reached; the oldest ones are
+    /// dropped first.
+    pub buffer_capacity: usize,
+    /// Delay before retrying after the first failure; doubled after every
+    /// subsequent one, up to `max_backoff`.
+    pub initial_backoff: Duration,
+    /// At most [`TelemetryConfig::MAX_BACKOFF`].
+    pub max_backoff: Duration,
+    /// Announces the plants to Home Assistant; see
+    /// [`home_assistant::announce`](crate::home_assistant::announce).
+    pub home_assistant: Option<DiscoveryConfig>,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            topic_template: "plant-wate-rs/{plant}/{metric}".to_owned(),
+            retain: true,
+            availability_topic: None,
+            publish_interval: Duration::from_secs(60),
+            buffer_capacity: 256,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5 * 60),
+            home_assistant: None,
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+    pub fn validate(&self) -> Result<(), TelemetryConfigError> {
+        if let Some(placeholder) = ["{plant}", "{metric}"]
+            .into_iter()
+            .find(|placeholder| !self.topic_template.contains(placeholder))
+        {
+            return Err(TelemetryConfigError::MissingPlaceholder(placeholder));
+        }
+        if self.buffer_capacity == 0 {
+            return Err(TelemetryConfigError::ZeroBufferCapacity);
+        }
+        if self.initial_backoff.is_zero()
+            || self.initial_backoff > self.max_backoff
+            || self.max_backoff > Self::MAX_BACKOFF
+        {
+            return Err(TelemetryConfigError::InvalidBackoff);
+        }
+        if let Some(home_assistant) = &self.home_assistant {
+            home_assistant.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `plant` can be a level of the topics: it must not be
+    /// empty, and must not contain `/` or the `+` and `#` wildcards.
+    pub fn validate_plant_name(plant: &str) -> Result<(), TelemetryConfigError> {
+        if plant.is_empty() || plant.contains(['/', '+', '#']) {
+            return Err(TelemetryConfigError::InvalidPlantName(plant.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    pub fn topic(&self, plant: &str, metric: Metric) -> String {
+        self.topic_template
+            .replace("{plant}", plant)
+            .replace("{metric}", metric.name())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TelemetryConfigError {
+    /// The topic template lacks the given placeholder.
+    MissingPlaceholder(&'static str),
+    ZeroBufferCapacity,
+    InvalidBackoff,
+    /// The plant name cannot be used in a topic.
+    InvalidPlantName(String),
+    Discovery(DiscoveryError),
+}
+
+impl Display for TelemetryConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TelemetryConfigError::MissingPlaceholder(placeholder) => {
+                write!(f, "the topic template must contain `{}`", placeholder)
+            }
+            TelemetryConfigError::ZeroBufferCapacity => {
+                write!(f, "the buffer capacity must not be zero")
+            }
+            TelemetryConfigError::InvalidBackoff => write!(
+                f,
+                "the initial backoff must be positive and at most the maximum backoff, itself at most {:?}",
+                TelemetryConfig::MAX_BACKOFF
+            ),
+            TelemetryConfigError::InvalidPlantName(plant) => write!(
+                f,
+                "plant `{}`: names used in MQTT topics must not be empty or contain `/`, `+` or `#`",
+                plant
+            ),
+            TelemetryConfigError::Discovery(error) => write!(f, "Home Assistant: {}", error),
+        }
+    }
+}
+
+impl Error for TelemetryConfigError {}
+
+impl From<DiscoveryError> for TelemetryConfigError {
+    fn from(error: DiscoveryError) -> Self {
+        TelemetryConfigError::Discovery(error)
+    }
+}
+
+/// Publishes the readings and decisions of the plants over MQTT.
+///
+/// Messages are buffered while the broker cannot be reached and sent once the
+/// connection is back, in order.
+#[derive(Debug)]
+pub struct Telemetry {
+    transport: Box<dyn MqttTransport>,
+    config: TelemetryConfig,
+    buffer: VecDeque<MqttMessage>,
+    /// When the readings of each plant were last published, and its status
+    /// then.
+    last_published: HashMap<String, (Instant, IrrigationStatus)>,
+    backoff: Duration,
+    next_attempt: Option<Instant>,
+    /// Topic filters to subscribe to on every connection.
+    subscriptions: Vec<String>,
+    /// Whether `subscriptions` are active on the current connection.
+    subscribed: bool,
+    /// Published on every connection.
+    on_connect: Vec<MqttMessage>,
+}
+
+impl Telemetry {
+    pub fn new(
+        mut transport: Box<dyn MqttTransport>,
+        config: TelemetryConfig,
+    ) -> Result<Self, TelemetryConfigError> {
+        config.validate()?;
+
+        if let Some(topic) = &config.availability_topic {
+            transport.set_last_will(MqttMessage::new(topic, PAYLOAD_OFFLINE, true));
+        }
+
+        Ok(Self {
+            transport,
+            backoff: config.initial_backoff,
+            config,
+            buffer: VecDeque::new(),
+            last_published: HashMap::new(),
+            next_attempt: None,
+            subscriptions: Vec::new(),
+            subscribed: false,
+            on_connect: Vec::new(),
+        })
+    }
+
+    #[inline]
+    pub fn config(&self) -> &TelemetryConfig {
+        &self.config
+    }
+
+    /// Number of messages waiting to be sent.
+    #[inline]
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Queues the readings of the event, unless the ones of the plant have
+    /// been published recently and its status has not changed.
+    pub fn publish_event(&mut self, plant: &str, event: &IrrigationEvent) {
+        let due = self
+            .last_published
+            .get(plant)
+            .map_or(true, |(time, status)| {
+                *status != event.decision
+                    || event.time.saturating_duration_since(*time) >= self.config.publish_interval
+            });
+        if !due {
+            return;
+        }
+        self.last_published
+            .insert(plant.to_owned(), (event.time, event.decision));
+
+        for metric in Metric::ALL {
+            let payload = match metric {
+                Metric::Moisture => event.moisture.map(|moisture| moisture.value().to_string()),
+                Metric::SensorValue => event.sensor_value.map(|value| value.value().to_string()),
+                Metric::TargetMin => Some(event.target.min_value().value().to_string()),
+                Metric::TargetMax => Some(event.target.max_value().value().to_string()),
+                Metric::Status => Some(status_payload(&event.decision).to_owned()),
+                Metric::Pump => {
+                    Some(on_off(event.decision == IrrigationStatus::Watered).to_owned())
+                }
+                Metric::Fault => Some(
+                    on_off(matches!(
+                        event.decision,
+                        IrrigationStatus::SensorFault(_)
+                            | IrrigationStatus::HardwareError(_)
+                            | IrrigationStatus::ReservoirSuspectedEmpty
+                    ))
+                    .to_owned(),
+                ),
+                Metric::LastWatered => event
+                    .wall_clock
+                    .filter(|_| event.decision == IrrigationStatus::Watered)
+                    .map(format_rfc3339),
+            };
+            if let Some(payload) = payload {
+                let topic = self.config.topic(plant, metric);
+                self.enqueue(MqttMessage::new(topic, payload, self.config.retain));
+            }
+        }
+    }
+
+    /// Queues a message to be sent by the next [`Telemetry::flush`].
+    pub fn enqueue(&mut self, message: MqttMessage) {
+        if self.buffer.len() == self.config.buffer_capacity {
+            warn!("Telemetry buffer full, dropping the oldest message");
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back(message);
+    }
+
+    /// Publishes `message` on every connection from now on, starting with the
+    /// current one if connected.
+    pub fn publish_on_connect(&mut self, message: MqttMessage) {
+        if self.transport.is_connected() {
+            self.enqueue(message.clone());
+        }
+        self.on_connect.push(message);
+    }
+
+    /// Subscribes to `filter` from the next [`Telemetry::flush`] on, and
+    /// again after every reconnection.
+    pub fn subscribe(&mut self, filter: impl Into<String>) {
+        self.subscriptions.push(filter.into());
+        self.subscribed = false;
+    }
+
+    /// The next message received on one of the subscribed topics, if any.
+    pub fn receive(&mut self) -> Option<MqttMessage> {
+        self.transport.receive()
+    }
+
+    /// Sends the buffered messages and renews the subscriptions, connecting
+    /// first if needed. After a failure, nothing is attempted until the
+    /// backoff delay has passed.
+    pub fn flush(&mut self, now: Instant) {
+        if self.next_attempt.map_or(false, |next| now < next) {
+            return;
+        }
+        let connected = self.transport.is_connected();
+        self.subscribed &= connected;
+        let connection_unused = self.subscriptions.is_empty() && self.on_connect.is_empty();
+        if self.buffer.is_empty() && (self.subscribed || connection_unused) {
+            return;
+        }
+
+        if !connected {
+            if let Err(error) = self.transport.connect() {
+                self.back_off(now, &error);
+                return;
+            }
+            info!("Connected to the MQTT broker");
+            for message in self.on_connect.iter().rev() {
+                self.buffer.push_front(message.clone());
+            }
+            if let Some(topic) = &self.config.availability_topic {
+                self.buffer
+                    .push_front(MqttMessage::new(topic, PAYLOAD_ONLINE, true));
+            }
+        }
+
+        if !self.subscribed {
+            let result = self
+                .subscriptions
+                .iter()
+                .try_for_each(|filter| self.transport.subscribe(filter));
+            if let Err(error) = result {
+                self.back_off(now, &error);
+                return;
+            }
+            self.subscribed = true;
+        }
+
+        while let Some(message) = self.buffer.front() {
+            if let Err(error) = self.transport.publish(message) {
+                self.back_off(now, &error);
+                return;
+            }
+            self.buffer.pop_front();
+        }
+
+        self.backoff = self.config.initial_backoff;
+        self.next_attempt = None;
+    }
+
+    fn back_off(&mut self, now: Instant, error: &TransportError) {
+        warn!(
+            "{}; {} messages buffered, retrying in {:?}",
+            error,
+            self.buffer.len(),
+            self.backoff
+        );
+        self.next_attempt = Some(now + self.backoff);
+        self.backoff = self.backoff.saturating_mul(2).min(self.config.max_backoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::event_history::DecisionReason;
+    use crate::mqtt::InProcessBroker;
+    use crate::plant_irrigator::{Percentage, TargetMoistureLevel};
+    use crate::uc::AnalogValue;
+
+    fn event(time: Instant, decision: IrrigationStatus) -> IrrigationEvent {
+        IrrigationEvent {
+            time,
+            wall_clock: Some(SystemTime::UNIX_EPOCH),
+            sensor_value: Some(AnalogValue::new(1800)),
+            moisture: Some(Percentage::new(35)),
+            target: TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+            decision,
+            reason: DecisionReason::BelowTarget,
+        }
+    }
+
+    fn topics(broker: &InProcessBroker) -> Vec<String> {
+        broker
+            .published()
+            .into_iter()
+            .map(|message| message.topic)
+            .collect()
+    }
+
+    #[test]
+    fn publishes_readings() {
+        let broker = InProcessBroker::new();
+        let config = TelemetryConfig {
+            topic_template: "home/garden/{plant}/{metric}".to_owned(),
+            ..TelemetryConfig::default()
+        };
+        let mut telemetry = Telemetry::new(Box::new(broker.client()), config).unwrap();
+        let now = Instant::now();
+
+        telemetry.publish_event("basil", &event(now, IrrigationStatus::Watered));
+        telemetry.flush(now);
+
+        assert_eq!(
+            topics(&broker),
+            vec![
+                "home/garden/basil/moisture",
+                "home/garden/basil/sensor_value",
+                "home/garden/basil/target_min",
+                "home/garden/basil/target_max",
+                "home/garden/basil/status",
+                "home/garden/basil/pump",
+                "home/garden/basil/fault",
+                "home/garden/basil/last_watered",
+            ]
+        );
+        assert_eq!(
+            broker.retained("home/garden/basil/moisture"),
+            Some(b"35".to_vec())
+        );
+        assert_eq!(
+            broker.retained("home/garden/basil/sensor_value"),
+            Some(b"1800".to_vec())
+        );
+        assert_eq!(
+            broker.retained("home/garden/basil/status"),
+            Some(b"watered".to_vec())
+        );
+        assert_eq!(
+            broker.retained("home/garden/basil/last_watered"),
+            Some(b"1970-01-01T00:00:00+00:00".to_vec())
+        );
+        assert_eq!(telemetry.buffered(), 0);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let broker = InProcessBroker::new();
+        let config = TelemetryConfig {
+            topic_template: "plant-wate-rs/{plant}".to_owned(),
+            ..TelemetryConfig::default()
+        };
+        assert_eq!(
+            Telemetry::new(Box::new(broker.client()), config).err(),
+            Some(TelemetryConfigError::MissingPlaceholder("{metric}"))
+        );
+
+        let config = TelemetryConfig {
+            initial_backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(5),
+            ..TelemetryConfig::default()
+        };
+        assert_eq!(config.validate(), Err(TelemetryConfigError::InvalidBackoff));
+        let config = TelemetryConfig {
+            max_backoff: Duration::MAX,
+            ..TelemetryConfig::default()
+        };
+        assert_eq!(config.validate(), Err(TelemetryConfigError::InvalidBackoff));
+
+        assert_eq!(TelemetryConfig::validate_plant_name("basil"), Ok(()));
+        for plant in ["", "herbs/basil", "basil+", "#"] {
+            assert_eq!(
+                TelemetryConfig::validate_plant_name(plant),
+                Err(TelemetryConfigError::InvalidPlantName(plant.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn publish_interval() {
+        let broker = InProcessBroker::new();
+        let mut telemetry =
+            Telemetry::new(Box::new(broker.client()), TelemetryConfig::default()).unwrap();
+        let now = Instant::now();
+
+        telemetry.publish_event("basil", &event(now, IrrigationStatus::Watered));
+        telemetry.publish_event(
+            "basil",
+            &event(now + Duration::from_secs(1), IrrigationStatus::Watered),
+        );
+        assert_eq!(telemetry.buffered(), 8);
+
+        telemetry.publish_event(
+            "basil",
+            &event(
+                now + Duration::from_secs(2),
+                IrrigationStatus::TargetReached,
+            ),
+        );
+        telemetry.publish_event("mint", &event(now, IrrigationStatus::NotWatered));
+        telemetry.publish_event(
+            "basil",
+            &event(
+                now + Duration::from_secs(62),
+                IrrigationStatus::TargetReached,
+            ),
+        );
+        // No last watering time without watering
+        assert_eq!(telemetry.buffered(), 8 + 3 * 7);
+    }
+
+    #[test]
+    fn buffers_while_offline_and_backs_off() {
+        let broker = InProcessBroker::new();
+        broker.set_online(false);
+        let config = TelemetryConfig {
+            buffer_capacity: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+            ..TelemetryConfig::default()
+        };
+        let mut telemetry = Telemetry::new(Box::new(broker.client()), config).unwrap();
+        let start = Instant::now();
+        let at = |secs: f32| start + Duration::from_secs_f32(secs);
+
+        telemetry.publish_event("basil", &event(start, IrrigationStatus::Watered));
+        telemetry.publish_event("mint", &event(start, IrrigationStatus::NotWatered));
+        // The first 5 messages of basil have been dropped
+        assert_eq!(telemetry.buffered(), 10);
+
+        telemetry.flush(at(0.0));
+        // Retries after 1 s, 2 s, then every 3 s
+        telemetry.flush(at(1.0));
+        telemetry.flush(at(3.0));
+        broker.set_online(true);
+        telemetry.flush(at(5.9));
+        assert!(broker.published().is_empty());
+
+        telemetry.flush(at(6.0));
+        assert_eq!(telemetry.buffered(), 0);
+        assert_eq!(
+            topics(&broker),
+            vec![
+                "plant-wate-rs/basil/pump",
+                "plant-wate-rs/basil/fault",
+                "plant-wate-rs/basil/last_watered",
+                "plant-wate-rs/mint/moisture",
+                "plant-wate-rs/mint/sensor_value",
+                "plant-wate-rs/mint/target_min",
+                "plant-wate-rs/mint/target_max",
+                "plant-wate-rs/mint/status",
+                "plant-wate-rs/mint/pump",
+                "plant-wate-rs/mint/fault",
+            ]
+        );
+
+        // The backoff is reset after a success
+        broker.set_online(false);
+        telemetry.publish_event("basil", &event(at(70.0), IrrigationStatus::Watered));
+        telemetry.flush(at(70.0));
+        broker.set_online(true);
+        telemetry.flush(at(71.0));
+        assert_eq!(telemetry.buffered(), 0);
+    }
+
+    #[test]
+    fn longest_backoff() {
+        let broker = InProcessBroker::new();
+        broker.set_online(false);
+        let config = TelemetryConfig {
+            initial_backoff: TelemetryConfig::MAX_BACKOFF,
+            max_backoff: TelemetryConfig::MAX_BACKOFF,
+            ..TelemetryConfig::default()
+        };
+        let mut telemetry = Telemetry::new(Box::new(broker.client()), config).unwrap();
+        let start = Instant::now();
+
+        telemetry.publish_event("basil", &event(start, IrrigationStatus::Watered));
+        for day in 0..3 {
+            telemetry.flush(start + TelemetryConfig::MAX_BACKOFF * day);
+        }
+        broker.set_online(true);
+        telemetry.flush(start + TelemetryConfig::MAX_BACKOFF * 3 - Duration::from_secs(1));
+        assert!(broker.published().is_empty());
+
+        telemetry.flush(start + TelemetryConfig::MAX_BACKOFF * 3);
+        assert_eq!(telemetry.buffered(), 0);
+    }
+
+    #[test]
+    fn availability() {
+        let broker = InProcessBroker::new();
+        let config = TelemetryConfig {
+            availability_topic: Some("plant-wate-rs/availability".to_owned()),
+            ..TelemetryConfig::default()
+        };
+        let mut telemetry = Telemetry::new(Box::new(broker.client()), config).unwrap();
+        let now = Instant::now();
+
+        telemetry.publish_event("basil", &event(now, IrrigationStatus::NotWatered));
+        telemetry.flush(now);
+        assert_eq!(topics(&broker)[0], "plant-wate-rs/availability");
+        assert_eq!(
+            broker.retained("plant-wate-rs/availability"),
+            Some(b"online".to_vec())
+        );
+
+        broker.drop_connections();
+        assert_eq!(
+            broker.retained("plant-wate-rs/availability"),
+            Some(b"offline".to_vec())
+        );
+
+        telemetry.publish_event("basil", &event(now, IrrigationStatus::Watered));
+        telemetry.flush(now);
+        assert_eq!(
+            broker.retained("plant-wate-rs/availability"),
+            Some(b"online".to_vec())
+        );
+        assert_eq!(
+            broker.retained("plant-wate-rs/basil/fault"),
+            Some(b"OFF".to_vec())
+        );
+    }
+
+    #[test]
+    fn subscriptions_are_renewed() {
+        let broker = InProcessBroker::new();
+        let mut telemetry =
+            Telemetry::new(Box::new(broker.client()), TelemetryConfig::default()).unwrap();
+        let now = Instant::now();
+        telemetry.subscribe("plant-wate-rs/+/command");
+
+        telemetry.flush(now);
+        broker.publish(MqttMessage::new("plant-wate-rs/basil/command", "1", false));
+        assert_eq!(telemetry.receive().unwrap().payload, b"1");
+
+        broker.drop_connections();
+        telemetry.flush(now);
+        broker.publish(MqttMessage::new("plant-wate-rs/basil/command", "2", false));
+        assert_eq!(telemetry.receive().unwrap().payload, b"2");
+        assert_eq!(telemetry.receive(), None);
+    }
+}
@@ -0,0 +1,275 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+
+use crate::uc::{AnalogInput, AnalogValue, GpioId, UcError};
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct Temperature(f32);
+
+impl Temperature {
+    #[inline]
+    pub const fn from_celsius(celsius: f32) -> Self {
+        Self(celsius)
+    }
+
+    #[inline]
+    pub const fn celsius(&self) -> f32 {
+        self.0
+    }
+}
+
+impl Display for Temperature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1} °C", self.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TemperatureSensorError {
+    Hardware(UcError),
+    /// The sensor responded, but the reading makes no sense (e.g. the sensor
+    /// is disconnected).
+    InvalidReading,
+}
+
+impl Display for TemperatureSensorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemperatureSensorError::Hardware(error) => write!(f, "{}", error),
+            TemperatureSensorError::InvalidReading => write!(f, "invalid temperature reading"),
+        }
+    }
+}
+
+impl Error for TemperatureSensorError {}
+
+impl From<UcError> for TemperatureSensorError {
+    fn from(error: UcError) -> Self {
+        TemperatureSensorError::Hardware(error)
+    }
+}
+
+pub trait TemperatureSensor: Debug {
+    fn read_temperature(&mut self) -> Result<Temperature, TemperatureSensorError>;
+}
+
+/// NTC thermistor described with the beta parameter equation.
+///
+/// The thermistor is expected to be connected between the GPIO and the
+/// ground, with the series resistor between the supply and the GPIO.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ThermistorConfig {
+    pub supply_voltage: AnalogValue,
+    /// In ohms.
+    pub series_resistance: f32,
+    /// Resistance at `nominal_temperature`, in ohms.
+    pub nominal_resistance: f32,
+    pub nominal_temperature: Temperature,
+    /// In kelvins.
+    pub beta: f32,
+}
+
+impl Default for ThermistorConfig {
+    fn default() -> Self {
+        Self {
+            supply_voltage: AnalogValue::new(3300),
+            series_resistance: 10_000.0,
+            nominal_resistance: 10_000.0,
+            nominal_temperature: Temperature::from_celsius(25.0),
+            beta: 3950.0,
+        }
+    }
+}
+
+impl ThermistorConfig {
+    pub fn is_valid(&self) -> bool {
+        [
+            self.series_resistance,
+            self.nominal_resistance,
+            self.nominal_temperature.celsius() - ABSOLUTE_ZERO_CELSIUS,
+            self.beta,
+        ]
+        .iter()
+        .all(|value| value.is_finite() && *value > 0.0)
+            && self.supply_voltage > AnalogValue::ZERO
+    }
+}
+
+const ABSOLUTE_ZERO_CELSIUS: f32 = -273.15;
+
+#[derive(Debug)]
+pub struct Thermistor<AnalogInputImpl: AnalogInput> {
+    input: AnalogInputImpl,
+    config: ThermistorConfig,
+}
+
+impl<AnalogInputImpl: AnalogInput> Thermistor<AnalogInputImpl> {
+    #[inline]
+    pub fn new(input: AnalogInputImpl, config: ThermistorConfig) -> Self {
+        debug_assert!(config.is_valid());
+
+        Self { input, config }
+    }
+
+    #[inline]
+    pub const fn config(&self) -> &ThermistorConfig {
+        &self.config
+    }
+
+    pub fn read_temperature(&mut self) -> Result<Temperature, TemperatureSensorError> {
+        let voltage = self.input.get_value()?;
+        if voltage == AnalogValue::ZERO || voltage >= self.config.supply_voltage {
+            return Err(TemperatureSensorError::InvalidReading);
+        }
+
+        let voltage = voltage.value() as f32;
+        let resistance = self.config.series_resistance * voltage
+            / (self.config.supply_voltage.value() as f32 - voltage);
+        let nominal_kelvin = self.config.nominal_temperature.celsius() - ABSOLUTE_ZERO_CELSIUS;
+        let kelvin = 1.0
+            / (1.0 / nominal_kelvin
+                + (resistance / self.config.nominal_resistance).ln() / self.config.beta);
+
+        Ok(Temperature::from_celsius(kelvin + ABSOLUTE_ZERO_CELSIUS))
+    }
+}
+
+impl<AnalogInputImpl: AnalogInput + Debug> TemperatureSensor for Thermistor<AnalogInputImpl> {
+    fn read_temperature(&mut self) -> Result<Temperature, TemperatureSensorError> {
+        Thermistor::read_temperature(self)
+    }
+}
+
+/// Temperature input of a single plant.
+#[derive(Debug)]
+pub enum TemperatureInput<AnalogInputImpl: AnalogInput> {
+    Thermistor(Thermistor<AnalogInputImpl>),
+    /// Any other sensor, e.g. a digital one.
+    Sensor(Box<dyn TemperatureSensor>),
+}
+
+impl<AnalogInputImpl: AnalogInput> TemperatureInput<AnalogInputImpl> {
+    pub fn read_temperature(&mut self) -> Result<Temperature, TemperatureSensorError> {
+        match self {
+            TemperatureInput::Thermistor(thermistor) => thermistor.read_temperature(),
+            TemperatureInput::Sensor(sensor) => sensor.read_temperature(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TemperatureSensorConfig {
+    Thermistor {
+        gpio: GpioId,
+        thermistor: ThermistorConfig,
+    },
+    /// The sensor is provided at runtime, with
+    /// [`Controller::set_temperature_sensor`](crate::controller::Controller::set_temperature_sensor).
+    External,
+}
+
+/// Removes the temperature drift of the soil moisture sensor output:
+/// `compensated = raw - linear_coefficient * dT - quadratic_coefficient * dT²`,
+/// where `dT` is the difference between the temperature and `reference`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TemperatureCompensation {
+    /// Temperature at which the sensor has been calibrated.
+    pub reference: Temperature,
+    /// In mV per °C.
+    pub linear_coefficient: f32,
+    /// In mV per °C².
+    pub quadratic_coefficient: f32,
+}
+
+impl TemperatureCompensation {
+    pub fn is_valid(&self) -> bool {
+        self.reference.celsius().is_finite()
+            && self.linear_coefficient.is_finite()
+            && self.quadratic_coefficient.is_finite()
+    }
+
+    pub fn apply(&self, value: AnalogValue, temperature: Temperature) -> AnalogValue {
+        let delta = temperature.celsius() - self.reference.celsius();
+        let drift = self.linear_coefficient * delta + self.quadratic_coefficient * delta * delta;
+        let compensated = (value.value() as f32 - drift).round();
+
+        AnalogValue::new(compensated.clamp(0.0, u16::MAX as f32) as u16)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TemperatureCompensationConfig {
+    pub sensor: TemperatureSensorConfig,
+    pub compensation: TemperatureCompensation,
+}
+
+impl TemperatureCompensationConfig {
+    pub fn is_valid(&self) -> bool {
+        let sensor_valid = match &self.sensor {
+            TemperatureSensorConfig::Thermistor { thermistor, .. } => thermistor.is_valid(),
+            TemperatureSensorConfig::External => true,
+        };
+
+        sensor_valid && self.compensation.is_valid()
+    }
+
+    /// GPIO used by the sensor, if it is configured here.
+    pub fn gpio(&self) -> Option<GpioId> {
+        match self.sensor {
+            TemperatureSensorConfig::Thermistor { gpio, .. } => Some(gpio),
+            TemperatureSensorConfig::External => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_uc::MockMicrocontroller;
+    use crate::uc::{Microcontroller, GPIO_3};
+
+    #[test]
+    fn thermistor() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let input = mock_uc.get_analog_input(GPIO_3).unwrap();
+        let mut thermistor = Thermistor::new(input, ThermistorConfig::default());
+
+        // Equal resistances; the nominal temperature
+        mock_uc.set_analog_value(GPIO_3, AnalogValue::new(1650));
+        let temperature = thermistor.read_temperature().unwrap();
+        assert!((temperature.celsius() - 25.0).abs() < 0.01);
+
+        // Lower resistance means higher temperature
+        mock_uc.set_analog_value(GPIO_3, AnalogValue::new(1000));
+        let temperature = thermistor.read_temperature().unwrap();
+        assert!((temperature.celsius() - 45.0).abs() < 0.01);
+
+        mock_uc.set_analog_value(GPIO_3, AnalogValue::new(0));
+        assert_eq!(
+            thermistor.read_temperature(),
+            Err(TemperatureSensorError::InvalidReading)
+        );
+    }
+
+    #[test]
+    fn compensation() {
+        let compensation = TemperatureCompensation {
+            reference: Temperature::from_celsius(20.0),
+            linear_coefficient: 4.0,
+            quadratic_coefficient: 0.1,
+        };
+
+        assert_eq!(
+            compensation.apply(AnalogValue::new(1500), Temperature::from_celsius(20.0)),
+            AnalogValue::new(1500)
+        );
+        assert_eq!(
+            compensation.apply(AnalogValue::new(1500), Temperature::from_celsius(30.0)),
+            AnalogValue::new(1450)
+        );
+        assert_eq!(
+            compensation.apply(AnalogValue::new(1500), Temperature::from_celsius(10.0)),
+            AnalogValue::new(1530)
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::SystemTime;
+
+pub(crate) const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Minutes since the Unix epoch in the local time, `utc_offset_minutes` ahead
+/// of UTC. Rounds towards the past.
+pub(crate) fn local_epoch_minutes(time: SystemTime, utc_offset_minutes: i16) -> i64 {
+    let utc_minutes = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since_epoch) => (since_epoch.as_secs() / 60) as i64,
+        Err(error) => -(((error.duration().as_secs() + 59) / 60) as i64),
+    };
+    utc_minutes + utc_offset_minutes as i64
+}
+
+/// Year, month (1–12) and day of month (1–31) of the day `days_since_epoch`
+/// days after 1970-01-01, in the proleptic Gregorian calendar.
+pub(crate) fn civil_date(days_since_epoch: i64) -> (i64, u8, u8) {
+    // Days since 0000-03-01, so that the leap day is the last one of the year
+    let days = days_since_epoch + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+
+    (year, month as u8, day as u8)
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp, truncated to seconds, e.g.
+/// `2023-11-14T22:13:20+00:00`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let seconds = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_secs() as i64,
+        Err(error) => -(error.duration().as_secs_f64().ceil() as i64),
+    };
+    let (year, month, day) = civil_date(seconds.div_euclid(86_400));
+    let second_of_day = seconds.rem_euclid(86_400);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60
+    )
+}
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct TimeOfDay(u16);
+
+impl TimeOfDay {
+    pub const MIDNIGHT: TimeOfDay = TimeOfDay::new(0, 0);
+
+    #[inline]
+    pub const fn new(hour: u8, minute: u8) -> Self {
+        debug_assert!(hour < 24);
+        debug_assert!(minute < 60);
+
+        Self(hour as u16 * 60 + minute as u16)
+    }
+
+    /// Local time of day at `time`, `utc_offset_minutes` ahead of UTC.
+    pub fn from_system_time(time: SystemTime, utc_offset_minutes: i16) -> Self {
+        let local_minutes =
+            local_epoch_minutes(time, utc_offset_minutes).rem_euclid(MINUTES_PER_DAY as i64);
+
+        Self(local_minutes as u16)
+    }
+
+    #[inline]
+    pub const fn hour(&self) -> u8 {
+        (self.0 / 60) as u8
+    }
+
+    #[inline]
+    pub const fn minute(&self) -> u8 {
+        (self.0 % 60) as u8
+    }
+
+    #[inline]
+    pub const fn minutes_since_midnight(&self) -> u16 {
+        self.0
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour(), self.minute())
+    }
+}
+
+/// Part of the day from `start` (inclusive) to `end` (exclusive). Wraps around
+/// midnight if `end` is earlier than `start`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TimeWindow {
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+}
+
+impl TimeWindow {
+    #[inline]
+    pub const fn new(start: TimeOfDay, end: TimeOfDay) -> Self {
+        Self { start, end }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.start != self.end
+    }
+
+    pub fn contains(&self, time: TimeOfDay) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl Display for TimeWindow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}–{}", self.start, self.end)
+    }
+}
+
+/// Restrictions on when any of the pumps may run.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TimeRestrictions {
+    /// Offset of the local time from UTC, used to tell the time of day from
+    /// the wall clock.
+    pub utc_offset_minutes: i16,
+    /// No plant is watered within these windows, unless its moisture is
+    /// critically low.
+    pub quiet_hours: Vec<TimeWindow>,
+}
+
+impl TimeRestrictions {
+    pub fn validate(&self) -> Result<(), TimeRestrictionsError> {
+        if self.utc_offset_minutes.unsigned_abs() >= MINUTES_PER_DAY {
+            return Err(TimeRestrictionsError::InvalidUtcOffset(
+                self.utc_offset_minutes,
+            ));
+        }
+
+        match self.quiet_hours.iter().find(|window| !window.is_valid()) {
+            Some(window) => Err(TimeRestrictionsError::InvalidQuietHours(*window)),
+            None => Ok(()),
+        }
+    }
+
+    /// Local time of day, or `None` if the wall clock has not been set.
+    pub fn time_of_day(&self, wall_clock: Option<SystemTime>) -> Option<TimeOfDay> {
+        wall_clock.map(|time| TimeOfDay::from_system_time(time, self.utc_offset_minutes))
+    }
+
+    pub fn is_quiet(&self, time: TimeOfDay) -> bool {
+        self.quiet_hours.iter().any(|window| window.contains(time))
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TimeRestrictionsError {
+    InvalidUtcOffset(i16),
+    InvalidQuietHours(TimeWindow),
+}
+
+impl Display for TimeRestrictionsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeRestrictionsError::InvalidUtcOffset(offset) => {
+                write!(f, "invalid UTC offset of {} minutes", offset)
+            }
+            TimeRestrictionsError::InvalidQuietHours(window) => {
+                write!(f, "empty quiet hours {}", window)
+            }
+        }
+    }
+}
+
+impl Error for TimeRestrictionsError {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn window_contains() {
+        let window = TimeWindow::new(TimeOfDay::new(6, 0), TimeOfDay::new(10, 30));
+
+        assert!(window.contains(TimeOfDay::new(6, 0)));
+        assert!(window.contains(TimeOfDay::new(10, 29)));
+        assert!(!window.contains(TimeOfDay::new(10, 30)));
+        assert!(!window.contains(TimeOfDay::new(5, 59)));
+    }
+
+    #[test]
+    fn window_wraps_around_midnight() {
+        let window = TimeWindow::new(TimeOfDay::new(22, 0), TimeOfDay::new(7, 0));
+
+        assert!(window.contains(TimeOfDay::new(23, 0)));
+        assert!(window.contains(TimeOfDay::MIDNIGHT));
+        assert!(window.contains(TimeOfDay::new(3, 0)));
+        assert!(!window.contains(TimeOfDay::new(7, 0)));
+        assert!(!window.contains(TimeOfDay::new(12, 0)));
+    }
+
+    #[test]
+    fn invalid_restrictions() {
+        let mut restrictions = TimeRestrictions {
+            utc_offset_minutes: -24 * 60,
+            quiet_hours: Vec::new(),
+        };
+        assert_eq!(
+            restrictions.validate(),
+            Err(TimeRestrictionsError::InvalidUtcOffset(-24 * 60))
+        );
+
+        restrictions.utc_offset_minutes = 0;
+        let window = TimeWindow::new(TimeOfDay::new(22, 0), TimeOfDay::new(22, 0));
+        restrictions.quiet_hours.push(window);
+        assert_eq!(
+            restrictions.validate(),
+            Err(TimeRestrictionsError::InvalidQuietHours(window))
+        );
+    }
+
+    #[test]
+    fn rfc3339() {
+        assert_eq!(
+            format_rfc3339(SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_900)),
+            "2023-11-14T22:13:20+00:00"
+        );
+        // 2024-02-29T00:00:00Z
+        assert_eq!(
+            format_rfc3339(SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_164_800)),
+            "2024-02-29T00:00:00+00:00"
+        );
+        assert_eq!(
+            format_rfc3339(SystemTime::UNIX_EPOCH - Duration::from_secs(1)),
+            "1969-12-31T23:59:59+00:00"
+        );
+    }
+
+    #[test]
+    fn time_of_day_from_system_time() {
+        // 2023-11-14T22:13:20Z
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(TimeOfDay::from_system_time(time, 0), TimeOfDay::new(22, 13));
+        assert_eq!(
+            TimeOfDay::from_system_time(time, 120),
+            TimeOfDay::new(0, 13)
+        );
+        assert_eq!(
+            TimeOfDay::from_system_time(time, -300),
+            TimeOfDay::new(17, 13)
+        );
+    }
+}
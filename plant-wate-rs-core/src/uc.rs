@@ -1,5 +1,6 @@
+use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
 #[repr(transparent)]
@@ -68,14 +69,39 @@ impl<T: Into<u16>> From<T> for AnalogValue {
     }
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UcError {
+    /// The GPIO has already been taken.
+    GpioInUse(GpioId),
+    /// The GPIO cannot be used in the requested mode.
+    GpioNotSupported(GpioId),
+    /// The GPIO driver could not be initialized.
+    GpioInitFailed(GpioId),
+    AnalogReadFailed(GpioId),
+    DigitalWriteFailed(GpioId),
+}
+
+impl Display for UcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UcError::GpioInUse(id) => write!(f, "{} already in use", id),
+            UcError::GpioNotSupported(id) => write!(f, "{} not supported in this mode", id),
+            UcError::GpioInitFailed(id) => write!(f, "could not initialize {}", id),
+            UcError::AnalogReadFailed(id) => write!(f, "could not read analog value from {}", id),
+            UcError::DigitalWriteFailed(id) => write!(f, "could not set the level of {}", id),
+        }
+    }
+}
+
+impl Error for UcError {}
+
 pub trait AnalogInput {
-    #[must_use]
-    fn get_value(&mut self) -> AnalogValue;
+    fn get_value(&mut self) -> Result<AnalogValue, UcError>;
 }
 
 pub trait DigitalOutput {
-    fn set_high(&mut self);
-    fn set_low(&mut self);
+    fn set_high(&mut self) -> Result<(), UcError>;
+    fn set_low(&mut self) -> Result<(), UcError>;
 }
 
 pub trait Microcontroller {
@@ -83,8 +109,12 @@ pub trait Microcontroller {
     type DigitalOutput: DigitalOutput;
 
     fn wait(&self, duration: Duration);
-    #[must_use]
-    fn get_analog_input(&mut self, id: GpioId) -> Self::AnalogInput;
-    #[must_use]
-    fn get_digital_output(&mut self, id: GpioId) -> Self::DigitalOutput;
+    /// Monotonic time; only meaningful relative to other values returned by
+    /// this method.
+    fn now(&self) -> Instant;
+    /// Current date and time, or `None` if the clock has not been set (e.g.
+    /// not synchronized over the network yet).
+    fn wall_clock(&self) -> Option<SystemTime>;
+    fn get_analog_input(&mut self, id: GpioId) -> Result<Self::AnalogInput, UcError>;
+    fn get_digital_output(&mut self, id: GpioId) -> Result<Self::DigitalOutput, UcError>;
 }
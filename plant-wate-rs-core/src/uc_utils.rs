@@ -1,4 +1,37 @@
-use crate::uc::AnalogValue;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::uc::{AnalogValue, Microcontroller, UcError};
+
+/// Number of attempts made for hardware operations that may fail transiently.
+pub const HARDWARE_ATTEMPTS: u8 = 3;
+pub const HARDWARE_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Runs `operation` up to `attempts` times, waiting `delay` between the
+/// attempts. Returns the first success or the last error.
+pub fn retry<MicrocontrollerImpl: Microcontroller, T>(
+    microcontroller: &MicrocontrollerImpl,
+    attempts: u8,
+    delay: Duration,
+    mut operation: impl FnMut() -> Result<T, UcError>,
+) -> Result<T, UcError> {
+    debug_assert!(attempts > 0);
+
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Ok(value) => return Ok(value),
+            Err(error) if attempt < attempts => {
+                warn!("{} (attempt {}/{}), retrying", error, attempt, attempts);
+                microcontroller.wait(delay);
+                attempt += 1;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
 
 pub trait AnalogValueMean {
     #[must_use]
@@ -9,6 +42,8 @@ impl<'a, T> AnalogValueMean for T
 where
     T: IntoIterator<Item = &'a AnalogValue>,
 {
+    // Newer Clippy versions suggest `checked_div` for the empty case
+    #[allow(unknown_lints, clippy::manual_checked_ops)]
     fn mean(self) -> AnalogValue {
         let mut total_value: u32 = 0;
         let mut count: u32 = 0;
@@ -26,10 +61,179 @@ where
     }
 }
 
+/// Combines a set of readings of a single sensor into one value.
+pub trait SampleFilter: Debug {
+    fn filter(&self, samples: &[AnalogValue]) -> AnalogValue;
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MeanFilter;
+
+impl SampleFilter for MeanFilter {
+    fn filter(&self, samples: &[AnalogValue]) -> AnalogValue {
+        samples.mean()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MedianFilter;
+
+impl SampleFilter for MedianFilter {
+    fn filter(&self, samples: &[AnalogValue]) -> AnalogValue {
+        median(&mut samples.to_vec())
+    }
+}
+
+/// Mean of the samples after discarding `trim_percent` percent of the lowest
+/// and the same share of the highest ones.
+#[derive(Debug, Copy, Clone)]
+pub struct TrimmedMeanFilter {
+    trim_percent: u8,
+}
+
+impl TrimmedMeanFilter {
+    #[inline]
+    pub fn new(trim_percent: u8) -> Self {
+        debug_assert!(trim_percent < 50);
+
+        Self { trim_percent }
+    }
+}
+
+impl SampleFilter for TrimmedMeanFilter {
+    fn filter(&self, samples: &[AnalogValue]) -> AnalogValue {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let trimmed = sorted.len() * self.trim_percent as usize / 100;
+
+        sorted[trimmed..sorted.len() - trimmed].mean()
+    }
+}
+
+/// Mean of the samples that are no further than `threshold` scaled median
+/// absolute deviations (MAD) away from the median.
+#[derive(Debug, Copy, Clone)]
+pub struct MadFilter {
+    threshold: f32,
+}
+
+impl MadFilter {
+    /// Makes the MAD a consistent estimator of the standard deviation of
+    /// normally distributed samples.
+    const MAD_SCALE: f32 = 1.4826;
+
+    #[inline]
+    pub fn new(threshold: f32) -> Self {
+        debug_assert!(threshold > 0.0);
+
+        Self { threshold }
+    }
+}
+
+impl SampleFilter for MadFilter {
+    fn filter(&self, samples: &[AnalogValue]) -> AnalogValue {
+        let median_value = median(&mut samples.to_vec());
+        let mut deviations: Vec<AnalogValue> = samples
+            .iter()
+            .map(|sample| AnalogValue::new(sample.value().abs_diff(median_value.value())))
+            .collect();
+        let mad = median(&mut deviations).value() as f32 * Self::MAD_SCALE;
+
+        samples
+            .iter()
+            .zip(&deviations)
+            .filter(|(_, deviation)| deviation.value() as f32 <= self.threshold * mad)
+            .map(|(sample, _)| sample)
+            .mean()
+    }
+}
+
+fn median(samples: &mut [AnalogValue]) -> AnalogValue {
+    if samples.is_empty() {
+        return AnalogValue::ZERO;
+    }
+
+    samples.sort_unstable();
+    let middle = samples.len() / 2;
+    if samples.len() % 2 == 0 {
+        samples[middle - 1..=middle].mean()
+    } else {
+        samples[middle]
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum SampleFilterConfig {
+    #[default]
+    Mean,
+    Median,
+    TrimmedMean {
+        trim_percent: u8,
+    },
+    MadOutlierRejection {
+        threshold: f32,
+    },
+}
+
+impl SampleFilterConfig {
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            SampleFilterConfig::Mean | SampleFilterConfig::Median => true,
+            SampleFilterConfig::TrimmedMean { trim_percent } => trim_percent < 50,
+            SampleFilterConfig::MadOutlierRejection { threshold } => {
+                threshold.is_finite() && threshold > 0.0
+            }
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn SampleFilter> {
+        match *self {
+            SampleFilterConfig::Mean => Box::new(MeanFilter),
+            SampleFilterConfig::Median => Box::new(MedianFilter),
+            SampleFilterConfig::TrimmedMean { trim_percent } => {
+                Box::new(TrimmedMeanFilter::new(trim_percent))
+            }
+            SampleFilterConfig::MadOutlierRejection { threshold } => {
+                Box::new(MadFilter::new(threshold))
+            }
+        }
+    }
+}
+
+/// How a sensor is sampled each time a measurement is taken.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SamplingConfig {
+    pub samples: u8,
+    /// Delay between two consecutive samples.
+    pub sample_delay: Duration,
+    pub filter: SampleFilterConfig,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            samples: 3,
+            sample_delay: Duration::from_millis(500),
+            filter: SampleFilterConfig::default(),
+        }
+    }
+}
+
+impl SamplingConfig {
+    pub fn is_valid(&self) -> bool {
+        self.samples > 0 && self.filter.is_valid()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::uc::AnalogValue;
-    use crate::uc_utils::AnalogValueMean;
+    use std::time::Duration;
+
+    use crate::mock_uc::{MockMicrocontroller, MockMicrocontrollerAction};
+    use crate::uc::{AnalogInput, AnalogValue, Microcontroller, UcError, GPIO_0};
+    use crate::uc_utils::{
+        retry, AnalogValueMean, MadFilter, MedianFilter, SampleFilter, TrimmedMeanFilter,
+    };
 
     #[test]
     fn analog_value_mean() {
@@ -55,6 +259,77 @@ mod tests {
         assert_eq!(vals.mean(), AnalogValue::new(u16::MAX));
     }
 
+    #[test]
+    fn median_filter() {
+        let filter = MedianFilter;
+
+        assert_eq!(filter.filter(&[]), AnalogValue::new(0));
+        assert_eq!(
+            filter.filter(&get_analog_values(vec![1500, 3000, 1400])),
+            AnalogValue::new(1500)
+        );
+        assert_eq!(
+            filter.filter(&get_analog_values(vec![1500, 0, 1400, 1600])),
+            AnalogValue::new(1450)
+        );
+    }
+
+    #[test]
+    fn trimmed_mean_filter() {
+        let vals = get_analog_values(vec![1500, 1600, 3000, 0, 1700]);
+
+        assert_eq!(TrimmedMeanFilter::new(0).filter(&vals), vals.mean());
+        assert_eq!(
+            TrimmedMeanFilter::new(20).filter(&vals),
+            AnalogValue::new(1600)
+        );
+    }
+
+    #[test]
+    fn mad_filter_rejects_outliers() {
+        let filter = MadFilter::new(3.0);
+
+        let vals = get_analog_values(vec![1500, 1510, 1490, 1505, 3100]);
+        assert_eq!(filter.filter(&vals), AnalogValue::new(1501));
+
+        // No deviation at all; everything but the exact median is an outlier
+        let vals = get_analog_values(vec![1500, 1500, 1500, 2000]);
+        assert_eq!(filter.filter(&vals), AnalogValue::new(1500));
+    }
+
+    #[test]
+    fn retry_until_success() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let mut input = mock_uc.get_analog_input(GPIO_0).unwrap();
+        mock_uc.inject_failures(GPIO_0, 2);
+        let delay = Duration::from_millis(10);
+
+        let result = retry(&mock_uc, 3, delay, || input.get_value());
+
+        assert_eq!(result, Ok(AnalogValue::new(0)));
+        assert_eq!(
+            mock_uc.actions()[1..],
+            [
+                MockMicrocontrollerAction::AnalogGpioGetValueFailed(GPIO_0),
+                MockMicrocontrollerAction::Wait(delay),
+                MockMicrocontrollerAction::AnalogGpioGetValueFailed(GPIO_0),
+                MockMicrocontrollerAction::Wait(delay),
+                MockMicrocontrollerAction::AnalogGpioGetValue(GPIO_0, AnalogValue::new(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn retry_gives_up() {
+        let mut mock_uc = MockMicrocontroller::new();
+        let mut input = mock_uc.get_analog_input(GPIO_0).unwrap();
+        mock_uc.inject_failures(GPIO_0, 3);
+
+        let result = retry(&mock_uc, 3, Duration::from_millis(10), || input.get_value());
+
+        assert_eq!(result, Err(UcError::AnalogReadFailed(GPIO_0)));
+    }
+
     fn get_analog_values(values: Vec<u16>) -> Vec<AnalogValue> {
         values.into_iter().map(|x| x.into()).collect()
     }
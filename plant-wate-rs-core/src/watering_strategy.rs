@@ -0,0 +1,623 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use crate::plant_irrigator::{Percentage, TargetMoistureLevel};
+
+/// Maximum number of pulses a single [`PulseAndSoakStrategy`] watering may
+/// consist of.
+pub const MAX_PULSES: u8 = 16;
+
+const MOISTURE_HISTORY_LENGTH: usize = 16;
+
+/// The most recent moisture readings of a plant, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct MoistureHistory {
+    readings: VecDeque<Percentage>,
+}
+
+impl MoistureHistory {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, moisture: Percentage) {
+        if self.readings.len() == MOISTURE_HISTORY_LENGTH {
+            self.readings.pop_front();
+        }
+        self.readings.push_back(moisture);
+    }
+
+    #[inline]
+    pub fn latest(&self) -> Option<Percentage> {
+        self.readings.back().copied()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.readings.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.readings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Percentage> + '_ {
+        self.readings.iter().copied()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WateringAction {
+    /// Do not water.
+    Skip,
+    /// The target has been reached; ends the current watering.
+    Finish,
+    /// Run the pump for the given time.
+    Water(Duration),
+    /// Run the pump, let the water soak in, then measure the moisture again and
+    /// ask the strategy for the next action.
+    Pulse {
+        pump_time: Duration,
+        soak_time: Duration,
+    },
+}
+
+/// Decides whether (and how much) to water a plant.
+pub trait WateringStrategy: Debug {
+    /// Returns the action to take given the current `moisture` reading.
+    ///
+    /// `history` already contains `moisture` as its latest entry.
+    fn next_action(
+        &mut self,
+        moisture: Percentage,
+        target: &TargetMoistureLevel,
+        history: &MoistureHistory,
+    ) -> WateringAction;
+
+    /// Returns `true` if the strategy is in the middle of a watering that
+    /// spans multiple cycles.
+    fn is_watering(&self) -> bool;
+}
+
+/// Threshold strategy: once the moisture drops below the target minimum, waters
+/// with a fixed dose each cycle until the target maximum is reached.
+#[derive(Debug, Clone)]
+pub struct FixedDoseStrategy {
+    dose: Duration,
+    watering: bool,
+}
+
+impl FixedDoseStrategy {
+    #[inline]
+    pub fn new(dose: Duration) -> Self {
+        Self {
+            dose,
+            watering: false,
+        }
+    }
+}
+
+impl WateringStrategy for FixedDoseStrategy {
+    fn next_action(
+        &mut self,
+        moisture: Percentage,
+        target: &TargetMoistureLevel,
+        _history: &MoistureHistory,
+    ) -> WateringAction {
+        if self.watering && moisture >= target.max_value() {
+            self.watering = false;
+            WateringAction::Finish
+        } else if self.watering || moisture < target.min_value() {
+            self.watering = true;
+            WateringAction::Water(self.dose)
+        } else {
+            WateringAction::Skip
+        }
+    }
+
+    fn is_watering(&self) -> bool {
+        self.watering
+    }
+}
+
+/// Waters in up to `pulses` short pulses, letting the water soak in and
+/// measuring the moisture again after each of them. Stops as soon as the target
+/// maximum is reached.
+#[derive(Debug, Clone)]
+pub struct PulseAndSoakStrategy {
+    pulses: u8,
+    pulse_time: Duration,
+    soak_time: Duration,
+    pulses_done: u8,
+}
+
+impl PulseAndSoakStrategy {
+    #[inline]
+    pub fn new(pulses: u8, pulse_time: Duration, soak_time: Duration) -> Self {
+        debug_assert!(pulses > 0 && pulses <= MAX_PULSES);
+
+        Self {
+            pulses,
+            pulse_time,
+            soak_time,
+            pulses_done: 0,
+        }
+    }
+
+    fn pulse(&mut self) -> WateringAction {
+        self.pulses_done += 1;
+        WateringAction::Pulse {
+            pump_time: self.pulse_time,
+            soak_time: self.soak_time,
+        }
+    }
+}
+
+impl WateringStrategy for PulseAndSoakStrategy {
+    fn next_action(
+        &mut self,
+        moisture: Percentage,
+        target: &TargetMoistureLevel,
+        _history: &MoistureHistory,
+    ) -> WateringAction {
+        if self.pulses_done == 0 {
+            if moisture < target.min_value() {
+                self.pulse()
+            } else {
+                WateringAction::Skip
+            }
+        } else if moisture >= target.max_value() {
+            self.pulses_done = 0;
+            WateringAction::Finish
+        } else if self.pulses_done < self.pulses {
+            self.pulse()
+        } else {
+            // Out of pulses for this cycle; start over in the next one
+            self.pulses_done = 0;
+            WateringAction::Skip
+        }
+    }
+
+    fn is_watering(&self) -> bool {
+        self.pulses_done > 0
+    }
+}
+
+/// Waters whenever the moisture is below the target minimum, with the pump time
+/// proportional to how far the moisture is from the target maximum.
+#[derive(Debug, Clone)]
+pub struct ProportionalStrategy {
+    time_per_percent: Duration,
+    max_pump_time: Duration,
+}
+
+impl ProportionalStrategy {
+    #[inline]
+    pub fn new(time_per_percent: Duration, max_pump_time: Duration) -> Self {
+        Self {
+            time_per_percent,
+            max_pump_time,
+        }
+    }
+}
+
+impl WateringStrategy for ProportionalStrategy {
+    fn next_action(
+        &mut self,
+        moisture: Percentage,
+        target: &TargetMoistureLevel,
+        _history: &MoistureHistory,
+    ) -> WateringAction {
+        if moisture >= target.min_value() {
+            return WateringAction::Skip;
+        }
+
+        let deficit = target.max_value().value() - moisture.value();
+        let pump_time = (self.time_per_percent * deficit as u32).min(self.max_pump_time);
+        WateringAction::Water(pump_time)
+    }
+
+    fn is_watering(&self) -> bool {
+        false
+    }
+}
+
+/// Gains of a [`PidStrategy`], in milliseconds of pump time per percent of
+/// moisture error.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+impl PidGains {
+    #[inline]
+    pub const fn new(kp: f32, ki: f32, kd: f32) -> Self {
+        Self { kp, ki, kd }
+    }
+
+    fn is_valid(&self) -> bool {
+        [self.kp, self.ki, self.kd]
+            .iter()
+            .all(|gain| gain.is_finite() && *gain >= 0.0)
+    }
+}
+
+/// PID controller computing the pump time from the error between the target
+/// moisture midpoint and the measured moisture, with one cycle as the time
+/// step.
+///
+/// The integral term is clamped to `±integral_limit` and is not accumulated
+/// while the output is saturated in the direction of the error (anti-windup).
+/// The derivative is taken on the measurement, so changing the target does not
+/// cause a pump time spike. Outputs shorter than `min_pump_time` are skipped.
+#[derive(Debug, Clone)]
+pub struct PidStrategy {
+    gains: PidGains,
+    min_pump_time: Duration,
+    max_pump_time: Duration,
+    integral_limit: f32,
+    integral: f32,
+}
+
+impl PidStrategy {
+    #[inline]
+    pub fn new(
+        gains: PidGains,
+        min_pump_time: Duration,
+        max_pump_time: Duration,
+        integral_limit: f32,
+    ) -> Self {
+        debug_assert!(min_pump_time <= max_pump_time);
+
+        Self {
+            gains,
+            min_pump_time,
+            max_pump_time,
+            integral_limit,
+            integral: 0.0,
+        }
+    }
+
+    #[inline]
+    pub const fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    fn compute_output(&self, error: f32, integral: f32, derivative: f32) -> f32 {
+        self.gains.kp * error + self.gains.ki * integral + self.gains.kd * derivative
+    }
+}
+
+impl WateringStrategy for PidStrategy {
+    fn next_action(
+        &mut self,
+        moisture: Percentage,
+        target: &TargetMoistureLevel,
+        history: &MoistureHistory,
+    ) -> WateringAction {
+        let setpoint =
+            (target.min_value().value() as f32 + target.max_value().value() as f32) / 2.0;
+        let measurement = moisture.value() as f32;
+        let error = setpoint - measurement;
+
+        let previous_measurement = history.iter().rev().nth(1);
+        let derivative =
+            previous_measurement.map_or(0.0, |previous| -(measurement - previous.value() as f32));
+
+        let max_output = self.max_pump_time.as_millis() as f32;
+        let integral = (self.integral + error).clamp(-self.integral_limit, self.integral_limit);
+        let output = self.compute_output(error, integral, derivative);
+        let saturated_high = output > max_output && error > 0.0;
+        let saturated_low = output < 0.0 && error < 0.0;
+        if !saturated_high && !saturated_low {
+            self.integral = integral;
+        }
+
+        let output = self
+            .compute_output(error, self.integral, derivative)
+            .clamp(0.0, max_output);
+        let pump_time = Duration::from_millis(output.round() as u64);
+        if pump_time.is_zero() || pump_time < self.min_pump_time {
+            WateringAction::Skip
+        } else {
+            WateringAction::Water(pump_time)
+        }
+    }
+
+    fn is_watering(&self) -> bool {
+        false
+    }
+}
+
+/// Declarative choice of a [`WateringStrategy`] for a plant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WateringStrategyConfig {
+    FixedDose {
+        dose: Duration,
+    },
+    PulseAndSoak {
+        pulses: u8,
+        pulse_time: Duration,
+        soak_time: Duration,
+    },
+    Proportional {
+        time_per_percent: Duration,
+        max_pump_time: Duration,
+    },
+    Pid {
+        gains: PidGains,
+        min_pump_time: Duration,
+        max_pump_time: Duration,
+        integral_limit: f32,
+    },
+}
+
+impl Default for WateringStrategyConfig {
+    fn default() -> Self {
+        Self::FixedDose {
+            dose: Duration::from_millis(500),
+        }
+    }
+}
+
+impl WateringStrategyConfig {
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            WateringStrategyConfig::FixedDose { dose } => !dose.is_zero(),
+            WateringStrategyConfig::PulseAndSoak {
+                pulses, pulse_time, ..
+            } => pulses > 0 && pulses <= MAX_PULSES && !pulse_time.is_zero(),
+            WateringStrategyConfig::Proportional {
+                time_per_percent,
+                max_pump_time,
+            } => !time_per_percent.is_zero() && !max_pump_time.is_zero(),
+            WateringStrategyConfig::Pid {
+                gains,
+                min_pump_time,
+                max_pump_time,
+                integral_limit,
+            } => {
+                gains.is_valid()
+                    && !max_pump_time.is_zero()
+                    && min_pump_time <= max_pump_time
+                    && integral_limit.is_finite()
+                    && integral_limit >= 0.0
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn build(&self) -> Box<dyn WateringStrategy> {
+        match *self {
+            WateringStrategyConfig::FixedDose { dose } => Box::new(FixedDoseStrategy::new(dose)),
+            WateringStrategyConfig::PulseAndSoak {
+                pulses,
+                pulse_time,
+                soak_time,
+            } => Box::new(PulseAndSoakStrategy::new(pulses, pulse_time, soak_time)),
+            WateringStrategyConfig::Proportional {
+                time_per_percent,
+                max_pump_time,
+            } => Box::new(ProportionalStrategy::new(time_per_percent, max_pump_time)),
+            WateringStrategyConfig::Pid {
+                gains,
+                min_pump_time,
+                max_pump_time,
+                integral_limit,
+            } => Box::new(PidStrategy::new(
+                gains,
+                min_pump_time,
+                max_pump_time,
+                integral_limit,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PULSE: WateringAction = WateringAction::Pulse {
+        pump_time: Duration::from_millis(200),
+        soak_time: Duration::from_secs(10),
+    };
+
+    #[test]
+    fn moisture_history_keeps_latest_readings() {
+        let mut history = MoistureHistory::new();
+        for value in 0..20 {
+            history.push(Percentage::new(value));
+        }
+
+        assert_eq!(history.len(), MOISTURE_HISTORY_LENGTH);
+        assert_eq!(history.iter().next(), Some(Percentage::new(4)));
+        assert_eq!(history.latest(), Some(Percentage::new(19)));
+    }
+
+    #[test]
+    fn fixed_dose_hysteresis() {
+        let mut strategy = FixedDoseStrategy::new(Duration::from_millis(500));
+
+        let actions = run_strategy(&mut strategy, &[50, 30, 50, 70, 50]);
+
+        let water = WateringAction::Water(Duration::from_millis(500));
+        assert_eq!(
+            actions,
+            vec![
+                WateringAction::Skip,
+                water,
+                water,
+                WateringAction::Finish,
+                WateringAction::Skip,
+            ]
+        );
+    }
+
+    #[test]
+    fn pulse_and_soak_stops_at_max() {
+        let mut strategy =
+            PulseAndSoakStrategy::new(3, Duration::from_millis(200), Duration::from_secs(10));
+
+        let actions = run_strategy(&mut strategy, &[30, 50, 75, 50]);
+
+        assert_eq!(
+            actions,
+            vec![PULSE, PULSE, WateringAction::Finish, WateringAction::Skip]
+        );
+        assert!(!strategy.is_watering());
+    }
+
+    #[test]
+    fn pulse_and_soak_limits_pulses() {
+        let mut strategy =
+            PulseAndSoakStrategy::new(2, Duration::from_millis(200), Duration::from_secs(10));
+
+        let actions = run_strategy(&mut strategy, &[30, 35, 40, 30]);
+
+        assert_eq!(actions, vec![PULSE, PULSE, WateringAction::Skip, PULSE]);
+    }
+
+    #[test]
+    fn proportional_scales_pump_time() {
+        let mut strategy =
+            ProportionalStrategy::new(Duration::from_millis(40), Duration::from_secs(2));
+
+        let actions = run_strategy(&mut strategy, &[50, 30, 10, 0]);
+
+        assert_eq!(
+            actions,
+            vec![
+                WateringAction::Skip,
+                WateringAction::Water(Duration::from_millis(1600)),
+                WateringAction::Water(Duration::from_millis(2000)),
+                WateringAction::Water(Duration::from_millis(2000)),
+            ]
+        );
+
+        let mut strategy =
+            ProportionalStrategy::new(Duration::from_millis(10), Duration::from_secs(2));
+        let actions = run_strategy(&mut strategy, &[39, 20]);
+        assert_eq!(
+            actions,
+            vec![
+                WateringAction::Water(Duration::from_millis(310)),
+                WateringAction::Water(Duration::from_millis(500)),
+            ]
+        );
+    }
+
+    #[test]
+    fn pid_integral_anti_windup() {
+        let mut strategy = PidStrategy::new(
+            PidGains::new(100.0, 10.0, 0.0),
+            Duration::from_millis(50),
+            Duration::from_secs(1),
+            1000.0,
+        );
+
+        // The pot stays dry: the output is saturated, so the integral must not
+        // keep growing
+        let actions = run_strategy(&mut strategy, &[20, 20, 20, 20, 20]);
+
+        assert!(actions
+            .iter()
+            .all(|action| *action == WateringAction::Water(Duration::from_secs(1))));
+        assert_eq!(strategy.integral(), 0.0);
+
+        // Without a wound-up integral, the pump stops as soon as the moisture
+        // gets above the midpoint
+        let actions = run_strategy(&mut strategy, &[60]);
+        assert_eq!(actions, vec![WateringAction::Skip]);
+    }
+
+    #[test]
+    fn pid_integral_limit() {
+        let mut strategy = PidStrategy::new(
+            PidGains::new(0.0, 1.0, 0.0),
+            Duration::ZERO,
+            Duration::from_secs(10),
+            50.0,
+        );
+
+        run_strategy(&mut strategy, &[35, 35, 35, 35]);
+
+        assert_eq!(strategy.integral(), 50.0);
+    }
+
+    #[test]
+    fn pid_skips_short_pulses() {
+        let mut strategy = PidStrategy::new(
+            PidGains::new(10.0, 0.0, 0.0),
+            Duration::from_millis(100),
+            Duration::from_secs(1),
+            100.0,
+        );
+
+        // Midpoint is 55%
+        let actions = run_strategy(&mut strategy, &[50, 45, 60]);
+
+        assert_eq!(
+            actions,
+            vec![
+                WateringAction::Skip,
+                WateringAction::Water(Duration::from_millis(100)),
+                WateringAction::Skip,
+            ]
+        );
+    }
+
+    #[test]
+    fn strategy_config_validation() {
+        assert!(WateringStrategyConfig::default().is_valid());
+        assert!(!WateringStrategyConfig::FixedDose {
+            dose: Duration::ZERO
+        }
+        .is_valid());
+        assert!(!WateringStrategyConfig::PulseAndSoak {
+            pulses: 0,
+            pulse_time: Duration::from_millis(200),
+            soak_time: Duration::from_secs(10),
+        }
+        .is_valid());
+        assert!(!WateringStrategyConfig::PulseAndSoak {
+            pulses: MAX_PULSES + 1,
+            pulse_time: Duration::from_millis(200),
+            soak_time: Duration::from_secs(10),
+        }
+        .is_valid());
+        assert!(!WateringStrategyConfig::Pid {
+            gains: PidGains::new(1.0, f32::NAN, 0.0),
+            min_pump_time: Duration::ZERO,
+            max_pump_time: Duration::from_secs(1),
+            integral_limit: 10.0,
+        }
+        .is_valid());
+        assert!(!WateringStrategyConfig::Pid {
+            gains: PidGains::new(1.0, 0.1, 0.0),
+            min_pump_time: Duration::from_secs(2),
+            max_pump_time: Duration::from_secs(1),
+            integral_limit: 10.0,
+        }
+        .is_valid());
+    }
+
+    fn run_strategy(strategy: &mut dyn WateringStrategy, readings: &[u8]) -> Vec<WateringAction> {
+        let target = TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70));
+        let mut history = MoistureHistory::new();
+
+        readings
+            .iter()
+            .map(|&reading| {
+                let moisture = Percentage::new(reading);
+                history.push(moisture);
+                strategy.next_action(moisture, &target, &history)
+            })
+            .collect()
+    }
+}
@@ -5,6 +5,9 @@ pub struct Config {
     wifi_ssid: &'static str,
     #[default("")]
     wifi_psk: &'static str,
+    /// E.g. `mqtt://192.168.1.10:1883`; telemetry is off if empty.
+    #[default("")]
+    mqtt_url: &'static str,
 }
 
 fn main() -> anyhow::Result<()> {
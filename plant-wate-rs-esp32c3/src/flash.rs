@@ -0,0 +1,113 @@
+use std::ffi::{c_void, CString};
+use std::fmt::{Debug, Formatter};
+use std::io;
+
+use anyhow::{bail, Result};
+use esp_idf_sys::{
+    esp, esp_partition_erase_range, esp_partition_find_first, esp_partition_read,
+    esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY, esp_partition_t,
+    esp_partition_type_t_ESP_PARTITION_TYPE_DATA, esp_partition_write, EspError,
+    SPI_FLASH_SEC_SIZE,
+};
+use log::error;
+use plant_wate_rs_core::flash::{Flash, FlashError};
+
+/// [`Flash`] over a data partition of the SPI flash, see `partitions.csv`.
+/// Its pages are the flash sectors.
+pub struct PartitionFlash {
+    label: String,
+    partition: *const esp_partition_t,
+    page_count: usize,
+}
+
+impl PartitionFlash {
+    pub fn new(label: &str) -> Result<Self> {
+        let c_label = CString::new(label)?;
+        let partition = unsafe {
+            esp_partition_find_first(
+                esp_partition_type_t_ESP_PARTITION_TYPE_DATA,
+                esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY,
+                c_label.as_ptr(),
+            )
+        };
+        if partition.is_null() {
+            bail!("No data partition labelled {}", label);
+        }
+
+        // Partitions are always sector aligned
+        let size = unsafe { (*partition).size } as usize;
+        Ok(Self {
+            label: label.to_owned(),
+            partition,
+            page_count: size / SPI_FLASH_SEC_SIZE as usize,
+        })
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result<(), FlashError> {
+        let size = self.page_size() * self.page_count;
+        match offset.checked_add(len) {
+            Some(end) if end <= size => Ok(()),
+            _ => Err(FlashError::OutOfBounds { offset, len }),
+        }
+    }
+
+    fn map_error(&self, operation: &str, error: EspError) -> FlashError {
+        error!(
+            "Could not {} the {} partition: {}",
+            operation, self.label, error
+        );
+        FlashError::Io(io::ErrorKind::Other)
+    }
+}
+
+impl Debug for PartitionFlash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PartitionFlash")
+            .field("label", &self.label)
+            .field("page_count", &self.page_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Flash for PartitionFlash {
+    fn page_size(&self) -> usize {
+        SPI_FLASH_SEC_SIZE as usize
+    }
+
+    fn page_count(&self) -> usize {
+        self.page_count
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<(), FlashError> {
+        self.check(offset, buffer.len())?;
+        esp!(unsafe {
+            esp_partition_read(
+                self.partition,
+                offset,
+                buffer.as_mut_ptr() as *mut c_void,
+                buffer.len(),
+            )
+        })
+        .map_err(|error| self.map_error("read", error))
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        self.check(offset, data.len())?;
+        esp!(unsafe {
+            esp_partition_write(
+                self.partition,
+                offset,
+                data.as_ptr() as *const c_void,
+                data.len(),
+            )
+        })
+        .map_err(|error| self.map_error("write", error))
+    }
+
+    fn erase_page(&mut self, page: usize) -> Result<(), FlashError> {
+        let page_size = self.page_size();
+        self.check(page * page_size, page_size)?;
+        esp!(unsafe { esp_partition_erase_range(self.partition, page * page_size, page_size) })
+            .map_err(|error| self.map_error("erase", error))
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use embedded_svc::http::Method as EspMethod;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::http::server::{Configuration, EspHttpConnection, EspHttpServer, Request};
+use plant_wate_rs_core::http::{
+    reason_phrase, HttpRequest, HttpResponse, HttpServer, Method, MAX_BODY_LEN,
+};
+
+/// How long a request waits for the control loop to handle it. Manual
+/// waterings keep the loop busy while the pump runs.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The request headers passed on to the control loop.
+const FORWARDED_HEADERS: [&str; 2] = ["Accept-Encoding", "Content-Type"];
+
+/// A request, whether the control loop or the timed out server task has
+/// claimed it, and where to send the response.
+type Exchange = (HttpRequest, Arc<AtomicBool>, SyncSender<HttpResponse>);
+
+/// Hands the requests received by the ESP-IDF HTTP server, on its own task, to
+/// the control loop polling it.
+pub struct HttpServerEsp32c3 {
+    _server: EspHttpServer,
+    requests: Receiver<Exchange>,
+}
+
+impl HttpServerEsp32c3 {
+    pub fn new() -> Result<Self> {
+        let mut server = EspHttpServer::new(&Configuration {
+            uri_match_wildcard: true,
+            ..Default::default()
+        })?;
+        let (sender, requests) = mpsc::channel();
+
+        for (esp_method, method) in [
+            (EspMethod::Get, Method::Get),
+            (EspMethod::Post, Method::Post),
+            (EspMethod::Put, Method::Put),
+            (EspMethod::Patch, Method::Patch),
+            (EspMethod::Delete, Method::Delete),
+        ] {
+            let sender = sender.clone();
+            server.fn_handler("/*", esp_method, move |request| {
+                forward(&sender, method, request)
+            })?;
+        }
+
+        Ok(Self {
+            _server: server,
+            requests,
+        })
+    }
+}
+
+impl Debug for HttpServerEsp32c3 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpServerEsp32c3").finish_non_exhaustive()
+    }
+}
+
+impl HttpServer for HttpServerEsp32c3 {
+    fn poll(&mut self, handler: &mut dyn FnMut(&HttpRequest) -> HttpResponse) {
+        while let Ok((request, claimed, reply)) = self.requests.try_recv() {
+            // A request that timed out was answered already and must not run
+            if claimed.swap(true, Ordering::AcqRel) {
+                continue;
+            }
+            let _ = reply.send(handler(&request));
+        }
+    }
+}
+
+fn forward(
+    sender: &Sender<Exchange>,
+    method: Method,
+    mut request: Request<&mut EspHttpConnection>,
+) -> embedded_svc::http::server::HandlerResult {
+    let content_length = request
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let response = if content_length > MAX_BODY_LEN {
+        HttpResponse::error(413, "request body too large")
+    } else {
+        let mut body = vec![0; content_length];
+        request.read_exact(&mut body)?;
+        let mut http_request = HttpRequest::new(method, request.uri(), body);
+        // The ESP-IDF server cannot list the headers; only those the handlers
+        // look at are passed on
+        for name in FORWARDED_HEADERS {
+            if let Some(value) = request.header(name) {
+                http_request = http_request.with_header(name, value);
+            }
+        }
+
+        let claimed = Arc::new(AtomicBool::new(false));
+        let (reply, response) = mpsc::sync_channel(1);
+        sender.send((http_request, claimed.clone(), reply))?;
+        match response.recv_timeout(RESPONSE_TIMEOUT) {
+            Ok(response) => response,
+            // Once the control loop has started on the request, its outcome is
+            // waited for
+            Err(_) if claimed.swap(true, Ordering::AcqRel) => response
+                .recv()
+                .unwrap_or_else(|_| HttpResponse::error(500, "request dropped")),
+            Err(_) => HttpResponse::error(503, "controller busy"),
+        }
+    };
+
+    let mut headers = vec![("Content-Type", response.content_type)];
+    headers.extend(response.headers.iter().copied());
+    request
+        .into_response(
+            response.status,
+            Some(reason_phrase(response.status)),
+            &headers,
+        )?
+        .write_all(&response.body)?;
+    Ok(())
+}
@@ -1,13 +1,30 @@
 use core::str;
 
 use anyhow::Result;
+use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::sntp::EspSntp;
 use esp_idf_sys as _;
+use log::error;
 use plant_wate_rs_core::controller::Controller;
+use plant_wate_rs_core::history_log::HistoryLog;
+use plant_wate_rs_core::plant_config::PlantConfig;
+use plant_wate_rs_core::plant_irrigator::{
+    Percentage, SensorCalibrationResult, TargetMoistureLevel,
+};
+use plant_wate_rs_core::rest_api::RestApiConfig;
+use plant_wate_rs_core::telemetry::{Telemetry, TelemetryConfig};
+use plant_wate_rs_core::uc::{AnalogValue, GPIO_0, GPIO_2};
 
+use crate::flash::PartitionFlash;
+use crate::http_server::HttpServerEsp32c3;
 use crate::microcontroller_esp32c3::MicrocontrollerEsp32c3;
+use crate::mqtt_client::MqttClientEsp32c3;
 
+mod flash;
+mod http_server;
 mod microcontroller_esp32c3;
+mod mqtt_client;
 mod wifi;
 
 #[toml_cfg::toml_config]
@@ -16,17 +33,68 @@ pub struct Config {
     wifi_ssid: &'static str,
     #[default("")]
     wifi_psk: &'static str,
+    /// E.g. `mqtt://192.168.1.10:1883`; telemetry is off if empty.
+    #[default("")]
+    mqtt_url: &'static str,
 }
 
 fn main() -> Result<()> {
     esp_idf_sys::link_patches();
     esp_idf_svc::log::EspLogger::initialize_default();
 
-    let _app_config = CONFIG;
-    let _sysloop = EspSystemEventLoop::take()?;
+    let app_config = CONFIG;
+    let sysloop = EspSystemEventLoop::take()?;
+    let peripherals = Peripherals::take().unwrap();
+
+    // Connects in the background; the plants are watered without the REST API
+    // and MQTT if the WiFi cannot be started
+    let network = match wifi::start(
+        app_config.wifi_ssid,
+        app_config.wifi_psk,
+        peripherals.modem,
+        sysloop,
+    ) {
+        Ok(()) => true,
+        Err(error) => {
+            error!("Could not start the WiFi: {}", error);
+            false
+        }
+    };
+    // The wall clock, and so the schedule and the timestamps, only work once
+    // synchronized
+    let _sntp = if network {
+        EspSntp::new_default()
+            .map_err(|error| error!("Could not start SNTP: {}", error))
+            .ok()
+    } else {
+        None
+    };
+
+    let plants = [PlantConfig::new(
+        "plant_1",
+        GPIO_0,
+        GPIO_2,
+        SensorCalibrationResult::new(AnalogValue::new(1027), AnalogValue::new(2526)),
+        TargetMoistureLevel::new(Percentage::new(40), Percentage::new(70)),
+    )];
 
-    let microcontroller = MicrocontrollerEsp32c3::new();
-    let mut controller = Controller::new(microcontroller);
+    let microcontroller = MicrocontrollerEsp32c3::new(peripherals.adc1, peripherals.pins);
+    let history_log = HistoryLog::open(Box::new(PartitionFlash::new("history")?))?;
+    let mut controller = Controller::new(microcontroller, &plants)?.with_history_log(history_log);
+    if network {
+        match HttpServerEsp32c3::new() {
+            Ok(server) => {
+                controller =
+                    controller.with_rest_api(Box::new(server), RestApiConfig::default())?;
+            }
+            Err(error) => error!("Could not start the HTTP server: {}", error),
+        }
+    }
+    if network && !app_config.mqtt_url.is_empty() {
+        let transport = MqttClientEsp32c3::new(app_config.mqtt_url, "plant-wate-rs");
+        let telemetry = Telemetry::new(Box::new(transport), TelemetryConfig::default())?;
+        controller = controller.with_telemetry(telemetry)?;
+    }
 
     controller.run();
 }
@@ -1,18 +1,22 @@
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 use esp_idf_hal::adc;
 use esp_idf_hal::adc::{AdcDriver, ADC1};
 use esp_idf_hal::gpio::{
-    Gpio0, Gpio1, Gpio2, Gpio3, Gpio4, Gpio5, Gpio6, Gpio7, Gpio8, Output, PinDriver,
+    Gpio0, Gpio1, Gpio2, Gpio3, Gpio4, Gpio5, Gpio6, Gpio7, Gpio8, Output, PinDriver, Pins,
 };
-use esp_idf_hal::peripherals::Peripherals;
+use esp_idf_sys::EspError;
+use log::warn;
 use plant_wate_rs_core::uc::{
-    AnalogInput, AnalogValue, DigitalOutput, GpioId, Microcontroller, GPIO_0, GPIO_2,
+    AnalogInput, AnalogValue, DigitalOutput, GpioId, Microcontroller, UcError, GPIO_0, GPIO_2,
 };
 
+/// 2023-01-01T00:00:00Z
+const WALL_CLOCK_MIN_VALID: Duration = Duration::from_secs(1_672_531_200);
+
 pub enum AnalogInputEsp32c3Pin<'a> {
     Gpio0(adc::AdcChannelDriver<'a, Gpio0, adc::Atten11dB<ADC1>>),
 }
@@ -38,12 +42,15 @@ impl<'a> AnalogInputEsp32c3<'a> {
 }
 
 impl<'a> AnalogInput for AnalogInputEsp32c3<'a> {
-    fn get_value(&mut self) -> AnalogValue {
+    fn get_value(&mut self) -> Result<AnalogValue, UcError> {
         let value = match &mut self.pin {
-            AnalogInputEsp32c3Pin::Gpio0(pin) => self.adc_driver.borrow_mut().read(pin).unwrap(),
+            AnalogInputEsp32c3Pin::Gpio0(pin) => self.adc_driver.borrow_mut().read(pin),
         };
 
-        AnalogValue::new(value)
+        value.map(AnalogValue::new).map_err(|error| {
+            warn!("ADC read on {} failed: {}", self.id, error);
+            UcError::AnalogReadFailed(self.id)
+        })
     }
 }
 
@@ -52,30 +59,36 @@ pub enum DigitalOutputEsp32c3Pin<'a> {
 }
 
 pub struct DigitalOutputEsp32c3<'a> {
+    id: GpioId,
     pin: DigitalOutputEsp32c3Pin<'a>,
 }
 
 impl<'a> DigitalOutputEsp32c3<'a> {
-    pub fn new(pin: DigitalOutputEsp32c3Pin<'a>) -> Self {
-        Self { pin }
+    pub fn new(id: GpioId, pin: DigitalOutputEsp32c3Pin<'a>) -> Self {
+        Self { id, pin }
+    }
+
+    fn write_error(&self, error: EspError) -> UcError {
+        warn!("Setting the level of {} failed: {}", self.id, error);
+        UcError::DigitalWriteFailed(self.id)
     }
 }
 
 impl<'a> DigitalOutput for DigitalOutputEsp32c3<'a> {
-    fn set_high(&mut self) {
-        match &mut self.pin {
-            DigitalOutputEsp32c3Pin::Gpio2(pin) => {
-                pin.set_high().unwrap();
-            }
-        }
+    fn set_high(&mut self) -> Result<(), UcError> {
+        let result = match &mut self.pin {
+            DigitalOutputEsp32c3Pin::Gpio2(pin) => pin.set_high(),
+        };
+
+        result.map_err(|error| self.write_error(error))
     }
 
-    fn set_low(&mut self) {
-        match &mut self.pin {
-            DigitalOutputEsp32c3Pin::Gpio2(pin) => {
-                pin.set_low().unwrap();
-            }
-        }
+    fn set_low(&mut self) -> Result<(), UcError> {
+        let result = match &mut self.pin {
+            DigitalOutputEsp32c3Pin::Gpio2(pin) => pin.set_low(),
+        };
+
+        result.map_err(|error| self.write_error(error))
     }
 }
 
@@ -100,53 +113,70 @@ impl<'a> Microcontroller for MicrocontrollerEsp32c3<'a> {
         thread::sleep(duration);
     }
 
-    fn get_analog_input(&mut self, id: GpioId) -> Self::AnalogInput {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_clock(&self) -> Option<SystemTime> {
+        // The RTC starts at the epoch on boot; anything before the clock is
+        // set (e.g. over SNTP) is meaningless
+        let now = SystemTime::now();
+        (now >= SystemTime::UNIX_EPOCH + WALL_CLOCK_MIN_VALID).then_some(now)
+    }
+
+    fn get_analog_input(&mut self, id: GpioId) -> Result<Self::AnalogInput, UcError> {
+        let init_error = |error: EspError| {
+            warn!("Initializing {} as analog input failed: {}", id, error);
+            UcError::GpioInitFailed(id)
+        };
+
         let pin = if id == GPIO_0 {
-            let pin = self.gpio_0.take().expect("GPIO0 already taken");
+            let pin = self.gpio_0.take().ok_or(UcError::GpioInUse(id))?;
             AnalogInputEsp32c3Pin::Gpio0(
-                adc::AdcChannelDriver::<Gpio0, adc::Atten11dB<ADC1>>::new(pin).unwrap(),
+                adc::AdcChannelDriver::<Gpio0, adc::Atten11dB<ADC1>>::new(pin)
+                    .map_err(init_error)?,
             )
         } else {
-            panic!("{} not supported as analog input", id)
+            return Err(UcError::GpioNotSupported(id));
         };
 
-        AnalogInputEsp32c3::new(id, pin, self.adc_driver_1.clone())
+        Ok(AnalogInputEsp32c3::new(id, pin, self.adc_driver_1.clone()))
     }
 
-    fn get_digital_output(&mut self, id: GpioId) -> Self::DigitalOutput {
+    fn get_digital_output(&mut self, id: GpioId) -> Result<Self::DigitalOutput, UcError> {
+        let init_error = |error: EspError| {
+            warn!("Initializing {} as digital output failed: {}", id, error);
+            UcError::GpioInitFailed(id)
+        };
+
         let pin = if id == GPIO_2 {
-            let pin = self
-                .gpio_2
-                .take()
-                .unwrap_or_else(|| panic!("{} already taken", id));
-            DigitalOutputEsp32c3Pin::Gpio2(PinDriver::output(pin).unwrap())
+            let pin = self.gpio_2.take().ok_or(UcError::GpioInUse(id))?;
+            DigitalOutputEsp32c3Pin::Gpio2(PinDriver::output(pin).map_err(init_error)?)
         } else {
-            panic!("{} not supported as digital output", id)
+            return Err(UcError::GpioNotSupported(id));
         };
 
-        DigitalOutputEsp32c3::new(pin)
+        Ok(DigitalOutputEsp32c3::new(id, pin))
     }
 }
 
 impl<'a> MicrocontrollerEsp32c3<'a> {
-    pub fn new() -> Self {
-        let peripherals = Peripherals::take().unwrap();
-        let adc_driver_1: AdcDriver<'_, ADC1> = AdcDriver::new(
-            peripherals.adc1,
-            &adc::config::Config::new().calibration(true),
-        )
-        .unwrap();
+    /// Takes the peripherals it needs; the rest, e.g. the modem, is left for
+    /// the caller.
+    pub fn new(adc1: ADC1, pins: Pins) -> Self {
+        let adc_driver_1: AdcDriver<'_, ADC1> =
+            AdcDriver::new(adc1, &adc::config::Config::new().calibration(true)).unwrap();
 
         Self {
-            gpio_0: Cell::new(Some(peripherals.pins.gpio0)),
-            gpio_1: Cell::new(Some(peripherals.pins.gpio1)),
-            gpio_2: Cell::new(Some(peripherals.pins.gpio2)),
-            gpio_3: Cell::new(Some(peripherals.pins.gpio3)),
-            gpio_4: Cell::new(Some(peripherals.pins.gpio4)),
-            gpio_5: Cell::new(Some(peripherals.pins.gpio5)),
-            gpio_6: Cell::new(Some(peripherals.pins.gpio6)),
-            gpio_7: Cell::new(Some(peripherals.pins.gpio7)),
-            gpio_8: Cell::new(Some(peripherals.pins.gpio8)),
+            gpio_0: Cell::new(Some(pins.gpio0)),
+            gpio_1: Cell::new(Some(pins.gpio1)),
+            gpio_2: Cell::new(Some(pins.gpio2)),
+            gpio_3: Cell::new(Some(pins.gpio3)),
+            gpio_4: Cell::new(Some(pins.gpio4)),
+            gpio_5: Cell::new(Some(pins.gpio5)),
+            gpio_6: Cell::new(Some(pins.gpio6)),
+            gpio_7: Cell::new(Some(pins.gpio7)),
+            gpio_8: Cell::new(Some(pins.gpio8)),
             adc_driver_1: Rc::new(RefCell::new(adc_driver_1)),
         }
     }
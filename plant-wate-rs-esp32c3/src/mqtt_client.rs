@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use embedded_svc::mqtt::client::{Client, Details, Event, Message, Publish, QoS};
+use esp_idf_svc::mqtt::client::{
+    EspMqttClient, EspMqttMessage, LwtConfiguration, MqttClientConfiguration,
+};
+use esp_idf_sys::EspError;
+use log::{info, warn};
+use plant_wate_rs_core::mqtt::{MqttMessage, MqttTransport, TransportError};
+
+/// Received messages not taken by the control loop yet; the oldest ones are
+/// dropped beyond that.
+const MAX_RECEIVED: usize = 32;
+
+/// State shared with the event handler, which runs on the task of the MQTT
+/// client.
+#[derive(Default)]
+struct Shared {
+    connected: AtomicBool,
+    /// Incremented on every connection.
+    connections: AtomicU32,
+    received: Mutex<VecDeque<MqttMessage>>,
+}
+
+/// [`MqttTransport`] over the ESP-IDF MQTT client, which connects and
+/// reconnects on its own task. The client is created by the first
+/// [`MqttTransport::connect`], once the last will is known.
+pub struct MqttClientEsp32c3 {
+    url: String,
+    client_id: String,
+    will: Option<MqttMessage>,
+    client: Option<EspMqttClient>,
+    shared: Arc<Shared>,
+    /// The connection the subscriptions were made on.
+    connection: u32,
+}
+
+impl MqttClientEsp32c3 {
+    /// `url` is e.g. `mqtt://192.168.1.10:1883`.
+    pub fn new(url: &str, client_id: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            client_id: client_id.to_owned(),
+            will: None,
+            client: None,
+            shared: Arc::new(Shared::default()),
+            connection: 0,
+        }
+    }
+
+    fn create_client(&self) -> Result<EspMqttClient, EspError> {
+        let config = MqttClientConfiguration {
+            client_id: Some(&self.client_id),
+            lwt: self.will.as_ref().map(|will| LwtConfiguration {
+                topic: &will.topic,
+                payload: &will.payload,
+                qos: QoS::AtLeastOnce,
+                retain: will.retain,
+            }),
+            ..Default::default()
+        };
+        let shared = self.shared.clone();
+        EspMqttClient::new(&self.url, &config, move |event| {
+            handle_event(&shared, event)
+        })
+    }
+}
+
+impl Debug for MqttClientEsp32c3 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttClientEsp32c3")
+            .field("url", &self.url)
+            .field("client_id", &self.client_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MqttTransport for MqttClientEsp32c3 {
+    fn connect(&mut self) -> Result<(), TransportError> {
+        if self.client.is_none() {
+            let client = self
+                .create_client()
+                .map_err(|error| TransportError::ConnectionFailed(error.to_string()))?;
+            self.client = Some(client);
+        }
+
+        // The client connects in the background
+        if !self.shared.connected.load(Ordering::SeqCst) {
+            return Err(TransportError::NotConnected);
+        }
+        self.connection = self.shared.connections.load(Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// A reconnection made by the client in the background counts as a lost
+    /// connection, so that the subscriptions are renewed.
+    fn is_connected(&self) -> bool {
+        self.shared.connected.load(Ordering::SeqCst)
+            && self.shared.connections.load(Ordering::SeqCst) == self.connection
+    }
+
+    fn set_last_will(&mut self, will: MqttMessage) {
+        self.will = Some(will);
+    }
+
+    fn publish(&mut self, message: &MqttMessage) -> Result<(), TransportError> {
+        let client = self.client.as_mut().ok_or(TransportError::NotConnected)?;
+        client
+            .publish(
+                &message.topic,
+                QoS::AtLeastOnce,
+                message.retain,
+                &message.payload,
+            )
+            .map_err(|error| TransportError::PublishFailed(error.to_string()))?;
+        Ok(())
+    }
+
+    fn subscribe(&mut self, filter: &str) -> Result<(), TransportError> {
+        let client = self.client.as_mut().ok_or(TransportError::NotConnected)?;
+        client
+            .subscribe(filter, QoS::AtLeastOnce)
+            .map_err(|error| TransportError::ConnectionFailed(error.to_string()))?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<MqttMessage> {
+        self.shared.received.lock().unwrap().pop_front()
+    }
+}
+
+fn handle_event(shared: &Shared, event: &Result<Event<EspMqttMessage>, EspError>) {
+    match event {
+        Ok(Event::Connected(_)) => {
+            info!("MQTT client connected");
+            shared.connections.fetch_add(1, Ordering::SeqCst);
+            shared.connected.store(true, Ordering::SeqCst);
+        }
+        Ok(Event::Disconnected) => {
+            warn!("MQTT client disconnected");
+            shared.connected.store(false, Ordering::SeqCst);
+        }
+        Ok(Event::Received(message)) => {
+            let (Details::Complete, Some(topic)) = (message.details(), message.topic()) else {
+                warn!("Ignoring an MQTT message received in chunks");
+                return;
+            };
+            // The ESP-IDF client does not tell retained messages apart;
+            // replayed commands are still rejected by their ID
+            let message = MqttMessage::new(topic.to_string(), message.data(), false);
+
+            let mut received = shared.received.lock().unwrap();
+            if received.len() == MAX_RECEIVED {
+                warn!("Too many MQTT messages received, dropping the oldest one");
+                received.pop_front();
+            }
+            received.push_back(message);
+        }
+        Ok(_) => {}
+        Err(error) => warn!("MQTT client error: {}", error),
+    }
+}
@@ -1,33 +1,63 @@
+use std::thread;
+use std::time::Duration;
+
 use anyhow::{bail, Result};
-use embedded_svc::wifi::{
-    AccessPointConfiguration, AuthMethod, ClientConfiguration, Configuration,
-};
+use embedded_svc::wifi::{AuthMethod, ClientConfiguration, Configuration};
 use esp_idf_hal::peripheral;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::wifi::{BlockingWifi, EspWifi};
-use log::info;
+use log::{error, info};
+
+/// How long to wait before connecting again after a failed or lost
+/// connection.
+const RETRY_PERIOD: Duration = Duration::from_secs(30);
+
+const STACK_SIZE: usize = 8192;
 
-pub fn wifi(
-    ssid: &str,
-    pass: &str,
+/// Starts the WiFi driver and keeps it connected to `ssid` on a thread of its
+/// own, so that the plants are watered while the network is down.
+pub fn start(
+    ssid: &'static str,
+    pass: &'static str,
     modem: impl peripheral::Peripheral<P = esp_idf_hal::modem::Modem> + 'static,
     sysloop: EspSystemEventLoop,
-) -> Result<Box<EspWifi<'static>>> {
-    let mut auth_method = AuthMethod::WPA2Personal;
+) -> Result<()> {
     if ssid.is_empty() {
         bail!("Missing WiFi name")
     }
+    let esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+    let mut wifi = BlockingWifi::wrap(esp_wifi, sysloop)?;
+
+    thread::Builder::new()
+        .name("wifi".to_owned())
+        .stack_size(STACK_SIZE)
+        .spawn(move || loop {
+            if !wifi.is_connected().unwrap_or(false) {
+                if let Err(error) = connect(&mut wifi, ssid, pass) {
+                    error!(
+                        "Could not connect to the WiFi, retrying in {:?}: {}",
+                        RETRY_PERIOD, error
+                    );
+                }
+            }
+            thread::sleep(RETRY_PERIOD);
+        })?;
+    Ok(())
+}
+
+fn connect(wifi: &mut BlockingWifi<EspWifi<'static>>, ssid: &str, pass: &str) -> Result<()> {
+    let mut auth_method = AuthMethod::WPA2Personal;
     if pass.is_empty() {
         auth_method = AuthMethod::None;
         info!("Wifi password is empty");
     }
-    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
 
-    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop)?;
-    wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+    if !wifi.is_started()? {
+        wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
 
-    info!("Starting wifi...");
-    wifi.start()?;
+        info!("Starting wifi...");
+        wifi.start()?;
+    }
 
     info!("Scanning...");
     let ap_infos = wifi.scan()?;
@@ -48,20 +78,13 @@ pub fn wifi(
         None
     };
 
-    wifi.set_configuration(&Configuration::Mixed(
-        ClientConfiguration {
-            ssid: ssid.into(),
-            password: pass.into(),
-            channel,
-            auth_method,
-            ..Default::default()
-        },
-        AccessPointConfiguration {
-            ssid: "aptest".into(),
-            channel: channel.unwrap_or(1),
-            ..Default::default()
-        },
-    ))?;
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+        ssid: ssid.into(),
+        password: pass.into(),
+        channel,
+        auth_method,
+        ..Default::default()
+    }))?;
 
     info!("Connecting wifi...");
     wifi.connect()?;
@@ -71,5 +94,5 @@ pub fn wifi(
 
     let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
     info!("Wifi DHCP info: {:?}", ip_info);
-    Ok(Box::new(esp_wifi))
+    Ok(())
 }